@@ -1,18 +1,21 @@
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-
-#[cfg(windows)]
-const PIPE_NAME: &str = r"\\.\pipe\SkylineDBd-v1";
-
-#[cfg(unix)]
-const PIPE_NAME: &str = "/tmp/skylinedb-v1.sock";
+use serde::Serialize;
+use skylinedb_protocol::{
+    Hello, QueryFormat, Request, Response, ResponseData, Statement, TransactionMode, PIPE_NAME,
+    PROTOCOL_VERSION,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 #[derive(Parser)]
 #[command(name = "skylinedb-cli")]
 #[command(about = "SQLite daemon CLI", long_about = None)]
 struct Cli {
+    /// Connect over TCP to a daemon started with --listen (e.g.
+    /// "127.0.0.1:9944") instead of the local pipe/socket
+    #[arg(long, global = true)]
+    addr: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -36,11 +39,29 @@ enum Commands {
         sql: Vec<String>,
     },
     
+    /// Run a read query and print the rows
+    Query {
+        /// Database name (e.g., "galaxy.db")
+        #[arg(long, default_value = "data.db")]
+        db: String,
+        /// SQL query
+        sql: String,
+        /// Write the result as CSV to stdout
+        #[arg(long, conflicts_with = "json")]
+        csv: bool,
+        /// Print the rows as a JSON array instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    
     /// Prepare database for maintenance (checkpoint WAL)
     PrepareForMaintenance {
         /// Database name (e.g., "galaxy.db")
         #[arg(long, default_value = "data.db")]
         db: String,
+        /// Checkpoint under an exclusive lock, locking out other processes
+        #[arg(long)]
+        exclusive: bool,
     },
     
     /// Close database for file replacement
@@ -55,92 +76,60 @@ enum Commands {
         /// Database name (e.g., "galaxy.db")
         #[arg(long, default_value = "data.db")]
         db: String,
+        /// Rename this file (inside the daemon's --import-dir) over the database before reopening
+        #[arg(long)]
+        from_path: Option<String>,
     },
     
-    /// Shutdown daemon gracefully
-    Shutdown,
-}
-
-// Protocol types (minimal copy for CLI)
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(tag = "type")]
-enum Request {
-    Ping {
-        db: String,
-    },
-    ExecBatch {
-        db: String,
-        stmts: Vec<Statement>,
-        #[serde(default = "default_tx_mode")]
-        tx: String,
-    },
-    PrepareForMaintenance {
+    /// Rebuild the database file to reclaim free space (VACUUM)
+    Vacuum {
+        /// Database name (e.g., "galaxy.db")
+        #[arg(long, default_value = "data.db")]
         db: String,
     },
-    CloseDatabase {
+    
+    /// Refresh query planner statistics (ANALYZE)
+    Analyze {
+        /// Database name (e.g., "galaxy.db")
+        #[arg(long, default_value = "data.db")]
         db: String,
     },
-    ReopenDatabase {
+    
+    /// Show or set the schema version (PRAGMA user_version)
+    SchemaVersion {
+        /// Database name (e.g., "galaxy.db")
+        #[arg(long, default_value = "data.db")]
         db: String,
+        /// Set the version instead of showing it
+        #[arg(long)]
+        set: Option<i32>,
     },
-    Shutdown,
-}
-
-fn default_tx_mode() -> String {
-    "atomic".to_string()
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Statement {
-    sql: String,
-    #[serde(default)]
-    params: Vec<serde_json::Value>,
-}
-
-#[derive(Debug, Deserialize)]
-#[serde(tag = "status")]
-enum Response {
-    #[serde(rename = "ok")]
-    Ok { #[serde(flatten)] data: ResponseData },
     
-    #[serde(rename = "error")]
-    Error { message: String },
-}
-
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum ResponseData {
-    Ping {
-        version: String,
-        db_path: String,
-        rev: i64,
-    },
-    ExecBatch {
-        rev: i64,
-        rows_affected: u64,
-    },
-    PrepareForMaintenance {
-        checkpointed: bool,
-    },
-    CloseDatabase {
-        closed: bool,
-    },
-    ReopenDatabase {
-        reopened: bool,
-        rev: i64,
+    /// Dump daemon state (workers, stats, recent errors, config) as JSON
+    Diagnostics {
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        out: Option<std::path::PathBuf>,
     },
+    
+    /// List the daemon's live workers (state, uptime, idle time, requests)
+    Status,
+    
+    /// Shutdown daemon gracefully
+    Shutdown,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
+    let addr = cli.addr.as_deref();
 
     match cli.command {
         Commands::Ping { db } => {
-            let response = send_request(Request::Ping { db: db.clone() }).await?;
+            let response = send_request(addr, Request::Ping { db: db.clone() }).await?;
             match response {
                 Response::Ok {
-                    data: ResponseData::Ping { version, db_path, rev },
+                    data: ResponseData::Ping { version, db_path, rev, .. },
                 } => {
                     println!("✓ Daemon is running");
                     println!("  Database: {}", db);
@@ -148,7 +137,7 @@ async fn main() -> Result<()> {
                     println!("  Path: {}", db_path);
                     println!("  Revision: {}", rev);
                 }
-                Response::Error { message } => {
+                Response::Error { message, .. } => {
                     eprintln!("✗ Error: {}", message);
                     std::process::exit(1);
                 }
@@ -160,27 +149,54 @@ async fn main() -> Result<()> {
         }
 
         Commands::Exec { db, sql } => {
-            let stmts = sql.into_iter().map(|s| Statement {
-                sql: s,
-                params: vec![],
-            }).collect();
-
-            let request = Request::ExecBatch {
-                db: db.clone(),
-                stmts,
-                tx: "atomic".to_string(),
-            };
+            let stmts = sql.into_iter().map(|s| Statement::new(s, vec![])).collect();
+            let request = Request::exec_batch(db.clone(), stmts, TransactionMode::Atomic);
 
-            let response = send_request(request).await?;
+            let response = send_request(addr, request).await?;
             match response {
                 Response::Ok {
-                    data: ResponseData::ExecBatch { rev, rows_affected },
+                    data: ResponseData::ExecBatch { rev, rows_affected, .. },
                 } => {
                     println!("✓ Executed successfully on database: {}", db);
                     println!("  Rows affected: {}", rows_affected);
-                    println!("  New revision: {}", rev);
+                    if let Some(rev) = rev {
+                        println!("  New revision: {}", rev);
+                    }
+                }
+                Response::Error { message, .. } => {
+                    eprintln!("✗ Error: {}", message);
+                    std::process::exit(1);
+                }
+                _ => {
+                    eprintln!("✗ Unexpected response");
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Commands::Query { db, sql, csv, json } => {
+            let format = if csv { QueryFormat::Csv } else { QueryFormat::Json };
+            let request = Request::query(db, sql, vec![], format);
+
+            let response = send_request(addr, request).await?;
+            match response {
+                Response::Ok {
+                    data: ResponseData::QueryCsv { csv },
+                } => {
+                    print!("{}", csv);
+                }
+                Response::Ok {
+                    data: ResponseData::Query { columns, rows, .. },
+                } => {
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&rows)?);
+                    } else {
+                        print_table(&columns, &rows);
+                    }
+                    let plural = if rows.len() == 1 { "" } else { "s" };
+                    eprintln!("({} row{})", rows.len(), plural);
                 }
-                Response::Error { message } => {
+                Response::Error { message, .. } => {
                     eprintln!("✗ Error: {}", message);
                     std::process::exit(1);
                 }
@@ -191,8 +207,9 @@ async fn main() -> Result<()> {
             }
         }
 
-        Commands::PrepareForMaintenance { db } => {
-            let response = send_request(Request::PrepareForMaintenance { db: db.clone() }).await?;
+        Commands::PrepareForMaintenance { db, exclusive } => {
+            let request = Request::PrepareForMaintenance { db: db.clone(), exclusive };
+            let response = send_request(addr, request).await?;
             match response {
                 Response::Ok {
                     data: ResponseData::PrepareForMaintenance { checkpointed },
@@ -200,7 +217,7 @@ async fn main() -> Result<()> {
                     println!("✓ Database prepared for maintenance: {}", db);
                     println!("  WAL checkpointed: {}", checkpointed);
                 }
-                Response::Error { message } => {
+                Response::Error { message, .. } => {
                     eprintln!("✗ Error: {}", message);
                     std::process::exit(1);
                 }
@@ -212,7 +229,7 @@ async fn main() -> Result<()> {
         }
 
         Commands::CloseDatabase { db } => {
-            let response = send_request(Request::CloseDatabase { db: db.clone() }).await?;
+            let response = send_request(addr, Request::CloseDatabase { db: db.clone() }).await?;
             match response {
                 Response::Ok {
                     data: ResponseData::CloseDatabase { closed },
@@ -221,7 +238,7 @@ async fn main() -> Result<()> {
                     println!("  Closed: {}", closed);
                     println!("  File locks released - safe to replace files");
                 }
-                Response::Error { message } => {
+                Response::Error { message, .. } => {
                     eprintln!("✗ Error: {}", message);
                     std::process::exit(1);
                 }
@@ -232,17 +249,153 @@ async fn main() -> Result<()> {
             }
         }
 
-        Commands::ReopenDatabase { db } => {
-            let response = send_request(Request::ReopenDatabase { db: db.clone() }).await?;
+        Commands::ReopenDatabase { db, from_path } => {
+            let request = Request::ReopenDatabase { db: db.clone(), from_path };
+            let response = send_request(addr, request).await?;
             match response {
                 Response::Ok {
-                    data: ResponseData::ReopenDatabase { reopened, rev },
+                    data: ResponseData::ReopenDatabase { reopened, rev, .. },
                 } => {
                     println!("✓ Database reopened: {}", db);
                     println!("  Reopened: {}", reopened);
                     println!("  Current revision: {}", rev);
                 }
-                Response::Error { message } => {
+                Response::Error { message, .. } => {
+                    eprintln!("✗ Error: {}", message);
+                    std::process::exit(1);
+                }
+                _ => {
+                    eprintln!("✗ Unexpected response");
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Commands::Vacuum { db } => {
+            let response = send_request(addr, Request::Vacuum { db: db.clone() }).await?;
+            match response {
+                Response::Ok {
+                    data: ResponseData::Vacuum { size_before, size_after, duration_ms, rev },
+                } => {
+                    println!("✓ Database vacuumed: {}", db);
+                    println!("  Size: {} -> {} bytes", size_before, size_after);
+                    println!("  Reclaimed: {} bytes", size_before.saturating_sub(size_after));
+                    println!("  Took: {} ms", duration_ms);
+                    println!("  Revision: {}", rev);
+                }
+                Response::Error { message, .. } => {
+                    eprintln!("✗ Error: {}", message);
+                    std::process::exit(1);
+                }
+                _ => {
+                    eprintln!("✗ Unexpected response");
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Commands::Analyze { db } => {
+            let response = send_request(addr, Request::Analyze { db: db.clone() }).await?;
+            match response {
+                Response::Ok {
+                    data: ResponseData::Analyze { analyzed_tables, duration_ms, rev },
+                } => {
+                    println!("✓ Database analyzed: {}", db);
+                    println!("  Tables with statistics: {}", analyzed_tables.join(", "));
+                    println!("  Took: {} ms", duration_ms);
+                    println!("  Revision: {}", rev);
+                }
+                Response::Error { message, .. } => {
+                    eprintln!("✗ Error: {}", message);
+                    std::process::exit(1);
+                }
+                _ => {
+                    eprintln!("✗ Unexpected response");
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Commands::SchemaVersion { db, set } => {
+            let request = match set {
+                Some(version) => Request::SetSchemaVersion { db: db.clone(), version },
+                None => Request::GetSchemaVersion { db: db.clone() },
+            };
+            let response = send_request(addr, request).await?;
+            match response {
+                Response::Ok { data: ResponseData::SchemaVersion { user_version } } => {
+                    println!("✓ Schema version of {}: {}", db, user_version);
+                }
+                Response::Error { message, .. } => {
+                    eprintln!("✗ Error: {}", message);
+                    std::process::exit(1);
+                }
+                _ => {
+                    eprintln!("✗ Unexpected response");
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Commands::Diagnostics { out } => {
+            let response = send_request(addr, Request::Diagnostics).await?;
+            match response {
+                Response::Ok {
+                    data: ResponseData::Diagnostics { process, config, workers },
+                } => {
+                    let json = serde_json::to_string_pretty(&serde_json::json!({
+                        "process": process,
+                        "config": config,
+                        "workers": workers,
+                    }))?;
+                    match out {
+                        Some(path) => {
+                            std::fs::write(&path, json)
+                                .with_context(|| format!("Failed to write {}", path.display()))?;
+                            println!("✓ Diagnostics written to {}", path.display());
+                        }
+                        None => println!("{}", json),
+                    }
+                }
+                Response::Error { message, .. } => {
+                    eprintln!("✗ Error: {}", message);
+                    std::process::exit(1);
+                }
+                _ => {
+                    eprintln!("✗ Unexpected response");
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        Commands::Status => {
+            let response = send_request(addr, Request::Status).await?;
+            match response {
+                Response::Ok {
+                    data: ResponseData::Status { workers },
+                } => {
+                    let columns: Vec<String> = ["db", "state", "uptime", "idle", "idles out in", "requests"]
+                        .iter()
+                        .map(|c| c.to_string())
+                        .collect();
+                    let rows: Vec<Vec<serde_json::Value>> = workers
+                        .iter()
+                        .map(|w| {
+                            vec![
+                                w.db.clone().into(),
+                                w.state.clone().into(),
+                                duration_text(w.uptime_secs).into(),
+                                duration_text(w.idle_secs).into(),
+                                w.idle_timeout_secs.map_or("-".to_string(), duration_text).into(),
+                                w.requests.into(),
+                            ]
+                        })
+                        .collect();
+                    print_table(&columns, &rows);
+                    let plural = if workers.len() == 1 { "" } else { "s" };
+                    eprintln!("({} worker{})", workers.len(), plural);
+                }
+                Response::Error { message, .. } => {
                     eprintln!("✗ Error: {}", message);
                     std::process::exit(1);
                 }
@@ -255,7 +408,7 @@ async fn main() -> Result<()> {
 
         Commands::Shutdown => {
             // Shutdown response is just empty OK, ignore parsing error
-            match send_request(Request::Shutdown).await {
+            match send_request(addr, Request::Shutdown).await {
                 Ok(_) | Err(_) => {
                     println!("✓ Daemon shutdown requested");
                 }
@@ -266,50 +419,120 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-#[cfg(windows)]
-async fn send_request(request: Request) -> Result<Response> {
-    use tokio::net::windows::named_pipe::ClientOptions;
-    
-    // Connect to daemon
-    let mut stream = ClientOptions::new()
-        .open(PIPE_NAME)
-        .context("Failed to connect to daemon. Is it running?")?;
+/// Print `rows` as an aligned ASCII table under `columns`
+fn print_table(columns: &[String], rows: &[Vec<serde_json::Value>]) {
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| row.iter().map(cell_text).collect())
+        .collect();
+
+    let mut widths: Vec<usize> = columns.iter().map(|c| c.chars().count()).collect();
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
 
-    // Serialize request
-    let json = serde_json::to_vec(&request)?;
-    let length = json.len() as u32;
+    let separator = widths
+        .iter()
+        .map(|w| "-".repeat(w + 2))
+        .collect::<Vec<_>>()
+        .join("+");
+    let line = |values: &[String]| {
+        let padded: Vec<String> = values
+            .iter()
+            .zip(&widths)
+            .map(|(value, &width)| format!(" {:<width$} ", value, width = width))
+            .collect();
+        println!("|{}|", padded.join("|"));
+    };
+
+    println!("+{}+", separator);
+    line(columns);
+    println!("+{}+", separator);
+    for row in &cells {
+        line(row);
+    }
+    println!("+{}+", separator);
+}
 
-    // Send request (length-prefixed)
-    stream.write_all(&length.to_le_bytes()).await?;
-    stream.write_all(&json).await?;
-    stream.flush().await?;
+/// `secs` as e.g. "1h02m", "4m05s" or "12s"
+fn duration_text(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
 
-    // Read response length
-    let mut len_buf = [0u8; 4];
-    stream.read_exact(&mut len_buf).await?;
-    let response_len = u32::from_le_bytes(len_buf) as usize;
+/// Table text for one value: strings unquoted, NULL spelled out
+fn cell_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => "NULL".to_string(),
+        serde_json::Value::String(s) => s.replace('\n', " "),
+        other => other.to_string(),
+    }
+}
 
-    // Read response body
-    let mut response_buf = vec![0u8; response_len];
-    stream.read_exact(&mut response_buf).await?;
+/// Send one request and wait for its response, over TCP when `addr` is
+/// given and the local pipe/socket otherwise
+async fn send_request(addr: Option<&str>, request: Request) -> Result<Response> {
+    match addr {
+        Some(addr) => {
+            let stream = tokio::net::TcpStream::connect(addr)
+                .await
+                .with_context(|| format!("Failed to connect to daemon at {}. Is it listening there?", addr))?;
+            exchange(stream, request).await
+        }
+        None => exchange(connect_local().await?, request).await,
+    }
+}
 
-    // Parse response
-    let response: Response = serde_json::from_slice(&response_buf)?;
+#[cfg(windows)]
+async fn connect_local() -> Result<tokio::net::windows::named_pipe::NamedPipeClient> {
+    use tokio::net::windows::named_pipe::ClientOptions;
 
-    Ok(response)
+    ClientOptions::new()
+        .open(PIPE_NAME)
+        .context("Failed to connect to daemon. Is it running?")
 }
 
 #[cfg(unix)]
-async fn send_request(request: Request) -> Result<Response> {
-    use tokio::net::UnixStream;
-    
-    // Connect to daemon
-    let mut stream = UnixStream::connect(PIPE_NAME)
+async fn connect_local() -> Result<tokio::net::UnixStream> {
+    tokio::net::UnixStream::connect(PIPE_NAME)
         .await
-        .context("Failed to connect to daemon. Is it running?")?;
+        .context("Failed to connect to daemon. Is it running?")
+}
+
+/// Handshake, then one request/response round trip on a fresh connection
+async fn exchange<S>(mut stream: S, request: Request) -> Result<Response>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // A daemon that can't speak our protocol version says so up front
+    let hello = Hello::new(PROTOCOL_VERSION);
+    let reply: Response = round_trip(&mut stream, &hello).await?;
+    if let Response::Error { message, .. } = reply {
+        anyhow::bail!("Daemon refused the connection: {}", message);
+    }
+
+    let response = round_trip(&mut stream, &request).await?;
+
+    // Close our side cleanly so the daemon sees EOF, not a broken pipe
+    stream.shutdown().await?;
+    drop(stream);
+
+    Ok(response)
+}
 
+/// Send one length-prefixed message and read the length-prefixed reply
+async fn round_trip<S, T>(stream: &mut S, message: &T) -> Result<Response>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    T: Serialize,
+{
     // Serialize request
-    let json = serde_json::to_vec(&request)?;
+    let json = serde_json::to_vec(message)?;
     let length = json.len() as u32;
 
     // Send request (length-prefixed)
@@ -0,0 +1,195 @@
+use crate::config::DaemonConfig;
+use crate::protocol::Response;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BreakerState {
+    /// Requests flow; consecutive database failures are counted
+    Closed,
+    /// Requests fail fast with `CIRCUIT_OPEN` until the cooldown ends
+    Open { until: Instant },
+    /// Cooldown over; the next request probes whether the database recovered
+    HalfOpen,
+}
+
+/// Per-worker circuit breaker. Only failures of the database itself (I/O
+/// errors, full disk, corruption) count; client errors such as bad SQL or
+/// constraint violations never trip it.
+pub struct CircuitBreaker {
+    threshold: u32,
+    window: Duration,
+    cooldown: Duration,
+    state: BreakerState,
+    failures: u32,
+    class: Option<&'static str>,
+    first_failure: Instant,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: &DaemonConfig) -> Self {
+        Self {
+            threshold: config.breaker_threshold,
+            window: Duration::from_secs(config.breaker_window_secs),
+            cooldown: Duration::from_secs(config.breaker_cooldown_secs),
+            state: BreakerState::Closed,
+            failures: 0,
+            class: None,
+            first_failure: Instant::now(),
+        }
+    }
+
+    /// Whether a request may run (moves Open to HalfOpen after the cooldown)
+    pub fn allow(&mut self) -> bool {
+        match self.state {
+            BreakerState::Open { until } if Instant::now() < until => false,
+            BreakerState::Open { .. } => {
+                info!("Circuit breaker half-open, probing database");
+                self.state = BreakerState::HalfOpen;
+                true
+            }
+            _ => true,
+        }
+    }
+
+    /// Update the breaker with the outcome of an allowed request
+    pub fn record(&mut self, resp: &Response) {
+        if self.threshold == 0 {
+            return;
+        }
+
+        let Some(class) = failure_class(resp) else {
+            // The database answered (success or a client error)
+            if self.state == BreakerState::HalfOpen {
+                info!("Circuit breaker closed, database recovered");
+            }
+            if matches!(resp, Response::Ok { .. }) || self.state == BreakerState::HalfOpen {
+                self.reset();
+            }
+            return;
+        };
+
+        if self.state == BreakerState::HalfOpen {
+            self.trip(class);
+            return;
+        }
+
+        if self.class == Some(class) && self.first_failure.elapsed() <= self.window {
+            self.failures += 1;
+        } else {
+            self.class = Some(class);
+            self.failures = 1;
+            self.first_failure = Instant::now();
+        }
+        if self.failures >= self.threshold {
+            self.trip(class);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.state = BreakerState::Closed;
+        self.failures = 0;
+        self.class = None;
+    }
+
+    fn trip(&mut self, class: &'static str) {
+        self.class = Some(class);
+        warn!(
+            class = class,
+            failures = self.failures,
+            cooldown_secs = self.cooldown.as_secs(),
+            "Circuit breaker open"
+        );
+        self.state = BreakerState::Open { until: Instant::now() + self.cooldown };
+    }
+
+    /// "closed", "open" or "half_open"
+    pub fn state_name(&self) -> &'static str {
+        match self.state {
+            BreakerState::Closed => "closed",
+            BreakerState::Open { .. } => "open",
+            BreakerState::HalfOpen => "half_open",
+        }
+    }
+
+    pub fn open_error(&self) -> Response {
+        Response::error_with_code(
+            format!(
+                "Database is failing repeatedly ({}); requests are paused",
+                self.class.unwrap_or("unknown")
+            ),
+            "CIRCUIT_OPEN",
+        )
+    }
+}
+
+/// Primary SQLite result code of an error response: its `sqlite_code`, or
+/// else the code sqlx puts in error messages ("(code: 266) disk I/O error"
+/// -> 10)
+pub fn result_code(resp: &Response) -> Option<i32> {
+    let Response::Error { message, sqlite_code, .. } = resp else {
+        return None;
+    };
+    if sqlite_code.is_some() {
+        return *sqlite_code;
+    }
+    let code: i32 = message
+        .split("(code: ")
+        .nth(1)?
+        .split(')')
+        .next()?
+        .parse()
+        .ok()?;
+
+    // Extended codes carry the primary code in the low byte
+    Some(code & 0xff)
+}
+
+/// Class of a database-level failure
+fn failure_class(resp: &Response) -> Option<&'static str> {
+    match result_code(resp)? {
+        10 => Some("io"),
+        11 | 26 => Some("corrupt"),
+        13 => Some("full"),
+        14 => Some("cantopen"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db_error(code: i32) -> Response {
+        Response::error(format!("error returned from database: (code: {}) boom", code))
+    }
+
+    #[test]
+    fn test_failure_class() {
+        assert_eq!(failure_class(&db_error(266)), Some("io"));
+        assert_eq!(failure_class(&db_error(13)), Some("full"));
+        assert_eq!(failure_class(&db_error(19)), None); // constraint
+        assert_eq!(failure_class(&Response::error("Empty statement batch")), None);
+    }
+
+    #[test]
+    fn test_breaker_trips_and_recovers() {
+        let config = DaemonConfig {
+            breaker_threshold: 2,
+            breaker_cooldown_secs: 0,
+            ..DaemonConfig::default()
+        };
+        let mut breaker = CircuitBreaker::new(&config);
+
+        breaker.record(&db_error(10));
+        assert_eq!(breaker.state_name(), "closed");
+        breaker.record(&db_error(10));
+        assert_eq!(breaker.state_name(), "open");
+
+        // Zero cooldown: the next request is the half-open probe
+        assert!(breaker.allow());
+        assert_eq!(breaker.state_name(), "half_open");
+        breaker.record(&Response::ok_shutdown());
+        assert_eq!(breaker.state_name(), "closed");
+    }
+}
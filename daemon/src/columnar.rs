@@ -0,0 +1,158 @@
+//! Arrow IPC encoding of query results (`arrow` feature)
+
+use anyhow::Result;
+use arrow_array::builder::{BinaryBuilder, Float64Builder, Int64Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, Schema};
+use serde_json::Value;
+use std::sync::Arc;
+
+/// Encode a result set as an Arrow IPC stream holding one record batch.
+///
+/// Column types follow the declared type's SQLite affinity (INT -> Int64,
+/// REAL/FLOA/DOUB -> Float64, CHAR/CLOB/TEXT -> Utf8, BLOB -> Binary); columns
+/// without a usable declared type (expressions, NUMERIC) are typed from their
+/// values. SQLite doesn't enforce declared types, so a column holding values
+/// of another type falls back to Utf8.
+pub fn to_arrow_ipc(
+    columns: &[String],
+    decl_types: &[Option<String>],
+    rows: &[Vec<Value>],
+) -> Result<Vec<u8>> {
+    let mut fields = Vec::with_capacity(columns.len());
+    let mut arrays: Vec<ArrayRef> = Vec::with_capacity(columns.len());
+
+    for (i, name) in columns.iter().enumerate() {
+        let values: Vec<&Value> = rows.iter().map(|row| row.get(i).unwrap_or(&Value::Null)).collect();
+        let declared = decl_types.get(i).and_then(|t| t.as_deref()).and_then(affinity_type);
+        let data_type = declared
+            .or_else(|| inferred_type(&values))
+            .filter(|t| values.iter().all(|v| fits(v, t)))
+            .unwrap_or(DataType::Utf8);
+
+        arrays.push(build_array(&data_type, &values));
+        fields.push(Field::new(name, data_type, true));
+    }
+
+    let schema = Arc::new(Schema::new(fields));
+    let batch = RecordBatch::try_new(schema.clone(), arrays)?;
+
+    let mut writer = StreamWriter::try_new(Vec::new(), &schema)?;
+    writer.write(&batch)?;
+    writer.finish()?;
+    Ok(writer.into_inner()?)
+}
+
+/// Arrow type for a declared column type, by SQLite's affinity rules
+fn affinity_type(decl_type: &str) -> Option<DataType> {
+    let upper = decl_type.to_uppercase();
+    if upper.contains("INT") {
+        Some(DataType::Int64)
+    } else if upper.contains("CHAR") || upper.contains("CLOB") || upper.contains("TEXT") {
+        Some(DataType::Utf8)
+    } else if upper.contains("BLOB") {
+        Some(DataType::Binary)
+    } else if upper.contains("REAL") || upper.contains("FLOA") || upper.contains("DOUB") {
+        Some(DataType::Float64)
+    } else {
+        None
+    }
+}
+
+/// Arrow type of the first non-null value; an all-null column has none
+fn inferred_type(values: &[&Value]) -> Option<DataType> {
+    let integers = values.iter().all(|v| v.is_null() || v.is_i64());
+    let first = values.iter().find(|v| !v.is_null())?;
+    Some(match first {
+        Value::Number(_) if integers => DataType::Int64,
+        Value::Number(_) => DataType::Float64,
+        Value::Object(_) if blob_bytes(first).is_some() => DataType::Binary,
+        _ => DataType::Utf8,
+    })
+}
+
+fn fits(value: &Value, data_type: &DataType) -> bool {
+    match (value, data_type) {
+        (Value::Null, _) => true,
+        (Value::Number(n), DataType::Int64) => n.is_i64(),
+        (Value::Number(_), DataType::Float64) => true,
+        (Value::Object(_), DataType::Binary) => blob_bytes(value).is_some(),
+        (_, DataType::Utf8) => true,
+        _ => false,
+    }
+}
+
+/// Bytes of a `{"$blob": ...}` value as written by `row_values`
+fn blob_bytes(value: &Value) -> Option<Vec<u8>> {
+    crate::query::blob_text(value).and_then(|text| crate::query::decode_blob(text).ok())
+}
+
+fn build_array(data_type: &DataType, values: &[&Value]) -> ArrayRef {
+    match data_type {
+        DataType::Int64 => {
+            let mut builder = Int64Builder::with_capacity(values.len());
+            values.iter().for_each(|v| builder.append_option(v.as_i64()));
+            Arc::new(builder.finish())
+        }
+        DataType::Float64 => {
+            let mut builder = Float64Builder::with_capacity(values.len());
+            values.iter().for_each(|v| builder.append_option(v.as_f64()));
+            Arc::new(builder.finish())
+        }
+        DataType::Binary => {
+            let mut builder = BinaryBuilder::new();
+            for value in values {
+                match blob_bytes(value) {
+                    Some(bytes) => builder.append_value(bytes),
+                    None => builder.append_null(),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        _ => {
+            let mut builder = StringBuilder::new();
+            for value in values {
+                match value {
+                    Value::Null => builder.append_null(),
+                    Value::String(s) => builder.append_value(s),
+                    other => builder.append_value(other.to_string()),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Array, Int64Array, StringArray};
+    use arrow_ipc::reader::StreamReader;
+    use serde_json::json;
+
+    #[test]
+    fn test_arrow_round_trip() {
+        let columns = vec!["id".to_string(), "name".to_string(), "mixed".to_string()];
+        let decl_types = vec![Some("INTEGER".to_string()), Some("TEXT".to_string()), Some("INTEGER".to_string())];
+        let rows = vec![
+            vec![json!(1), json!("a"), json!(5)],
+            vec![json!(2), Value::Null, json!("not a number")],
+        ];
+
+        let bytes = to_arrow_ipc(&columns, &decl_types, &rows).unwrap();
+        let mut reader = StreamReader::try_new(bytes.as_slice(), None).unwrap();
+        let batch = reader.next().unwrap().unwrap();
+
+        let schema = batch.schema();
+        assert_eq!(schema.field(0).data_type(), &DataType::Int64);
+        assert_eq!(schema.field(1).data_type(), &DataType::Utf8);
+        // Declared INTEGER but holds text
+        assert_eq!(schema.field(2).data_type(), &DataType::Utf8);
+
+        let ids = batch.column(0).as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(ids.values(), &[1, 2]);
+        let names = batch.column(1).as_any().downcast_ref::<StringArray>().unwrap();
+        assert!(names.is_null(1));
+    }
+}
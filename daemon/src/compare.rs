@@ -0,0 +1,115 @@
+use crate::protocol::TableComparison;
+use crate::schema::is_daemon_table;
+use anyhow::Result;
+use futures_util::TryStreamExt;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{Connection, SqliteConnection};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Row count and content checksum of one table
+pub struct TableSummary {
+    pub rows: u64,
+    pub checksum: u64,
+}
+
+/// Compare the user tables of two database files, read-only. Each side is
+/// read inside a single transaction, so it is a consistent snapshot even
+/// while its worker keeps writing.
+pub async fn compare_databases(path_a: &Path, path_b: &Path) -> Result<Vec<TableComparison>> {
+    let a = summarize(path_a).await?;
+    let b = summarize(path_b).await?;
+
+    let mut names: Vec<&String> = a.keys().chain(b.keys()).collect();
+    names.sort();
+    names.dedup();
+
+    Ok(names
+        .into_iter()
+        .map(|name| {
+            let (sa, sb) = (a.get(name), b.get(name));
+            TableComparison {
+                name: name.clone(),
+                rows_a: sa.map(|s| s.rows),
+                rows_b: sb.map(|s| s.rows),
+                identical: matches!((sa, sb), (Some(x), Some(y)) if x.rows == y.rows && x.checksum == y.checksum),
+            }
+        })
+        .collect())
+}
+
+async fn summarize(path: &Path) -> Result<BTreeMap<String, TableSummary>> {
+    let options = SqliteConnectOptions::new().filename(path).read_only(true);
+    let mut conn = SqliteConnection::connect_with(&options).await?;
+    let mut tx = conn.begin().await?;
+
+    let tables: Vec<String> = sqlx::query_scalar(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut summaries = BTreeMap::new();
+    for table in tables {
+        if is_daemon_table(&table) {
+            continue;
+        }
+
+        let summary = summarize_table(&mut tx, &table).await?;
+        summaries.insert(table, summary);
+    }
+
+    tx.rollback().await?;
+    Ok(summaries)
+}
+
+/// Count and checksum the rows of `table`. Rows are visited in primary key
+/// order (rowid for tables without one) so the checksum doesn't depend on
+/// physical row order. The checksum is 64-bit FNV-1a over each row's JSON
+/// encoding, stable across daemon builds.
+pub async fn summarize_table(conn: &mut SqliteConnection, table: &str) -> Result<TableSummary> {
+    let pk: Vec<String> = sqlx::query_scalar(
+        "SELECT name FROM pragma_table_info(?) WHERE pk > 0 ORDER BY pk",
+    )
+    .bind(table)
+    .fetch_all(&mut *conn)
+    .await?;
+    let order_by = if pk.is_empty() {
+        "rowid".to_string()
+    } else {
+        pk.iter().map(|c| quote_ident(c)).collect::<Vec<_>>().join(", ")
+    };
+
+    let sql = format!("SELECT * FROM {} ORDER BY {}", quote_ident(table), order_by);
+    let mut rows = sqlx::query(&sql).fetch(&mut *conn);
+    let mut hasher = Fnv64::default();
+    let mut count = 0u64;
+    while let Some(row) = rows.try_next().await? {
+        hasher.write(serde_json::to_string(&crate::query::row_values(&row))?.as_bytes());
+        count += 1;
+    }
+
+    Ok(TableSummary { rows: count, checksum: hasher.0 })
+}
+
+/// 64-bit FNV-1a
+struct Fnv64(u64);
+
+impl Default for Fnv64 {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv64 {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
@@ -0,0 +1,546 @@
+use crate::functions::{builtin, BUILTIN_FUNCTIONS};
+use crate::protocol::{ClientAuthorizer, SyncLevel};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Default cap on a single bound parameter (4 MB)
+pub const DEFAULT_MAX_PARAM_BYTES: usize = 4 * 1024 * 1024;
+
+/// Default cap on all parameters of one statement combined (8 MB)
+pub const DEFAULT_MAX_PARAMS_TOTAL_BYTES: usize = 8 * 1024 * 1024;
+
+/// Default cap on a per-request `cache_size` override (1 GiB)
+pub const DEFAULT_MAX_CACHE_SIZE_KIB: u64 = 1024 * 1024;
+
+/// Default initial capacity of a connection's read buffer (4 KB)
+pub const DEFAULT_READ_BUFFER_BYTES: usize = 4096;
+
+/// Default cap on the encoded size of one query result (64 MB)
+pub const DEFAULT_MAX_RESULT_BYTES: usize = 64 * 1024 * 1024;
+
+/// SQLite threading mode connections are opened with.
+///
+/// Every connection belongs to one worker and is only used by one task at a
+/// time, so `MultiThread` (no per-connection mutex) is sufficient; it may
+/// move between runtime threads, which rules out single-thread mode (a
+/// build-time/process-wide setting that cannot be chosen per connection).
+/// `Serialized` adds SQLite's connection mutex, for custom functions or
+/// shared-cache use that touch a connection from elsewhere.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThreadMode {
+    #[default]
+    MultiThread,
+    Serialized,
+}
+
+impl FromStr for ThreadMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "multi-thread" => Ok(ThreadMode::MultiThread),
+            "serialized" => Ok(ThreadMode::Serialized),
+            other => bail!("expected multi-thread or serialized, got {}", other),
+        }
+    }
+}
+
+/// How a connection waits when another process holds the lock it needs.
+///
+/// `Timeout` is SQLite's flat `busy_timeout` (`--busy-timeout-ms`, 5 s by
+/// default). `YieldToWriter` backs a
+/// blocked read off progressively while a blocked write retries every few
+/// milliseconds, so occasional writes aren't starved by a stream of readers
+/// from other processes. Both give up after `--busy-timeout-ms`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BusyPolicy {
+    #[default]
+    Timeout,
+    YieldToWriter,
+}
+
+impl FromStr for BusyPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "timeout" => Ok(BusyPolicy::Timeout),
+            "yield_to_writer" => Ok(BusyPolicy::YieldToWriter),
+            other => bail!("expected timeout or yield_to_writer, got {}", other),
+        }
+    }
+}
+
+/// Rules SQLite's authorizer enforces on client statements while they run;
+/// a statement they deny fails with `AUTHORIZER_DENIED` and its batch rolls
+/// back like on any other error
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AuthorizerRules {
+    /// Tables client statements may read, write or alter; empty allows all
+    pub allowed_tables: BTreeSet<String>,
+    /// Authorizer action codes always denied (see `ffi::action_code`)
+    pub denied_actions: BTreeSet<i32>,
+}
+
+impl AuthorizerRules {
+    /// No rules configured, so statements need no authorizer pass
+    pub fn is_empty(&self) -> bool {
+        self.allowed_tables.is_empty() && self.denied_actions.is_empty()
+    }
+
+    /// The rule sets among the daemon's and a client's that hold any rules;
+    /// a statement must pass all of them
+    pub fn combined(daemon: &AuthorizerRules, client: Option<&AuthorizerRules>) -> Vec<AuthorizerRules> {
+        [Some(daemon), client].into_iter().flatten().filter(|rules| !rules.is_empty()).cloned().collect()
+    }
+
+    /// Rules a client set for its connection in `Hello`
+    pub fn from_client(client: &ClientAuthorizer) -> Result<Self> {
+        let denied_actions = client
+            .denied_actions
+            .iter()
+            .map(|name| {
+                crate::ffi::action_code(name).with_context(|| format!("Unknown authorizer action: {}", name))
+            })
+            .collect::<Result<_>>()?;
+        Ok(AuthorizerRules { allowed_tables: client.allowed_tables.iter().cloned().collect(), denied_actions })
+    }
+}
+
+/// Daemon configuration, parsed from command-line arguments
+#[derive(Debug, Clone, Serialize)]
+pub struct DaemonConfig {
+    /// Database directory (defaults to the current directory)
+    pub db_dir: Option<PathBuf>,
+    /// Maximum size of a single parameter value in bytes
+    pub max_param_bytes: usize,
+    /// Maximum combined size of a statement's parameters in bytes
+    pub max_params_total_bytes: usize,
+    /// Create database files that don't exist yet (`--no-auto-create` disables)
+    pub auto_create: bool,
+    /// Record the tables touched by each committed batch (`--change-feed`)
+    pub change_feed: bool,
+    /// Shut a worker down after this many seconds without a request
+    /// (`--idle-timeout-secs`); 0 keeps it open until the daemon stops
+    pub idle_timeout_secs: u64,
+    /// Per-database overrides of `idle_timeout_secs`, keyed by file name
+    /// (`--db-idle-timeout-secs NAME=SECS`, repeatable)
+    pub db_idle_timeouts: BTreeMap<String, u64>,
+    /// After the idle timeout, keep a parked worker (connection released)
+    /// this many seconds before exiting; 0 exits immediately
+    pub idle_grace_secs: u64,
+    /// Directory a `ReopenDatabase` `from_path` must be in; unset disables
+    /// `from_path` (`--import-dir`)
+    pub import_dir: Option<PathBuf>,
+    /// Fsync the database directory after a `from_path` replacement so the
+    /// rename survives a crash (`--fsync-dir`; no-op on Windows)
+    pub fsync_dir: bool,
+    /// Cap on new workers spawned per second, with bursts up to the same
+    /// number; 0 means unlimited (`--max-spawns-per-sec`)
+    pub max_spawns_per_sec: u32,
+    /// Cap on live workers (open databases); opening one more first stops
+    /// the least recently used idle worker. 0 means unlimited
+    /// (`--max-workers`)
+    pub max_workers: usize,
+    /// Open connections with `trusted_schema=OFF` and SQLite defensive mode
+    /// (`--no-hardening` disables)
+    pub hardening: bool,
+    /// Enforce `FOREIGN KEY` constraints (`PRAGMA foreign_keys`) on the write
+    /// connection (`--no-foreign-keys` disables)
+    pub foreign_keys: bool,
+    /// Built-in SQL functions registered on every connection
+    /// (`--functions regexp,uuid`; empty for none, default all)
+    pub functions: Vec<String>,
+    /// Consecutive database failures (I/O, full, corrupt) of one class that
+    /// open a worker's circuit breaker; 0 disables (`--breaker-threshold`)
+    pub breaker_threshold: u32,
+    /// Failures only count as consecutive within this window (`--breaker-window-secs`)
+    pub breaker_window_secs: u64,
+    /// How long an open breaker fails fast before probing (`--breaker-cooldown-secs`)
+    pub breaker_cooldown_secs: u64,
+    /// Recycle a worker after this many seconds even if it is busy; 0 never
+    /// (`--max-worker-lifetime-secs`)
+    pub max_worker_lifetime_secs: u64,
+    /// Initial read buffer capacity per client connection; raise it for
+    /// mostly large batches, lower it for many idle connections
+    /// (`--read-buffer-bytes`)
+    pub read_buffer_bytes: usize,
+    /// Tokio runtime worker threads; None uses one per CPU core
+    /// (`--worker-threads`)
+    pub worker_threads: Option<usize>,
+    /// Largest `cache_size` (KiB) a single Query/ExecBatch may ask for
+    /// (`--max-cache-size-kib`)
+    pub max_cache_size_kib: u64,
+    /// SQL run in order after migrations every time a database is opened,
+    /// including reopens (`--on-open SQL`, repeatable)
+    pub on_open: Vec<String>,
+    /// Times an atomic batch failing with SQLITE_BUSY/LOCKED is retried,
+    /// unless the request sets `max_retries` (`--busy-retries`)
+    pub busy_retries: u32,
+    /// Delay before the first such retry, doubling each time
+    /// (`--busy-retry-backoff-ms`)
+    pub busy_retry_backoff_ms: u64,
+    /// Directory `VacuumInto` and `Backup` write to; unset disables them
+    /// (`--backup-dir`)
+    pub backup_dir: Option<PathBuf>,
+    /// Also accept clients over TCP on this address, with the same framing
+    /// as the pipe/socket; unset listens locally only (`--listen ADDR:PORT`)
+    pub listen: Option<SocketAddr>,
+    /// Allow `listen` on an address other than loopback; TCP clients are
+    /// not authenticated (`--listen-public`)
+    pub listen_public: bool,
+    /// Open flags for new connections (`--thread-mode multi-thread|serialized`)
+    pub thread_mode: ThreadMode,
+    /// `ListMaintenance` flags databases preparing or closed longer than
+    /// this as overdue (`--maintenance-warn-secs`)
+    pub maintenance_warn_secs: u64,
+    /// Reopen a database left preparing or closed for this many seconds,
+    /// e.g. after the maintaining client crashed; 0 never
+    /// (`--max-maintenance-secs`)
+    pub max_maintenance_secs: u64,
+    /// Accept ExecBatch while a database is preparing or closed: batches are
+    /// held in memory and applied, in order, right after the reopen
+    /// (`--journal-closed-writes`); lost if the daemon exits first
+    pub journal_closed_writes: bool,
+    /// Most batches held per database; later ones fail with `JOURNAL_FULL`
+    /// (`--max-journaled-batches`)
+    pub max_journaled_batches: usize,
+    /// Hold requests that arrive while a database is still opening for up
+    /// to this long, then answer `DB_INITIALIZING`; 0 answers immediately
+    /// (`--init-wait-ms`)
+    pub init_wait_ms: u64,
+    /// Truncate the WAL after a write once it holds this many frames, so it
+    /// stops growing between idle periods; 0 leaves it to SQLite
+    /// (`--checkpoint-truncate-frames`)
+    pub checkpoint_truncate_frames: i64,
+    /// Busy handling for every database (`--busy-policy timeout|yield_to_writer`)
+    pub busy_policy: BusyPolicy,
+    /// Per-database overrides of `busy_policy`, keyed by file name
+    /// (`--db-busy-policy NAME=POLICY`, repeatable)
+    pub db_busy_policies: BTreeMap<String, BusyPolicy>,
+    /// Fail a query with `RESULT_TOO_LARGE` once its rows pass this many
+    /// bytes of JSON, instead of buffering them all; 0 disables
+    /// (`--max-result-bytes`)
+    pub max_result_bytes: usize,
+    /// Accept `Benchmark` requests (`--enable-benchmark`); off so a
+    /// production daemon can't be loaded with synthetic writes
+    pub enable_benchmark: bool,
+    /// Statement authorizer rules (`--allow-table NAME` and
+    /// `--deny-action ACTION`, both repeatable)
+    pub authorizer: AuthorizerRules,
+    /// Pragmas client statements may run besides
+    /// `validation::ALLOWED_PRAGMAS`, lowercased (`--allow-pragma NAME`,
+    /// repeatable)
+    pub allowed_pragmas: Vec<String>,
+    /// Hold a plain atomic ExecBatch this many milliseconds and commit it
+    /// together with any that arrive meanwhile, in one transaction
+    /// (`--coalesce-window-ms`); 0 commits each batch on its own
+    pub coalesce_window_ms: u64,
+    /// Per-database overrides of `coalesce_window_ms`, keyed by file name
+    /// (`--db-coalesce-window-ms NAME=MS`, repeatable)
+    pub db_coalesce_windows: BTreeMap<String, u64>,
+    /// Release a cursor after this many seconds without a fetch, ending
+    /// the read transaction that holds back checkpoints (`--cursor-idle-secs`)
+    pub cursor_idle_secs: u64,
+    /// How long a connection waits on another process's lock before
+    /// failing with SQLITE_BUSY (`--busy-timeout-ms`)
+    pub busy_timeout_ms: u64,
+    /// `PRAGMA synchronous` for every connection
+    /// (`--synchronous off|normal|full|extra`)
+    pub synchronous: SyncLevel,
+    /// `PRAGMA wal_autocheckpoint` pages; 0 leaves checkpoints to the
+    /// daemon's own after each write (`--wal-autocheckpoint`)
+    pub wal_autocheckpoint: u32,
+    /// Roll back a `Begin` transaction after this many seconds without a
+    /// request for it, releasing the write lock (`--tx-idle-secs`)
+    pub tx_idle_secs: u64,
+    /// Read-only connections per database serving `Query` alongside the
+    /// write connection; 0 sends reads through the write connection
+    /// (`--read-connections`)
+    pub read_connections: u32,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            db_dir: None,
+            max_param_bytes: DEFAULT_MAX_PARAM_BYTES,
+            max_params_total_bytes: DEFAULT_MAX_PARAMS_TOTAL_BYTES,
+            auto_create: true,
+            change_feed: false,
+            idle_timeout_secs: 300,
+            db_idle_timeouts: BTreeMap::new(),
+            idle_grace_secs: 0,
+            import_dir: None,
+            fsync_dir: false,
+            max_spawns_per_sec: 0,
+            max_workers: 0,
+            hardening: true,
+            foreign_keys: true,
+            functions: BUILTIN_FUNCTIONS.iter().map(|f| f.name.to_string()).collect(),
+            breaker_threshold: 5,
+            breaker_window_secs: 60,
+            breaker_cooldown_secs: 30,
+            max_worker_lifetime_secs: 0,
+            read_buffer_bytes: DEFAULT_READ_BUFFER_BYTES,
+            worker_threads: None,
+            max_cache_size_kib: DEFAULT_MAX_CACHE_SIZE_KIB,
+            on_open: Vec::new(),
+            busy_retries: 0,
+            busy_retry_backoff_ms: 50,
+            backup_dir: None,
+            listen: None,
+            listen_public: false,
+            thread_mode: ThreadMode::default(),
+            maintenance_warn_secs: 600,
+            max_maintenance_secs: 0,
+            journal_closed_writes: false,
+            max_journaled_batches: 10_000,
+            init_wait_ms: 5_000,
+            checkpoint_truncate_frames: 0,
+            busy_policy: BusyPolicy::default(),
+            db_busy_policies: BTreeMap::new(),
+            max_result_bytes: DEFAULT_MAX_RESULT_BYTES,
+            enable_benchmark: false,
+            authorizer: AuthorizerRules::default(),
+            allowed_pragmas: Vec::new(),
+            coalesce_window_ms: 0,
+            db_coalesce_windows: BTreeMap::new(),
+            cursor_idle_secs: 60,
+            read_connections: 4,
+            tx_idle_secs: 30,
+            busy_timeout_ms: 5_000,
+            synchronous: SyncLevel::Normal,
+            wal_autocheckpoint: 1000,
+        }
+    }
+}
+
+impl DaemonConfig {
+    /// Parse `[DB_DIR] [--option value]...` (program name already stripped)
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut config = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--max-param-bytes" => {
+                    config.max_param_bytes = parse_value(&arg, args.next())?;
+                }
+                "--max-params-total-bytes" => {
+                    config.max_params_total_bytes = parse_value(&arg, args.next())?;
+                }
+                "--import-dir" => {
+                    config.import_dir = Some(PathBuf::from(parse_value::<String>(&arg, args.next())?));
+                }
+                "--no-auto-create" => config.auto_create = false,
+                "--change-feed" => config.change_feed = true,
+                "--fsync-dir" => config.fsync_dir = true,
+                "--no-hardening" => config.hardening = false,
+                "--no-foreign-keys" => config.foreign_keys = false,
+                "--functions" => {
+                    let list: String = parse_value(&arg, args.next())?;
+                    config.functions = Vec::new();
+                    for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                        match builtin(name) {
+                            Some(function) => config.functions.push(function.name.to_string()),
+                            None => bail!("Unknown function for --functions: {}", name),
+                        }
+                    }
+                }
+                "--max-spawns-per-sec" => {
+                    config.max_spawns_per_sec = parse_value(&arg, args.next())?;
+                }
+                "--max-workers" => {
+                    config.max_workers = parse_value(&arg, args.next())?;
+                }
+                "--idle-timeout-secs" => {
+                    config.idle_timeout_secs = parse_value(&arg, args.next())?;
+                }
+                "--db-idle-timeout-secs" => {
+                    let value = args.next().with_context(|| format!("Missing value for {}", arg))?;
+                    let (db, secs) = value
+                        .split_once('=')
+                        .with_context(|| format!("Expected NAME=SECS for {}, got {}", arg, value))?;
+                    let secs = secs
+                        .parse()
+                        .with_context(|| format!("Invalid value for {}: {}", arg, value))?;
+                    config.db_idle_timeouts.insert(db.to_string(), secs);
+                }
+                "--idle-grace-secs" => {
+                    config.idle_grace_secs = parse_value(&arg, args.next())?;
+                }
+                "--breaker-threshold" => {
+                    config.breaker_threshold = parse_value(&arg, args.next())?;
+                }
+                "--breaker-window-secs" => {
+                    config.breaker_window_secs = parse_value(&arg, args.next())?;
+                }
+                "--breaker-cooldown-secs" => {
+                    config.breaker_cooldown_secs = parse_value(&arg, args.next())?;
+                }
+                "--max-worker-lifetime-secs" => {
+                    config.max_worker_lifetime_secs = parse_value(&arg, args.next())?;
+                }
+                "--read-buffer-bytes" => {
+                    config.read_buffer_bytes = parse_value(&arg, args.next())?;
+                }
+                "--worker-threads" => {
+                    let threads: usize = parse_value(&arg, args.next())?;
+                    if threads == 0 {
+                        bail!("--worker-threads must be at least 1");
+                    }
+                    config.worker_threads = Some(threads);
+                }
+                "--max-cache-size-kib" => {
+                    config.max_cache_size_kib = parse_value(&arg, args.next())?;
+                }
+                "--on-open" => config.on_open.push(parse_value(&arg, args.next())?),
+                "--busy-retries" => {
+                    config.busy_retries = parse_value(&arg, args.next())?;
+                }
+                "--busy-retry-backoff-ms" => {
+                    config.busy_retry_backoff_ms = parse_value(&arg, args.next())?;
+                }
+                "--backup-dir" => {
+                    config.backup_dir = Some(PathBuf::from(parse_value::<String>(&arg, args.next())?));
+                }
+                "--listen" => config.listen = Some(parse_value(&arg, args.next())?),
+                "--listen-public" => config.listen_public = true,
+                "--maintenance-warn-secs" => {
+                    config.maintenance_warn_secs = parse_value(&arg, args.next())?;
+                }
+                "--max-maintenance-secs" => {
+                    config.max_maintenance_secs = parse_value(&arg, args.next())?;
+                }
+                "--journal-closed-writes" => config.journal_closed_writes = true,
+                "--max-journaled-batches" => {
+                    config.max_journaled_batches = parse_value(&arg, args.next())?;
+                }
+                "--init-wait-ms" => {
+                    config.init_wait_ms = parse_value(&arg, args.next())?;
+                }
+                "--checkpoint-truncate-frames" => {
+                    config.checkpoint_truncate_frames = parse_value(&arg, args.next())?;
+                }
+                "--enable-benchmark" => config.enable_benchmark = true,
+                "--busy-timeout-ms" => {
+                    config.busy_timeout_ms = parse_value(&arg, args.next())?;
+                }
+                "--synchronous" => {
+                    let value = args.next().with_context(|| format!("Missing value for {}", arg))?;
+                    config.synchronous = value
+                        .parse()
+                        .with_context(|| format!("Invalid value for {}", arg))?;
+                }
+                "--wal-autocheckpoint" => {
+                    config.wal_autocheckpoint = parse_value(&arg, args.next())?;
+                }
+                "--tx-idle-secs" => {
+                    config.tx_idle_secs = parse_value(&arg, args.next())?;
+                }
+                "--read-connections" => {
+                    config.read_connections = parse_value(&arg, args.next())?;
+                }
+                "--cursor-idle-secs" => {
+                    config.cursor_idle_secs = parse_value(&arg, args.next())?;
+                }
+                "--coalesce-window-ms" => {
+                    config.coalesce_window_ms = parse_value(&arg, args.next())?;
+                }
+                "--db-coalesce-window-ms" => {
+                    let value = args.next().with_context(|| format!("Missing value for {}", arg))?;
+                    let (db, ms) = value
+                        .split_once('=')
+                        .with_context(|| format!("Expected NAME=MS for {}, got {}", arg, value))?;
+                    let ms = ms
+                        .parse()
+                        .with_context(|| format!("Invalid value for {}: {}", arg, value))?;
+                    config.db_coalesce_windows.insert(db.to_string(), ms);
+                }
+                "--allow-table" => {
+                    let table = parse_value::<String>(&arg, args.next())?;
+                    config.authorizer.allowed_tables.insert(table);
+                }
+                "--allow-pragma" => {
+                    let name = parse_value::<String>(&arg, args.next())?;
+                    config.allowed_pragmas.push(name.to_lowercase());
+                }
+                "--deny-action" => {
+                    let value = args.next().with_context(|| format!("Missing value for {}", arg))?;
+                    let code = crate::ffi::action_code(&value)
+                        .with_context(|| format!("Unknown authorizer action for {}: {}", arg, value))?;
+                    config.authorizer.denied_actions.insert(code);
+                }
+                "--max-result-bytes" => {
+                    config.max_result_bytes = parse_value(&arg, args.next())?;
+                }
+                "--busy-policy" => {
+                    let value = args.next().with_context(|| format!("Missing value for {}", arg))?;
+                    config.busy_policy = value
+                        .parse()
+                        .with_context(|| format!("Invalid value for {}", arg))?;
+                }
+                "--db-busy-policy" => {
+                    let value = args.next().with_context(|| format!("Missing value for {}", arg))?;
+                    let (db, policy) = value
+                        .split_once('=')
+                        .with_context(|| format!("Expected NAME=POLICY for {}, got {}", arg, value))?;
+                    let policy = policy
+                        .parse()
+                        .with_context(|| format!("Invalid value for {}", arg))?;
+                    config.db_busy_policies.insert(db.to_string(), policy);
+                }
+                "--thread-mode" => {
+                    let value = args.next().with_context(|| format!("Missing value for {}", arg))?;
+                    config.thread_mode = value
+                        .parse()
+                        .with_context(|| format!("Invalid value for {}", arg))?;
+                }
+                flag if flag.starts_with("--") => bail!("Unknown option: {}", flag),
+                _ => {
+                    if config.db_dir.is_some() {
+                        bail!("Unexpected argument: {}", arg);
+                    }
+                    config.db_dir = Some(PathBuf::from(&arg));
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Busy policy for the database file `db_name`
+    pub fn busy_policy_for(&self, db_name: &str) -> BusyPolicy {
+        self.db_busy_policies.get(db_name).copied().unwrap_or(self.busy_policy)
+    }
+
+    /// Write coalescing window (ms) for the database file `db_name`
+    pub fn coalesce_window_for(&self, db_name: &str) -> u64 {
+        self.db_coalesce_windows.get(db_name).copied().unwrap_or(self.coalesce_window_ms)
+    }
+
+    /// Idle timeout for the database file `db_name`; `None` never times out
+    pub fn idle_timeout_for(&self, db_name: &str) -> Option<Duration> {
+        let secs = self.db_idle_timeouts.get(db_name).copied().unwrap_or(self.idle_timeout_secs);
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+}
+
+fn parse_value<T>(flag: &str, value: Option<String>) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let value = value.with_context(|| format!("Missing value for {}", flag))?;
+    value
+        .parse()
+        .with_context(|| format!("Invalid value for {}: {}", flag, value))
+}
@@ -0,0 +1,214 @@
+use crate::config::AuthorizerRules;
+use crate::protocol::Statement;
+use crate::query::{bind_param, row_values};
+use anyhow::{Context, Result};
+use futures_util::TryStreamExt;
+use serde_json::Value;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{Column, Connection, Executor, SqliteConnection};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+/// Most cursors open at once across all databases
+pub const MAX_OPEN_CURSORS: usize = 64;
+
+/// Most rows one `FetchCursor` may return
+pub const MAX_FETCH_ROWS: u32 = 10_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CursorError {
+    #[error("Cursor not found (closed, finished or expired): {0}")]
+    NotFound(String),
+    #[error("Too many open cursors (max {MAX_OPEN_CURSORS})")]
+    TooMany,
+}
+
+impl CursorError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CursorError::NotFound(_) => "CURSOR_NOT_FOUND",
+            CursorError::TooMany => "TOO_MANY_CURSORS",
+        }
+    }
+}
+
+/// Rows of one fetch and whether the result is exhausted
+type FetchResult = Result<(Vec<Vec<Value>>, bool)>;
+
+struct Fetch {
+    n: u32,
+    reply: oneshot::Sender<FetchResult>,
+}
+
+struct OpenCursor {
+    /// Database the cursor reads
+    db: String,
+    sender: mpsc::Sender<Fetch>,
+    /// Ends once the cursor's connection is closed
+    task: JoinHandle<()>,
+}
+
+/// Open cursors by id. Each cursor is a task owning its own read-only
+/// connection, so paging never ties up the database's worker; its read
+/// transaction does pin the WAL (blocking checkpoints from completing)
+/// until the cursor finishes, is closed or sits idle too long.
+#[derive(Default)]
+pub struct Cursors {
+    open: Arc<Mutex<HashMap<String, OpenCursor>>>,
+}
+
+impl Cursors {
+    /// Start a cursor over `stmt`; returns its id and column names
+    pub async fn open(
+        &self,
+        db: &str,
+        db_path: PathBuf,
+        stmt: Statement,
+        rules: Vec<AuthorizerRules>,
+        idle: Duration,
+    ) -> Result<(String, Vec<String>)> {
+        if self.open.lock().unwrap().len() >= MAX_OPEN_CURSORS {
+            return Err(CursorError::TooMany.into());
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let (sender, rx) = mpsc::channel(1);
+        let (ready_tx, ready) = oneshot::channel();
+        let open = Arc::clone(&self.open);
+        let task_id = id.clone();
+        let task = tokio::spawn(async move {
+            run_cursor(db_path, stmt, rules, idle, rx, ready_tx).await;
+            open.lock().unwrap().remove(&task_id);
+            debug!(cursor = %task_id, "Cursor released");
+        });
+
+        let columns = ready.await.context("Cursor task ended before opening")??;
+        let cursor = OpenCursor { db: db.to_string(), sender, task };
+        self.open.lock().unwrap().insert(id.clone(), cursor);
+        Ok((id, columns))
+    }
+
+    /// Next `n` rows of cursor `id`
+    pub async fn fetch(&self, id: &str, n: u32) -> FetchResult {
+        let not_found = || CursorError::NotFound(id.to_string());
+        let sender = self.open.lock().unwrap().get(id).map(|c| c.sender.clone()).ok_or_else(not_found)?;
+        let (reply, rx) = oneshot::channel();
+        if sender.send(Fetch { n, reply }).await.is_err() {
+            self.open.lock().unwrap().remove(id);
+            return Err(not_found().into());
+        }
+        rx.await.map_err(|_| not_found())?
+    }
+
+    /// Number of cursors currently open
+    pub fn count(&self) -> usize {
+        self.open.lock().unwrap().len()
+    }
+
+    /// Release cursor `id`; returns whether it was open
+    pub fn close(&self, id: &str) -> bool {
+        // Dropping the sender ends the cursor's task
+        self.open.lock().unwrap().remove(id).is_some()
+    }
+
+    /// Release every cursor on `db` and wait for their connections to
+    /// close, so none pins the WAL through maintenance; returns how many
+    pub async fn close_db(&self, db: &str) -> usize {
+        let closing: Vec<OpenCursor> = {
+            let mut open = self.open.lock().unwrap();
+            let ids: Vec<String> =
+                open.iter().filter(|(_, c)| c.db == db).map(|(id, _)| id.clone()).collect();
+            ids.iter().filter_map(|id| open.remove(id)).collect()
+        };
+        let count = closing.len();
+        for OpenCursor { sender, task, .. } in closing {
+            drop(sender);
+            // Only fails if the task panicked, which released it all the same
+            let _ = task.await;
+        }
+        count
+    }
+}
+
+async fn run_cursor(
+    db_path: PathBuf,
+    stmt: Statement,
+    rules: Vec<AuthorizerRules>,
+    idle: Duration,
+    mut rx: mpsc::Receiver<Fetch>,
+    ready: oneshot::Sender<Result<Vec<String>>>,
+) {
+    let options = SqliteConnectOptions::new().filename(&db_path).read_only(true);
+    let mut conn = match SqliteConnection::connect_with(&options).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            let _ = ready.send(Err(e.into()));
+            return;
+        }
+    };
+    // The connection is the cursor's own, so the rules stay armed until it closes
+    if !rules.is_empty() {
+        if let Err(e) = crate::ffi::set_authorizer(&mut conn, rules).await {
+            let _ = ready.send(Err(e));
+            return;
+        }
+    }
+    let prepared = match conn.prepare(stmt.sql.as_str()).await {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            let _ = ready.send(Err(e.into()));
+            return;
+        }
+    };
+    let columns = sqlx::Statement::columns(&prepared)
+        .iter()
+        .map(|c| c.name().to_string())
+        .collect();
+
+    let mut query = sqlx::Statement::query(&prepared);
+    for param in &stmt.params {
+        query = bind_param(query, param);
+    }
+    let mut rows = query.fetch(&mut conn);
+    if ready.send(Ok(columns)).is_err() {
+        return;
+    }
+
+    loop {
+        let fetch = match tokio::time::timeout(idle, rx.recv()).await {
+            Ok(Some(fetch)) => fetch,
+            // Closed by the client
+            Ok(None) => return,
+            Err(_) => {
+                debug!(db = %db_path.display(), "Cursor idle timeout");
+                return;
+            }
+        };
+
+        let result = next_rows(&mut rows, fetch.n).await;
+        let done = !matches!(result, Ok((_, false)));
+        let _ = fetch.reply.send(result);
+        if done {
+            return;
+        }
+    }
+}
+
+async fn next_rows<S>(rows: &mut S, n: u32) -> FetchResult
+where
+    S: futures_util::Stream<Item = Result<sqlx::sqlite::SqliteRow, sqlx::Error>> + Unpin,
+{
+    let mut batch = Vec::with_capacity(n as usize);
+    while batch.len() < n as usize {
+        match rows.try_next().await? {
+            Some(row) => batch.push(row_values(&row)),
+            None => return Ok((batch, true)),
+        }
+    }
+    Ok((batch, false))
+}
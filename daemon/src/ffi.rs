@@ -0,0 +1,614 @@
+//! Raw SQLite C API calls that sqlx does not expose
+
+use crate::config::AuthorizerRules;
+#[cfg(feature = "session")]
+use crate::protocol::{ChangesetConflict, ChangesetConflictPolicy};
+use anyhow::{bail, Result};
+use libsqlite3_sys as ffi;
+use sqlx::SqliteConnection;
+use std::collections::BTreeMap;
+#[cfg(feature = "session")]
+use std::collections::BTreeSet;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Virtual machine instructions between deadline checks
+const DEADLINE_CHECK_OPS: c_int = 1000;
+
+/// Origin of the millisecond deadlines handed to `past_deadline`
+static DEADLINE_CLOCK: OnceLock<Instant> = OnceLock::new();
+
+/// Enable or disable `SQLITE_DBCONFIG_DEFENSIVE` on a connection, which
+/// blocks schema-corrupting operations (writable_schema, shadow tables,
+/// `PRAGMA journal_mode=OFF` ...). Returns the resulting state.
+pub async fn set_defensive(conn: &mut SqliteConnection, on: bool) -> Result<bool> {
+    db_config_flag(conn, ffi::SQLITE_DBCONFIG_DEFENSIVE, on as i32).await
+}
+
+/// Current `SQLITE_DBCONFIG_DEFENSIVE` state of a connection
+pub async fn defensive(conn: &mut SqliteConnection) -> Result<bool> {
+    db_config_flag(conn, ffi::SQLITE_DBCONFIG_DEFENSIVE, -1).await
+}
+
+/// Set (0/1) or query (-1) a boolean `sqlite3_db_config` option
+async fn db_config_flag(conn: &mut SqliteConnection, op: i32, value: i32) -> Result<bool> {
+    let mut handle = conn.lock_handle().await?;
+    let db = handle.as_raw_handle().as_ptr();
+    let mut state: i32 = 0;
+    // SAFETY: `db` is a live connection held exclusively via the lock;
+    // boolean db_config options take (int, int*) varargs.
+    let rc = unsafe { ffi::sqlite3_db_config(db, op, value, &mut state as *mut i32) };
+    if rc != ffi::SQLITE_OK {
+        bail!("sqlite3_db_config({}) failed with code {}", op, rc);
+    }
+    Ok(state != 0)
+}
+
+/// `sqlite3_total_changes64`: rows changed by INSERT/UPDATE/DELETE on this
+/// connection since it was opened, including changes made by triggers
+pub async fn total_changes(conn: &mut SqliteConnection) -> Result<i64> {
+    let mut handle = conn.lock_handle().await?;
+    let db = handle.as_raw_handle().as_ptr();
+    // SAFETY: `db` is a live connection held exclusively via the lock
+    Ok(unsafe { ffi::sqlite3_total_changes64(db) })
+}
+
+/// Whether `conn` is inside a transaction (`sqlite3_get_autocommit` is 0).
+/// After some errors (`SQLITE_FULL`, `SQLITE_IOERR`, an `ON CONFLICT
+/// ROLLBACK` constraint, an interrupt) SQLite rolls the transaction back
+/// on its own.
+pub async fn in_transaction(conn: &mut SqliteConnection) -> Result<bool> {
+    let mut handle = conn.lock_handle().await?;
+    let db = handle.as_raw_handle().as_ptr();
+    // SAFETY: `db` is a live connection held exclusively via the lock
+    Ok(unsafe { ffi::sqlite3_get_autocommit(db) } == 0)
+}
+
+/// Whether every statement in `sql` is read-only (`sqlite3_stmt_readonly`),
+/// compiling each on `conn` and finalizing it without running. Transaction
+/// control statements (BEGIN, COMMIT) count as read-only.
+pub async fn statements_readonly(conn: &mut SqliteConnection, sql: &str) -> Result<bool> {
+    let sql = CString::new(sql)?;
+    let mut handle = conn.lock_handle().await?;
+    let db = handle.as_raw_handle().as_ptr();
+    let mut rest = sql.as_ptr();
+    // SAFETY: `db` is held exclusively via the lock. `rest` stays within
+    // `sql` (SQLite points it at the unused tail), and each statement is
+    // finalized before the next is compiled.
+    unsafe {
+        while *rest != 0 {
+            let mut stmt = std::ptr::null_mut();
+            let rc = ffi::sqlite3_prepare_v2(db, rest, -1, &mut stmt, &mut rest);
+            if rc != ffi::SQLITE_OK {
+                bail!("sqlite3_prepare_v2 failed with code {}", rc);
+            }
+            // Null for a tail of only whitespace or comments
+            if stmt.is_null() {
+                break;
+            }
+            let readonly = ffi::sqlite3_stmt_readonly(stmt) != 0;
+            ffi::sqlite3_finalize(stmt);
+            if !readonly {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Argument of the yield-to-writer busy handler
+struct YieldToWriter {
+    db: *mut ffi::sqlite3,
+    /// Total wait before giving up
+    give_up_ms: u64,
+}
+
+// SAFETY: `db` is only dereferenced by SQLite's own calls to the handler
+unsafe impl Send for YieldToWriter {}
+
+/// Busy handler arguments, keyed by the connection's `sqlite3` handle like
+/// `ARMED_RULES`, so each lives as long as SQLite may call its handler
+static YIELD_TO_WRITER: Mutex<BTreeMap<usize, Box<YieldToWriter>>> = Mutex::new(BTreeMap::new());
+
+/// Replace the connection's `busy_timeout` with the yield-to-writer busy
+/// handler (see `BusyPolicy::YieldToWriter`), giving up after `give_up_ms`
+/// like the timeout it replaces
+pub async fn set_yield_to_writer(conn: &mut SqliteConnection, give_up_ms: u64) -> Result<()> {
+    let mut handle = conn.lock_handle().await?;
+    let db = handle.as_raw_handle().as_ptr();
+    let arg = Box::new(YieldToWriter { db, give_up_ms });
+    let arg_ptr = &*arg as *const YieldToWriter as *mut c_void;
+    let mut handlers = YIELD_TO_WRITER.lock().unwrap_or_else(PoisonError::into_inner);
+    // SAFETY: `db` is held exclusively via the lock. The argument stays in
+    // `YIELD_TO_WRITER` until another handler is set on the same handle.
+    let rc = unsafe { ffi::sqlite3_busy_handler(db, Some(yield_to_writer), arg_ptr) };
+    if rc != ffi::SQLITE_OK {
+        bail!("sqlite3_busy_handler failed with code {}", rc);
+    }
+    handlers.insert(db as usize, arg);
+    Ok(())
+}
+
+/// Make statements on `conn` fail with `SQLITE_INTERRUPT` once `deadline`
+/// passes, or remove the deadline (`None`). SQLite checks it from inside the
+/// running statement, which stops where it is and leaves the transaction to
+/// be rolled back; dropping the future instead would not stop the statement
+/// on sqlx's connection thread. Must be removed before the connection goes
+/// back to its pool.
+pub async fn set_deadline(conn: &mut SqliteConnection, deadline: Option<Instant>) -> Result<()> {
+    let mut handle = conn.lock_handle().await?;
+    let db = handle.as_raw_handle().as_ptr();
+    match deadline {
+        Some(deadline) => {
+            let clock = *DEADLINE_CLOCK.get_or_init(Instant::now);
+            let at_ms = deadline.saturating_duration_since(clock).as_millis() as usize;
+            // SAFETY: `db` is held exclusively via the lock. The deadline
+            // travels in the argument pointer itself, so nothing is freed.
+            let arg = at_ms as *mut c_void;
+            unsafe { ffi::sqlite3_progress_handler(db, DEADLINE_CHECK_OPS, Some(past_deadline), arg) };
+        }
+        // SAFETY: `db` is held exclusively via the lock
+        None => unsafe { ffi::sqlite3_progress_handler(db, 0, None, std::ptr::null_mut()) },
+    }
+    Ok(())
+}
+
+/// Progress handler: non-zero (interrupt) once the clock reaches the
+/// deadline in `arg`, in ms since `DEADLINE_CLOCK`
+unsafe extern "C" fn past_deadline(arg: *mut c_void) -> c_int {
+    let clock = DEADLINE_CLOCK.get_or_init(Instant::now);
+    (clock.elapsed().as_millis() as usize >= arg as usize) as c_int
+}
+
+/// Busy handler: a blocked write polls every 1-10 ms so it takes the lock
+/// as soon as it frees up; a blocked read backs off 5 ms doubling to 200 ms,
+/// leaving the gaps to writers. Both give up after the `give_up_ms` in
+/// `arg`, a [`YieldToWriter`].
+unsafe extern "C" fn yield_to_writer(arg: *mut c_void, count: c_int) -> c_int {
+    let arg = &*(arg as *const YieldToWriter);
+    let writing = is_writing(arg.db);
+    let delay = |n: c_int| -> u64 {
+        let n = n.clamp(0, 6) as u32;
+        if writing { (1u64 << n).min(10) } else { (5u64 << n).min(200) }
+    };
+    let waited: u64 = (0..count).map(delay).sum();
+    if waited >= arg.give_up_ms {
+        return 0;
+    }
+    // SQLite calls this on the connection's own thread, which sqlx
+    // dedicates to it; sleeping here is what busy_timeout does too
+    std::thread::sleep(Duration::from_millis(delay(count)));
+    1
+}
+
+/// Whether a running statement on `db` writes. Transaction control
+/// statements (BEGIN, COMMIT) count as reads.
+unsafe fn is_writing(db: *mut ffi::sqlite3) -> bool {
+    let mut stmt = ffi::sqlite3_next_stmt(db, std::ptr::null_mut());
+    while !stmt.is_null() {
+        if ffi::sqlite3_stmt_busy(stmt) != 0 && ffi::sqlite3_stmt_readonly(stmt) == 0 {
+            return true;
+        }
+        stmt = ffi::sqlite3_next_stmt(db, stmt);
+    }
+    false
+}
+
+/// Authorizer action names accepted by `--deny-action`
+const ACTIONS: &[(&str, i32)] = &[
+    ("CREATE_INDEX", ffi::SQLITE_CREATE_INDEX),
+    ("CREATE_TABLE", ffi::SQLITE_CREATE_TABLE),
+    ("CREATE_TEMP_INDEX", ffi::SQLITE_CREATE_TEMP_INDEX),
+    ("CREATE_TEMP_TABLE", ffi::SQLITE_CREATE_TEMP_TABLE),
+    ("CREATE_TEMP_TRIGGER", ffi::SQLITE_CREATE_TEMP_TRIGGER),
+    ("CREATE_TEMP_VIEW", ffi::SQLITE_CREATE_TEMP_VIEW),
+    ("CREATE_TRIGGER", ffi::SQLITE_CREATE_TRIGGER),
+    ("CREATE_VIEW", ffi::SQLITE_CREATE_VIEW),
+    ("DELETE", ffi::SQLITE_DELETE),
+    ("DROP_INDEX", ffi::SQLITE_DROP_INDEX),
+    ("DROP_TABLE", ffi::SQLITE_DROP_TABLE),
+    ("DROP_TEMP_INDEX", ffi::SQLITE_DROP_TEMP_INDEX),
+    ("DROP_TEMP_TABLE", ffi::SQLITE_DROP_TEMP_TABLE),
+    ("DROP_TEMP_TRIGGER", ffi::SQLITE_DROP_TEMP_TRIGGER),
+    ("DROP_TEMP_VIEW", ffi::SQLITE_DROP_TEMP_VIEW),
+    ("DROP_TRIGGER", ffi::SQLITE_DROP_TRIGGER),
+    ("DROP_VIEW", ffi::SQLITE_DROP_VIEW),
+    ("INSERT", ffi::SQLITE_INSERT),
+    ("PRAGMA", ffi::SQLITE_PRAGMA),
+    ("READ", ffi::SQLITE_READ),
+    ("SELECT", ffi::SQLITE_SELECT),
+    ("TRANSACTION", ffi::SQLITE_TRANSACTION),
+    ("UPDATE", ffi::SQLITE_UPDATE),
+    ("ATTACH", ffi::SQLITE_ATTACH),
+    ("DETACH", ffi::SQLITE_DETACH),
+    ("ALTER_TABLE", ffi::SQLITE_ALTER_TABLE),
+    ("REINDEX", ffi::SQLITE_REINDEX),
+    ("ANALYZE", ffi::SQLITE_ANALYZE),
+    ("CREATE_VTABLE", ffi::SQLITE_CREATE_VTABLE),
+    ("DROP_VTABLE", ffi::SQLITE_DROP_VTABLE),
+    ("FUNCTION", ffi::SQLITE_FUNCTION),
+    ("SAVEPOINT", ffi::SQLITE_SAVEPOINT),
+    ("RECURSIVE", ffi::SQLITE_RECURSIVE),
+];
+
+/// Authorizer action code for a name like `ATTACH` or `create_table`
+pub fn action_code(name: &str) -> Option<i32> {
+    ACTIONS
+        .iter()
+        .find(|(action, _)| action.eq_ignore_ascii_case(name))
+        .map(|&(_, code)| code)
+}
+
+/// Rule sets armed on each connection, keyed by its `sqlite3` handle.
+/// Owning them here keeps the authorizer's argument alive for as long as
+/// SQLite may call it, even if a connection goes back to its pool still armed.
+/// Boxed so the address SQLite holds survives the map moving its entries.
+#[allow(clippy::box_collection)]
+static ARMED_RULES: Mutex<BTreeMap<usize, Box<Vec<AuthorizerRules>>>> = Mutex::new(BTreeMap::new());
+
+/// Enforce `rules` on every statement the connection compiles from now on;
+/// a statement must pass every set. No sets removes the authorizer. Arming
+/// expires the connection's prepared statements, so cached ones are
+/// compiled again under the rules; a denied statement fails with
+/// `SQLITE_AUTH` (see [`denied`]). Must be removed before the connection
+/// goes back to its pool.
+pub async fn set_authorizer(conn: &mut SqliteConnection, rules: Vec<AuthorizerRules>) -> Result<()> {
+    let mut handle = conn.lock_handle().await?;
+    let db = handle.as_raw_handle().as_ptr();
+    let mut armed = ARMED_RULES.lock().unwrap_or_else(PoisonError::into_inner);
+    if rules.is_empty() {
+        // SAFETY: `db` is held exclusively via the lock
+        unsafe { ffi::sqlite3_set_authorizer(db, None, std::ptr::null_mut()) };
+        armed.remove(&(db as usize));
+    } else {
+        let rules = Box::new(rules);
+        // SAFETY: `db` is held exclusively via the lock. The rules stay in
+        // `ARMED_RULES` until the authorizer is replaced or removed.
+        let arg = &*rules as *const Vec<AuthorizerRules> as *mut c_void;
+        unsafe { ffi::sqlite3_set_authorizer(db, Some(authorizer), arg) };
+        armed.insert(db as usize, rules);
+    }
+    Ok(())
+}
+
+/// Whether `err` is a statement an armed authorizer denied (`SQLITE_AUTH`)
+pub fn denied(err: &(dyn std::error::Error + 'static)) -> bool {
+    crate::query::extended_code(err).is_some_and(|code| code & 0xff == ffi::SQLITE_AUTH)
+}
+
+/// Authorizer callback; `arg` points at the armed `Vec<AuthorizerRules>`
+unsafe extern "C" fn authorizer(
+    arg: *mut c_void,
+    action: c_int,
+    arg1: *const c_char,
+    arg2: *const c_char,
+    _db_name: *const c_char,
+    _trigger: *const c_char,
+) -> c_int {
+    let sets = &*(arg as *const Vec<AuthorizerRules>);
+    if sets.iter().all(|rules| permits(rules, action, arg1, arg2)) {
+        ffi::SQLITE_OK
+    } else {
+        ffi::SQLITE_DENY
+    }
+}
+
+/// Whether one rule set allows `action` on the table its arguments name
+unsafe fn permits(rules: &AuthorizerRules, action: c_int, arg1: *const c_char, arg2: *const c_char) -> bool {
+    if rules.denied_actions.contains(&action) {
+        return false;
+    }
+    if rules.allowed_tables.is_empty() {
+        return true;
+    }
+
+    // Which argument names the table depends on the action
+    let table = match action {
+        ffi::SQLITE_CREATE_TABLE
+        | ffi::SQLITE_CREATE_TEMP_TABLE
+        | ffi::SQLITE_DROP_TABLE
+        | ffi::SQLITE_DROP_TEMP_TABLE
+        | ffi::SQLITE_DELETE
+        | ffi::SQLITE_INSERT
+        | ffi::SQLITE_READ
+        | ffi::SQLITE_UPDATE
+        | ffi::SQLITE_ANALYZE => arg1,
+        ffi::SQLITE_CREATE_INDEX
+        | ffi::SQLITE_CREATE_TEMP_INDEX
+        | ffi::SQLITE_DROP_INDEX
+        | ffi::SQLITE_DROP_TEMP_INDEX
+        | ffi::SQLITE_CREATE_TRIGGER
+        | ffi::SQLITE_CREATE_TEMP_TRIGGER
+        | ffi::SQLITE_DROP_TRIGGER
+        | ffi::SQLITE_DROP_TEMP_TRIGGER
+        | ffi::SQLITE_ALTER_TABLE => arg2,
+        _ => return true,
+    };
+    table.is_null() || rules.allowed_tables.contains(CStr::from_ptr(table).to_string_lossy().as_ref())
+}
+
+/// What `apply_changeset` did
+#[cfg(feature = "session")]
+#[derive(Debug, Default)]
+pub struct ChangesetOutcome {
+    /// Tables the changeset wrote, in changeset order
+    pub tables: Vec<String>,
+    /// Tables left out because `--allow-table` doesn't list them
+    pub denied: Vec<String>,
+    pub conflicts: Vec<ChangesetConflict>,
+    /// A conflict aborted the apply; nothing was written
+    pub aborted: bool,
+}
+
+/// SQLite refused the changeset itself (malformed, or a table that
+/// doesn't exist)
+#[cfg(feature = "session")]
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct ChangesetRejected(pub String);
+
+#[cfg(feature = "session")]
+struct ApplyContext<'a> {
+    policy: ChangesetConflictPolicy,
+    rules: &'a [AuthorizerRules],
+    outcome: ChangesetOutcome,
+}
+
+/// `sqlite3changeset_apply` on `conn`, resolving conflicts per `policy`.
+/// Tables outside a non-empty `allowed_tables` of any of `rules` are
+/// skipped and listed in `denied`. SQLite applies the changeset under a savepoint, so an aborted
+/// apply leaves the caller's transaction as it was.
+#[cfg(feature = "session")]
+pub async fn apply_changeset(
+    conn: &mut SqliteConnection,
+    changeset: &[u8],
+    policy: ChangesetConflictPolicy,
+    rules: &[AuthorizerRules],
+) -> Result<ChangesetOutcome> {
+    let len = c_int::try_from(changeset.len())?;
+    let mut ctx = ApplyContext { policy, rules, outcome: ChangesetOutcome::default() };
+    let mut handle = conn.lock_handle().await?;
+    let db = handle.as_raw_handle().as_ptr();
+
+    // SAFETY: `db` is held exclusively via the lock. SQLite only reads the
+    // changeset buffer, and `ctx` outlives the call, which makes all of
+    // its callbacks before returning.
+    let rc = unsafe {
+        ffi::sqlite3changeset_apply(
+            db,
+            len,
+            changeset.as_ptr() as *mut c_void,
+            Some(changeset_filter),
+            Some(changeset_conflict),
+            &mut ctx as *mut ApplyContext as *mut c_void,
+        )
+    };
+    if rc == ffi::SQLITE_ABORT && ctx.outcome.aborted {
+        return Ok(ctx.outcome);
+    }
+    if rc != ffi::SQLITE_OK {
+        // SAFETY: as above; the message is copied before the lock is released
+        let message = unsafe { CStr::from_ptr(ffi::sqlite3_errmsg(db)) }.to_string_lossy();
+        return Err(ChangesetRejected(format!("{} (code {})", message, rc)).into());
+    }
+    Ok(ctx.outcome)
+}
+
+/// Changeset table filter; `arg` points at the `ApplyContext`
+#[cfg(feature = "session")]
+unsafe extern "C" fn changeset_filter(arg: *mut c_void, table: *const c_char) -> c_int {
+    let ctx = &mut *(arg as *mut ApplyContext);
+    let table = CStr::from_ptr(table).to_string_lossy().into_owned();
+    let allowed = ctx
+        .rules
+        .iter()
+        .all(|rules| rules.allowed_tables.is_empty() || rules.allowed_tables.contains(&table));
+    let list = if allowed { &mut ctx.outcome.tables } else { &mut ctx.outcome.denied };
+    if !list.contains(&table) {
+        list.push(table);
+    }
+    allowed as c_int
+}
+
+/// Changeset conflict handler; `arg` points at the `ApplyContext`
+#[cfg(feature = "session")]
+unsafe extern "C" fn changeset_conflict(
+    arg: *mut c_void,
+    conflict: c_int,
+    iter: *mut ffi::sqlite3_changeset_iter,
+) -> c_int {
+    let ctx = &mut *(arg as *mut ApplyContext);
+    let kind = match conflict {
+        ffi::SQLITE_CHANGESET_DATA => "data",
+        ffi::SQLITE_CHANGESET_NOTFOUND => "not_found",
+        ffi::SQLITE_CHANGESET_CONFLICT => "conflict",
+        ffi::SQLITE_CHANGESET_CONSTRAINT => "constraint",
+        _ => "foreign_key",
+    };
+
+    // A foreign key conflict is reported once for the whole changeset,
+    // with no current change to describe
+    let (mut table, mut op) = (None, None);
+    if conflict != ffi::SQLITE_CHANGESET_FOREIGN_KEY {
+        let mut name: *const c_char = std::ptr::null();
+        let (mut columns, mut code, mut indirect) = (0, 0, 0);
+        let rc = ffi::sqlite3changeset_op(iter, &mut name, &mut columns, &mut code, &mut indirect);
+        if rc == ffi::SQLITE_OK {
+            table = Some(CStr::from_ptr(name).to_string_lossy().into_owned());
+            op = match code {
+                ffi::SQLITE_INSERT => Some("insert".to_string()),
+                ffi::SQLITE_UPDATE => Some("update".to_string()),
+                ffi::SQLITE_DELETE => Some("delete".to_string()),
+                _ => None,
+            };
+        }
+    }
+    ctx.outcome.conflicts.push(ChangesetConflict { kind: kind.to_string(), table, op });
+
+    // Omitting a foreign key conflict would commit the dangling reference
+    let replaceable = matches!(conflict, ffi::SQLITE_CHANGESET_DATA | ffi::SQLITE_CHANGESET_CONFLICT);
+    match ctx.policy {
+        ChangesetConflictPolicy::Abort => {}
+        _ if conflict == ffi::SQLITE_CHANGESET_FOREIGN_KEY => {}
+        ChangesetConflictPolicy::Replace if replaceable => return ffi::SQLITE_CHANGESET_REPLACE,
+        ChangesetConflictPolicy::Replace | ChangesetConflictPolicy::Skip => {
+            return ffi::SQLITE_CHANGESET_OMIT
+        }
+    }
+    ctx.outcome.aborted = true;
+    ffi::SQLITE_CHANGESET_ABORT
+}
+
+/// A session-extension session recording every row change made on one
+/// connection, from [`start_session`] until [`Session::changeset`]
+#[cfg(feature = "session")]
+pub struct Session(*mut ffi::sqlite3_session);
+
+// SAFETY: the session is only used while its connection's handle is locked
+#[cfg(feature = "session")]
+unsafe impl Send for Session {}
+
+/// Without the `session` feature no session can be started
+#[cfg(not(feature = "session"))]
+pub struct Session(std::convert::Infallible);
+
+/// Start recording the changes made on `conn` to every table of `main`,
+/// including tables created while recording
+#[cfg(feature = "session")]
+pub async fn start_session(conn: &mut SqliteConnection) -> Result<Session> {
+    let mut handle = conn.lock_handle().await?;
+    let db = handle.as_raw_handle().as_ptr();
+    let mut session = std::ptr::null_mut();
+    // SAFETY: `db` is held exclusively via the lock
+    let rc = unsafe { ffi::sqlite3session_create(db, c"main".as_ptr(), &mut session) };
+    if rc != ffi::SQLITE_OK {
+        bail!("sqlite3session_create failed (code {})", rc);
+    }
+    let session = Session(session);
+    // SAFETY: as above; a null table name attaches every table
+    let rc = unsafe { ffi::sqlite3session_attach(session.0, std::ptr::null()) };
+    if rc != ffi::SQLITE_OK {
+        bail!("sqlite3session_attach failed (code {})", rc);
+    }
+    Ok(session)
+}
+
+#[cfg(not(feature = "session"))]
+pub async fn start_session(_conn: &mut SqliteConnection) -> Result<Session> {
+    bail!("Changesets require a daemon built with the `session` feature")
+}
+
+impl Session {
+    /// The changes recorded so far, as a changeset; ends the session
+    #[cfg(feature = "session")]
+    pub async fn changeset(self, conn: &mut SqliteConnection) -> Result<Vec<u8>> {
+        let _handle = conn.lock_handle().await?;
+        let (mut len, mut buf) = (0, std::ptr::null_mut());
+        // SAFETY: the session's connection is locked. SQLite allocates
+        // `buf`, which is copied and freed before the lock is released.
+        let rc = unsafe { ffi::sqlite3session_changeset(self.0, &mut len, &mut buf) };
+        let changeset = if buf.is_null() {
+            Vec::new()
+        } else {
+            let bytes = unsafe { std::slice::from_raw_parts(buf as *const u8, len as usize) }.to_vec();
+            unsafe { ffi::sqlite3_free(buf) };
+            bytes
+        };
+        // Deleted while the connection is still locked
+        drop(self);
+        if rc != ffi::SQLITE_OK {
+            bail!("sqlite3session_changeset failed (code {})", rc);
+        }
+        Ok(changeset)
+    }
+
+    #[cfg(not(feature = "session"))]
+    pub async fn changeset(self, _conn: &mut SqliteConnection) -> Result<Vec<u8>> {
+        match self.0 {}
+    }
+}
+
+#[cfg(feature = "session")]
+impl Drop for Session {
+    fn drop(&mut self) {
+        // SAFETY: every path ends the session before its connection is
+        // used again, so nothing else touches the handle meanwhile
+        unsafe { ffi::sqlite3session_delete(self.0) };
+    }
+}
+
+/// `sqlite3_threadsafe()` of the linked library: 0 single-thread,
+/// 1 serialized, 2 multi-thread (the compile-time default mode)
+pub fn threadsafe() -> i32 {
+    // SAFETY: reads a compile-time constant; no connection involved
+    unsafe { ffi::sqlite3_threadsafe() }
+}
+
+/// `sqlite3_complete()`: whether `sql` ends with a complete statement, so
+/// a `;` inside a string, comment or trigger body doesn't end one
+pub fn is_complete(sql: &str) -> bool {
+    let Ok(c_sql) = CString::new(sql) else { return false };
+    // SAFETY: a pure function of the NUL-terminated string
+    unsafe { ffi::sqlite3_complete(c_sql.as_ptr()) != 0 }
+}
+
+/// Outcome of compiling one statement with `sqlite3_prepare_v2`
+pub struct PrepareCheck {
+    /// Error message and byte offset into the SQL (if SQLite reports one)
+    pub error: Option<(String, Option<usize>)>,
+    /// Bind parameter names in index order (`?N` for anonymous ones)
+    pub params: Vec<String>,
+    /// Result columns as (name, declared type)
+    pub columns: Vec<(String, Option<String>)>,
+}
+
+/// Compile (but never step) the first statement of `sql`
+pub async fn try_prepare(conn: &mut SqliteConnection, sql: &str) -> Result<PrepareCheck> {
+    let c_sql = CString::new(sql)?;
+    let mut handle = conn.lock_handle().await?;
+    let db = handle.as_raw_handle().as_ptr();
+
+    // SAFETY: `db` is held exclusively via the lock; the statement is
+    // finalized before returning and strings are copied out first.
+    unsafe {
+        let mut stmt = std::ptr::null_mut();
+        let rc = ffi::sqlite3_prepare_v2(db, c_sql.as_ptr(), -1, &mut stmt, std::ptr::null_mut());
+        if rc != ffi::SQLITE_OK {
+            let message = CStr::from_ptr(ffi::sqlite3_errmsg(db)).to_string_lossy().into_owned();
+            let offset = usize::try_from(ffi::sqlite3_error_offset(db)).ok();
+            return Ok(PrepareCheck { error: Some((message, offset)), params: Vec::new(), columns: Vec::new() });
+        }
+        if stmt.is_null() {
+            // Only whitespace or comments
+            return Ok(PrepareCheck {
+                error: Some(("empty statement".to_string(), None)),
+                params: Vec::new(),
+                columns: Vec::new(),
+            });
+        }
+
+        let count = ffi::sqlite3_bind_parameter_count(stmt);
+        let params = (1..=count)
+            .map(|i| {
+                let name = ffi::sqlite3_bind_parameter_name(stmt, i);
+                if name.is_null() {
+                    format!("?{}", i)
+                } else {
+                    CStr::from_ptr(name).to_string_lossy().into_owned()
+                }
+            })
+            .collect();
+        let columns = (0..ffi::sqlite3_column_count(stmt))
+            .map(|i| {
+                let name = CStr::from_ptr(ffi::sqlite3_column_name(stmt, i)).to_string_lossy().into_owned();
+                let decl = ffi::sqlite3_column_decltype(stmt, i);
+                let decl = (!decl.is_null()).then(|| CStr::from_ptr(decl).to_string_lossy().into_owned());
+                (name, decl)
+            })
+            .collect();
+        ffi::sqlite3_finalize(stmt);
+
+        Ok(PrepareCheck { error: None, params, columns })
+    }
+}
@@ -0,0 +1,141 @@
+//! Built-in application-defined SQL functions, registered on every worker
+//! connection through the raw sqlite handle
+
+use anyhow::{bail, Context, Result};
+use libsqlite3_sys as ffi;
+use regex::Regex;
+use sqlx::SqliteConnection;
+use std::ffi::{c_char, c_int, c_void, CString};
+
+type ScalarFn = unsafe extern "C" fn(*mut ffi::sqlite3_context, c_int, *mut *mut ffi::sqlite3_value);
+
+/// A scalar function implemented in Rust
+pub struct BuiltinFunction {
+    pub name: &'static str,
+    pub n_args: i32,
+    pub description: &'static str,
+    /// Same inputs always give the same output (usable in indexes)
+    pub deterministic: bool,
+    func: ScalarFn,
+}
+
+pub const BUILTIN_FUNCTIONS: &[BuiltinFunction] = &[
+    BuiltinFunction {
+        name: "regexp",
+        n_args: 2,
+        description: "regexp(pattern, text): 1 if text matches the pattern; enables `text REGEXP pattern`",
+        deterministic: true,
+        func: regexp,
+    },
+    BuiltinFunction {
+        name: "uuid",
+        n_args: 0,
+        description: "uuid(): random version 4 UUID as text",
+        deterministic: false,
+        func: uuid_v4,
+    },
+];
+
+pub fn builtin(name: &str) -> Option<&'static BuiltinFunction> {
+    BUILTIN_FUNCTIONS.iter().find(|f| f.name.eq_ignore_ascii_case(name))
+}
+
+/// Register the named built-ins on a connection
+pub async fn register(conn: &mut SqliteConnection, names: &[String]) -> Result<()> {
+    let mut handle = conn.lock_handle().await?;
+    let db = handle.as_raw_handle().as_ptr();
+
+    for name in names {
+        let function = builtin(name).with_context(|| format!("Unknown function: {}", name))?;
+        let c_name = CString::new(function.name)?;
+        // Innocuous: no side effects, so allowed in schema SQL even with
+        // trusted_schema=OFF
+        let mut flags = ffi::SQLITE_UTF8 | ffi::SQLITE_INNOCUOUS;
+        if function.deterministic {
+            flags |= ffi::SQLITE_DETERMINISTIC;
+        }
+        // SAFETY: `db` is held exclusively via the lock; the name is copied
+        // by SQLite and the callback is a plain function without user data.
+        let rc = unsafe {
+            ffi::sqlite3_create_function_v2(
+                db,
+                c_name.as_ptr(),
+                function.n_args,
+                flags,
+                std::ptr::null_mut(),
+                Some(function.func),
+                None,
+                None,
+                None,
+            )
+        };
+        if rc != ffi::SQLITE_OK {
+            bail!("Failed to register function {} (code {})", function.name, rc);
+        }
+    }
+    Ok(())
+}
+
+/// UTF-8 text of a value, or None for NULL / invalid UTF-8
+unsafe fn value_text<'a>(value: *mut ffi::sqlite3_value) -> Option<&'a str> {
+    let text = ffi::sqlite3_value_text(value);
+    if text.is_null() {
+        return None;
+    }
+    let len = ffi::sqlite3_value_bytes(value) as usize;
+    std::str::from_utf8(std::slice::from_raw_parts(text, len)).ok()
+}
+
+unsafe fn result_error(ctx: *mut ffi::sqlite3_context, message: &str) {
+    ffi::sqlite3_result_error(ctx, message.as_ptr() as *const c_char, message.len() as c_int);
+}
+
+unsafe extern "C" fn drop_regex(ptr: *mut c_void) {
+    drop(Box::from_raw(ptr as *mut Regex));
+}
+
+unsafe extern "C" fn regexp(
+    ctx: *mut ffi::sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut ffi::sqlite3_value,
+) {
+    let args = std::slice::from_raw_parts(argv, argc as usize);
+    let (Some(pattern), Some(text)) = (value_text(args[0]), value_text(args[1])) else {
+        ffi::sqlite3_result_null(ctx);
+        return;
+    };
+
+    // SQLite keeps the compiled pattern as auxdata while argument 0 stays
+    // constant (e.g. across the rows of one query)
+    let cached = ffi::sqlite3_get_auxdata(ctx, 0) as *const Regex;
+    let is_match = if !cached.is_null() {
+        (*cached).is_match(text)
+    } else {
+        let compiled = match Regex::new(pattern) {
+            Ok(re) => re,
+            Err(e) => {
+                result_error(ctx, &format!("regexp: {}", e));
+                return;
+            }
+        };
+        let is_match = compiled.is_match(text);
+        let boxed = Box::into_raw(Box::new(compiled));
+        ffi::sqlite3_set_auxdata(ctx, 0, boxed as *mut c_void, Some(drop_regex));
+        is_match
+    };
+    ffi::sqlite3_result_int(ctx, is_match as c_int);
+}
+
+unsafe extern "C" fn uuid_v4(
+    ctx: *mut ffi::sqlite3_context,
+    _argc: c_int,
+    _argv: *mut *mut ffi::sqlite3_value,
+) {
+    let uuid = uuid::Uuid::new_v4().to_string();
+    ffi::sqlite3_result_text(
+        ctx,
+        uuid.as_ptr() as *const c_char,
+        uuid.len() as c_int,
+        ffi::SQLITE_TRANSIENT(),
+    );
+}
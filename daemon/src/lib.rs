@@ -0,0 +1,50 @@
+//! SQLite daemon as an embeddable library.
+//!
+//! The [`Router`] owns one worker per database and serializes writes to it.
+//! Co-located components can call [`Router::route_request`] directly while
+//! [`server::run_server`] exposes the same router over IPC to other processes:
+//!
+//! ```no_run
+//! use skylinedb_daemon::{Request, Router};
+//! use std::sync::Arc;
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let router = Arc::new(Router::builder().base_path("D:/MyApp/data").build());
+//!
+//! tokio::spawn(skylinedb_daemon::server::run_server(
+//!     skylinedb_daemon::PIPE_NAME,
+//!     Arc::clone(&router),
+//! ));
+//!
+//! let resp = router.route_request(Request::Ping { db: "galaxy.db".into() }).await;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod config;
+pub mod protocol;
+pub mod router;
+pub mod server;
+pub mod single_instance;
+
+mod breaker;
+#[cfg(feature = "arrow")]
+mod columnar;
+mod compare;
+mod cursor;
+mod ffi;
+mod functions;
+mod query;
+mod schema;
+mod snapshot;
+mod sql;
+mod stats;
+mod validation;
+mod worker;
+
+pub use config::DaemonConfig;
+pub use protocol::{
+    Request, Response, ResponseData, Statement, TransactionMode, MEMORY_DB, MIN_PROTOCOL_VERSION,
+    PIPE_NAME, PROTOCOL_VERSION,
+};
+pub use router::{Router, RouterBuilder};
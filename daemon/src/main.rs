@@ -1,24 +1,14 @@
-mod protocol;
-mod server;
-mod single_instance;
-mod worker;
-mod router;
-
 use anyhow::{Context, Result};
-use router::Router;
-use single_instance::SingleInstanceGuard;
-use std::path::PathBuf;
+use skylinedb_daemon::single_instance::SingleInstanceGuard;
+use skylinedb_daemon::{server, DaemonConfig, Router, PIPE_NAME};
+use std::sync::Arc;
+use std::time::Instant;
 use tracing::{error, info};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-#[cfg(windows)]
-const PIPE_NAME: &str = r"\\.\pipe\SkylineDBd-v1";
-
-#[cfg(unix)]
-const PIPE_NAME: &str = "/tmp/skylinedb-v1.sock";
+fn main() -> Result<()> {
+    let started = Instant::now();
 
-#[tokio::main]
-async fn main() -> Result<()> {
     // Initialize logging
     tracing_subscriber::registry()
         .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
@@ -31,14 +21,30 @@ async fn main() -> Result<()> {
         "Starting SQLite daemon"
     );
 
+    let config = DaemonConfig::from_args(std::env::args().skip(1))
+        .context("Invalid command-line arguments")?;
+
+    // Build the runtime by hand so --worker-threads can cap CPU usage
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(threads) = config.worker_threads {
+        info!(threads, "Runtime worker threads");
+        runtime.worker_threads(threads);
+    }
+    let runtime = runtime.build().context("Failed to build async runtime")?;
+
+    runtime.block_on(run(config, started))
+}
+
+async fn run(config: DaemonConfig, started: Instant) -> Result<()> {
     // Acquire single-instance lock (prevents multiple daemons)
     let _instance_guard = SingleInstanceGuard::try_acquire()
         .context("Failed to acquire single-instance lock")?;
 
     // Get database directory from args or use default
-    let db_dir = std::env::args()
-        .nth(1)
-        .map(PathBuf::from)
+    let db_dir = config
+        .db_dir
+        .clone()
         .unwrap_or_else(|| {
             std::env::current_dir()
                 .expect("Failed to get current directory")
@@ -47,7 +53,14 @@ async fn main() -> Result<()> {
     info!(db_dir = %db_dir.display(), "Database directory");
 
     // Create router
-    let router = Router::new(db_dir);
+    let router = Arc::new(
+        Router::builder()
+            .base_path(db_dir)
+            .config(config)
+            .started(started)
+            .transport(PIPE_NAME)
+            .build(),
+    );
 
     // Run IPC server with router
     let server_result = server::run_server(PIPE_NAME, router).await;
@@ -0,0 +1,463 @@
+use crate::protocol::{NullStyle, QueryFormat, Response};
+use base64::Engine;
+use futures_util::TryStreamExt;
+use serde_json::Value;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Executor, Row, TypeInfo, ValueRef};
+
+/// A query result passed `--max-result-bytes` while it was being read
+#[derive(Debug, thiserror::Error)]
+#[error("Query result exceeds {max_bytes} bytes; add a LIMIT or select fewer columns")]
+pub struct ResultTooLarge {
+    pub max_bytes: usize,
+}
+
+/// Fetch every row of `query` as JSON values, stopping with
+/// [`ResultTooLarge`] once their approximate encoded size passes
+/// `max_bytes` (0 = no limit)
+pub async fn fetch_rows<'q, 'c: 'q, E>(
+    query: sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>>,
+    executor: E,
+    max_bytes: usize,
+    nulls: NullStyle,
+) -> anyhow::Result<Vec<Vec<Value>>>
+where
+    E: 'q + sqlx::Executor<'c, Database = sqlx::Sqlite>,
+{
+    let mut stream = query.fetch(executor);
+    let mut rows = Vec::new();
+    let mut size = 0usize;
+    while let Some(row) = stream.try_next().await? {
+        let values = row_values_with(&row, nulls);
+        size += values.iter().map(json_size).sum::<usize>();
+        if max_bytes > 0 && size > max_bytes {
+            return Err(ResultTooLarge { max_bytes }.into());
+        }
+        rows.push(values);
+    }
+    Ok(rows)
+}
+
+/// Approximate JSON-encoded size of a value (escapes not counted)
+fn json_size(value: &Value) -> usize {
+    match value {
+        Value::Null => 4,
+        Value::Bool(_) => 5,
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => {
+                let digits = i.unsigned_abs().checked_ilog10().map_or(1, |d| d as usize + 1);
+                digits + (i < 0) as usize
+            }
+            // Large unsigned or floating point
+            None => 24,
+        },
+        Value::String(s) => s.len() + 2,
+        Value::Array(items) => 2 + items.iter().map(|v| json_size(v) + 1).sum::<usize>(),
+        Value::Object(map) => 2 + map.iter().map(|(k, v)| k.len() + 4 + json_size(v)).sum::<usize>(),
+    }
+}
+
+/// Convert a result row into JSON values, one per column.
+///
+/// SQLite values map as: NULL -> null, INTEGER -> number, REAL -> number
+/// (non-finite -> null), TEXT -> string, BLOB -> `{"$blob": "<base64>"}`,
+/// the same shape `bind_param` accepts.
+pub fn row_values(row: &SqliteRow) -> Vec<Value> {
+    row_values_with(row, NullStyle::Null)
+}
+
+/// [`row_values`] with SQL NULL written as `nulls` asks
+pub fn row_values_with(row: &SqliteRow, nulls: NullStyle) -> Vec<Value> {
+    (0..row.len()).map(|i| column_value(row, i, nulls)).collect()
+}
+
+fn column_value(row: &SqliteRow, index: usize, nulls: NullStyle) -> Value {
+    let type_name = match row.try_get_raw(index) {
+        Ok(raw) if raw.is_null() => {
+            return match nulls {
+                NullStyle::Null => Value::Null,
+                NullStyle::Sentinel => serde_json::json!({ "$null": true }),
+            }
+        }
+        Ok(raw) => raw.type_info().name().to_string(),
+        Err(_) => return Value::Null,
+    };
+
+    let value = match type_name.as_str() {
+        "INTEGER" | "BOOLEAN" => row.try_get::<i64, _>(index).map(Value::from),
+        "REAL" => row.try_get::<f64, _>(index).map(|f| {
+            serde_json::Number::from_f64(f)
+                .map(Value::Number)
+                .unwrap_or(Value::Null)
+        }),
+        "BLOB" => row.try_get::<Vec<u8>, _>(index).map(|bytes| blob_value(&bytes)),
+        _ => row.try_get::<String, _>(index).map(Value::String),
+    };
+
+    value.unwrap_or(Value::Null)
+}
+
+/// SQLite's extended result code behind `err` (searching its sources),
+/// if SQLite reported the failure
+pub fn extended_code(err: &(dyn std::error::Error + 'static)) -> Option<i32> {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(db_err) = err.downcast_ref::<sqlx::Error>().and_then(|e| e.as_database_error()) {
+            return db_err.code()?.parse().ok();
+        }
+        source = err.source();
+    }
+    None
+}
+
+/// Key of the JSON object that carries a BLOB as base64
+pub const BLOB_KEY: &str = "$blob";
+
+/// `{"$blob": "<base64>"}` for `bytes`
+pub fn blob_value(bytes: &[u8]) -> Value {
+    serde_json::json!({ BLOB_KEY: base64::engine::general_purpose::STANDARD.encode(bytes) })
+}
+
+/// The base64 text of a `{"$blob": ...}` parameter; `None` for any other
+/// value, including objects with extra keys
+pub fn blob_text(value: &Value) -> Option<&Value> {
+    match value.as_object() {
+        Some(map) if map.len() == 1 => map.get(BLOB_KEY),
+        _ => None,
+    }
+}
+
+/// Decode the bytes of a `{"$blob": ...}` parameter
+pub fn decode_blob(text: &Value) -> Result<Vec<u8>, String> {
+    let text = text.as_str().ok_or("value must be a base64 string")?;
+    base64::engine::general_purpose::STANDARD
+        .decode(text)
+        .map_err(|e| e.to_string())
+}
+
+/// Bind a JSON parameter: null, bool (as 0/1), integer, real, text,
+/// `{"$blob": "<base64>"}` as a BLOB; other arrays and objects are bound
+/// as their JSON text. A malformed `$blob` is rejected by
+/// `validate_statement` before it gets here.
+///
+/// Integers above `i64::MAX` don't fit SQLite's INTEGER, so they are bound
+/// as their decimal TEXT rather than wrapping negative. They read back
+/// exactly from untyped or TEXT columns; INTEGER and NUMERIC affinity
+/// would still turn them into an approximate REAL.
+///
+/// Every other number is an `f64` and is bound as REAL unchanged, `-0.0`
+/// included. JSON has no NaN or Infinity and the request parser rejects
+/// numbers beyond `f64` range, so a REAL parameter is always finite. An
+/// integer literal beyond `u64::MAX` has already been rounded to the
+/// nearest `f64` by then; send such values as strings to keep every digit.
+pub fn bind_param<'q>(
+    query: sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>>,
+    value: &'q serde_json::Value,
+) -> sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>> {
+    use serde_json::Value;
+
+    match value {
+        Value::Null => query.bind(None::<String>),
+        Value::Bool(b) => query.bind(*b as i64),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                query.bind(i)
+            } else if let Some(u) = n.as_u64() {
+                query.bind(u.to_string())
+            } else if let Some(f) = n.as_f64() {
+                query.bind(f)
+            } else {
+                query.bind(None::<i64>)
+            }
+        }
+        Value::String(s) => query.bind(s.as_str()),
+        Value::Object(_) => match blob_text(value).map(decode_blob) {
+            Some(Ok(bytes)) => query.bind(bytes),
+            _ => query.bind(value.to_string()),
+        },
+        Value::Array(_) => query.bind(value.to_string()),
+    }
+}
+
+/// Prepare `sql` once and execute it with each parameter set in order.
+/// Returns the total rows affected, or the index of the set that failed.
+pub async fn execute_many(
+    conn: &mut sqlx::SqliteConnection,
+    sql: &str,
+    param_sets: &[Vec<Value>],
+) -> Result<u64, (usize, sqlx::Error)> {
+    let prepared = (&mut *conn).prepare(sql).await.map_err(|e| (0, e))?;
+    let mut total_rows = 0;
+    for (i, params) in param_sets.iter().enumerate() {
+        let mut query = sqlx::Statement::query(&prepared);
+        for param in params {
+            query = bind_param(query, param);
+        }
+        total_rows += query.execute(&mut *conn).await.map_err(|e| (i, e))?.rows_affected();
+    }
+    Ok(total_rows)
+}
+
+/// Render a result set as RFC 4180 CSV (CRLF line endings, header row).
+///
+/// SQL NULL is written as an empty unquoted field, while an empty string is
+/// written as `""`, so the two stay distinguishable. Non-scalar values are
+/// written as their JSON text.
+pub fn to_csv(columns: &[String], rows: &[Vec<Value>]) -> String {
+    let mut out = String::new();
+
+    let header: Vec<String> = columns.iter().map(|c| csv_field(c)).collect();
+    out.push_str(&header.join(","));
+    out.push_str("\r\n");
+
+    for row in rows {
+        let fields: Vec<String> = row
+            .iter()
+            .map(|value| match value {
+                Value::Null => String::new(),
+                Value::String(s) if s.is_empty() => "\"\"".to_string(),
+                Value::String(s) => csv_field(s),
+                Value::Bool(_) | Value::Number(_) => value.to_string(),
+                Value::Array(_) | Value::Object(_) => csv_field(&value.to_string()),
+            })
+            .collect();
+        out.push_str(&fields.join(","));
+        out.push_str("\r\n");
+    }
+
+    out
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Type name of each of `count` columns: its declared type if known,
+/// otherwise the storage class of its first non-NULL value in `rows`
+/// (`NULL` when every value is)
+pub fn column_types(count: usize, decl_types: &[Option<String>], rows: &[Vec<Value>]) -> Vec<String> {
+    (0..count)
+        .map(|i| match decl_types.get(i) {
+            Some(Some(decl_type)) => decl_type.clone(),
+            _ => rows
+                .iter()
+                .find_map(|row| row.get(i).and_then(storage_class))
+                .unwrap_or("NULL")
+                .to_string(),
+        })
+        .collect()
+}
+
+/// SQLite storage class of a value produced by [`row_values_with`]; `None`
+/// for NULL in either style
+fn storage_class(value: &Value) -> Option<&'static str> {
+    match value {
+        Value::Number(n) if n.is_f64() => Some("REAL"),
+        Value::Number(_) => Some("INTEGER"),
+        Value::String(_) => Some("TEXT"),
+        Value::Object(_) if blob_text(value).is_some() => Some("BLOB"),
+        _ => None,
+    }
+}
+
+/// Build the response for a result set in the requested encoding.
+/// `decl_types` (one per column, if known) type JSON `column_types` and
+/// Arrow columns.
+pub fn format_results(
+    columns: Vec<String>,
+    decl_types: &[Option<String>],
+    rows: Vec<Vec<Value>>,
+    format: QueryFormat,
+) -> Response {
+    match format {
+        QueryFormat::Json => {
+            let column_types = column_types(columns.len(), decl_types, &rows);
+            Response::ok_query(columns, column_types, rows)
+        }
+        QueryFormat::Csv => Response::ok_query_csv(to_csv(&columns, &rows)),
+        #[cfg(feature = "arrow")]
+        QueryFormat::Arrow => match crate::columnar::to_arrow_ipc(&columns, decl_types, &rows) {
+            Ok(bytes) => {
+                Response::ok_query_arrow(base64::engine::general_purpose::STANDARD.encode(bytes))
+            }
+            Err(e) => Response::error(format!("Failed to encode Arrow results: {}", e)),
+        },
+        #[cfg(not(feature = "arrow"))]
+        QueryFormat::Arrow => {
+            let _ = decl_types;
+            Response::error_with_code(
+                "Arrow output requires a daemon built with the `arrow` feature",
+                "FORMAT_UNSUPPORTED",
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::{Column, Connection};
+
+    #[tokio::test]
+    async fn test_column_types_fall_back_to_storage_class() {
+        let mut conn = sqlx::SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE t (n INTEGER, r REAL)").execute(&mut conn).await.unwrap();
+        sqlx::query("INSERT INTO t VALUES (1, 2.0)").execute(&mut conn).await.unwrap();
+
+        let sql = "SELECT n, r, n * 1.5, 'x', x'00', NULL FROM t";
+        let prepared = (&mut conn).prepare(sql).await.unwrap();
+        let decl_types: Vec<Option<String>> = sqlx::Statement::columns(&prepared)
+            .iter()
+            .map(|c| Some(c.type_info().name().to_string()).filter(|t| t != "NULL"))
+            .collect();
+        let rows = fetch_rows(sqlx::query(sql), &mut conn, 0, NullStyle::Sentinel).await.unwrap();
+
+        assert_eq!(
+            column_types(6, &decl_types, &rows),
+            ["INTEGER", "REAL", "REAL", "TEXT", "BLOB", "NULL"]
+        );
+        // A whole REAL stays REAL
+        assert_eq!(column_types(1, &[], &[vec![serde_json::json!(2.0)]]), ["REAL"]);
+    }
+
+    #[tokio::test]
+    async fn test_float_params_bind_as_real() {
+        // Binds the parsed JSON into an untyped and a REAL column
+        async fn read_back(conn: &mut sqlx::SqliteConnection, text: &str) -> Vec<Value> {
+            let value: Value = serde_json::from_str(text).unwrap();
+            sqlx::query("DELETE FROM t").execute(&mut *conn).await.unwrap();
+            let insert = bind_param(sqlx::query("INSERT INTO t VALUES (?, ?)"), &value);
+            bind_param(insert, &value).execute(&mut *conn).await.unwrap();
+            let row = sqlx::query("SELECT v, r, typeof(v) FROM t").fetch_one(&mut *conn).await.unwrap();
+            row_values(&row)
+        }
+        let mut conn = sqlx::SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE t (v, r REAL)").execute(&mut conn).await.unwrap();
+
+        let max = read_back(&mut conn, "1e308").await;
+        assert_eq!(max, [Value::from(1e308), Value::from(1e308), Value::from("real")]);
+
+        // The sign survives in an untyped column; REAL affinity stores 0.0
+        let zero = read_back(&mut conn, "-0.0").await;
+        assert!(zero[0].as_f64().unwrap().is_sign_negative());
+        assert_eq!(zero[1].as_f64(), Some(0.0));
+
+        // Past u64 the parser already rounded it: REAL, digits lost
+        let huge = read_back(&mut conn, "123456789012345678901234567890").await;
+        assert_eq!(huge[0], Value::from(1.2345678901234568e29));
+        assert_eq!(huge[2], Value::from("real"));
+
+        // Out of f64 range is a parse error, never an infinite REAL
+        assert!(serde_json::from_str::<Value>("1e400").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_u64_above_i64_max_round_trips() {
+        let mut conn = sqlx::SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE t (v)").execute(&mut conn).await.unwrap();
+
+        let value = serde_json::json!(18446744073709551615u64);
+        bind_param(sqlx::query("INSERT INTO t VALUES (?)"), &value)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let row = sqlx::query("SELECT v FROM t").fetch_one(&mut conn).await.unwrap();
+        assert_eq!(row_values(&row), vec![Value::String("18446744073709551615".into())]);
+    }
+
+    /// Tables `a` and `b` plus `rows` parameter sets for them
+    async fn execute_many_fixture(rows: i64) -> (sqlx::SqliteConnection, Vec<Vec<Value>>) {
+        let mut conn = sqlx::SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE a (id INTEGER, name TEXT)").execute(&mut conn).await.unwrap();
+        sqlx::query("CREATE TABLE b (id INTEGER, name TEXT)").execute(&mut conn).await.unwrap();
+        let sets = (0..rows)
+            .map(|i| vec![Value::from(i), Value::from(format!("row {}", i))])
+            .collect();
+        (conn, sets)
+    }
+
+    /// Insert `sets` into `a` one statement at a time, in one transaction
+    async fn insert_separately(conn: &mut sqlx::SqliteConnection, sets: &[Vec<Value>]) {
+        sqlx::query("BEGIN").execute(&mut *conn).await.unwrap();
+        for params in sets {
+            let mut query = sqlx::query("INSERT INTO a VALUES (?, ?)");
+            for param in params {
+                query = bind_param(query, param);
+            }
+            query.execute(&mut *conn).await.unwrap();
+        }
+        sqlx::query("COMMIT").execute(&mut *conn).await.unwrap();
+    }
+
+    /// Insert `sets` into `b` with `execute_many`, in one transaction
+    async fn insert_many(conn: &mut sqlx::SqliteConnection, sets: &[Vec<Value>]) -> u64 {
+        sqlx::query("BEGIN").execute(&mut *conn).await.unwrap();
+        let rows = execute_many(&mut *conn, "INSERT INTO b VALUES (?, ?)", sets).await.unwrap();
+        sqlx::query("COMMIT").execute(&mut *conn).await.unwrap();
+        rows
+    }
+
+    #[tokio::test]
+    async fn test_execute_many_matches_separate_statements() {
+        const ROWS: i64 = 500;
+        let (mut conn, sets) = execute_many_fixture(ROWS).await;
+        insert_separately(&mut conn, &sets).await;
+        let rows = insert_many(&mut conn, &sets).await;
+
+        assert_eq!(rows, ROWS as u64);
+        let summary = "SELECT COUNT(*), SUM(id), SUM(LENGTH(name)) FROM";
+        let a: (i64, i64, i64) =
+            sqlx::query_as(&format!("{} a", summary)).fetch_one(&mut conn).await.unwrap();
+        let b: (i64, i64, i64) =
+            sqlx::query_as(&format!("{} b", summary)).fetch_one(&mut conn).await.unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a.0, ROWS);
+    }
+
+    /// Timing comparison, run on demand with `cargo test -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore]
+    async fn bench_execute_many_vs_separate_statements() {
+        const ROWS: i64 = 10_000;
+        let (mut conn, sets) = execute_many_fixture(ROWS).await;
+        let start = std::time::Instant::now();
+        insert_separately(&mut conn, &sets).await;
+        let separate = start.elapsed();
+        let start = std::time::Instant::now();
+        insert_many(&mut conn, &sets).await;
+        let many = start.elapsed();
+        eprintln!("{} inserts: separate statements {:?}, execute_many {:?}", ROWS, separate, many);
+    }
+
+    #[tokio::test]
+    async fn test_extended_code_of_unique_violation() {
+        let mut conn = sqlx::SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE t (v UNIQUE)").execute(&mut conn).await.unwrap();
+        sqlx::query("INSERT INTO t VALUES (1)").execute(&mut conn).await.unwrap();
+
+        let e = sqlx::query("INSERT INTO t VALUES (1)").execute(&mut conn).await.unwrap_err();
+        assert_eq!(extended_code(&e), Some(2067));
+        let e = anyhow::Error::from(e).context("Statement 0");
+        assert_eq!(extended_code(&*e), Some(2067));
+    }
+
+    #[tokio::test]
+    async fn test_blob_round_trips() {
+        let mut conn = sqlx::SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE t (v)").execute(&mut conn).await.unwrap();
+
+        let value = blob_value(&[0x08, 0x96, 0x01, 0x00, 0xff]);
+        bind_param(sqlx::query("INSERT INTO t VALUES (?)"), &value)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        let row = sqlx::query("SELECT v, typeof(v) FROM t").fetch_one(&mut conn).await.unwrap();
+        assert_eq!(row_values(&row), vec![value, Value::String("blob".into())]);
+    }
+}
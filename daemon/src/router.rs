@@ -1,80 +1,367 @@
-use crate::protocol::{Request, Response};
-use crate::worker::{WorkerCommand, worker_loop};
-use anyhow::Result;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use crate::config::{AuthorizerRules, DaemonConfig};
+use crate::cursor::{CursorError, Cursors, MAX_FETCH_ROWS};
+use crate::functions::BUILTIN_FUNCTIONS;
+use crate::protocol::{
+    FunctionInfo, MaintenanceEntry, ProcessDiagnostics, QueryFormat, Request, Response, Statement,
+    TransactionMode, WorkerDiagnostics, WorkerStatus, MEMORY_DB,
+};
+use crate::validation::validate_statement;
+use crate::worker::{ProgressSender, Reply, WorkerCommand, WorkerSnapshot, worker_loop};
+use futures_util::FutureExt;
+use sqlx::{Connection, SqliteConnection};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::panic::AssertUnwindSafe;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::{mpsc, oneshot, RwLock};
-use tracing::{error, info};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot, watch, RwLock};
+use tracing::{debug, error, info, warn};
+
+/// Errors resolving or spawning the worker for a database
+#[derive(Debug, thiserror::Error)]
+enum RouterError {
+    #[error("Database not found: {0}")]
+    DbNotFound(String),
+    #[error("Invalid database name: {0}")]
+    InvalidDbName(String),
+    #[error("Too many new databases opened at once, retry shortly: {0}")]
+    SpawnThrottled(String),
+    #[error("Daemon is shutting down")]
+    ShuttingDown,
+    #[error("Too many open databases ({0}) and none idle enough to close, retry shortly")]
+    TooManyWorkers(usize),
+    #[error("Not supported for the in-memory database, it has no file")]
+    InMemory,
+}
+
+impl RouterError {
+    fn code(&self) -> &'static str {
+        match self {
+            RouterError::DbNotFound(_) => "DB_NOT_FOUND",
+            RouterError::InvalidDbName(_) => "INVALID_DB_NAME",
+            RouterError::SpawnThrottled(_) => "SPAWN_THROTTLED",
+            RouterError::ShuttingDown => "SHUTTING_DOWN",
+            RouterError::TooManyWorkers(_) => "TOO_MANY_WORKERS",
+            RouterError::InMemory => "IN_MEMORY_DB",
+        }
+    }
+}
+
+/// Token bucket limiting how fast new workers are spawned. Requests to
+/// databases that already have a worker never take a token.
+struct SpawnLimiter {
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl SpawnLimiter {
+    fn new(per_sec: u32) -> Self {
+        Self {
+            rate: per_sec as f64,
+            tokens: per_sec as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn try_acquire(&mut self) -> bool {
+        if self.rate == 0.0 {
+            return true;
+        }
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+static NEXT_WORKER_ID: AtomicU64 = AtomicU64::new(0);
 
 struct WorkerHandle {
+    /// Distinguishes a respawned worker from the one it replaced
+    id: u64,
     sender: mpsc::Sender<WorkerCommand>,
+    task: tokio::task::JoinHandle<()>,
+    /// Milliseconds after the router started that a request last went to
+    /// this worker
+    last_used: AtomicU64,
+    /// Set by the worker while `--max-workers` may evict it
+    evictable: Arc<AtomicBool>,
 }
 
 pub struct Router {
     workers: Arc<RwLock<HashMap<String, WorkerHandle>>>,
     base_path: PathBuf,
+    config: Arc<DaemonConfig>,
+    spawn_limiter: std::sync::Mutex<SpawnLimiter>,
+    started: Instant,
+    transport: String,
+    /// Whether the IPC server should stop accepting connections
+    accept_paused: watch::Sender<bool>,
+    /// Set by `Shutdown`; the IPC server stops and workers are drained
+    shutdown: watch::Sender<bool>,
+    cursors: Cursors,
+}
+
+/// Builder for an embedded [`Router`]
+#[derive(Debug, Default)]
+pub struct RouterBuilder {
+    base_path: Option<PathBuf>,
+    config: DaemonConfig,
+    started: Option<Instant>,
+    transport: Option<String>,
+}
+
+impl RouterBuilder {
+    /// Directory holding the database files (defaults to `config.db_dir`,
+    /// then the current directory)
+    pub fn base_path(mut self, base_path: impl Into<PathBuf>) -> Self {
+        self.base_path = Some(base_path.into());
+        self
+    }
+
+    /// Limits and feature switches, as parsed from the command line
+    pub fn config(mut self, config: DaemonConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// When the process started, for `DaemonInfo` (defaults to `build()` time)
+    pub fn started(mut self, started: Instant) -> Self {
+        self.started = Some(started);
+        self
+    }
+
+    /// Pipe or socket name reported by `DaemonInfo` (defaults to "embedded")
+    pub fn transport(mut self, transport: impl Into<String>) -> Self {
+        self.transport = Some(transport.into());
+        self
+    }
+
+    pub fn build(self) -> Router {
+        let base_path = self
+            .base_path
+            .or_else(|| self.config.db_dir.clone())
+            .unwrap_or_else(|| std::env::current_dir().unwrap_or_default());
+        let mut router = Router::new(base_path, self.config);
+        if let Some(started) = self.started {
+            router.started = started;
+        }
+        if let Some(transport) = self.transport {
+            router.transport = transport;
+        }
+        router
+    }
 }
 
 impl Router {
-    pub fn new(base_path: PathBuf) -> Self {
+    pub fn new(base_path: PathBuf, config: DaemonConfig) -> Self {
+        // Workers run on a multi-threaded runtime, so connections must be
+        // usable from any thread
+        match crate::ffi::threadsafe() {
+            0 => error!(
+                "SQLite was built with SQLITE_THREADSAFE=0; connections are not safe on \
+                 a multi-threaded runtime"
+            ),
+            level => info!(
+                threadsafe = level,
+                thread_mode = ?config.thread_mode,
+                "SQLite threading"
+            ),
+        }
         Self {
             workers: Arc::new(RwLock::new(HashMap::new())),
             base_path,
+            spawn_limiter: std::sync::Mutex::new(SpawnLimiter::new(config.max_spawns_per_sec)),
+            started: Instant::now(),
+            transport: "embedded".to_string(),
+            accept_paused: watch::Sender::new(false),
+            shutdown: watch::Sender::new(false),
+            cursors: Cursors::default(),
+            config: Arc::new(config),
         }
     }
 
+    pub fn builder() -> RouterBuilder {
+        RouterBuilder::default()
+    }
+
+    pub fn config(&self) -> &DaemonConfig {
+        &self.config
+    }
+
+    /// Paused flag set by `PauseAccept` / `ResumeAccept`, for the accept loop
+    pub fn accept_paused(&self) -> watch::Receiver<bool> {
+        self.accept_paused.subscribe()
+    }
+
+    /// Handle one request and return its response. This is the whole
+    /// protocol: the IPC server only frames JSON around this call, so
+    /// embedders get identical behavior in-process. Safe to call
+    /// concurrently; requests for one database are serialized by its worker.
     pub async fn route_request(&self, req: Request) -> Response {
-        let db_name = match Self::extract_db_name(&req) {
-            Some(name) => name,
-            None => {
-                // Shutdown request doesn't need DB name
-                if matches!(req, Request::Shutdown) {
-                    return Response::ok_shutdown();
-                }
-                return Response::error("Missing database name in request");
-            }
-        };
+        self.route_client_request(req, None, None).await
+    }
 
-        // Get or create worker for this database
-        let worker = match self.get_or_create_worker(&db_name).await {
-            Ok(w) => w,
-            Err(e) => {
-                error!(db = %db_name, error = %e, "Failed to get worker");
-                return Response::error(format!("Failed to get worker: {}", e));
+    /// [`Self::route_request`] for a client whose connection set authorizer
+    /// rules of its own in `Hello`, enforced on top of the daemon's. A
+    /// request that streams progress frames (`Migrate` with `progress`)
+    /// sends them to `progress` before returning its response.
+    pub async fn route_client_request(
+        &self,
+        req: Request,
+        rules: Option<Arc<AuthorizerRules>>,
+        progress: Option<ProgressSender>,
+    ) -> Response {
+        // Requests handled by the router itself (no DB name)
+        if *self.shutdown.borrow() {
+            return Response::error_with_code("Daemon is shutting down", "SHUTTING_DOWN");
+        }
+        match &req {
+            Request::Shutdown => {
+                info!("Shutdown requested");
+                self.shutdown.send_replace(true);
+                return Response::ok_shutdown();
+            }
+            Request::CleanupSidecars { remove } => return self.cleanup_sidecars(*remove).await,
+            // Connection defaults are tracked by the server; nothing to route
+            Request::UseDatabase { db } => return Response::ok_use_database(db.clone()),
+            Request::ListFunctions => return self.list_functions(),
+            Request::DaemonInfo => return self.daemon_info().await,
+            Request::Diagnostics => return self.diagnostics().await,
+            Request::Status => return self.status().await,
+            Request::ListMaintenance => return self.list_maintenance().await,
+            Request::PauseAccept => return self.set_accept_paused(true),
+            Request::ResumeAccept => return self.set_accept_paused(false),
+            Request::FetchCursor { cursor_id, n } => return self.fetch_cursor(cursor_id, *n).await,
+            Request::CloseCursor { cursor_id } => {
+                return Response::ok_cursor_closed(self.cursors.close(cursor_id))
             }
+            _ => {}
+        }
+        let req = match req {
+            Request::ExecAll { pattern, stmts, tx } => return self.exec_all(&pattern, stmts, tx, rules).await,
+            Request::ResolvePath { db } => return self.resolve_path(&db).await,
+            Request::ValidateSql { sql } => return validate_sql(&sql).await,
+            Request::Compare { db_a, db_b } => return self.compare(&db_a, &db_b).await,
+            Request::QuerySnapshot { db, sql, params, format } => {
+                let stmt = Statement { sql, params, named_params: None };
+                return self.query_snapshot(&db, stmt, format, rules.as_deref()).await;
+            }
+            Request::OpenCursor { db, sql, params } => {
+                return self.open_cursor(&db, Statement { sql, params, named_params: None }, rules.as_deref()).await
+            }
+            req => req,
         };
 
-        // Send request to worker
-        let (reply_tx, reply_rx) = oneshot::channel();
-        let cmd = WorkerCommand::Request {
-            req,
-            reply: reply_tx,
+        let db_name = match Self::extract_db_name(&req) {
+            Some(name) => name,
+            None => return Response::error("Missing database name in request"),
         };
 
-        if let Err(e) = worker.send(cmd).await {
-            error!(db = %db_name, error = %e, "Failed to send to worker");
-            // Worker might have died, remove it
-            self.remove_worker(&db_name).await;
-            return Response::error("Worker communication failed");
+        // Maintenance acts on a database that exists; it never creates one
+        let maintenance = matches!(req, Request::PrepareForMaintenance { .. } | Request::CloseDatabase { .. });
+        if maintenance && !self.exists(&db_name).await {
+            let e = RouterError::DbNotFound(db_name);
+            return Response::error_with_code(e.to_string(), e.code());
         }
+        // Open cursors read the file directly; they must not outlive its
+        // checkpoint or its replacement
+        if maintenance {
+            let closed = self.cursors.close_db(&db_name).await;
+            if closed > 0 {
+                info!(db = %db_name, cursors = closed, "Closed cursors for maintenance");
+            }
+        }
+
+        self.dispatch(db_name, req, rules, progress).await
+    }
+
+    /// Send a request to the worker for `db_name`, spawning it if needed
+    async fn dispatch(
+        &self,
+        db_name: String,
+        req: Request,
+        rules: Option<Arc<AuthorizerRules>>,
+        progress: Option<ProgressSender>,
+    ) -> Response {
+        let mut req = req;
+
+        // A worker that retired (max lifetime) refuses new requests; those
+        // never ran, so they are retried once on a fresh worker. A request
+        // whose worker died (panicked) before replying may have run, so only
+        // one that doesn't write is resent.
+        for attempt in 0..2 {
+            // Get or create worker for this database
+            let (worker_id, worker) = match self.get_or_create_worker(&db_name).await {
+                Ok(w) => w,
+                Err(e) => {
+                    error!(db = %db_name, error = %e, "Failed to get worker");
+                    return Response::error_with_code(e.to_string(), e.code());
+                }
+            };
+
+            // Send request to worker, keeping a copy to resend should the
+            // worker die before it replies
+            let resend = (attempt == 0 && is_read_only(&req)).then(|| req.clone());
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let cmd = WorkerCommand::Request {
+                req,
+                rules: rules.clone(),
+                reply: Reply::new(reply_tx, progress.clone()),
+            };
+
+            if let Err(mpsc::error::SendError(cmd)) = worker.send(cmd).await {
+                debug!(db = %db_name, "Worker no longer accepts requests, respawning");
+                self.remove_worker(&db_name, worker_id).await;
+                let WorkerCommand::Request { req: returned, .. } = cmd else {
+                    unreachable!("dispatch only sends requests");
+                };
+                req = returned;
+                continue;
+            }
 
-        match reply_rx.await {
-            Ok(response) => response,
-            Err(_) => {
-                error!(db = %db_name, "Worker reply channel closed");
-                self.remove_worker(&db_name).await;
-                Response::error("Worker communication failed")
+            match reply_rx.await {
+                Ok(response) => return response,
+                Err(_) => {
+                    self.remove_worker(&db_name, worker_id).await;
+                    let Some(again) = resend else {
+                        error!(db = %db_name, "Worker reply channel closed");
+                        return Response::error_with_code(
+                            "Worker died before replying; the request may or may not have been applied",
+                            "WORKER_DIED",
+                        );
+                    };
+                    warn!(db = %db_name, "Worker stopped without replying, retrying on a fresh worker");
+                    req = again;
+                }
             }
         }
+
+        error!(db = %db_name, "Failed to send to worker");
+        Response::error("Worker communication failed")
     }
 
-    async fn get_or_create_worker(&self, db_name: &str) -> Result<mpsc::Sender<WorkerCommand>> {
+    /// Sender for the worker of `db_name` plus the worker's id, spawning it
+    /// if needed
+    async fn get_or_create_worker(
+        &self,
+        db_name: &str,
+    ) -> Result<(u64, mpsc::Sender<WorkerCommand>), RouterError> {
+        let now = self.started.elapsed().as_millis() as u64;
         // Fast path: check if worker exists
         {
             let workers = self.workers.read().await;
             if let Some(handle) = workers.get(db_name) {
-                return Ok(handle.sender.clone());
+                handle.last_used.store(now, Ordering::Relaxed);
+                return Ok((handle.id, handle.sender.clone()));
             }
         }
 
@@ -83,56 +370,1384 @@ impl Router {
         
         // Double-check after acquiring write lock
         if let Some(handle) = workers.get(db_name) {
-            return Ok(handle.sender.clone());
+            handle.last_used.store(now, Ordering::Relaxed);
+            return Ok((handle.id, handle.sender.clone()));
+        }
+
+        // Workers being drained are gone from the map; a request that raced
+        // the shutdown must not start a new one nobody will stop
+        if *self.shutdown.borrow() {
+            return Err(RouterError::ShuttingDown);
+        }
+
+        let db_path = self.db_path(db_name)?;
+        if db_name != MEMORY_DB && !self.config.auto_create && !db_path.exists() {
+            return Err(RouterError::DbNotFound(db_name.to_string()));
         }
 
+        // The slot is taken from the victim under this lock, so concurrent
+        // spawns can't overshoot `--max-workers`
+        let full = self.config.max_workers > 0 && workers.len() >= self.config.max_workers;
+        let victim = match full.then(|| Self::eviction_victim(&workers, db_name)) {
+            Some(None) => {
+                warn!(db = %db_name, max_workers = self.config.max_workers, "No idle worker to evict");
+                return Err(RouterError::TooManyWorkers(self.config.max_workers));
+            }
+            Some(Some(victim)) => Some(victim),
+            None => None,
+        };
+
+        if !self.spawn_limiter.lock().unwrap().try_acquire() {
+            warn!(db = %db_name, "Worker spawn throttled");
+            return Err(RouterError::SpawnThrottled(db_name.to_string()));
+        }
+        let evicted = victim.and_then(|victim| {
+            info!(db = %victim, for_db = %db_name, "Evicting least recently used worker");
+            workers.remove_entry(&victim)
+        });
+
         info!(db = %db_name, "Spawning new worker");
 
-        let db_path = self.base_path.join(db_name);
         let (worker_tx, worker_rx) = mpsc::channel(1000);
+        let worker_id = NEXT_WORKER_ID.fetch_add(1, Ordering::Relaxed);
 
         let db_name_clone = db_name.to_string();
         let workers_clone = Arc::clone(&self.workers);
         let db_path_clone = db_path.clone();
+        let config = Arc::clone(&self.config);
+        let evictable = Arc::new(AtomicBool::new(false));
+        let evictable_clone = Arc::clone(&evictable);
         
-        tokio::spawn(async move {
-            worker_loop(worker_rx, db_path_clone, db_name_clone.clone()).await;
+        let task = tokio::spawn(async move {
+            let run =
+                worker_loop(worker_rx, db_path_clone, db_name_clone.clone(), config, evictable_clone);
+            // A panic ends this worker only; `dispatch` resends the request
+            // it was handling to a fresh one
+            if let Err(panic) = AssertUnwindSafe(run).catch_unwind().await {
+                error!(db = %db_name_clone, panic = %panic_message(&*panic), "Worker panicked");
+            }
             
-            // Worker terminated, remove from map
+            // Worker terminated, remove from map (unless already replaced)
             info!(db = %db_name_clone, "Worker terminated, removing from router");
             let mut workers = workers_clone.write().await;
-            workers.remove(&db_name_clone);
+            if workers.get(&db_name_clone).is_some_and(|h| h.id == worker_id) {
+                workers.remove(&db_name_clone);
+            }
         });
 
         let handle = WorkerHandle {
+            id: worker_id,
             sender: worker_tx.clone(),
+            task,
+            last_used: AtomicU64::new(now),
+            evictable,
         };
 
         workers.insert(db_name.to_string(), handle);
+        drop(workers);
+
+        // The evicted worker answers whatever reached it meanwhile, then
+        // checkpoints and closes its pool before the new one is used
+        if let Some((victim, handle)) = evicted {
+            // Fails only if the worker already stopped
+            let _ = handle.sender.send(WorkerCommand::Drain).await;
+            drop(handle.sender);
+            if let Err(e) = handle.task.await {
+                error!(db = %victim, error = %e, "Worker task failed");
+            }
+        }
+
+        Ok((worker_id, worker_tx))
+    }
+
+    /// Least recently used worker to stop so a new one fits under
+    /// `--max-workers`. Only workers counting down to their idle timeout
+    /// qualify: never one initializing, holding a transaction, mid-maintenance
+    /// or with requests waiting, nor the in-memory database, whose data would
+    /// be lost.
+    fn eviction_victim(workers: &HashMap<String, WorkerHandle>, db_name: &str) -> Option<String> {
+        workers
+            .iter()
+            .filter(|(name, handle)| {
+                *name != db_name && *name != MEMORY_DB && handle.evictable.load(Ordering::Relaxed)
+            })
+            .min_by_key(|(_, handle)| handle.last_used.load(Ordering::Relaxed))
+            .map(|(name, _)| name.clone())
+    }
 
-        Ok(worker_tx)
+    /// Join a database name onto `base_path`, rejecting names that could
+    /// escape it (absolute paths, `..`, drive prefixes). [`MEMORY_DB`] is
+    /// passed through as is for the worker to open in memory.
+    fn db_path(&self, db_name: &str) -> Result<PathBuf, RouterError> {
+        if db_name == MEMORY_DB {
+            return Ok(PathBuf::from(MEMORY_DB));
+        }
+        let name = Path::new(db_name);
+        let sandboxed = !db_name.is_empty()
+            && name.components().all(|c| matches!(c, Component::Normal(_)));
+        if !sandboxed {
+            return Err(RouterError::InvalidDbName(db_name.to_string()));
+        }
+        Ok(self.base_path.join(name))
+    }
+
+    /// Whether `db_name` has a live worker or a file on disk
+    async fn exists(&self, db_name: &str) -> bool {
+        db_name == MEMORY_DB
+            || self.workers.read().await.contains_key(db_name)
+            || self.db_path(db_name).is_ok_and(|path| path.exists())
+    }
+
+    /// Like [`Self::db_path`], for requests that read the file directly
+    /// rather than going through the worker
+    fn file_path(&self, db_name: &str) -> Result<PathBuf, RouterError> {
+        if db_name == MEMORY_DB {
+            return Err(RouterError::InMemory);
+        }
+        self.db_path(db_name)
+    }
+
+    /// Absolute, canonicalized path for `db_name` without opening it. For a
+    /// file that doesn't exist yet, the canonical base directory is joined
+    /// with the name, i.e. where the daemon would create it.
+    async fn resolve_path(&self, db_name: &str) -> Response {
+        let db_path = match self.file_path(db_name) {
+            Ok(path) => path,
+            Err(e) => return Response::error_with_code(e.to_string(), e.code()),
+        };
+
+        let resolved = match tokio::fs::canonicalize(&db_path).await {
+            Ok(path) => Ok((path, true)),
+            Err(_) => tokio::fs::canonicalize(&self.base_path)
+                .await
+                .map(|base| (base.join(db_name), false)),
+        };
+
+        match resolved {
+            Ok((path, exists)) => Response::ok_resolve_path(path.display().to_string(), exists),
+            Err(e) => Response::error(format!("Failed to resolve database directory: {}", e)),
+        }
+    }
+
+    /// Ask every live worker for a snapshot; workers that exit meanwhile
+    /// are skipped. Sorted by database name.
+    async fn worker_snapshots(&self) -> Vec<(String, WorkerSnapshot)> {
+        let senders: Vec<(String, mpsc::Sender<WorkerCommand>)> = self
+            .workers
+            .read()
+            .await
+            .iter()
+            .map(|(name, handle)| (name.clone(), handle.sender.clone()))
+            .collect();
+
+        let mut snapshots = Vec::with_capacity(senders.len());
+        for (name, sender) in senders {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            if sender.send(WorkerCommand::Snapshot { reply: reply_tx }).await.is_err() {
+                continue;
+            }
+            if let Ok(snapshot) = reply_rx.await {
+                snapshots.push((name, snapshot));
+            }
+        }
+        snapshots.sort_by(|a, b| a.0.cmp(&b.0));
+        snapshots
+    }
+
+    async fn list_maintenance(&self) -> Response {
+        let warn_after = std::time::Duration::from_secs(self.config.maintenance_warn_secs);
+        let databases = self
+            .worker_snapshots()
+            .await
+            .into_iter()
+            .filter(|(_, s)| matches!(s.state, "preparing" | "closed"))
+            .map(|(db, s)| MaintenanceEntry {
+                db,
+                state: s.state.to_string(),
+                since_secs: s.state_age.as_secs(),
+                overdue: s.state_age >= warn_after,
+            })
+            .collect();
+        Response::ok_list_maintenance(databases)
     }
 
-    async fn remove_worker(&self, db_name: &str) {
+    async fn daemon_info(&self) -> Response {
+        let started_at = time::OffsetDateTime::now_utc().unix_timestamp()
+            - self.started.elapsed().as_secs() as i64;
+        Response::ok_daemon_info(
+            std::process::id(),
+            env!("CARGO_PKG_VERSION").to_string(),
+            started_at,
+            self.started.elapsed().as_secs(),
+            self.base_path.display().to_string(),
+            self.transport.clone(),
+            self.worker_count().await,
+            *self.accept_paused.borrow(),
+        )
+    }
+
+    async fn diagnostics(&self) -> Response {
+        let process = ProcessDiagnostics {
+            pid: std::process::id(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at: time::OffsetDateTime::now_utc().unix_timestamp()
+                - self.started.elapsed().as_secs() as i64,
+            uptime_secs: self.started.elapsed().as_secs(),
+            base_dir: self.base_path.display().to_string(),
+            transport: self.transport.clone(),
+            accept_paused: *self.accept_paused.borrow(),
+            open_cursors: self.cursors.count(),
+        };
+        let config = match serde_json::to_value(&*self.config) {
+            Ok(config) => config,
+            Err(e) => return Response::error(format!("Failed to encode config: {}", e)),
+        };
+        let workers = self
+            .worker_snapshots()
+            .await
+            .into_iter()
+            .map(|(db, s)| WorkerDiagnostics {
+                db,
+                state: s.state.to_string(),
+                state_age_secs: s.state_age.as_secs(),
+                worker_age_secs: s.worker_age.as_secs(),
+                queued: s.queued,
+                requests: s.requests,
+                errors: s.errors,
+                latency: s.latency,
+                circuit: s.circuit.to_string(),
+                recent_errors: s.recent_errors,
+            })
+            .collect();
+        Response::ok_diagnostics(process, config, workers)
+    }
+
+    async fn status(&self) -> Response {
+        let workers = self
+            .worker_snapshots()
+            .await
+            .into_iter()
+            .map(|(db, s)| WorkerStatus {
+                db,
+                state: s.state.to_string(),
+                uptime_secs: s.worker_age.as_secs(),
+                idle_secs: s.idle.as_secs(),
+                idle_timeout_secs: s.idle_timeout_in.map(|d| d.as_secs()),
+                requests: s.requests,
+            })
+            .collect();
+        Response::ok_status(workers)
+    }
+
+    fn set_accept_paused(&self, paused: bool) -> Response {
+        if self.accept_paused.send_replace(paused) != paused {
+            info!(paused, "Accepting new connections {}", if paused { "paused" } else { "resumed" });
+        }
+        Response::ok_accept_paused(paused)
+    }
+
+    fn list_functions(&self) -> Response {
+        let functions = BUILTIN_FUNCTIONS
+            .iter()
+            .map(|f| FunctionInfo {
+                name: f.name.to_string(),
+                n_args: f.n_args,
+                deterministic: f.deterministic,
+                enabled: self.config.functions.iter().any(|name| name == f.name),
+                description: f.description.to_string(),
+            })
+            .collect();
+        Response::ok_list_functions(functions)
+    }
+
+    /// Compare two databases through separate read-only connections; the
+    /// workers keep running, but either being held for maintenance refuses
+    async fn compare(&self, db_a: &str, db_b: &str) -> Response {
+        let mut paths = Vec::with_capacity(2);
+        for db in [db_a, db_b] {
+            match self.file_path(db) {
+                Ok(path) if path.exists() => paths.push(path),
+                Ok(_) => {
+                    let e = RouterError::DbNotFound(db.to_string());
+                    return Response::error_with_code(e.to_string(), e.code());
+                }
+                Err(e) => return Response::error_with_code(e.to_string(), e.code()),
+            }
+        }
+        for db in [db_a, db_b] {
+            if let Err(resp) = self.ensure_open(db).await {
+                return resp;
+            }
+        }
+
+        match crate::compare::compare_databases(&paths[0], &paths[1]).await {
+            Ok(tables) => Response::ok_compare(tables),
+            Err(e) => {
+                error!(db_a = %db_a, db_b = %db_b, error = %e, "Compare failed");
+                Response::error(format!("Failed to compare databases: {}", e))
+            }
+        }
+    }
+
+    /// Run a query on a throwaway copy of the database. Handled here rather
+    /// than by the worker so a long query doesn't hold up its write queue.
+    async fn query_snapshot(
+        &self,
+        db: &str,
+        stmt: Statement,
+        format: QueryFormat,
+        client_rules: Option<&AuthorizerRules>,
+    ) -> Response {
+        let db_path = match self.file_path(db) {
+            Ok(path) if path.exists() => path,
+            Ok(_) => {
+                let e = RouterError::DbNotFound(db.to_string());
+                return Response::error_with_code(e.to_string(), e.code());
+            }
+            Err(e) => return Response::error_with_code(e.to_string(), e.code()),
+        };
+
+        // The copy is thrown away, so a frozen schema doesn't matter here
+        if let Err(e) = validate_statement(&stmt, &self.config, false) {
+            return Response::error_with_code(e.to_string(), e.code());
+        }
+        if let Err(resp) = self.ensure_open(db).await {
+            return resp;
+        }
+
+        let max_bytes = self.config.max_result_bytes;
+        let rules = AuthorizerRules::combined(&self.config.authorizer, client_rules);
+        match crate::snapshot::query_snapshot(&db_path, &stmt, max_bytes, rules).await {
+            Ok((columns, rows)) => crate::query::format_results(columns, &[], rows, format),
+            Err(e) if e.is::<crate::query::ResultTooLarge>() => {
+                Response::error_with_code(e.to_string(), "RESULT_TOO_LARGE")
+            }
+            Err(e) if crate::ffi::denied(&*e) => {
+                Response::error_with_code(format!("{:#}", e), "AUTHORIZER_DENIED")
+            }
+            Err(e) => {
+                debug!(db = %db, error = %e, "Snapshot query failed");
+                Response::error_with_code(format!("{:#}", e), "SQL_ERROR")
+                    .with_sqlite_code(crate::query::extended_code(&*e))
+            }
+        }
+    }
+
+    async fn open_cursor(&self, db: &str, stmt: Statement, client_rules: Option<&AuthorizerRules>) -> Response {
+        let db_path = match self.file_path(db) {
+            Ok(path) if path.exists() => path,
+            Ok(_) => {
+                let e = RouterError::DbNotFound(db.to_string());
+                return Response::error_with_code(e.to_string(), e.code());
+            }
+            Err(e) => return Response::error_with_code(e.to_string(), e.code()),
+        };
+        // The cursor's connection is read-only, so a frozen schema can't be touched
+        if let Err(e) = validate_statement(&stmt, &self.config, false) {
+            return Response::error_with_code(e.to_string(), e.code());
+        }
+        if let Err(resp) = self.ensure_open(db).await {
+            return resp;
+        }
+
+        let idle = Duration::from_secs(self.config.cursor_idle_secs);
+        let rules = AuthorizerRules::combined(&self.config.authorizer, client_rules);
+        match self.cursors.open(db, db_path, stmt, rules, idle).await {
+            Ok((cursor_id, columns)) => {
+                debug!(db = %db, cursor = %cursor_id, "Cursor opened");
+                Response::ok_cursor_opened(cursor_id, columns)
+            }
+            Err(e) => cursor_error_response(e),
+        }
+    }
+
+    /// Refuse unless `db`'s worker has it open, for requests served from
+    /// the file here rather than by the worker. A `Ping` through the worker
+    /// spawns or unparks it as needed, and answers `DATABASE_PREPARING` or
+    /// `DATABASE_CLOSED` while the database is held for maintenance.
+    async fn ensure_open(&self, db: &str) -> Result<(), Response> {
+        let ping = Request::Ping { db: db.to_string() };
+        match self.dispatch(db.to_string(), ping, None, None).await {
+            resp @ Response::Error { .. } => Err(resp),
+            _ => Ok(()),
+        }
+    }
+
+    async fn fetch_cursor(&self, cursor_id: &str, n: u32) -> Response {
+        if n == 0 || n > MAX_FETCH_ROWS {
+            return Response::error_with_code(
+                format!("n must be between 1 and {}", MAX_FETCH_ROWS),
+                "INVALID_FETCH_SIZE",
+            );
+        }
+        match self.cursors.fetch(cursor_id, n).await {
+            Ok((rows, done)) => Response::ok_cursor_rows(rows, done),
+            Err(e) => cursor_error_response(e),
+        }
+    }
+
+    /// Shutdown flag set by `Shutdown`, for the accept loop
+    pub fn shutdown_requested(&self) -> watch::Receiver<bool> {
+        self.shutdown.subscribe()
+    }
+
+    /// Stop every worker and wait for them to exit. Each one answers the
+    /// requests already queued, then checkpoints and closes its database;
+    /// requests arriving later get `SHUTTING_DOWN` instead of a new worker.
+    pub async fn shutdown_workers(&self) {
+        self.shutdown.send_replace(true);
+        let handles: Vec<(String, WorkerHandle)> = self.workers.write().await.drain().collect();
+        info!(workers = handles.len(), "Draining workers");
+        for (_, handle) in &handles {
+            // Fails only if the worker already stopped
+            let _ = handle.sender.send(WorkerCommand::Drain).await;
+        }
+        for (db_name, handle) in handles {
+            if let Err(e) = handle.task.await {
+                error!(db = %db_name, error = %e, "Worker task failed");
+            }
+        }
+    }
+
+    /// Drop the router's handle to worker `id` of `db_name`; a newer worker
+    /// registered under the same name is left alone
+    async fn remove_worker(&self, db_name: &str, id: u64) {
         let mut workers = self.workers.write().await;
-        if workers.remove(db_name).is_some() {
+        if workers.get(db_name).is_some_and(|h| h.id == id) {
+            workers.remove(db_name);
             info!(db = %db_name, "Worker removed from router");
         }
     }
 
+    /// Apply a batch to every database whose name matches `pattern`: files
+    /// on disk under `base_path` plus any live worker. Each database runs
+    /// the batch through its own worker and transaction, so results are
+    /// independent: some databases may commit while others fail.
+    async fn exec_all(
+        &self,
+        pattern: &str,
+        stmts: Vec<Statement>,
+        tx: TransactionMode,
+        rules: Option<Arc<AuthorizerRules>>,
+    ) -> Response {
+        let base_path = self.base_path.clone();
+        let on_disk = tokio::task::spawn_blocking(move || {
+            let mut names = Vec::new();
+            collect_db_names(&base_path, &base_path, &mut names).map(|_| names)
+        })
+        .await;
+
+        let mut names: BTreeSet<String> = match on_disk {
+            Ok(Ok(names)) => names.into_iter().collect(),
+            Ok(Err(e)) => return Response::error(format!("Failed to scan database directory: {}", e)),
+            Err(e) => return Response::error(format!("Database scan task failed: {}", e)),
+        };
+        names.extend(self.workers.read().await.keys().cloned());
+        names.retain(|name| glob_match(pattern, name));
+
+        info!(pattern = %pattern, matched = names.len(), "Executing batch on all matching databases");
+
+        let mut results = BTreeMap::new();
+        for db in names {
+            let req = Request::exec_batch(db.clone(), stmts.clone(), tx);
+            let resp = self.dispatch(db.clone(), req, rules.clone(), None).await;
+            results.insert(db, resp);
+        }
+
+        Response::ok_exec_all(results)
+    }
+
+    /// Find (and optionally remove) `-wal`/`-shm` files under `base_path`
+    /// whose main database file no longer exists. Sidecars belonging to a
+    /// live worker are never touched, even if the main file is missing
+    /// (e.g. mid file-replacement).
+    async fn cleanup_sidecars(&self, remove: bool) -> Response {
+        let live: Vec<PathBuf> = self
+            .workers
+            .read()
+            .await
+            .keys()
+            .map(|name| self.base_path.join(name))
+            .collect();
+        let base_path = self.base_path.clone();
+
+        let result = tokio::task::spawn_blocking(move || {
+            let mut sidecars = Vec::new();
+            collect_sidecars(&base_path, &mut sidecars)?;
+
+            let mut orphans = Vec::new();
+            let mut removed = Vec::new();
+            for (sidecar, main) in sidecars {
+                if main.exists() || live.contains(&main) {
+                    continue;
+                }
+                let display = sidecar
+                    .strip_prefix(&base_path)
+                    .unwrap_or(&sidecar)
+                    .display()
+                    .to_string();
+                if remove {
+                    match std::fs::remove_file(&sidecar) {
+                        Ok(()) => removed.push(display.clone()),
+                        Err(e) => error!(file = %sidecar.display(), error = %e, "Failed to remove sidecar"),
+                    }
+                }
+                orphans.push(display);
+            }
+            Ok::<_, std::io::Error>((orphans, removed))
+        })
+        .await;
+
+        match result {
+            Ok(Ok((orphans, removed))) => {
+                info!(orphans = orphans.len(), removed = removed.len(), "Sidecar cleanup finished");
+                Response::ok_cleanup_sidecars(orphans, removed)
+            }
+            Ok(Err(e)) => Response::error(format!("Failed to scan database directory: {}", e)),
+            Err(e) => Response::error(format!("Sidecar cleanup task failed: {}", e)),
+        }
+    }
+
     fn extract_db_name(req: &Request) -> Option<String> {
         match req {
             Request::Ping { db } => Some(db.clone()),
+            Request::UseDatabase { .. } => None,
             Request::ExecBatch { db, .. } => Some(db.clone()),
-            Request::PrepareForMaintenance { db } => Some(db.clone()),
+            Request::Query { db, .. } => Some(db.clone()),
+            Request::MultiQueryScalar { db, .. } => Some(db.clone()),
+            Request::Explain { db, .. } => Some(db.clone()),
+            Request::DescribeStatement { db, .. } => Some(db.clone()),
+            Request::ChangeFeed { db, .. } => Some(db.clone()),
+            Request::RevHistory { db, .. } => Some(db.clone()),
+            Request::Increment { db, .. } => Some(db.clone()),
+            Request::ApplyChangeset { db, .. } => Some(db.clone()),
+            Request::Benchmark { db, .. } => Some(db.clone()),
+            Request::Stats { db } => Some(db.clone()),
+            Request::PragmaStats { db } => Some(db.clone()),
+            Request::CheckpointHistory { db } => Some(db.clone()),
+            Request::ClearStatementCache { db } => Some(db.clone()),
+            Request::SetSynchronous { db, .. } => Some(db.clone()),
+            Request::ExecMany { db, .. } => Some(db.clone()),
+            Request::Begin { db } => Some(db.clone()),
+            Request::ExecInTx { db, .. } => Some(db.clone()),
+            Request::Commit { db, .. } => Some(db.clone()),
+            Request::Rollback { db, .. } => Some(db.clone()),
+            Request::FreezeSchema { db } => Some(db.clone()),
+            Request::ThawSchema { db } => Some(db.clone()),
+            Request::PrepareForMaintenance { db, .. } => Some(db.clone()),
             Request::CloseDatabase { db } => Some(db.clone()),
-            Request::ReopenDatabase { db } => Some(db.clone()),
+            Request::ReopenDatabase { db, .. } => Some(db.clone()),
+            Request::ExportSchema { db } => Some(db.clone()),
+            Request::PruneMeta { db, .. } => Some(db.clone()),
+            Request::Vacuum { db } => Some(db.clone()),
+            Request::Analyze { db } => Some(db.clone()),
+            Request::GetSchemaVersion { db } => Some(db.clone()),
+            Request::SetSchemaVersion { db, .. } => Some(db.clone()),
+            Request::Migrate { db, .. } => Some(db.clone()),
+            Request::VacuumInto { db, .. } => Some(db.clone()),
+            Request::Backup { db, .. } => Some(db.clone()),
+            Request::CleanupSidecars { .. } => None,
+            Request::ExecAll { .. } => None,
+            Request::ResolvePath { .. } => None,
+            Request::ValidateSql { .. } => None,
+            Request::Compare { .. } => None,
+            Request::QuerySnapshot { .. } => None,
+            Request::ListFunctions => None,
+            Request::DaemonInfo => None,
+            Request::Diagnostics => None,
+            Request::Status => None,
+            Request::PauseAccept => None,
+            Request::ResumeAccept => None,
+            Request::OpenCursor { .. } => None,
+            Request::FetchCursor { .. } => None,
+            Request::CloseCursor { .. } => None,
+            Request::ListMaintenance => None,
             Request::Shutdown => None,
         }
     }
 
-    #[allow(dead_code)]
+    /// Number of databases with a live worker
     pub async fn worker_count(&self) -> usize {
         self.workers.read().await.len()
     }
 }
+
+/// Text of a panic payload (`panic!` passes a `&str` or a `String`)
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("(non-string payload)")
+}
+
+/// Requests that don't write, so running one twice is harmless. `Query`
+/// counts because the worker refuses statements in it that would write
+/// (`QUERY_NOT_READ_ONLY`).
+fn is_read_only(req: &Request) -> bool {
+    matches!(
+        req,
+        Request::Ping { .. }
+            | Request::Query { .. }
+            | Request::MultiQueryScalar { .. }
+            | Request::DescribeStatement { .. }
+            | Request::Explain { .. }
+            | Request::ChangeFeed { .. }
+            | Request::RevHistory { .. }
+            | Request::PragmaStats { .. }
+            | Request::Stats { .. }
+            | Request::CheckpointHistory { .. }
+            | Request::ExportSchema { .. }
+            | Request::GetSchemaVersion { .. }
+    )
+}
+
+fn cursor_error_response(e: anyhow::Error) -> Response {
+    if let Some(e) = e.downcast_ref::<CursorError>() {
+        return Response::error_with_code(e.to_string(), e.code());
+    }
+    if crate::ffi::denied(&*e) {
+        return Response::error_with_code(format!("{:#}", e), "AUTHORIZER_DENIED");
+    }
+    Response::error_with_code(format!("{:#}", e), "SQL_ERROR")
+        .with_sqlite_code(crate::query::extended_code(&*e))
+}
+
+/// Compile `sql` on a throwaway in-memory database. Schema lookups fail
+/// there ("no such table"), so those count as valid syntax and the
+/// parameters come from the lexer instead.
+async fn validate_sql(sql: &str) -> Response {
+    let check = async {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await?;
+        let check = crate::ffi::try_prepare(&mut conn, sql).await?;
+        conn.close().await?;
+        Ok::<_, anyhow::Error>(check)
+    };
+
+    let check = match check.await {
+        Ok(check) => check,
+        Err(e) => return Response::error(format!("Failed to validate SQL: {}", e)),
+    };
+
+    match check.error {
+        Some((message, _)) if is_schema_error(&message) => {
+            let mut params: Vec<String> = Vec::new();
+            for token in crate::sql::tokenize(sql) {
+                if let crate::sql::Token::Param(name) = token {
+                    if name == "?" || !params.contains(&name) {
+                        params.push(name);
+                    }
+                }
+            }
+            Response::ok_validate_sql(None, params)
+        }
+        error => Response::ok_validate_sql(error, check.params),
+    }
+}
+
+/// Errors caused by objects missing from the empty validation database
+fn is_schema_error(message: &str) -> bool {
+    ["no such table", "no such column", "no such function", "no such index", "no such collation"]
+        .iter()
+        .any(|prefix| message.starts_with(prefix))
+}
+
+/// Recursively collect `(sidecar, main_db)` path pairs for `-wal`/`-shm` files
+fn collect_sidecars(dir: &Path, out: &mut Vec<(PathBuf, PathBuf)>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_sidecars(&path, out)?;
+            continue;
+        }
+        let name = path.to_string_lossy();
+        if let Some(main) = name.strip_suffix("-wal").or_else(|| name.strip_suffix("-shm")) {
+            out.push((path.clone(), PathBuf::from(main)));
+        }
+    }
+    Ok(())
+}
+
+/// Recursively collect SQLite database files as names relative to `base`
+fn collect_db_names(base: &Path, dir: &Path, out: &mut Vec<String>) -> std::io::Result<()> {
+    use std::io::Read;
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_db_names(base, &path, out)?;
+            continue;
+        }
+
+        // Only files with the SQLite header (skips sidecars and other files)
+        let mut header = [0u8; 16];
+        let is_db = std::fs::File::open(&path)
+            .and_then(|mut f| f.read_exact(&mut header))
+            .map(|_| &header == b"SQLite format 3\0")
+            .unwrap_or(false);
+        if !is_db {
+            continue;
+        }
+
+        if let Ok(relative) = path.strip_prefix(base) {
+            let name: Vec<String> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            out.push(name.join("/"));
+        }
+    }
+    Ok(())
+}
+
+/// Match `name` against a glob where `*` matches any run of characters
+/// and `?` matches exactly one
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = backtrack {
+            p = star_p + 1;
+            n = star_n + 1;
+            backtrack = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.db", "tenant1.db"));
+        assert!(glob_match("tenant?.db", "tenant7.db"));
+        assert!(glob_match("tenants/*", "tenants/a.db"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("*.db", "tenant1.sqlite"));
+        assert!(!glob_match("tenant?.db", "tenant12.db"));
+    }
+
+    #[tokio::test]
+    async fn test_reopen_from_path_must_be_inside_import_dir() {
+        let dir = std::env::temp_dir().join(format!("skylinedb-import-{}", std::process::id()));
+        let import_dir = dir.join("import");
+        std::fs::create_dir_all(&import_dir).unwrap();
+        let config = DaemonConfig { import_dir: Some(import_dir.clone()), ..DaemonConfig::default() };
+        let router = Router::new(dir.clone(), config);
+        let db = "a.db".to_string();
+        let resp = router.route_request(Request::Ping { db: db.clone() }).await;
+        assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+        std::fs::copy(dir.join(&db), dir.join("outside.db")).unwrap();
+        std::fs::copy(dir.join(&db), import_dir.join("new.db")).unwrap();
+        let close = router.route_request(Request::CloseDatabase { db: db.clone() }).await;
+        assert!(matches!(close, Response::Ok { .. }), "{:?}", close);
+
+        let reopen = |from_path: String| Request::ReopenDatabase { db: db.clone(), from_path: Some(from_path) };
+        let outside = dir.join("outside.db").display().to_string();
+        for from_path in [outside, "../outside.db".to_string(), "missing.db".to_string()] {
+            let resp = router.route_request(reopen(from_path)).await;
+            assert!(matches!(&resp, Response::Error { code: Some(code), .. } if code == "INVALID_FROM_PATH"), "{:?}", resp);
+        }
+        assert!(dir.join("outside.db").exists());
+        let resp = router.route_request(reopen("new.db".to_string())).await;
+        assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+        assert!(!import_dir.join("new.db").exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_db_path_sandbox() {
+        let router = Router::new(PathBuf::from("dbs"), DaemonConfig::default());
+        assert_eq!(router.db_path("a.db").unwrap(), Path::new("dbs").join("a.db"));
+        assert!(router.db_path("tenants/a.db").is_ok());
+        assert!(router.db_path("").is_err());
+        assert!(router.db_path("../a.db").is_err());
+        assert!(router.db_path("tenants/../../a.db").is_err());
+        assert!(router.db_path("./a.db").is_err());
+        assert!(router.db_path("/etc/passwd").is_err());
+        assert_eq!(router.db_path(MEMORY_DB).unwrap(), Path::new(MEMORY_DB));
+        assert!(matches!(router.file_path(MEMORY_DB), Err(RouterError::InMemory)));
+    }
+
+    #[test]
+    fn test_spawn_limiter_burst() {
+        let mut limiter = SpawnLimiter::new(3);
+        assert!((0..3).all(|_| limiter.try_acquire()));
+        assert!(!limiter.try_acquire());
+
+        let mut unlimited = SpawnLimiter::new(0);
+        assert!((0..1000).all(|_| unlimited.try_acquire()));
+    }
+
+    #[tokio::test]
+    async fn test_foreign_keys_enforced() {
+        let exec = |sqls: &[&str]| -> Request {
+            let stmts: Vec<_> = sqls.iter().map(|sql| serde_json::json!({ "sql": sql })).collect();
+            serde_json::from_value(serde_json::json!({
+                "type": "ExecBatch",
+                "db": MEMORY_DB,
+                "stmts": stmts,
+            }))
+            .unwrap()
+        };
+        let orphan = "INSERT INTO child (parent_id) VALUES (42)";
+
+        for foreign_keys in [true, false] {
+            let config = DaemonConfig { foreign_keys, ..DaemonConfig::default() };
+            let router = Router::new(PathBuf::from("dbs"), config);
+            let resp = router
+                .route_request(exec(&[
+                    "CREATE TABLE parent (id INTEGER PRIMARY KEY)",
+                    "CREATE TABLE child (parent_id INTEGER REFERENCES parent(id))",
+                ]))
+                .await;
+            assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+
+            let resp = router.route_request(exec(&[orphan])).await;
+            if foreign_keys {
+                // SQLITE_CONSTRAINT_FOREIGNKEY
+                assert!(
+                    matches!(resp, Response::Error { sqlite_extended_code: Some(787), .. }),
+                    "{:?}",
+                    resp
+                );
+            } else {
+                assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+            }
+            router.shutdown_workers().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timeout_interrupts_and_rolls_back() {
+        let router = Router::new(PathBuf::from("dbs"), DaemonConfig::default());
+        let request = |value: serde_json::Value| -> Request { serde_json::from_value(value).unwrap() };
+        let endless = "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c)";
+
+        let resp = router
+            .route_request(request(serde_json::json!({
+                "type": "ExecBatch",
+                "db": MEMORY_DB,
+                "stmts": [{ "sql": "CREATE TABLE t (x INTEGER)" }],
+            })))
+            .await;
+        assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+
+        let resp = router
+            .route_request(request(serde_json::json!({
+                "type": "ExecBatch",
+                "db": MEMORY_DB,
+                "stmts": [
+                    { "sql": "INSERT INTO t VALUES (1)" },
+                    { "sql": format!("{} INSERT INTO t SELECT x FROM c", endless) },
+                ],
+                "timeout_ms": 50,
+            })))
+            .await;
+        assert!(matches!(&resp, Response::Error { code: Some(code), .. } if code == "TIMEOUT"), "{:?}", resp);
+
+        let resp = router
+            .route_request(request(serde_json::json!({
+                "type": "Query",
+                "db": MEMORY_DB,
+                "sql": format!("{} SELECT count(*) FROM c", endless),
+                "timeout_ms": 50,
+            })))
+            .await;
+        assert!(matches!(&resp, Response::Error { code: Some(code), .. } if code == "TIMEOUT"), "{:?}", resp);
+
+        // Rolled back, and the connection no longer carries the deadline
+        let resp = router
+            .route_request(request(serde_json::json!({
+                "type": "Query",
+                "db": MEMORY_DB,
+                "sql": "SELECT count(*) FROM t",
+            })))
+            .await;
+        let Response::Ok { data: crate::protocol::ResponseData::Query { rows, .. } } = resp else {
+            panic!("{:?}", resp);
+        };
+        assert_eq!(rows, [[serde_json::json!(0)]]);
+        router.shutdown_workers().await;
+    }
+
+    #[tokio::test]
+    async fn test_migrate_applies_newer_and_stops_at_failure() {
+        use crate::protocol::ResponseData;
+        let router = Router::new(PathBuf::from("dbs"), DaemonConfig::default());
+        let migrate = |migrations: serde_json::Value| -> Request {
+            serde_json::from_value(serde_json::json!({
+                "type": "Migrate",
+                "db": MEMORY_DB,
+                "migrations": migrations,
+            }))
+            .unwrap()
+        };
+
+        let resp = router
+            .route_request(migrate(serde_json::json!([
+                { "version": 2, "sql": "ALTER TABLE a ADD COLUMN y INTEGER" },
+                { "version": 1, "sql": "CREATE TABLE a (x INTEGER); INSERT INTO a (x) VALUES (1)" },
+            ])))
+            .await;
+        assert!(
+            matches!(resp, Response::Ok { data: ResponseData::Migrate { migrations_applied: 2, user_version: 2, .. } }),
+            "{:?}",
+            resp
+        );
+
+        // 1 and 2 are skipped; 3 commits, 4 fails and rolls back alone
+        let resp = router
+            .route_request(migrate(serde_json::json!([
+                { "version": 1, "sql": "CREATE TABLE a (x INTEGER)" },
+                { "version": 3, "sql": "CREATE TABLE b (x INTEGER)" },
+                { "version": 4, "sql": "CREATE TABLE c (x INTEGER); INSERT INTO missing VALUES (1)" },
+            ])))
+            .await;
+        assert!(matches!(&resp, Response::Error { code: Some(code), .. } if code == "MIGRATION_FAILED"), "{:?}", resp);
+
+        let resp = router.route_request(Request::GetSchemaVersion { db: MEMORY_DB.to_string() }).await;
+        assert!(
+            matches!(resp, Response::Ok { data: ResponseData::SchemaVersion { user_version: 3 } }),
+            "{:?}",
+            resp
+        );
+        let resp = router
+            .route_request(migrate(serde_json::json!([
+                { "version": 5, "sql": "SELECT * FROM c" },
+            ])))
+            .await;
+        assert!(matches!(&resp, Response::Error { code: Some(code), .. } if code == "MIGRATION_FAILED"), "{:?}", resp);
+        router.shutdown_workers().await;
+    }
+
+    #[tokio::test]
+    async fn test_changeset_needs_an_atomic_batch_and_the_session_feature() {
+        use crate::protocol::TransactionMode;
+        let router = Router::new(PathBuf::from("dbs"), DaemonConfig::default());
+        let batch = |tx: TransactionMode| {
+            let mut req = Request::exec_batch(MEMORY_DB, vec![Statement::new("SELECT 1", Vec::new())], tx);
+            if let Request::ExecBatch { changeset, .. } = &mut req {
+                *changeset = true;
+            }
+            req
+        };
+        let expected = match cfg!(feature = "session") {
+            true => "CHANGESET_NEEDS_ATOMIC",
+            false => "FEATURE_UNSUPPORTED",
+        };
+        let resp = router.route_request(batch(TransactionMode::None)).await;
+        assert!(matches!(&resp, Response::Error { code: Some(code), .. } if code == expected), "{:?}", resp);
+        if !cfg!(feature = "session") {
+            let resp = router.route_request(batch(TransactionMode::Atomic)).await;
+            assert!(matches!(&resp, Response::Error { code: Some(code), .. } if code == expected), "{:?}", resp);
+        }
+        router.shutdown_workers().await;
+    }
+
+    #[tokio::test]
+    async fn test_client_authorizer_rules_apply_to_their_requests_only() {
+        use crate::protocol::TransactionMode;
+        let router = Router::new(PathBuf::from("dbs"), DaemonConfig::default());
+        let mut client = AuthorizerRules::default();
+        client.denied_actions.insert(crate::ffi::action_code("DELETE").unwrap());
+        let client = Some(Arc::new(client));
+        let batch = |sql: &str| {
+            Request::exec_batch(MEMORY_DB, vec![Statement::new(sql, Vec::new())], TransactionMode::Atomic)
+        };
+        let is_ok = |resp: &Response| matches!(resp, Response::Ok { .. });
+
+        let resp = router.route_request(batch("CREATE TABLE t (x INTEGER)")).await;
+        assert!(is_ok(&resp), "{:?}", resp);
+        // Cached from an unrestricted run, the statement is still checked
+        let resp = router.route_request(batch("DELETE FROM t")).await;
+        assert!(is_ok(&resp), "{:?}", resp);
+        let resp = router.route_client_request(batch("DELETE FROM t"), client.clone(), None).await;
+        assert!(matches!(&resp, Response::Error { code: Some(code), .. } if code == "AUTHORIZER_DENIED"), "{:?}", resp);
+        let resp = router.route_client_request(batch("INSERT INTO t (x) VALUES (1)"), client, None).await;
+        assert!(is_ok(&resp), "{:?}", resp);
+        let resp = router.route_request(batch("DELETE FROM t")).await;
+        assert!(is_ok(&resp), "{:?}", resp);
+        router.shutdown_workers().await;
+    }
+
+    #[tokio::test]
+    async fn test_read_is_resent_when_worker_dies_but_write_is_not() {
+        use crate::protocol::{ResponseData, TransactionMode};
+        let router = Router::new(PathBuf::from("dbs"), DaemonConfig::default());
+        let create = Statement::new("CREATE TABLE t (x INTEGER)", Vec::new());
+        let resp = router
+            .route_request(Request::exec_batch(MEMORY_DB, vec![create], TransactionMode::Atomic))
+            .await;
+        assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+
+        // The worker panics on the query; a fresh one answers it
+        let (first_id, worker) = router.get_or_create_worker(MEMORY_DB).await.unwrap();
+        worker.send(WorkerCommand::Panic).await.unwrap();
+        let query = Request::query(MEMORY_DB, "SELECT 1", Vec::new(), Default::default());
+        let resp = router.route_request(query).await;
+        let Response::Ok { data: ResponseData::Query { rows, .. } } = resp else {
+            panic!("{:?}", resp);
+        };
+        assert_eq!(rows, [[serde_json::json!(1)]]);
+        let (second_id, worker) = router.get_or_create_worker(MEMORY_DB).await.unwrap();
+        assert_ne!(second_id, first_id);
+
+        // A resent query can't write twice: writing in one is refused
+        worker.send(WorkerCommand::Panic).await.unwrap();
+        let query = Request::query(MEMORY_DB, "CREATE TABLE u (x INTEGER)", Vec::new(), Default::default());
+        let resp = router.route_request(query).await;
+        assert!(matches!(&resp, Response::Error { code: Some(code), .. } if code == "QUERY_NOT_READ_ONLY"), "{:?}", resp);
+        let (_, worker) = router.get_or_create_worker(MEMORY_DB).await.unwrap();
+
+        // A write may have committed before the panic, so it isn't resent
+        worker.send(WorkerCommand::Panic).await.unwrap();
+        let insert = Statement::new("INSERT INTO t (x) VALUES (1)", Vec::new());
+        let resp = router
+            .route_request(Request::exec_batch(MEMORY_DB, vec![insert], TransactionMode::Atomic))
+            .await;
+        assert!(matches!(&resp, Response::Error { code: Some(code), .. } if code == "WORKER_DIED"), "{:?}", resp);
+        router.shutdown_workers().await;
+    }
+
+    #[tokio::test]
+    async fn test_legacy_daemon_tables_are_renamed_and_not_exported() {
+        use crate::protocol::ResponseData;
+        use sqlx::{Connection, SqliteConnection};
+        let dir = std::env::temp_dir().join(format!("skylinedb-legacy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let options = sqlx::sqlite::SqliteConnectOptions::new()
+            .filename(dir.join("old.db"))
+            .create_if_missing(true);
+        let mut conn = SqliteConnection::connect_with(&options).await.unwrap();
+        for sql in [
+            "CREATE TABLE meta (rev INTEGER NOT NULL PRIMARY KEY, ts INTEGER NOT NULL)",
+            "INSERT INTO meta (rev, ts) VALUES (5, 0)",
+            // A user table that only shares the name
+            "CREATE TABLE counters (id INTEGER PRIMARY KEY, label TEXT)",
+        ] {
+            sqlx::query(sql).execute(&mut conn).await.unwrap();
+        }
+        conn.close().await.unwrap();
+        let router = Router::new(dir.clone(), DaemonConfig::default());
+
+        let query = Request::query("old.db", "SELECT rev FROM _daemon_meta", Vec::new(), Default::default());
+        let resp = router.route_request(query).await;
+        let Response::Ok { data: ResponseData::Query { rows, .. } } = resp else {
+            panic!("{:?}", resp);
+        };
+        assert_eq!(rows, [[serde_json::json!(5)]]);
+        let resp = router.route_request(Request::ExportSchema { db: "old.db".to_string() }).await;
+        let Response::Ok { data: ResponseData::ExportSchema { ddl } } = resp else {
+            panic!("{:?}", resp);
+        };
+        assert_eq!(ddl, "CREATE TABLE counters (id INTEGER PRIMARY KEY, label TEXT);\n");
+
+        router.shutdown_workers().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_max_workers_evicts_least_recently_used() {
+        let dir = std::env::temp_dir().join(format!("skylinedb-evict-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = DaemonConfig { max_workers: 2, ..DaemonConfig::default() };
+        let router = Router::new(dir.clone(), config);
+
+        for db in ["a.db", "b.db", "c.db", "d.db"] {
+            let resp = router.route_request(Request::Ping { db: db.to_string() }).await;
+            assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+            assert!(router.worker_count().await <= 2);
+        }
+        // The two oldest made room for c.db and d.db
+        let mut open: Vec<String> = router.workers.read().await.keys().cloned().collect();
+        open.sort();
+        assert_eq!(open, ["c.db", "d.db"]);
+
+        // An evicted database reopens on demand
+        let resp = router.route_request(Request::Ping { db: "a.db".to_string() }).await;
+        assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+        assert_eq!(router.worker_count().await, 2);
+
+        // Recency counts requests, not spawn order: d.db was used last
+        for db in ["d.db", "b.db"] {
+            let resp = router.route_request(Request::Ping { db: db.to_string() }).await;
+            assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+        }
+        let mut open: Vec<String> = router.workers.read().await.keys().cloned().collect();
+        open.sort();
+        assert_eq!(open, ["b.db", "d.db"]);
+
+        router.shutdown_workers().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_max_workers_holds_under_concurrent_spawns() {
+        let dir = std::env::temp_dir().join(format!("skylinedb-evict-race-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = DaemonConfig { max_workers: 2, ..DaemonConfig::default() };
+        let router = Arc::new(Router::new(dir.clone(), config));
+
+        let pings = (0..8).map(|i| {
+            let router = Arc::clone(&router);
+            tokio::spawn(async move { router.route_request(Request::Ping { db: format!("{}.db", i) }).await })
+        });
+        for ping in pings.collect::<Vec<_>>() {
+            let resp = ping.await.unwrap();
+            // Opening workers aren't evictable yet, so some spawns are refused
+            assert!(
+                matches!(&resp, Response::Ok { .. })
+                    || matches!(&resp, Response::Error { code: Some(code), .. } if code == "TOO_MANY_WORKERS"),
+                "{:?}",
+                resp
+            );
+            assert!(router.worker_count().await <= 2);
+        }
+
+        router.shutdown_workers().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_zero_idle_timeout_keeps_worker_open() {
+        use crate::protocol::ResponseData;
+        let dir = std::env::temp_dir().join(format!("skylinedb-keepwarm-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = DaemonConfig {
+            max_workers: 2,
+            db_idle_timeouts: [("a.db".to_string(), 0)].into(),
+            ..DaemonConfig::default()
+        };
+        let router = Router::new(dir.clone(), config);
+
+        for db in ["a.db", "b.db", "c.db"] {
+            let resp = router.route_request(Request::Ping { db: db.to_string() }).await;
+            assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+        }
+        // a.db has no idle countdown, so b.db made room for c.db
+        let mut open: Vec<String> = router.workers.read().await.keys().cloned().collect();
+        open.sort();
+        assert_eq!(open, ["a.db", "c.db"]);
+
+        let resp = router.route_request(Request::Status).await;
+        let Response::Ok { data: ResponseData::Status { workers } } = resp else {
+            panic!("unexpected response: {:?}", resp);
+        };
+        let timeout_of = |db: &str| workers.iter().find(|w| w.db == db).unwrap().idle_timeout_secs;
+        assert_eq!(timeout_of("a.db"), None);
+        assert!(timeout_of("c.db").is_some_and(|secs| secs <= 300));
+
+        router.shutdown_workers().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_replace_counts_replaced_rows_only_on_request() {
+        use crate::protocol::ResponseData;
+        let dir = std::env::temp_dir().join(format!("skylinedb-replace-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let router = Router::new(dir.clone(), DaemonConfig::default());
+        let batch = |sql: &str, count_replaced: bool| -> Request {
+            serde_json::from_value(serde_json::json!({
+                "type": "ExecBatch",
+                "db": "a.db",
+                "stmts": [{ "sql": sql }],
+                "tx": "none",
+                "count_replaced": count_replaced,
+            }))
+            .unwrap()
+        };
+        let resp = router.route_request(batch("CREATE TABLE t (id INTEGER PRIMARY KEY)", false)).await;
+        assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+        let resp = router.route_request(batch("INSERT INTO t (id) VALUES (1)", false)).await;
+        assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+        // Without the opt-in the split is unknown: both rows count as written
+        for (count_replaced, expected) in [(false, (2, None)), (true, (1, Some(1)))] {
+            let sql = "INSERT OR REPLACE INTO t (id) VALUES (1), (2)";
+            if count_replaced {
+                let resp = router.route_request(batch("DELETE FROM t WHERE id = 2", false)).await;
+                assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+            }
+            let resp = router.route_request(batch(sql, count_replaced)).await;
+            let Response::Ok { data: ResponseData::ExecBatch { conflicts, .. } } = resp else {
+                panic!("{:?}", resp);
+            };
+            assert_eq!((conflicts[0].inserted, conflicts[0].replaced), expected);
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_authorizer_checks_statements_as_they_run() {
+        use crate::protocol::ResponseData;
+        let dir = std::env::temp_dir().join(format!("skylinedb-authorizer-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = DaemonConfig::default();
+        config.authorizer.denied_actions.insert(crate::ffi::action_code("DELETE").unwrap());
+        let router = Router::new(dir.clone(), config);
+        let batch = |sqls: &[&str]| -> Request {
+            let stmts: Vec<_> = sqls.iter().map(|sql| serde_json::json!({ "sql": sql })).collect();
+            serde_json::from_value(serde_json::json!({
+                "type": "ExecBatch",
+                "db": "a.db",
+                "stmts": stmts,
+                "tx": "atomic",
+            }))
+            .unwrap()
+        };
+
+        // The DELETE can't compile before the CREATE has run, yet is denied
+        let resp = router
+            .route_request(batch(&[
+                "CREATE TABLE t (x INTEGER)",
+                "INSERT INTO t (x) VALUES (1)",
+                "DELETE FROM t",
+            ]))
+            .await;
+        assert!(matches!(&resp, Response::Error { code: Some(code), .. } if code == "AUTHORIZER_DENIED"), "{:?}", resp);
+
+        // Rolled back, and daemon bookkeeping still runs unrestricted
+        let resp = router
+            .route_request(batch(&["CREATE TABLE t (x INTEGER)", "INSERT INTO t (x) VALUES (1)"]))
+            .await;
+        assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+        let query = serde_json::json!({ "type": "Query", "db": "a.db", "sql": "SELECT count(*) FROM t" });
+        let resp = router.route_request(serde_json::from_value(query).unwrap()).await;
+        let Response::Ok { data: ResponseData::Query { rows, .. } } = resp else {
+            panic!("{:?}", resp);
+        };
+        assert_eq!(rows, [[serde_json::json!(1)]]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_query_refuses_writes_on_both_connections() {
+        use crate::protocol::{QueryFormat, ResponseData, TransactionMode};
+        let dir = std::env::temp_dir().join(format!("skylinedb-query-ro-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let router = Router::new(dir.clone(), DaemonConfig::default());
+        let create = vec![Statement::new("CREATE TABLE t (x INTEGER)", Vec::new())];
+        let resp = router.route_request(Request::exec_batch("a.db", create, TransactionMode::Atomic)).await;
+        assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+        let query = |sql: &str, cache_size: Option<u64>| {
+            let mut req = Request::query("a.db", sql, Vec::new(), QueryFormat::Json);
+            if let Request::Query { cache_size: size, .. } = &mut req {
+                *size = cache_size;
+            }
+            req
+        };
+
+        // The read pool, then the write connection (a cache_size override)
+        for cache_size in [None, Some(4096)] {
+            for sql in ["INSERT INTO t (x) VALUES (1)", "SELECT 1; DELETE FROM t"] {
+                let resp = router.route_request(query(sql, cache_size)).await;
+                assert!(matches!(&resp, Response::Error { code: Some(code), .. } if code == "QUERY_NOT_READ_ONLY"), "{:?}", resp);
+            }
+            let resp = router.route_request(query("SELECT count(*) FROM t", cache_size)).await;
+            let Response::Ok { data: ResponseData::Query { rows, .. } } = resp else {
+                panic!("{:?}", resp);
+            };
+            assert_eq!(rows, [[serde_json::json!(0)]]);
+        }
+        router.shutdown_workers().await;
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_closes_and_refuses_cursors() {
+        use crate::protocol::{ResponseData, TransactionMode};
+        let dir = std::env::temp_dir().join(format!("skylinedb-cursor-maint-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let router = Router::new(dir.clone(), DaemonConfig::default());
+        let create = vec![Statement::new("CREATE TABLE t (x INTEGER)", Vec::new())];
+        let resp = router.route_request(Request::exec_batch("a.db", create, TransactionMode::Atomic)).await;
+        assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+        let open = || -> Request {
+            serde_json::from_value(serde_json::json!({ "type": "OpenCursor", "db": "a.db", "sql": "SELECT x FROM t" }))
+                .unwrap()
+        };
+
+        let resp = router.route_request(open()).await;
+        let Response::Ok { data: ResponseData::CursorOpened { cursor_id, .. } } = resp else {
+            panic!("{:?}", resp);
+        };
+        let prepare = Request::PrepareForMaintenance { db: "a.db".to_string(), exclusive: false };
+        let resp = router.route_request(prepare).await;
+        assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+
+        let fetch = Request::FetchCursor { cursor_id, n: 10 };
+        let resp = router.route_request(fetch).await;
+        assert!(matches!(&resp, Response::Error { code: Some(code), .. } if code == "CURSOR_NOT_FOUND"), "{:?}", resp);
+        let resp = router.route_request(open()).await;
+        assert!(matches!(&resp, Response::Error { code: Some(code), .. } if code == "DATABASE_PREPARING"), "{:?}", resp);
+        router.shutdown_workers().await;
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_and_compare_refused_during_maintenance() {
+        use crate::protocol::TransactionMode;
+        let dir = std::env::temp_dir().join(format!("skylinedb-snapshot-maint-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let router = Router::new(dir.clone(), DaemonConfig::default());
+        for db in ["a.db", "b.db"] {
+            let create = vec![Statement::new("CREATE TABLE t (x INTEGER)", Vec::new())];
+            let resp = router.route_request(Request::exec_batch(db, create, TransactionMode::Atomic)).await;
+            assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+        }
+        let snapshot = || -> Request {
+            serde_json::from_value(serde_json::json!({ "type": "QuerySnapshot", "db": "a.db", "sql": "SELECT x FROM t" }))
+                .unwrap()
+        };
+        let compare = || -> Request {
+            serde_json::from_value(serde_json::json!({ "type": "Compare", "db_a": "a.db", "db_b": "b.db" })).unwrap()
+        };
+        let refused = |resp: &Response, expected: &str| {
+            matches!(resp, Response::Error { code: Some(code), .. } if code == expected)
+        };
+
+        let prepare = Request::PrepareForMaintenance { db: "a.db".to_string(), exclusive: false };
+        assert!(matches!(router.route_request(prepare).await, Response::Ok { .. }));
+        let resp = router.route_request(snapshot()).await;
+        assert!(refused(&resp, "DATABASE_PREPARING"), "{:?}", resp);
+        let resp = router.route_request(compare()).await;
+        assert!(refused(&resp, "DATABASE_PREPARING"), "{:?}", resp);
+
+        let close = Request::CloseDatabase { db: "a.db".to_string() };
+        assert!(matches!(router.route_request(close).await, Response::Ok { .. }));
+        let resp = router.route_request(snapshot()).await;
+        assert!(refused(&resp, "DATABASE_CLOSED"), "{:?}", resp);
+        let resp = router.route_request(compare()).await;
+        assert!(refused(&resp, "DATABASE_CLOSED"), "{:?}", resp);
+
+        let reopen = Request::ReopenDatabase { db: "a.db".to_string(), from_path: None };
+        assert!(matches!(router.route_request(reopen).await, Response::Ok { .. }));
+        let resp = router.route_request(snapshot()).await;
+        assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+        let resp = router.route_request(compare()).await;
+        assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+        router.shutdown_workers().await;
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
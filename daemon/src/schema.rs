@@ -0,0 +1,248 @@
+use anyhow::Result;
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, HashSet};
+
+/// Prefix of every table the daemon creates and maintains itself; such
+/// tables are never exported or compared
+pub const DAEMON_TABLE_PREFIX: &str = "_daemon_";
+
+/// Daemon tables as named before they took `DAEMON_TABLE_PREFIX`, with
+/// their columns, renamed on open by [`rename_legacy_tables`]
+const LEGACY_TABLES: &[(&str, &str)] = &[
+    ("meta", "rev,ts"),
+    ("db_meta", "key,value"),
+    ("change_log", "rev,tables,ts"),
+    ("rev_history", "rev,ts"),
+    ("counters", "name,value"),
+];
+
+/// `_daemon_db_meta` key of the frozen-schema flag ("1" when set)
+pub const SCHEMA_FROZEN_KEY: &str = "schema_frozen";
+
+/// Daemon tables that only accumulate history; each has a unix-seconds
+/// `ts` column and may be trimmed by `PruneMeta`
+pub const PRUNABLE_TABLES: &[&str] = &["_daemon_change_log", "_daemon_rev_history"];
+
+/// Revisions kept in `_daemon_rev_history`; older entries are dropped as new
+/// revisions are recorded
+pub const REV_HISTORY_DEPTH: i64 = 1000;
+
+struct SchemaObject {
+    kind: String,
+    name: String,
+    tbl_name: String,
+    sql: String,
+}
+
+/// Export the user schema as ready-to-apply DDL (no data).
+///
+/// Tables come first, ordered so that tables referenced by foreign keys are
+/// created before the tables referencing them, followed by indexes, views and
+/// triggers in their original creation order.
+pub async fn export_schema(pool: &SqlitePool) -> Result<String> {
+    let rows: Vec<(String, String, String, String)> = sqlx::query_as(
+        r#"
+        SELECT type, name, tbl_name, sql
+        FROM sqlite_master
+        WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite_%'
+        ORDER BY rowid
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    // Shadow tables (an FTS index's `_data`, `_idx`, ...) are created by
+    // their virtual table's own CREATE statement
+    let shadow: HashSet<String> = sqlx::query_scalar::<_, String>(
+        "SELECT name FROM pragma_table_list WHERE schema = 'main' AND type = 'shadow'",
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|name| name.to_ascii_lowercase())
+    .collect();
+
+    let objects: Vec<SchemaObject> = rows
+        .into_iter()
+        .map(|(kind, name, tbl_name, sql)| SchemaObject { kind, name, tbl_name, sql })
+        .filter(|o| !is_daemon_table(&o.tbl_name))
+        .filter(|o| !shadow.contains(&o.tbl_name.to_ascii_lowercase()))
+        .collect();
+
+    let tables: Vec<&SchemaObject> = objects.iter().filter(|o| o.kind == "table").collect();
+    // SQLite matches table names case-insensitively (ASCII only), so a
+    // REFERENCES clause need not spell its parent the way it was created
+    let table_names: HashSet<String> = tables.iter().map(|t| t.name.to_ascii_lowercase()).collect();
+
+    // Collect foreign key parents for every table (ignoring self-references)
+    let mut parents = Vec::with_capacity(tables.len());
+    for table in &tables {
+        let refs: Vec<String> =
+            sqlx::query_scalar(r#"SELECT "table" FROM pragma_foreign_key_list(?)"#)
+                .bind(&table.name)
+                .fetch_all(pool)
+                .await?;
+        let refs: Vec<String> = refs
+            .into_iter()
+            .map(|r| r.to_ascii_lowercase())
+            .filter(|r| !r.eq_ignore_ascii_case(&table.name) && table_names.contains(r))
+            .collect();
+        parents.push(refs);
+    }
+
+    let mut ddl = Vec::with_capacity(objects.len());
+
+    // Emit tables whose parents are already emitted; fall back to creation
+    // order when the remaining tables form a cycle.
+    let mut emitted: HashSet<String> = HashSet::new();
+    let mut remaining: Vec<usize> = (0..tables.len()).collect();
+    while !remaining.is_empty() {
+        let ready = remaining
+            .iter()
+            .position(|&i| parents[i].iter().all(|p| emitted.contains(p)))
+            .unwrap_or(0);
+        let i = remaining.remove(ready);
+        emitted.insert(tables[i].name.to_ascii_lowercase());
+        ddl.push(tables[i].sql.as_str());
+    }
+
+    for kind in ["index", "view", "trigger"] {
+        ddl.extend(objects.iter().filter(|o| o.kind == kind).map(|o| o.sql.as_str()));
+    }
+
+    Ok(ddl
+        .iter()
+        .map(|sql| format!("{};\n", sql.trim_end().trim_end_matches(';')))
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+/// Whether `table` is one of the daemon's own
+pub fn is_daemon_table(table: &str) -> bool {
+    table.starts_with(DAEMON_TABLE_PREFIX)
+}
+
+/// Give a database's daemon tables from before `DAEMON_TABLE_PREFIX` their
+/// prefixed names, keeping the revision and history. Only tables with
+/// exactly the columns the daemon created are renamed, so a user table that
+/// happens to share a name (say `meta`) is left alone.
+pub async fn rename_legacy_tables(pool: &SqlitePool) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    for &(table, columns) in LEGACY_TABLES {
+        let prefixed = format!("{}{}", DAEMON_TABLE_PREFIX, table);
+        let (found, taken): (Option<String>, bool) = sqlx::query_as(
+            r#"
+            SELECT (SELECT group_concat(name, ',') FROM pragma_table_info(?)),
+                   EXISTS(SELECT 1 FROM sqlite_master WHERE name = ?)
+            "#,
+        )
+        .bind(table)
+        .bind(&prefixed)
+        .fetch_one(&mut *tx)
+        .await?;
+        if found.as_deref() != Some(columns) || taken {
+            continue;
+        }
+        // Recreated under its new name, on the renamed table
+        if table == "meta" {
+            sqlx::query("DROP TRIGGER IF EXISTS rev_history_record").execute(&mut *tx).await?;
+        }
+        sqlx::query(&format!(r#"ALTER TABLE "{}" RENAME TO "{}""#, table, prefixed))
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Read a `_daemon_db_meta` value
+pub async fn db_meta_get(pool: &SqlitePool, key: &str) -> Result<Option<String>> {
+    let value = sqlx::query_scalar("SELECT value FROM _daemon_db_meta WHERE key = ?")
+        .bind(key)
+        .fetch_optional(pool)
+        .await?;
+    Ok(value)
+}
+
+/// Store a `_daemon_db_meta` value, replacing any previous one
+pub async fn db_meta_set(pool: &SqlitePool, key: &str, value: &str) -> Result<()> {
+    sqlx::query("INSERT OR REPLACE INTO _daemon_db_meta(key, value) VALUES (?, ?)")
+        .bind(key)
+        .bind(value)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Delete rows older than `before_ts` (unix seconds) from every prunable
+/// daemon table present in the database, in one transaction. Returns the
+/// number of rows removed per table.
+pub async fn prune_daemon_tables(pool: &SqlitePool, before_ts: i64) -> Result<BTreeMap<String, u64>> {
+    let mut tx = pool.begin().await?;
+    let mut removed = BTreeMap::new();
+
+    for &table in PRUNABLE_TABLES {
+        let exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?)",
+        )
+        .bind(table)
+        .fetch_one(&mut *tx)
+        .await?;
+        if !exists {
+            continue;
+        }
+
+        let result = sqlx::query(&format!("DELETE FROM {} WHERE ts < ?", table))
+            .bind(before_ts)
+            .execute(&mut *tx)
+            .await?;
+        removed.insert(table.to_string(), result.rows_affected());
+    }
+
+    tx.commit().await?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn memory_pool() -> SqlitePool {
+        // One connection, or each would open its own empty database
+        SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap()
+    }
+
+    async fn master(pool: &SqlitePool) -> Vec<(String, String, Option<String>)> {
+        sqlx::query_as("SELECT type, name, sql FROM sqlite_master ORDER BY type, name")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_export_schema_applies_to_a_fresh_database() {
+        let source = memory_pool().await;
+        let schema = [
+            // References its parent in another case, before the parent exists
+            "CREATE TABLE child (id INTEGER PRIMARY KEY, parent_id INTEGER REFERENCES PARENT(id))",
+            "CREATE TABLE Parent (id INTEGER PRIMARY KEY, name TEXT)",
+            "CREATE INDEX parent_name ON Parent(name)",
+            "CREATE VIRTUAL TABLE docs USING fts5(body)",
+            "CREATE VIEW named AS SELECT name FROM Parent",
+            "CREATE TRIGGER child_ai AFTER INSERT ON child BEGIN SELECT 1; END",
+            "CREATE TABLE _daemon_meta (rev INTEGER, ts INTEGER)",
+        ];
+        for sql in schema {
+            sqlx::query(sql).execute(&source).await.unwrap();
+        }
+
+        let ddl = export_schema(&source).await.unwrap();
+        assert!(ddl.find("CREATE TABLE Parent").unwrap() < ddl.find("CREATE TABLE child").unwrap(), "{}", ddl);
+        assert!(!ddl.contains("docs_data") && !ddl.contains(DAEMON_TABLE_PREFIX), "{}", ddl);
+
+        let target = memory_pool().await;
+        sqlx::raw_sql(&ddl).execute(&target).await.unwrap();
+        let expected: Vec<_> = master(&source).await.into_iter().filter(|(_, name, _)| !is_daemon_table(name)).collect();
+        assert_eq!(master(&target).await, expected);
+    }
+}
@@ -1,91 +1,248 @@
+use crate::config::AuthorizerRules;
 use crate::router::Router;
-use crate::protocol::{Request, Response};
-use anyhow::Result;
+use crate::worker::ProgressSender;
+use crate::protocol::{Hello, Request, Response, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use anyhow::{bail, Context, Result};
 use bytes::{Buf, BytesMut};
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::windows::named_pipe::{ServerOptions, NamedPipeServer};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, watch};
+use tokio::task::{JoinHandle, JoinSet};
+#[cfg(windows)]
+use tokio::net::windows::named_pipe::ServerOptions;
 use tracing::{debug, error, info, warn};
 
 const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024; // 10 MB
 
+/// How long shutdown waits, after the workers have drained, for connections
+/// to write their last responses
+const CONNECTION_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[cfg(windows)]
-pub async fn run_server(pipe_name: &str, router: Router) -> Result<()> {
+pub async fn run_server(pipe_name: &str, router: Arc<Router>) -> Result<()> {
+    let tcp = spawn_tcp_listener(&router).await?;
     info!(pipe_name = %pipe_name, "IPC server listening");
-
-    let router = Arc::new(router);
+    let mut paused = router.accept_paused();
+    let mut shutdown = router.shutdown_requested();
+    let mut connections = JoinSet::new();
 
     loop {
+        // While paused no pipe instance exists, so a standby can create one
+        tokio::select! {
+            _ = wait_until_resumed(&mut paused) => {}
+            _ = shutdown.wait_for(|shutdown| *shutdown) => break,
+        }
+
         // Create a new pipe instance for each connection
-        let server = ServerOptions::new()
+        let mut server = ServerOptions::new()
             .first_pipe_instance(false)  // Allow multiple instances
             .create(pipe_name)?;
         
-        // Wait for a client to connect
-        server.connect().await?;
-        
-        debug!("Client connected");
+        // Wait for a client to connect; drop the idle instance if paused meanwhile
+        tokio::select! {
+            connected = server.connect() => connected?,
+            _ = paused.wait_for(|paused| *paused) => continue,
+            _ = shutdown.wait_for(|shutdown| *shutdown) => break,
+        }
         
         // Handle this connection in a separate task
         let router = Arc::clone(&router);
-        tokio::spawn(async move {
-            if let Err(e) = handle_connection(server, router).await {
+        connections.spawn(async move {
+            if let Err(e) = handle_connection(&mut server, router).await {
                 debug!(error = %e, "Connection handler error");
             }
+            // Release this pipe instance right away
+            let _ = server.disconnect();
         });
+        while connections.try_join_next().is_some() {}
     }
+
+    info!("IPC server stopped");
+    router.shutdown_workers().await;
+    finish_connections(connections, tcp).await;
+    Ok(())
 }
 
 #[cfg(unix)]
-pub async fn run_server(pipe_name: &str, router: Router) -> Result<()> {
+pub async fn run_server(pipe_name: &str, router: Arc<Router>) -> Result<()> {
     use tokio::net::UnixListener;
     
     // Remove existing socket if any
     let _ = std::fs::remove_file(pipe_name);
     
     let listener = UnixListener::bind(pipe_name)?;
+    let tcp = spawn_tcp_listener(&router).await?;
     info!(pipe_name = %pipe_name, "IPC server listening");
-
-    let router = Arc::new(router);
+    let mut paused = router.accept_paused();
+    let mut shutdown = router.shutdown_requested();
+    let mut connections = JoinSet::new();
 
     loop {
-        match listener.accept().await {
-            Ok((stream, _addr)) => {
+        tokio::select! {
+            _ = wait_until_resumed(&mut paused) => {}
+            _ = shutdown.wait_for(|shutdown| *shutdown) => break,
+        }
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = paused.wait_for(|paused| *paused) => continue,
+            _ = shutdown.wait_for(|shutdown| *shutdown) => break,
+        };
+        match accepted {
+            Ok((mut stream, _addr)) => {
                 let router = Arc::clone(&router);
-                tokio::spawn(async move {
-                    if let Err(e) = handle_connection_unix(stream, router).await {
+                connections.spawn(async move {
+                    if let Err(e) = handle_connection(&mut stream, router).await {
                         debug!(error = %e, "Connection handler error");
                     }
                 });
+                while connections.try_join_next().is_some() {}
             }
             Err(e) => {
                 error!(error = %e, "Failed to accept connection");
             }
         }
     }
+
+    info!("IPC server stopped");
+    drop(listener);
+    let _ = std::fs::remove_file(pipe_name);
+    router.shutdown_workers().await;
+    finish_connections(connections, tcp).await;
+    Ok(())
+}
+
+/// Bind `--listen`, if set, and accept TCP clients alongside the pipe or
+/// socket until shutdown. The task returns its connections so shutdown can
+/// wait for them as well. Anything but a loopback address needs
+/// `--listen-public`, since TCP clients are not authenticated.
+async fn spawn_tcp_listener(router: &Arc<Router>) -> Result<Option<JoinHandle<JoinSet<()>>>> {
+    let Some(addr) = router.config().listen else {
+        return Ok(None);
+    };
+    if !addr.ip().is_loopback() && !router.config().listen_public {
+        bail!(
+            "Refusing to listen on {}: TCP clients are not authenticated, so only a \
+             loopback address is accepted unless --listen-public is given",
+            addr
+        );
+    }
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    info!(addr = %addr, "TCP server listening");
+    Ok(Some(tokio::spawn(accept_tcp(listener, Arc::clone(router)))))
 }
 
-#[cfg(windows)]
-async fn handle_connection(
-    mut stream: NamedPipeServer,
-    router: Arc<Router>,
-) -> Result<()> {
+async fn accept_tcp(listener: TcpListener, router: Arc<Router>) -> JoinSet<()> {
+    let mut paused = router.accept_paused();
+    let mut shutdown = router.shutdown_requested();
+    let mut connections = JoinSet::new();
+
+    loop {
+        tokio::select! {
+            _ = wait_until_resumed(&mut paused) => {}
+            _ = shutdown.wait_for(|shutdown| *shutdown) => break,
+        }
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = paused.wait_for(|paused| *paused) => continue,
+            _ = shutdown.wait_for(|shutdown| *shutdown) => break,
+        };
+        match accepted {
+            Ok((mut stream, peer)) => {
+                debug!(peer = %peer, "TCP client connected");
+                // One small request, one small response: don't wait to coalesce
+                let _ = stream.set_nodelay(true);
+                let router = Arc::clone(&router);
+                connections.spawn(async move {
+                    if let Err(e) = handle_connection(&mut stream, router).await {
+                        debug!(error = %e, "Connection handler error");
+                    }
+                });
+                while connections.try_join_next().is_some() {}
+            }
+            Err(e) => {
+                error!(error = %e, "Failed to accept TCP connection");
+            }
+        }
+    }
+
+    info!("TCP server stopped");
+    connections
+}
+
+/// Wait for connection handlers to send the responses of requests the
+/// workers drained; idle connections close on their own at shutdown
+async fn finish_connections(connections: JoinSet<()>, tcp: Option<JoinHandle<JoinSet<()>>>) {
+    let mut sets = vec![connections];
+    if let Some(tcp) = tcp {
+        match tcp.await {
+            Ok(connections) => sets.push(connections),
+            Err(e) => error!(error = %e, "TCP server task failed"),
+        }
+    }
+    let busy = |sets: &[JoinSet<()>]| sets.iter().map(JoinSet::len).sum::<usize>();
+    if busy(&sets) == 0 {
+        return;
+    }
+    debug!(connections = busy(&sets), "Waiting for connections to finish");
+    let finished = tokio::time::timeout(CONNECTION_DRAIN_TIMEOUT, async {
+        for connections in sets.iter_mut() {
+            while connections.join_next().await.is_some() {}
+        }
+    })
+    .await;
+    if finished.is_err() {
+        warn!(connections = busy(&sets), "Connections still busy at shutdown, closing them");
+    }
+}
+
+/// Block the accept loop while `PauseAccept` is in effect
+async fn wait_until_resumed(paused: &mut watch::Receiver<bool>) {
+    if *paused.borrow() {
+        info!("Not accepting new connections until ResumeAccept");
+    }
+    // The router owns the sender and outlives the server
+    let _ = paused.wait_for(|paused| !*paused).await;
+}
+
+/// Serve one client until it disconnects: length-prefixed JSON requests in,
+/// length-prefixed JSON responses out. The same for every transport.
+async fn handle_connection<S>(stream: &mut S, router: Arc<Router>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
     debug!("Client connected");
 
-    let mut read_buf = BytesMut::with_capacity(4096);
+    let mut read_buf = BytesMut::with_capacity(router.config().read_buffer_bytes);
+    let mut conn = ConnectionState::default();
+    let mut shutdown = router.shutdown_requested();
+    let (progress_tx, mut progress_rx) = mpsc::unbounded_channel();
 
     loop {
         // Read length prefix (4 bytes)
         while read_buf.len() < 4 {
-            let n = stream.read_buf(&mut read_buf).await?;
+            let n = if read_buf.is_empty() {
+                // Between requests: nothing in flight, so shutdown may close
+                tokio::select! {
+                    n = read_more(stream, &mut read_buf) => n?,
+                    _ = shutdown.wait_for(|shutdown| *shutdown) => {
+                        debug!("Daemon shutting down, closing idle connection");
+                        return Ok(());
+                    }
+                }
+            } else {
+                read_more(stream, &mut read_buf).await?
+            };
             if n == 0 {
                 if read_buf.is_empty() {
                     debug!("Client disconnected");
-                    return Ok(());
                 } else {
                     warn!("Client disconnected mid-message");
-                    return Ok(());
                 }
+                return Ok(());
             }
         }
 
@@ -99,7 +256,7 @@ async fn handle_connection(
 
         // Read full message
         while read_buf.len() < 4 + length {
-            let n = stream.read_buf(&mut read_buf).await?;
+            let n = read_more(stream, &mut read_buf).await?;
             if n == 0 {
                 warn!("Client disconnected while sending message");
                 return Ok(());
@@ -110,133 +267,175 @@ async fn handle_connection(
         read_buf.advance(4); // Skip length prefix
         let message_bytes = read_buf.split_to(length);
 
-        // Parse request
-        let request: Request = match serde_json::from_slice(&message_bytes) {
-            Ok(req) => req,
-            Err(e) => {
-                error!(error = %e, "Failed to parse request");
-                let response = Response::error(format!("Invalid request: {}", e));
-                write_response(&mut stream, &response).await?;
-                continue;
+        // Progress frames go out while the request runs, then the response
+        let processing = process_message(&message_bytes, &mut conn, &router, &progress_tx);
+        tokio::pin!(processing);
+        let (response, close) = loop {
+            tokio::select! {
+                biased;
+                Some(progress) = progress_rx.recv() => {
+                    write_response(stream, &Response::ok_migrate_progress(progress)).await?;
+                }
+                done = &mut processing => break done,
             }
         };
-
-        debug!(request = ?request, "Received request");
-
-        // Check if this is a shutdown request
-        let is_shutdown = matches!(request, Request::Shutdown);
-
-        // Route request to appropriate worker
-        let response = router.route_request(request).await;
+        while let Ok(progress) = progress_rx.try_recv() {
+            write_response(stream, &Response::ok_migrate_progress(progress)).await?;
+        }
 
         // Send response
-        write_response(&mut stream, &response).await?;
+        write_response(stream, &response).await?;
 
-        // If shutdown requested, close connection
-        if is_shutdown {
-            debug!("Shutdown acknowledged, closing connection");
+        // After a shutdown request or a rejected handshake
+        if close {
+            debug!("Closing connection");
             return Ok(());
         }
     }
 }
 
-#[cfg(unix)]
-async fn handle_connection_unix(
-    mut stream: tokio::net::UnixStream,
-    router: Arc<Router>,
-) -> Result<()> {
-    debug!("Client connected");
+/// Read more bytes into `buf`. A pipe or socket torn down by the client
+/// after its last response (the normal end of a CLI call on Windows) is
+/// reported as a clean EOF rather than an error.
+async fn read_more<S: AsyncRead + Unpin>(stream: &mut S, buf: &mut BytesMut) -> std::io::Result<usize> {
+    use std::io::ErrorKind;
+
+    match stream.read_buf(buf).await {
+        Err(e)
+            if matches!(
+                e.kind(),
+                ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted
+            ) =>
+        {
+            Ok(0)
+        }
+        result => result,
+    }
+}
 
-    let mut read_buf = BytesMut::with_capacity(4096);
+/// State carried across requests on one client connection
+#[derive(Default)]
+struct ConnectionState {
+    /// Database used for requests that omit `db` (set by `UseDatabase`)
+    default_db: Option<String>,
+    /// A message was already handled; a `Hello` must come before any
+    started: bool,
+    /// Authorizer rules from the `Hello`, applied to every request
+    authorizer: Option<Arc<AuthorizerRules>>,
+}
 
-    loop {
-        // Read length prefix (4 bytes)
-        while read_buf.len() < 4 {
-            let n = stream.read_buf(&mut read_buf).await?;
-            if n == 0 {
-                if read_buf.is_empty() {
-                    debug!("Client disconnected");
-                    return Ok(());
-                } else {
-                    warn!("Client disconnected mid-message");
-                    return Ok(());
-                }
-            }
+/// Parse one message, fill in the connection's default database and route it.
+/// Returns the response and whether the connection should close afterwards.
+async fn process_message(
+    message_bytes: &[u8],
+    conn: &mut ConnectionState,
+    router: &Router,
+    progress: &ProgressSender,
+) -> (Response, bool) {
+    let mut value: serde_json::Value = match serde_json::from_slice(message_bytes) {
+        Ok(value) => value,
+        Err(e) => {
+            error!(error = %e, "Failed to parse request");
+            return (Response::error(format!("Invalid request: {}", e)), false);
         }
+    };
 
-        // Parse length
-        let length = (&read_buf[..4]).get_u32_le() as usize;
+    let first = !std::mem::replace(&mut conn.started, true);
+    if value.get("type").is_none() && value.get("protocol").is_some() {
+        return handshake(value, first, conn);
+    }
 
-        if length > MAX_MESSAGE_SIZE {
-            error!(length = length, "Message too large");
-            return Ok(()); // Close connection
+    // Requests that omit `db` use the connection default (explicit `db` wins)
+    if let (Some(obj), Some(default_db)) = (value.as_object_mut(), &conn.default_db) {
+        if !obj.contains_key("db") {
+            obj.insert("db".to_string(), serde_json::Value::String(default_db.clone()));
         }
+    }
 
-        // Read full message
-        while read_buf.len() < 4 + length {
-            let n = stream.read_buf(&mut read_buf).await?;
-            if n == 0 {
-                warn!("Client disconnected while sending message");
-                return Ok(());
+    let type_name = value.get("type").and_then(|t| t.as_str()).map(str::to_string);
+
+    let request: Request = match serde_json::from_value(value) {
+        Ok(req) => req,
+        Err(e) => {
+            // A newer client may send a request type this daemon predates
+            if let Some(type_name) = type_name {
+                if e.to_string().contains(&format!("unknown variant `{}`", type_name)) {
+                    warn!(request_type = %type_name, "Unknown request type");
+                    return (
+                        Response::error_with_code(
+                            format!("Unknown request type: {}", type_name),
+                            "UNKNOWN_REQUEST_TYPE",
+                        ),
+                        false,
+                    );
+                }
             }
+            error!(error = %e, "Failed to parse request");
+            return (Response::error(format!("Invalid request: {}", e)), false);
         }
+    };
 
-        // Extract message
-        read_buf.advance(4); // Skip length prefix
-        let message_bytes = read_buf.split_to(length);
-
-        // Parse request
-        let request: Request = match serde_json::from_slice(&message_bytes) {
-            Ok(req) => req,
-            Err(e) => {
-                error!(error = %e, "Failed to parse request");
-                let response = Response::error(format!("Invalid request: {}", e));
-                write_response_unix(&mut stream, &response).await?;
-                continue;
-            }
-        };
-
-        debug!(request = ?request, "Received request");
+    debug!(request = ?request, "Received request");
 
+    match request {
+        Request::UseDatabase { db } => {
+            debug!(db = %db, "Connection default database set");
+            conn.default_db = Some(db.clone());
+            (Response::ok_use_database(db), false)
+        }
         // Check if this is a shutdown request
-        let is_shutdown = matches!(request, Request::Shutdown);
-
+        Request::Shutdown => (router.route_request(Request::Shutdown).await, true),
         // Route request to appropriate worker
-        let response = router.route_request(request).await;
-
-        // Send response
-        write_response_unix(&mut stream, &response).await?;
-
-        // If shutdown requested, close connection
-        if is_shutdown {
-            debug!("Shutdown acknowledged, closing connection");
-            return Ok(());
+        request => {
+            let progress = matches!(request, Request::Migrate { progress: true, .. }).then(|| progress.clone());
+            (router.route_client_request(request, conn.authorizer.clone(), progress).await, false)
         }
     }
 }
 
-#[cfg(windows)]
-async fn write_response(stream: &mut NamedPipeServer, response: &Response) -> Result<()> {
-    let json = serde_json::to_vec(response)?;
-    
-    if json.len() > MAX_MESSAGE_SIZE {
-        error!("Response too large");
-        return Ok(()); // Just close connection
+/// Answer a `Hello`. An unsupported version closes the connection, so a
+/// mismatched client fails on its first message instead of misreading
+/// responses later. Authorizer rules in it are kept for the connection.
+fn handshake(value: serde_json::Value, first: bool, conn: &mut ConnectionState) -> (Response, bool) {
+    if !first {
+        return (
+            Response::error_with_code(
+                "Hello must be the first message on a connection",
+                "HANDSHAKE_NOT_FIRST",
+            ),
+            false,
+        );
     }
-
-    // Write length prefix
-    let length = json.len() as u32;
-    stream.write_all(&length.to_le_bytes()).await?;
-
-    // Write message
-    stream.write_all(&json).await?;
-    stream.flush().await?;
-
-    Ok(())
+    let unsupported = |detail: String| {
+        let message = format!(
+            "{}; daemon {} speaks protocol versions {} to {}",
+            detail,
+            env!("CARGO_PKG_VERSION"),
+            MIN_PROTOCOL_VERSION,
+            PROTOCOL_VERSION
+        );
+        (Response::error_with_code(message, "UNSUPPORTED_PROTOCOL"), true)
+    };
+    let hello: Hello = match serde_json::from_value(value) {
+        Ok(hello) => hello,
+        Err(e) => return unsupported(format!("Invalid hello: {}", e)),
+    };
+    if !hello.is_supported() {
+        warn!(client_protocol = hello.protocol, "Unsupported client protocol version");
+        return unsupported(format!("Unsupported protocol version {}", hello.protocol));
+    }
+    if let Some(client) = &hello.authorizer {
+        match AuthorizerRules::from_client(client) {
+            Ok(rules) if rules.is_empty() => {}
+            Ok(rules) => conn.authorizer = Some(Arc::new(rules)),
+            Err(e) => return (Response::error_with_code(e.to_string(), "INVALID_AUTHORIZER"), true),
+        }
+    }
+    debug!(client_protocol = hello.protocol, "Handshake complete");
+    (Response::ok_hello(env!("CARGO_PKG_VERSION").to_string()), false)
 }
 
-#[cfg(unix)]
-async fn write_response_unix(stream: &mut tokio::net::UnixStream, response: &Response) -> Result<()> {
+async fn write_response<S: AsyncWrite + Unpin>(stream: &mut S, response: &Response) -> Result<()> {
     let json = serde_json::to_vec(response)?;
     
     if json.len() > MAX_MESSAGE_SIZE {
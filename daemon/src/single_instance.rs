@@ -86,6 +86,7 @@ impl SingleInstanceGuard {
         // Try to create lock file with exclusive access
         let lock_file = OpenOptions::new()
             .create(true)
+            .truncate(false)
             .write(true)
             .mode(0o644)
             .open(lock_path)?;
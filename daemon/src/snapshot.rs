@@ -0,0 +1,70 @@
+use crate::config::AuthorizerRules;
+use crate::protocol::{NullStyle, Statement};
+use crate::query::{bind_param, fetch_rows};
+use anyhow::{Context, Result};
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{Column, Connection, Executor, SqliteConnection};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static SNAPSHOT_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Temporary snapshot file, deleted on drop
+struct SnapshotFile(PathBuf);
+
+impl Drop for SnapshotFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Copy `db_path` to a temporary file with `VACUUM INTO` and run `stmt`
+/// against the copy, read-only.
+///
+/// The live database is only read for the duration of the copy; the query
+/// itself holds no transaction on it, so writes and WAL checkpoints proceed.
+/// Results reflect the database at the moment the copy was taken.
+pub async fn query_snapshot(
+    db_path: &Path,
+    stmt: &Statement,
+    max_result_bytes: usize,
+    rules: Vec<AuthorizerRules>,
+) -> Result<(Vec<String>, Vec<Vec<serde_json::Value>>)> {
+    let snapshot = SnapshotFile(std::env::temp_dir().join(format!(
+        "skylinedb-snapshot-{}-{}.db",
+        std::process::id(),
+        SNAPSHOT_COUNTER.fetch_add(1, Ordering::Relaxed)
+    )));
+
+    let source = SqliteConnectOptions::new().filename(db_path).read_only(true);
+    let mut conn = SqliteConnection::connect_with(&source).await?;
+    sqlx::query("VACUUM INTO ?")
+        .bind(snapshot.0.to_string_lossy().into_owned())
+        .execute(&mut conn)
+        .await
+        .context("Failed to create snapshot")?;
+    conn.close().await?;
+
+    let copy = SqliteConnectOptions::new().filename(&snapshot.0).read_only(true);
+    let mut conn = SqliteConnection::connect_with(&copy).await?;
+
+    // The copy's connection is closed right after, so the rules stay armed
+    if !rules.is_empty() {
+        crate::ffi::set_authorizer(&mut conn, rules).await?;
+    }
+
+    let prepared = conn.prepare(stmt.sql.as_str()).await?;
+    let columns: Vec<String> = sqlx::Statement::columns(&prepared)
+        .iter()
+        .map(|c| c.name().to_string())
+        .collect();
+
+    let mut query = sqlx::Statement::query(&prepared);
+    for param in &stmt.params {
+        query = bind_param(query, param);
+    }
+    let rows = fetch_rows(query, &mut conn, max_result_bytes, NullStyle::Null).await?;
+
+    conn.close().await?;
+    Ok((columns, rows))
+}
@@ -0,0 +1,411 @@
+//! Minimal SQL lexer used for statement inspection. The only rewrite it
+//! does for execution is renumbering named parameters.
+
+use serde_json::{Map, Value};
+use std::ops::Range;
+
+/// A lexical token of a SQL statement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// Bare word: keyword, unquoted identifier or number, as written
+    Word(String),
+    /// Quoted identifier (`"x"`, `[x]`, `` `x` ``) without its quotes
+    Ident(String),
+    /// String literal without its quotes
+    Str(String),
+    /// Bind parameter as written (`?`, `?3`, `:name`, `@name`, `$name`)
+    Param(String),
+    /// Any other character (operators, punctuation)
+    Symbol(char),
+}
+
+impl Token {
+    /// True if this is a bare word matching `keyword` case-insensitively
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Word(w) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    /// Identifier text for words and quoted identifiers
+    fn name(&self) -> Option<&str> {
+        match self {
+            Token::Word(w) | Token::Ident(w) => Some(w),
+            _ => None,
+        }
+    }
+}
+
+/// Split SQL into tokens, skipping whitespace and comments
+pub fn tokenize(sql: &str) -> Vec<Token> {
+    let chars: Vec<char> = sql.chars().collect();
+    scan(&chars).into_iter().map(|(token, _)| token).collect()
+}
+
+/// Tokens with the range of `chars` each was read from
+fn scan(chars: &[char]) -> Vec<(Token, Range<usize>)> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '-' if chars.get(i + 1) == Some(&'-') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    i += 1;
+                }
+                i = (i + 2).min(chars.len());
+                continue;
+            }
+            '\'' => {
+                let (text, next) = read_quoted(chars, i, '\'');
+                i = next;
+                Token::Str(text)
+            }
+            '"' | '`' => {
+                let (text, next) = read_quoted(chars, i, c);
+                i = next;
+                Token::Ident(text)
+            }
+            '[' => {
+                while i < chars.len() && chars[i] != ']' {
+                    i += 1;
+                }
+                let text = chars[start + 1..i].iter().collect();
+                i = (i + 1).min(chars.len());
+                Token::Ident(text)
+            }
+            '?' => {
+                i += 1;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+                Token::Param(chars[start..i].iter().collect())
+            }
+            ':' | '@' | '$' if chars.get(i + 1).is_some_and(|&n| is_word_char(n)) => {
+                i += 1;
+                while i < chars.len() && is_word_char(chars[i]) {
+                    i += 1;
+                }
+                Token::Param(chars[start..i].iter().collect())
+            }
+            c if is_word_char(c) => {
+                while i < chars.len() && is_word_char(chars[i]) {
+                    i += 1;
+                }
+                Token::Word(chars[start..i].iter().collect())
+            }
+            c => {
+                i += 1;
+                Token::Symbol(c)
+            }
+        };
+        tokens.push((token, start..i));
+    }
+
+    tokens
+}
+
+/// Value for a named placeholder like `:id`, keyed `":id"` or `"id"`
+pub fn named_value<'a>(named: &'a Map<String, Value>, placeholder: &str) -> Option<&'a Value> {
+    named.get(placeholder).or_else(|| named.get(&placeholder[1..]))
+}
+
+/// Rewrite named placeholders (`:name`, `@name`, `$name`) as numbered ones
+/// and list their values in that order, since sqlx only binds by position.
+/// A placeholder written several times binds one value; one missing from
+/// `named` binds NULL, as an unbound parameter would.
+pub fn number_named_params(sql: &str, named: &Map<String, Value>) -> (String, Vec<Value>) {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut numbered = String::with_capacity(sql.len());
+    let mut seen: Vec<String> = Vec::new();
+    let mut values = Vec::new();
+    let mut copied = 0;
+    for (token, range) in scan(&chars) {
+        let Token::Param(placeholder) = token else { continue };
+        if placeholder.starts_with('?') {
+            continue;
+        }
+        let number = match seen.iter().position(|p| *p == placeholder) {
+            Some(index) => index + 1,
+            None => {
+                values.push(named_value(named, &placeholder).cloned().unwrap_or(Value::Null));
+                seen.push(placeholder);
+                seen.len()
+            }
+        };
+        numbered.extend(&chars[copied..range.start]);
+        numbered.push_str(&format!("?{}", number));
+        copied = range.end;
+    }
+    numbered.extend(&chars[copied..]);
+    (numbered, values)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || !c.is_ascii()
+}
+
+/// Read a quoted run starting at `start` (the opening quote); a doubled
+/// quote is an escaped quote. Returns the unquoted text and the next index.
+fn read_quoted(chars: &[char], start: usize, quote: char) -> (String, usize) {
+    let mut text = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        if chars[i] == quote {
+            if chars.get(i + 1) == Some(&quote) {
+                text.push(quote);
+                i += 2;
+                continue;
+            }
+            return (text, i + 1);
+        }
+        text.push(chars[i]);
+        i += 1;
+    }
+    (text, i)
+}
+
+/// Split a script into its statements, trimmed, dropping empty ones
+/// (bare `;`, comments only)
+pub fn split_statements(sql: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    for (end, _) in sql.match_indices(';') {
+        if crate::ffi::is_complete(&sql[start..=end]) {
+            statements.push(sql[start..=end].trim());
+            start = end + 1;
+        }
+    }
+    statements.push(sql[start..].trim());
+    statements.retain(|stmt| tokenize(stmt).iter().any(|t| *t != Token::Symbol(';')));
+    statements
+}
+
+/// First keyword of the statement, uppercased (e.g. "INSERT", "PRAGMA")
+pub fn leading_keyword(sql: &str) -> Option<String> {
+    match tokenize(sql).into_iter().next() {
+        Some(Token::Word(w)) => Some(w.to_uppercase()),
+        _ => None,
+    }
+}
+
+/// Lowercased name of every PRAGMA in `sql`, one per statement (after any
+/// `EXPLAIN [QUERY PLAN]`), without a schema qualifier, and whether it is
+/// given an argument (`= x` or `(x)`), which sets a setting pragma. A name
+/// SQLite would accept but this can't read (e.g. missing) comes back empty,
+/// so callers checking an allowlist reject it.
+pub fn pragmas(sql: &str) -> Vec<(String, bool)> {
+    let tokens = tokenize(sql);
+    let mut pragmas = Vec::new();
+    for stmt in tokens.split(|t| *t == Token::Symbol(';')) {
+        let mut rest = stmt;
+        if rest.first().is_some_and(|t| t.is_keyword("EXPLAIN")) {
+            rest = &rest[1..];
+            if rest.first().is_some_and(|t| t.is_keyword("QUERY")) {
+                rest = rest.get(2..).unwrap_or_default();
+            }
+        }
+        if !rest.first().is_some_and(|t| t.is_keyword("PRAGMA")) {
+            continue;
+        }
+        // SQLite also takes the name (and schema) as a string literal
+        let name = |token: Option<&Token>| match token {
+            Some(Token::Word(w) | Token::Ident(w) | Token::Str(w)) => w.to_lowercase(),
+            _ => String::new(),
+        };
+        let at = if rest.get(2) == Some(&Token::Symbol('.')) { 3 } else { 1 };
+        let argument = matches!(rest.get(at + 1), Some(Token::Symbol('=' | '(')));
+        pragmas.push((name(rest.get(at)), argument));
+    }
+    pragmas
+}
+
+/// Name of the table a statement writes to (INSERT/REPLACE/UPDATE/DELETE
+/// and CREATE/DROP/ALTER TABLE), ignoring any schema qualifier.
+pub fn written_table(sql: &str) -> Option<String> {
+    let tokens = tokenize(sql);
+    let (start, verb) = main_verb(&tokens)?;
+    let rest = &tokens[start + 1..];
+
+    let name_at = match verb.as_str() {
+        "INSERT" | "REPLACE" => rest.iter().position(|t| t.is_keyword("INTO"))? + 1,
+        "DELETE" => rest.iter().position(|t| t.is_keyword("FROM"))? + 1,
+        "UPDATE" => {
+            if rest.first()?.is_keyword("OR") {
+                2
+            } else {
+                0
+            }
+        }
+        _ => {
+            let table = rest.iter().position(|t| t.is_keyword("TABLE"))?;
+            let mut at = table + 1;
+            while rest.get(at).is_some_and(|t| {
+                t.is_keyword("IF") || t.is_keyword("NOT") || t.is_keyword("EXISTS")
+            }) {
+                at += 1;
+            }
+            at
+        }
+    };
+
+    qualified_name(&rest[name_at..])
+}
+
+/// Conflict resolution of an INSERT: "REPLACE" for `REPLACE INTO` and
+/// `INSERT OR REPLACE`, otherwise the `OR` clause (e.g. "IGNORE"), if any
+pub fn insert_conflict_resolution(sql: &str) -> Option<String> {
+    let tokens = tokenize(sql);
+    let (start, verb) = main_verb(&tokens)?;
+    match verb.as_str() {
+        "REPLACE" => Some(verb),
+        "INSERT" if tokens.get(start + 1)?.is_keyword("OR") => match tokens.get(start + 2)? {
+            Token::Word(w) => Some(w.to_uppercase()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// True for schema changes (CREATE/DROP/ALTER), including behind a WITH
+pub fn is_ddl(sql: &str) -> bool {
+    main_verb(&tokenize(sql)).is_some_and(|(_, verb)| matches!(verb.as_str(), "CREATE" | "DROP" | "ALTER"))
+}
+
+/// Index and uppercased text of the statement's main write verb, found at
+/// paren depth 0 (skips CTE bodies of WITH ...)
+fn main_verb(tokens: &[Token]) -> Option<(usize, String)> {
+    let mut depth = 0i32;
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Symbol('(') => depth += 1,
+            Token::Symbol(')') => depth -= 1,
+            Token::Word(w) if depth == 0 => {
+                let w = w.to_uppercase();
+                if matches!(
+                    w.as_str(),
+                    "INSERT" | "REPLACE" | "UPDATE" | "DELETE" | "CREATE" | "DROP" | "ALTER"
+                ) {
+                    return Some((i, w));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Read `name` or `schema.name` from the start of `tokens`, returning `name`
+fn qualified_name(tokens: &[Token]) -> Option<String> {
+    let first = tokens.first()?.name()?;
+    if tokens.get(1) == Some(&Token::Symbol('.')) {
+        if let Some(name) = tokens.get(2).and_then(Token::name) {
+            return Some(name.to_string());
+        }
+    }
+    Some(first.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_written_table() {
+        assert_eq!(written_table("INSERT INTO users VALUES (1)").as_deref(), Some("users"));
+        assert_eq!(written_table("insert or ignore into \"Order Items\"(a) values (?)").as_deref(), Some("Order Items"));
+        assert_eq!(written_table("UPDATE OR REPLACE main.t SET a = 1").as_deref(), Some("t"));
+        assert_eq!(written_table("DELETE FROM [logs] WHERE id = :id").as_deref(), Some("logs"));
+        assert_eq!(written_table("WITH x AS (SELECT 1) INSERT INTO t SELECT * FROM x").as_deref(), Some("t"));
+        assert_eq!(written_table("CREATE TABLE IF NOT EXISTS t (id INTEGER)").as_deref(), Some("t"));
+        assert_eq!(written_table("-- comment\nSELECT * FROM t"), None);
+    }
+
+    #[test]
+    fn test_insert_conflict_resolution() {
+        assert_eq!(insert_conflict_resolution("INSERT OR IGNORE INTO t VALUES (1)").as_deref(), Some("IGNORE"));
+        assert_eq!(insert_conflict_resolution("insert or replace into t values (1)").as_deref(), Some("REPLACE"));
+        assert_eq!(insert_conflict_resolution("REPLACE INTO t VALUES (1)").as_deref(), Some("REPLACE"));
+        assert_eq!(insert_conflict_resolution("INSERT INTO t VALUES (1)"), None);
+        assert_eq!(insert_conflict_resolution("UPDATE OR REPLACE t SET a = 1"), None);
+    }
+
+    #[test]
+    fn test_is_ddl() {
+        assert!(is_ddl("CREATE INDEX idx ON t(a)"));
+        assert!(is_ddl("  drop table t"));
+        assert!(is_ddl("ALTER TABLE t ADD COLUMN b"));
+        assert!(!is_ddl("INSERT INTO t VALUES ('CREATE TABLE x')"));
+        assert!(!is_ddl("SELECT * FROM t -- DROP TABLE t"));
+    }
+
+    #[test]
+    fn test_tokenize_skips_comments_and_strings() {
+        let tokens = tokenize("/* PRAGMA x */ SELECT 'it''s', :name, ?2 -- tail");
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Word("SELECT".into()),
+                Token::Str("it's".into()),
+                Token::Symbol(','),
+                Token::Param(":name".into()),
+                Token::Symbol(','),
+                Token::Param("?2".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pragmas() {
+        let pragma = |name: &str, argument| (name.to_string(), argument);
+        assert_eq!(pragmas("PRAGMA foreign_keys = ON"), [pragma("foreign_keys", true)]);
+        assert_eq!(pragmas("pragma main.User_Version"), [pragma("user_version", false)]);
+        assert_eq!(pragmas("PRAGMA main.foreign_keys(0)"), [pragma("foreign_keys", true)]);
+        assert_eq!(pragmas("PRAGMA \"writable_schema\" = 1"), [pragma("writable_schema", true)]);
+        assert_eq!(pragmas("PRAGMA 'writable_schema' = 1"), [pragma("writable_schema", true)]);
+        assert_eq!(pragmas("EXPLAIN QUERY PLAN PRAGMA table_info(t)"), [pragma("table_info", true)]);
+        assert_eq!(pragmas("SELECT 1; PRAGMA journal_mode = DELETE"), [pragma("journal_mode", true)]);
+        assert_eq!(pragmas("PRAGMA"), [pragma("", false)]);
+        assert!(pragmas("SELECT * FROM pragma_table_info('t')").is_empty());
+    }
+
+    #[test]
+    fn test_split_statements() {
+        let script = "CREATE TABLE t (a TEXT); -- one;\n;INSERT INTO t VALUES ('x;y');\n\
+            CREATE TRIGGER tr AFTER INSERT ON t BEGIN DELETE FROM t WHERE a = ''; END;\n\
+            /* last; */ SELECT 1";
+        assert_eq!(
+            split_statements(script),
+            [
+                "CREATE TABLE t (a TEXT);",
+                "INSERT INTO t VALUES ('x;y');",
+                "CREATE TRIGGER tr AFTER INSERT ON t BEGIN DELETE FROM t WHERE a = ''; END;",
+                "/* last; */ SELECT 1",
+            ]
+        );
+        assert!(split_statements(" ; -- nothing").is_empty());
+    }
+
+    #[test]
+    fn test_number_named_params() {
+        let named = serde_json::json!({ "a": 1, ":b": "x", "$c": null });
+        let named = named.as_object().unwrap();
+        let (sql, values) = number_named_params(
+            "INSERT INTO t VALUES (:a, ':a', :b, @a, :a) -- :c",
+            named,
+        );
+        assert_eq!(sql, "INSERT INTO t VALUES (?1, ':a', ?2, ?3, ?1) -- :c");
+        assert_eq!(values, vec![serde_json::json!(1), serde_json::json!("x"), serde_json::json!(1)]);
+    }
+}
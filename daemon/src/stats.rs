@@ -0,0 +1,152 @@
+use crate::protocol::{CheckpointEvent, ErrorEvent, LatencyQuantiles, Response};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Number of recent requests kept for latency quantiles
+pub const LATENCY_SAMPLES: usize = 1024;
+
+/// Number of recent checkpoints kept per worker
+pub const CHECKPOINT_HISTORY: usize = 256;
+
+/// Number of recent failed requests kept per worker
+pub const ERROR_HISTORY: usize = 32;
+
+/// Request counters and recent latencies of one worker
+#[derive(Default)]
+pub struct WorkerStats {
+    pub requests: u64,
+    pub errors: u64,
+    pub latency: LatencyWindow,
+    pub checkpoints: CheckpointLog,
+    /// The last `ERROR_HISTORY` failed requests, oldest first
+    pub recent_errors: VecDeque<ErrorEvent>,
+}
+
+impl WorkerStats {
+    /// Count a handled request and its latency
+    pub fn record(&mut self, resp: &Response, elapsed: Duration) {
+        self.requests += 1;
+        self.latency.record(elapsed);
+        if let Response::Error { message, code, .. } = resp {
+            self.errors += 1;
+            if self.recent_errors.len() == ERROR_HISTORY {
+                self.recent_errors.pop_front();
+            }
+            self.recent_errors.push_back(ErrorEvent {
+                ts: time::OffsetDateTime::now_utc().unix_timestamp(),
+                code: code.clone(),
+                message: message.clone(),
+            });
+        }
+    }
+}
+
+/// The last `CHECKPOINT_HISTORY` checkpoints, oldest dropped first
+#[derive(Default)]
+pub struct CheckpointLog {
+    events: VecDeque<CheckpointEvent>,
+}
+
+impl CheckpointLog {
+    pub fn record(&mut self, event: CheckpointEvent) {
+        if self.events.len() == CHECKPOINT_HISTORY {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    /// Newest first
+    pub fn recent(&self) -> Vec<CheckpointEvent> {
+        self.events.iter().rev().cloned().collect()
+    }
+}
+
+/// Ring buffer of the last `LATENCY_SAMPLES` request durations. Recording is
+/// a single store; quantiles sort a copy and are only computed on demand.
+pub struct LatencyWindow {
+    samples: Box<[u32; LATENCY_SAMPLES]>,
+    len: usize,
+    next: usize,
+}
+
+impl Default for LatencyWindow {
+    fn default() -> Self {
+        Self {
+            samples: Box::new([0; LATENCY_SAMPLES]),
+            len: 0,
+            next: 0,
+        }
+    }
+}
+
+impl LatencyWindow {
+    pub fn record(&mut self, elapsed: Duration) {
+        self.samples[self.next] = micros(elapsed);
+        self.next = (self.next + 1) % LATENCY_SAMPLES;
+        self.len = (self.len + 1).min(LATENCY_SAMPLES);
+    }
+
+    pub fn quantiles(&self) -> LatencyQuantiles {
+        quantiles(self.samples[..self.len].to_vec())
+    }
+}
+
+/// Duration in whole microseconds, saturating at `u32::MAX` (~71 minutes)
+pub fn micros(elapsed: Duration) -> u32 {
+    elapsed.as_micros().min(u32::MAX as u128) as u32
+}
+
+/// Nearest-rank quantiles of latencies in microseconds
+pub fn quantiles(mut samples: Vec<u32>) -> LatencyQuantiles {
+    samples.sort_unstable();
+    let at = |q: f64| -> u32 {
+        if samples.is_empty() {
+            return 0;
+        }
+        let rank = ((samples.len() as f64) * q).ceil() as usize;
+        samples[rank.clamp(1, samples.len()) - 1]
+    };
+    LatencyQuantiles {
+        samples: samples.len(),
+        p50_us: at(0.50),
+        p95_us: at(0.95),
+        p99_us: at(0.99),
+        max_us: samples.last().copied().unwrap_or(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_quantiles() {
+        let mut window = LatencyWindow::default();
+        assert_eq!(window.quantiles().samples, 0);
+
+        for us in 1..=100 {
+            window.record(Duration::from_micros(us));
+        }
+        let q = window.quantiles();
+        assert_eq!((q.samples, q.p50_us, q.p95_us, q.p99_us, q.max_us), (100, 50, 95, 99, 100));
+
+        // Old samples roll out of the window
+        for _ in 0..LATENCY_SAMPLES {
+            window.record(Duration::from_micros(7));
+        }
+        assert_eq!(window.quantiles().max_us, 7);
+    }
+
+    #[test]
+    fn test_recent_errors_capped() {
+        let mut stats = WorkerStats::default();
+        stats.record(&Response::ok_shutdown(), Duration::from_micros(5));
+        for i in 0..ERROR_HISTORY + 3 {
+            stats.record(&Response::error_with_code(format!("e{}", i), "X"), Duration::ZERO);
+        }
+        let n = ERROR_HISTORY as u64;
+        assert_eq!((stats.requests, stats.errors), (n + 4, n + 3));
+        assert_eq!(stats.recent_errors.len(), ERROR_HISTORY);
+        assert_eq!(stats.recent_errors.front().unwrap().message, "e3");
+    }
+}
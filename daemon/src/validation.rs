@@ -0,0 +1,216 @@
+use crate::config::DaemonConfig;
+use crate::protocol::Statement;
+use serde_json::Value;
+
+/// Pragmas client statements may run: schema introspection, integrity
+/// checks and per-application settings. Those that change how the daemon
+/// manages the file or connection (journal_mode, synchronous, locking_mode,
+/// writable_schema, ...) are left out; `--allow-pragma NAME` adds more.
+pub const ALLOWED_PRAGMAS: &[&str] = &[
+    "analysis_limit",
+    "application_id",
+    "collation_list",
+    "compile_options",
+    "data_version",
+    "database_list",
+    "defer_foreign_keys",
+    "foreign_key_check",
+    "foreign_key_list",
+    "freelist_count",
+    "function_list",
+    "incremental_vacuum",
+    "index_info",
+    "index_list",
+    "index_xinfo",
+    "integrity_check",
+    "module_list",
+    "optimize",
+    "page_count",
+    "page_size",
+    "pragma_list",
+    "quick_check",
+    "recursive_triggers",
+    "table_info",
+    "table_list",
+    "table_xinfo",
+    "user_version",
+];
+
+/// Pragmas client statements may read but not set: they change the write
+/// connection every client shares. `--allow-pragma NAME` allows setting.
+pub const READ_ONLY_PRAGMAS: &[&str] = &["foreign_keys"];
+
+/// Reasons a statement is rejected before it reaches SQLite
+#[derive(Debug, thiserror::Error)]
+pub enum ValidationError {
+    #[error("SQL statement too long (max 100KB)")]
+    SqlTooLong,
+    #[error("Too many parameters (SQLite limit is 999)")]
+    TooManyParams,
+    #[error("PRAGMA {0} is not allowed (the daemon can be started with --allow-pragma {0})")]
+    PragmaNotAllowed(String),
+    #[error("Schema is frozen; CREATE/ALTER/DROP statements are rejected")]
+    SchemaFrozen,
+    #[error("Parameter {index} is {size} bytes (max {max})")]
+    ParamTooLarge { index: usize, size: usize, max: usize },
+    #[error("Parameters total {size} bytes (max {max})")]
+    ParamsTooLarge { size: usize, max: usize },
+    #[error("Statement has both params and named_params; use one or the other")]
+    MixedParams,
+    #[error("Positional placeholder {0} used with named_params")]
+    PositionalWithNamed(String),
+    #[error("No value in named_params for {0}")]
+    MissingNamedParam(String),
+    #[error("Parameter {index} is not a valid $blob: {reason}")]
+    InvalidBlob { index: usize, reason: String },
+}
+
+impl ValidationError {
+    /// Machine-readable error code for the response
+    pub fn code(&self) -> &'static str {
+        match self {
+            ValidationError::SqlTooLong
+            | ValidationError::TooManyParams
+            | ValidationError::PragmaNotAllowed(_)
+            | ValidationError::MixedParams
+            | ValidationError::PositionalWithNamed(_)
+            | ValidationError::MissingNamedParam(_)
+            | ValidationError::InvalidBlob { .. } => "INVALID_STATEMENT",
+            ValidationError::ParamTooLarge { .. } | ValidationError::ParamsTooLarge { .. } => {
+                "PARAM_TOO_LARGE"
+            }
+            ValidationError::SchemaFrozen => "SCHEMA_FROZEN",
+        }
+    }
+}
+
+/// Check a statement against size limits and forbidden SQL; with
+/// `schema_frozen` set, DDL is rejected too
+pub fn validate_statement(
+    stmt: &Statement,
+    config: &DaemonConfig,
+    schema_frozen: bool,
+) -> Result<(), ValidationError> {
+    if stmt.sql.len() > 100_000 {
+        return Err(ValidationError::SqlTooLong);
+    }
+
+    let named = stmt.named_params.iter().flat_map(|named| named.values());
+    if stmt.params.len() + named.clone().count() > 999 {
+        return Err(ValidationError::TooManyParams);
+    }
+
+    if let Some(named) = &stmt.named_params {
+        if !stmt.params.is_empty() {
+            return Err(ValidationError::MixedParams);
+        }
+        for token in crate::sql::tokenize(&stmt.sql) {
+            match token {
+                crate::sql::Token::Param(p) if p.starts_with('?') => {
+                    return Err(ValidationError::PositionalWithNamed(p));
+                }
+                crate::sql::Token::Param(p) if crate::sql::named_value(named, &p).is_none() => {
+                    return Err(ValidationError::MissingNamedParam(p));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut total = 0usize;
+    for (index, param) in stmt.params.iter().chain(named).enumerate() {
+        let size = match crate::query::blob_text(param).map(crate::query::decode_blob) {
+            Some(Ok(bytes)) => bytes.len(),
+            Some(Err(reason)) => return Err(ValidationError::InvalidBlob { index, reason }),
+            None => param_size(param),
+        };
+        if size > config.max_param_bytes {
+            return Err(ValidationError::ParamTooLarge {
+                index,
+                size,
+                max: config.max_param_bytes,
+            });
+        }
+        total += size;
+    }
+    if total > config.max_params_total_bytes {
+        return Err(ValidationError::ParamsTooLarge {
+            size: total,
+            max: config.max_params_total_bytes,
+        });
+    }
+
+    for (name, argument) in crate::sql::pragmas(&stmt.sql) {
+        let readable = !argument && READ_ONLY_PRAGMAS.contains(&name.as_str());
+        if !ALLOWED_PRAGMAS.contains(&name.as_str()) && !readable && !config.allowed_pragmas.contains(&name) {
+            return Err(ValidationError::PragmaNotAllowed(name));
+        }
+    }
+
+    if schema_frozen && crate::sql::is_ddl(&stmt.sql) {
+        return Err(ValidationError::SchemaFrozen);
+    }
+
+    Ok(())
+}
+
+/// Size of a parameter as it will be bound (see `bind_param`)
+fn param_size(value: &Value) -> usize {
+    match value {
+        Value::Null | Value::Bool(_) | Value::Number(_) => 8,
+        Value::String(s) => s.len(),
+        Value::Array(_) | Value::Object(_) => value.to_string().len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(sql: &str, config: &DaemonConfig) -> Result<(), ValidationError> {
+        let stmt = Statement { sql: sql.to_string(), params: vec![], named_params: None };
+        validate_statement(&stmt, config, false)
+    }
+
+    #[test]
+    fn test_pragma_hidden_by_comments_is_rejected() {
+        let config = DaemonConfig::default();
+        for sql in [
+            "PRAGMA writable_schema = ON",
+            "/* harmless */ PRAGMA writable_schema=ON",
+            "PRAGMA /* x */ writable_schema = ON",
+            "-- note\nPRAGMA\twritable_schema = 1",
+            "PRAGMA main.writable_schema = 1",
+            "SELECT 1; PRAGMA journal_mode = DELETE",
+        ] {
+            assert!(
+                matches!(check(sql, &config), Err(ValidationError::PragmaNotAllowed(_))),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn test_pragma_text_in_string_literal_is_allowed() {
+        let config = DaemonConfig::default();
+        assert!(check("INSERT INTO notes VALUES ('PRAGMA writable_schema = ON')", &config).is_ok());
+        assert!(check("SELECT 1 -- PRAGMA writable_schema = ON", &config).is_ok());
+    }
+
+    #[test]
+    fn test_pragma_allowlist() {
+        let mut config = DaemonConfig::default();
+        assert!(check("PRAGMA foreign_keys", &config).is_ok());
+        assert!(check("PRAGMA main.foreign_keys", &config).is_ok());
+        assert!(check("PRAGMA foreign_keys = OFF", &config).is_err());
+        assert!(check("PRAGMA foreign_keys(0)", &config).is_err());
+        assert!(check("pragma USER_VERSION = 3", &config).is_ok());
+        assert!(check("PRAGMA cache_size = -64000", &config).is_err());
+
+        config.allowed_pragmas.push("cache_size".to_string());
+        assert!(check("PRAGMA cache_size = -64000", &config).is_ok());
+        config.allowed_pragmas.push("foreign_keys".to_string());
+        assert!(check("PRAGMA foreign_keys = OFF", &config).is_ok());
+    }
+}
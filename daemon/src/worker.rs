@@ -1,62 +1,329 @@
-use crate::protocol::{Request, Response, Statement, TransactionMode};
-use anyhow::{bail, Context, Result};
-use sqlx::{SqlitePool, sqlite::SqliteConnectOptions};
-use std::path::PathBuf;
+use crate::breaker::CircuitBreaker;
+use crate::config::{AuthorizerRules, BusyPolicy, DaemonConfig, ThreadMode};
+use crate::protocol::{
+    BenchmarkOp, ChangeEvent, ChangesetConflictPolicy, CheckpointEvent, ColumnInfo,
+    ConflictOutcome, ErrorEvent, ExplainOp, LatencyQuantiles, MigratePhase, MigrateProgress, Migration,
+    NullStyle, QueryFormat, Request,
+    Response, ResponseData, RevisionEntry, ScalarQuery, Statement, SyncLevel, TableDigest,
+    TotalChanges, TransactionMode, TxStatementResult, MEMORY_DB,
+};
+use crate::query::bind_param;
+use crate::schema::{db_meta_get, db_meta_set, rename_legacy_tables, REV_HISTORY_DEPTH, SCHEMA_FROZEN_KEY};
+use crate::stats::WorkerStats;
+use crate::validation::validate_statement;
+use anyhow::{Context, Result};
+use sqlx::pool::PoolConnection;
+use sqlx::{Column, Connection, Executor, Sqlite, SqliteConnection, SqlitePool, TypeInfo, sqlite::{SqliteConnectOptions, SqlitePoolOptions}};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, oneshot};
 use tracing::{debug, error, info, warn};
 
-const WORKER_IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60); // 5 minutes
+// How often a worker with no idle timeout wakes to re-check its timers
+const NO_IDLE_TIMEOUT_WAKE: Duration = Duration::from_secs(60 * 60);
 pub enum WorkerCommand {
     Request {
         req: Request,
-        reply: oneshot::Sender<Response>,
+        /// Authorizer rules the client's connection set in `Hello`
+        rules: ClientRules,
+        reply: Reply,
     },
+    /// Report the worker's state without touching the database or
+    /// counting as activity
+    Snapshot {
+        reply: oneshot::Sender<WorkerSnapshot>,
+    },
+    /// Stop taking commands, finish everything already queued, then
+    /// checkpoint, close and exit (daemon shutdown)
+    Drain,
+    /// Panic on receiving the next request, as a worker that crashes
+    /// before replying would
+    #[cfg(test)]
+    Panic,
+}
+
+/// Point-in-time view of a worker, for router-wide listings
+pub struct WorkerSnapshot {
+    /// "initializing", "open", "preparing", "closed" or "parked"
+    pub state: &'static str,
+    /// How long the database has been in `state`
+    pub state_age: Duration,
+    pub worker_age: Duration,
+    /// Time since the last request arrived
+    pub idle: Duration,
+    /// Time left before the worker parks or stops if nothing arrives; None
+    /// when it is not counting down (initializing, transaction, maintenance)
+    pub idle_timeout_in: Option<Duration>,
+    /// Commands waiting in the worker's queue
+    pub queued: usize,
+    pub requests: u64,
+    pub errors: u64,
+    pub latency: LatencyQuantiles,
+    pub circuit: &'static str,
+    /// Newest first
+    pub recent_errors: Vec<ErrorEvent>,
 }
 enum DatabaseState {
     Open(SqlitePool),
     Preparing,  // Checkpointing in progress
     Closed,     // File replacement allowed
+    Parked,     // Idle: connection released, worker kept for the grace period
 }
 struct WorkerState {
     db_state: DatabaseState,
     db_path: PathBuf,
     db_name: String,
     last_activity: Instant,
+    /// Last change of `db_state`
+    state_since: Instant,
+    started: Instant,
+    breaker: CircuitBreaker,
+    stats: WorkerStats,
+    /// Mirrors the `schema_frozen` flag in `_daemon_db_meta`
+    schema_frozen: bool,
+    /// Batches accepted during maintenance (`--journal-closed-writes`),
+    /// with their client's rules to replay them under
+    journal: Vec<(Vec<Statement>, TransactionMode, ClientRules)>,
+    /// Read-only connections for `Query`, opened on first use while the
+    /// database is open and closed whenever the write pool is
+    read_pool: Option<SqlitePool>,
+    /// Transaction opened by `Begin`, holding the write connection
+    tx: Option<OpenTx>,
+    /// [`MEMORY_DB`]: the data lives in the write connection, so it is never
+    /// parked or retired and there is no file for other connections to open
+    in_memory: bool,
+    /// Rules of the client whose request is running
+    client_rules: ClientRules,
+    /// Where the running request's progress frames go, if it asked for them
+    progress: Option<ProgressSender>,
+    #[cfg(test)]
+    panic_on_request: bool,
+    config: Arc<DaemonConfig>,
+}
+
+/// A transaction held open across requests, from `Begin` until `Commit`,
+/// `Rollback` or `--tx-idle-secs` without a request
+struct OpenTx {
+    token: String,
+    /// The pool's only connection, inside `BEGIN IMMEDIATE`
+    conn: PoolConnection<Sqlite>,
+    last_used: Instant,
+    /// `total_changes()` at `Begin`, to tell at commit whether rows changed
+    changes_at_begin: i64,
+    /// A statement returning no rows (a write or DDL) ran
+    wrote: bool,
+    /// Tables written, for the change feed
+    changed: Vec<String>,
 }
+/// `evictable` tells the router, without a round trip, whether the worker
+/// is idle and counting down to its idle timeout, so `--max-workers` may
+/// stop it to make room for another database.
 pub async fn worker_loop(
     mut rx: mpsc::Receiver<WorkerCommand>,
     db_path: PathBuf,
     db_name: String,
+    config: Arc<DaemonConfig>,
+    evictable: Arc<AtomicBool>,
 ) {
     let mut state = WorkerState {
         db_state: DatabaseState::Closed,
         db_path: db_path.clone(),
         db_name: db_name.clone(),
         last_activity: Instant::now(),
+        state_since: Instant::now(),
+        started: Instant::now(),
+        breaker: CircuitBreaker::new(&config),
+        stats: WorkerStats::default(),
+        schema_frozen: false,
+        journal: Vec::new(),
+        read_pool: None,
+        tx: None,
+        in_memory: db_name == MEMORY_DB,
+        client_rules: None,
+        progress: None,
+        #[cfg(test)]
+        panic_on_request: false,
+        config,
+    };
+    // Keep answering the channel while the database opens, so a slow cold
+    // open (large file, migrations) gives callers a bounded wait
+    let init_wait = Duration::from_millis(state.config.init_wait_ms);
+    let init_config = state.config.clone();
+    let init = init_database(&db_path, &db_name, &init_config);
+    tokio::pin!(init);
+    let mut held: VecDeque<(Instant, PendingRequest)> = VecDeque::new();
+    let mut drain_requested = false;
+    let init_result = loop {
+        let wait = held
+            .front()
+            .map_or(init_wait, |(at, ..)| init_wait.saturating_sub(at.elapsed()));
+        tokio::select! {
+            biased;
+            result = &mut init => break result,
+            _ = tokio::time::sleep(wait), if !held.is_empty() => {
+                while held.front().is_some_and(|(at, ..)| at.elapsed() >= init_wait) {
+                    if let Some((_, (_, _, reply))) = held.pop_front() {
+                        let _ = reply.send(initializing_response());
+                    }
+                }
+            }
+            Some(cmd) = rx.recv() => match cmd {
+                WorkerCommand::Request { reply, .. } if init_wait.is_zero() => {
+                    let _ = reply.send(initializing_response());
+                }
+                WorkerCommand::Request { req, rules, reply } => {
+                    held.push_back((Instant::now(), (req, rules, reply)));
+                }
+                WorkerCommand::Snapshot { reply } => {
+                    let mut info = snapshot(&state, held.len() + rx.len());
+                    info.state = "initializing";
+                    info.state_age = state.started.elapsed();
+                    info.idle_timeout_in = None;
+                    let _ = reply.send(info);
+                }
+                // Acted on once the database is open and `held` has run
+                WorkerCommand::Drain => drain_requested = true,
+                #[cfg(test)]
+                WorkerCommand::Panic => state.panic_on_request = true,
+            },
+        }
     };
-    match init_database(&db_path).await {
-        Ok(pool) => {
+    match init_result {
+        Ok((pool, schema_frozen)) => {
             state.db_state = DatabaseState::Open(pool);
+            state.schema_frozen = schema_frozen;
             info!(db = %db_name, "Worker started and database opened");
+            for (_, (req, rules, reply)) in held {
+                handle_client(req, rules, reply, &mut state).await;
+            }
         }
         Err(e) => {
             error!(db = %db_name, error = %e, "Failed to initialize database");
+            // Tell whoever is already queued why instead of dropping them
+            let resp = open_error_response(&e);
+            for (_, (_, _, reply)) in held {
+                let _ = reply.send(resp.clone());
+            }
+            rx.close();
+            while let Some(cmd) = rx.recv().await {
+                if let WorkerCommand::Request { reply, .. } = cmd {
+                    let _ = reply.send(resp.clone());
+                }
+            }
             return;
         }
     }
+    let idle_timeout = state.config.idle_timeout_for(&db_name);
+    let idle_grace = Duration::from_secs(state.config.idle_grace_secs);
+    let max_lifetime = Duration::from_secs(state.config.max_worker_lifetime_secs);
+    let max_maintenance = Duration::from_secs(state.config.max_maintenance_secs);
+    let coalesce_window = Duration::from_millis(state.config.coalesce_window_for(&db_name));
+    let tx_idle = Duration::from_secs(state.config.tx_idle_secs);
+    let mut parked_since: Option<Instant> = None;
+    // Requests that need the write connection while a transaction holds it
+    let mut waiting: VecDeque<PendingRequest> = VecDeque::new();
+    // A command read while gathering coalesced writes, handled next
+    let mut deferred: Option<WorkerCommand> = drain_requested.then_some(WorkerCommand::Drain);
+    // Outcomes of queries running on the read pool, for stats and breaker
+    let (reads_done_tx, mut reads_done) = mpsc::unbounded_channel::<(Duration, Response)>();
     loop {
-        let time_until_timeout = WORKER_IDLE_TIMEOUT.saturating_sub(state.last_activity.elapsed());
+        while state.tx.is_none() {
+            let Some((req, rules, reply)) = waiting.pop_front() else { break };
+            handle_client(req, rules, reply, &mut state).await;
+        }
+        let tx_remaining = state
+            .tx
+            .as_ref()
+            .map(|tx| tx_idle.saturating_sub(tx.last_used.elapsed()))
+            .unwrap_or_default();
+        let mut time_until_timeout = match parked_since {
+            Some(since) => idle_grace.saturating_sub(since.elapsed()),
+            None => idle_timeout.map_or(NO_IDLE_TIMEOUT_WAKE, |timeout| {
+                timeout.saturating_sub(state.last_activity.elapsed())
+            }),
+        };
+        let in_maintenance =
+            matches!(state.db_state, DatabaseState::Preparing | DatabaseState::Closed);
+        let idle = state.tx.is_none() && waiting.is_empty() && !in_maintenance;
+        evictable.store(idle && (idle_timeout.is_some() || parked_since.is_some()), Ordering::Relaxed);
+        if in_maintenance && !max_maintenance.is_zero() {
+            time_until_timeout = time_until_timeout
+                .min(max_maintenance.saturating_sub(state.state_since.elapsed()));
+        }
         tokio::select! {
             biased;
-            maybe_cmd = rx.recv() => {
+            maybe_cmd = next_command(&mut rx, &mut deferred) => {
                 match maybe_cmd {
-                    Some(WorkerCommand::Request { req, reply }) => {
+                    Some(WorkerCommand::Request { req, rules, reply }) => {
+                        #[cfg(test)]
+                        if state.panic_on_request {
+                            panic!("worker told to panic");
+                        }
+                        evictable.store(false, Ordering::Relaxed);
                         state.last_activity = Instant::now();
-                        let resp = handle_request(req, &mut state).await;
-                        let _ = reply.send(resp);
+                        if parked_since.take().is_some() {
+                            if let Err(resp) = unpark(&mut state).await {
+                                let _ = reply.send(resp);
+                                continue;
+                            }
+                        }
+                        let open = matches!(state.db_state, DatabaseState::Open(_));
+                        let Some((req, rules, reply)) =
+                            spawn_read((req, rules, reply), &reads_done_tx, &mut state).await
+                        else {
+                            continue;
+                        };
+                        if state.tx.is_some() && !is_tx_request(&req) {
+                            waiting.push_back((req, rules, reply));
+                            continue;
+                        }
+                        if !coalesce_window.is_zero() && open && is_coalescible(&req) {
+                            let group = gather_coalesced(
+                                (req, rules, reply),
+                                coalesce_window,
+                                &mut rx,
+                                &mut deferred,
+                                &state,
+                            )
+                            .await;
+                            handle_coalesced(group, &mut state).await;
+                        } else {
+                            handle_client(req, rules, reply, &mut state).await;
+                        }
+
+                        if !max_lifetime.is_zero()
+                            && !state.in_memory
+                            && state.started.elapsed() >= max_lifetime
+                            && state.tx.is_none()
+                            && waiting.is_empty()
+                        {
+                            info!(
+                                db = %db_name,
+                                age_secs = state.started.elapsed().as_secs(),
+                                "Worker reached its maximum lifetime, retiring"
+                            );
+                            finish_queued(&mut rx, &mut waiting, &mut state).await;
+                            break;
+                        }
+                    }
+                    Some(WorkerCommand::Snapshot { reply }) => {
+                        let _ = reply.send(snapshot(&state, waiting.len() + rx.len()));
                     }
+                    Some(WorkerCommand::Drain) => {
+                        info!(
+                            db = %db_name,
+                            queued = waiting.len() + rx.len(),
+                            "Draining worker"
+                        );
+                        finish_queued(&mut rx, &mut waiting, &mut state).await;
+                        break;
+                    }
+                    #[cfg(test)]
+                    Some(WorkerCommand::Panic) => state.panic_on_request = true,
                     None => {
                         info!(db = %db_name, "Command channel closed, shutting down worker");
                         break;
@@ -64,8 +331,53 @@ pub async fn worker_loop(
                 }
             }
 
+            Some((elapsed, outcome)) = reads_done.recv() => {
+                state.breaker.record(&outcome);
+                state.stats.record(&outcome, elapsed);
+            }
+
+            _ = tokio::time::sleep(tx_remaining), if state.tx.is_some() => {
+                abandon_tx(&mut state, "idle timeout").await;
+            }
+
             _ = tokio::time::sleep(time_until_timeout) => {
-                if rx.is_empty() && state.last_activity.elapsed() >= WORKER_IDLE_TIMEOUT {
+                if !rx.is_empty() {
+                    continue;
+                }
+                // The transaction's own timeout ends it first
+                if state.tx.is_some() {
+                    state.last_activity = Instant::now();
+                    continue;
+                }
+                if in_maintenance
+                    && !max_maintenance.is_zero()
+                    && state.state_since.elapsed() >= max_maintenance
+                {
+                    reopen_stuck_maintenance(&mut state).await;
+                    continue;
+                }
+                if let Some(since) = parked_since {
+                    if since.elapsed() >= idle_grace {
+                        info!(db = %db_name, "Idle grace period elapsed, shutting down worker");
+                        break;
+                    }
+                } else if idle_timeout
+                    .is_some_and(|timeout| state.last_activity.elapsed() >= timeout)
+                {
+                    // A database mid-maintenance stays out of service (and
+                    // listed by ListMaintenance) until it is reopened
+                    if matches!(state.db_state, DatabaseState::Preparing | DatabaseState::Closed) {
+                        state.last_activity = Instant::now();
+                        continue;
+                    }
+                    if !idle_grace.is_zero()
+                        && !state.in_memory
+                        && matches!(state.db_state, DatabaseState::Open(_))
+                    {
+                        park(&mut state).await;
+                        parked_since = Some(Instant::now());
+                        continue;
+                    }
                     info!(
                         db = %db_name,
                         idle_duration_secs = state.last_activity.elapsed().as_secs(),
@@ -77,31 +389,730 @@ pub async fn worker_loop(
         }
     }
 
+    for (req, rules, reply) in waiting {
+        state.client_rules = rules;
+        let resp = handle_stopping(req, &mut state).await;
+        let _ = reply.send(resp);
+    }
+    abandon_tx(&mut state, "worker stopping").await;
+    close_read_pool(&mut state).await;
+    if let DatabaseState::Open(pool) = std::mem::replace(&mut state.db_state, DatabaseState::Closed) {
+        if let Err(e) = checkpoint_wal(&pool, &mut state.stats).await {
+            warn!(db = %db_name, error = %e, "Failed checkpoint before stopping");
+        }
+        pool.close().await;
+    }
     info!(db = %db_name, "Worker stopped");
 }
-async fn init_database(db_path: &PathBuf) -> Result<SqlitePool> {
-    let db_url = format!("sqlite:{}", db_path.display());
-    let options = SqliteConnectOptions::from_str(&db_url)?
-        .create_if_missing(true)
-        .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal)
-        .synchronous(sqlx::sqlite::SqliteSynchronous::Normal)
-        .busy_timeout(std::time::Duration::from_secs(5));
-    let pool = SqlitePool::connect_with(options)
+
+async fn next_command(
+    rx: &mut mpsc::Receiver<WorkerCommand>,
+    deferred: &mut Option<WorkerCommand>,
+) -> Option<WorkerCommand> {
+    match deferred.take() {
+        Some(cmd) => Some(cmd),
+        None => rx.recv().await,
+    }
+}
+
+/// Most batches committed in one coalesced transaction
+const MAX_COALESCED_BATCHES: usize = 256;
+
+/// Atomic batches whose responses don't depend on committing alone
+fn is_coalescible(req: &Request) -> bool {
+    matches!(
+        req,
+        Request::ExecBatch {
+            stmts,
+            tx: TransactionMode::Atomic,
+            cache_size: None,
+            max_retries: None,
+            retry_backoff_ms: None,
+            total_changes: false,
+            digest: false,
+            timeout_ms: None,
+            count_replaced: false,
+            changeset: false,
+            ..
+        } if !stmts.is_empty()
+    )
+}
+
+/// Authorizer rules a client's connection set in `Hello`, if any
+pub type ClientRules = Option<Arc<AuthorizerRules>>;
+
+/// Progress frames of a request that streams them (`Migrate` with `progress`)
+pub type ProgressSender = mpsc::UnboundedSender<MigrateProgress>;
+
+/// Where a request's response goes, and its progress frames if it asked
+/// for them; those all arrive before the response
+pub struct Reply {
+    response: oneshot::Sender<Response>,
+    progress: Option<ProgressSender>,
+}
+
+impl Reply {
+    pub fn new(response: oneshot::Sender<Response>, progress: Option<ProgressSender>) -> Self {
+        Self { response, progress }
+    }
+
+    /// False if the requester stopped waiting
+    fn send(self, resp: Response) -> bool {
+        self.response.send(resp).is_ok()
+    }
+}
+
+type PendingRequest = (Request, ClientRules, Reply);
+
+/// Collect coalescible batches arriving within `window` of `first`. Stops
+/// early at anything else, which is left in `deferred` to run afterwards.
+async fn gather_coalesced(
+    first: PendingRequest,
+    window: Duration,
+    rx: &mut mpsc::Receiver<WorkerCommand>,
+    deferred: &mut Option<WorkerCommand>,
+    state: &WorkerState,
+) -> Vec<PendingRequest> {
+    let deadline = tokio::time::Instant::now() + window;
+    let mut group = vec![first];
+    while group.len() < MAX_COALESCED_BATCHES {
+        match tokio::time::timeout_at(deadline, rx.recv()).await {
+            Ok(Some(WorkerCommand::Request { req, rules, reply })) if is_coalescible(&req) => {
+                group.push((req, rules, reply));
+            }
+            Ok(Some(WorkerCommand::Snapshot { reply })) => {
+                let _ = reply.send(snapshot(state, group.len() + rx.len()));
+            }
+            Ok(Some(cmd)) => {
+                *deferred = Some(cmd);
+                break;
+            }
+            // Window over, or channel closed (noticed on the next receive)
+            Ok(None) | Err(_) => break,
+        }
+    }
+    group
+}
+
+/// Commit a group of atomic batches in one transaction, with one revision
+/// bump and one WAL sync. Each batch runs under its own savepoint, so one
+/// that fails is rolled back and reported alone; the rest share the rev.
+async fn handle_coalesced(mut group: Vec<PendingRequest>, state: &mut WorkerState) {
+    if group.len() == 1 {
+        let (req, rules, reply) = group.remove(0);
+        handle_client(req, rules, reply, state).await;
+        return;
+    }
+
+    let start = Instant::now();
+    let responses = if state.breaker.allow() {
+        // A busy database fails the whole group at BEGIN or COMMIT, so the
+        // group is retried as a unit like a single batch would be
+        let retry = BusyRetry::from_config(&state.config);
+        let mut retries = 0;
+        loop {
+            let responses = commit_coalesced(&group, retries, state).await;
+            if !responses.iter().all(is_busy) || retries >= retry.max_retries {
+                break responses;
+            }
+            debug!(db = %state.db_name, retries, "Coalesced batches hit a busy database, retrying");
+            tokio::time::sleep(retry.delay(retries)).await;
+            retries += 1;
+        }
+    } else {
+        vec![state.breaker.open_error(); group.len()]
+    };
+    debug!(
+        db = %state.db_name,
+        batches = group.len(),
+        duration_ms = start.elapsed().as_millis() as u64,
+        "Committed coalesced batches"
+    );
+
+    for ((_, _, reply), resp) in group.into_iter().zip(responses) {
+        state.breaker.record(&resp);
+        state.stats.record(&resp, start.elapsed());
+        let _ = reply.send(resp);
+    }
+}
+
+/// A coalesced batch's Ok((rows, conflicts, return_rev)) or its error
+type BatchOutcome = Result<(u64, Vec<ConflictOutcome>, bool), Response>;
+
+/// One response per batch of `group`, in order; `retries` is how many
+/// attempts before this one hit a busy database
+async fn commit_coalesced(group: &[PendingRequest], retries: u32, state: &mut WorkerState) -> Vec<Response> {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool.clone(),
+        Err(resp) => return vec![resp; group.len()],
+    };
+
+    let mut outcomes: Vec<BatchOutcome> = Vec::with_capacity(group.len());
+    for (req, _, _) in group {
+        let Request::ExecBatch { stmts, return_rev, .. } = req else {
+            unreachable!("only ExecBatch is coalesced");
+        };
+        let invalid = stmts.iter().enumerate().find_map(|(i, stmt)| {
+            validate_statement(stmt, &state.config, state.schema_frozen)
+                .err()
+                .map(|e| Response::error_with_code(format!("Statement {}: {}", i, e), e.code()))
+        });
+        outcomes.push(match invalid {
+            Some(resp) => Err(resp),
+            None => Ok((0, Vec::new(), *return_rev)),
+        });
+    }
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            let resp = sqlite_error(e.to_string(), "TX_BEGIN_FAILED", &e);
+            return vec![resp; group.len()];
+        }
+    };
+
+    let fail_all = |message: String| vec![Response::error(message); group.len()];
+    let mut changed: Vec<String> = Vec::new();
+    for ((req, client_rules, _), outcome) in group.iter().zip(outcomes.iter_mut()) {
+        let (Request::ExecBatch { stmts, .. }, Ok((rows, conflicts, _))) = (req, &mut *outcome)
+        else {
+            continue;
+        };
+        if let Err(e) = sqlx::query("SAVEPOINT coalesced").execute(&mut *tx).await {
+            return fail_all(format!("Failed to open savepoint: {}", e));
+        }
+        let rules = AuthorizerRules::combined(&state.config.authorizer, client_rules.as_deref());
+        if let Err(resp) = arm_authorizer(&mut tx, &rules).await {
+            return vec![resp; group.len()];
+        }
+        let executed = execute_statements(stmts, false, &mut tx).await;
+        disarm_authorizer(&mut tx, &rules).await;
+        match executed {
+            Ok((batch_rows, batch_conflicts)) => {
+                *rows = batch_rows;
+                *conflicts = batch_conflicts;
+                for table in changed_tables(stmts) {
+                    if !changed.contains(&table) {
+                        changed.push(table);
+                    }
+                }
+            }
+            Err((i, e)) => {
+                debug!(error = %e, statement_index = i, "Coalesced batch failed, rolled back alone");
+                let message = format!("Statement {}: {}", i, e);
+                *outcome = Err(sqlite_error(message, "SQL_ERROR", &e));
+                if let Err(e) = sqlx::query("ROLLBACK TO coalesced").execute(&mut *tx).await {
+                    return fail_all(format!("Failed to roll back savepoint: {}", e));
+                }
+            }
+        }
+        if let Err(e) = sqlx::query("RELEASE coalesced").execute(&mut *tx).await {
+            return fail_all(format!("Failed to release savepoint: {}", e));
+        }
+    }
+
+    let mut rev = None;
+    if outcomes.iter().any(|o| o.is_ok()) {
+        let committed: Result<Option<i64>> = async {
+            let rev = bump_revision_in_tx(&mut tx, true).await?;
+            if let (true, Some(rev)) = (state.config.change_feed, rev) {
+                record_change(&mut tx, rev, &changed).await?;
+            }
+            tx.commit().await?;
+            Ok(rev)
+        }
+        .await;
+        match committed {
+            Ok(committed) => rev = committed,
+            Err(e) => {
+                error!(db = %state.db_name, error = %e, "Failed to commit coalesced batches");
+                let resp = sqlite_error(e.to_string(), "TX_COMMIT_FAILED", &*e);
+                return vec![resp; group.len()];
+            }
+        }
+        checkpoint_after_write(&pool, state).await;
+    }
+
+    outcomes
+        .into_iter()
+        .map(|outcome| match outcome {
+            Ok((rows, conflicts, return_rev)) => {
+                Response::ok_exec(rev.filter(|_| return_rev), rows, conflicts, retries, None)
+            }
+            Err(resp) => resp,
+        })
+        .collect()
+}
+
+/// Safety net for a maintenance cycle that never finished (the client
+/// crashed between CloseDatabase and ReopenDatabase)
+async fn reopen_stuck_maintenance(state: &mut WorkerState) {
+    error!(
+        db = %state.db_name,
+        secs = state.state_since.elapsed().as_secs(),
+        "Database exceeded the maximum maintenance duration, reopening it"
+    );
+    if let Response::Error { message, .. } = handle_reopen_database(None, state).await {
+        error!(db = %state.db_name, error = %message, "Automatic reopen failed, will retry");
+        // Try again after another full period
+        state.state_since = Instant::now();
+    }
+}
+
+/// Stop taking requests and answer every one already queued, so none is
+/// dropped unanswered; the caller then checkpoints and closes. Requests for
+/// an open transaction run first, so a queued `Commit` still lands; the
+/// rest keep their order and run once it ends (or is rolled back). Senders
+/// see a closed channel, and the router respawns a fresh worker for later
+/// requests unless the daemon is shutting down.
+async fn finish_queued(
+    rx: &mut mpsc::Receiver<WorkerCommand>,
+    waiting: &mut VecDeque<PendingRequest>,
+    state: &mut WorkerState,
+) {
+    rx.close();
+    while let Some(cmd) = rx.recv().await {
+        match cmd {
+            WorkerCommand::Request { req, rules, reply }
+                if !is_tx_request(&req) && (state.tx.is_some() || !waiting.is_empty()) =>
+            {
+                waiting.push_back((req, rules, reply));
+            }
+            WorkerCommand::Request { req, rules, reply } => {
+                if matches!(state.db_state, DatabaseState::Parked) {
+                    if let Err(resp) = unpark(state).await {
+                        let _ = reply.send(resp);
+                        continue;
+                    }
+                }
+                handle_client(req, rules, reply, state).await;
+            }
+            WorkerCommand::Snapshot { reply } => {
+                let _ = reply.send(snapshot(state, waiting.len() + rx.len()));
+            }
+            WorkerCommand::Drain => {}
+            #[cfg(test)]
+            WorkerCommand::Panic => {}
+        }
+    }
+    while let Some((req, rules, reply)) = waiting.pop_front() {
+        state.client_rules = rules;
+        let resp = handle_stopping(req, state).await;
+        let _ = reply.send(resp);
+    }
+}
+
+/// Start a plain `Query` or a `MultiQueryScalar` on the read pool in its
+/// own task, so it runs alongside writes and other reads; the reply is sent
+/// from that task. Anything else (or a query that can't use the read pool)
+/// is handed back.
+async fn spawn_read(
+    (req, rules, reply): PendingRequest,
+    reads_done: &mpsc::UnboundedSender<(Duration, Response)>,
+    state: &mut WorkerState,
+) -> Option<PendingRequest> {
+    if matches!(req, Request::Backup { .. }) {
+        return spawn_backup((req, rules, reply), reads_done, state);
+    }
+    // A cache_size override applies to the write connection
+    if !matches!(req, Request::Query { cache_size: None, .. } | Request::MultiQueryScalar { .. }) {
+        return Some((req, rules, reply));
+    }
+    // A read-only connection to `:memory:` would get a new, empty database
+    if state.config.read_connections == 0 || state.in_memory || open_pool(state).is_err() {
+        return Some((req, rules, reply));
+    }
+    let pool = match read_pool(state).await {
+        Ok(pool) => pool.clone(),
+        Err(e) => {
+            warn!(db = %state.db_name, error = %e, "No read pool, querying on the write connection");
+            return Some((req, rules, reply));
+        }
+    };
+    if !state.breaker.allow() {
+        let resp = state.breaker.open_error();
+        state.stats.record(&resp, Duration::ZERO);
+        let _ = reply.send(resp);
+        return None;
+    }
+
+    let schema_frozen = state.schema_frozen;
+    let config = Arc::clone(&state.config);
+    let rules = AuthorizerRules::combined(&config.authorizer, rules.as_deref());
+    let db_name = state.db_name.clone();
+    let reads_done = reads_done.clone();
+    tokio::spawn(async move {
+        let start = Instant::now();
+        let resp = match req {
+            Request::Query { sql, params, format, nulls, timeout_ms, .. } => {
+                let stmt = Statement { sql, params, named_params: None };
+                let deadline = timeout_ms.map(|ms| start + Duration::from_millis(ms));
+                run_query(
+                    &stmt,
+                    format,
+                    nulls,
+                    deadline,
+                    &pool,
+                    &config,
+                    &rules,
+                    schema_frozen,
+                    &db_name,
+                )
+                .await
+            }
+            Request::MultiQueryScalar { queries, .. } => {
+                run_multi_query_scalar(&queries, &pool, &config, &rules, schema_frozen).await
+            }
+            _ => unreachable!(),
+        };
+        // Stats and the breaker only need the outcome, not the rows
+        let outcome = match &resp {
+            Response::Ok { .. } => Response::ok_query(Vec::new(), Vec::new(), Vec::new()),
+            error => error.clone(),
+        };
+        let _ = reply.send(resp);
+        let _ = reads_done.send((start.elapsed(), outcome));
+    });
+    None
+}
+
+/// Start a `Backup` in its own task on its own connection, so the worker
+/// keeps serving while the copy is written
+fn spawn_backup(
+    (req, rules, reply): PendingRequest,
+    reads_done: &mpsc::UnboundedSender<(Duration, Response)>,
+    state: &mut WorkerState,
+) -> Option<PendingRequest> {
+    if open_pool(state).is_err() {
+        return Some((req, rules, reply));
+    }
+    if state.in_memory {
+        let resp = in_memory_error("Backup needs a database file; use VacuumInto");
+        state.stats.record(&resp, Duration::ZERO);
+        let _ = reply.send(resp);
+        return None;
+    }
+    let Request::Backup { dest_path, overwrite, .. } = req else { unreachable!() };
+    let dest = match backup_dest(&dest_path, overwrite, &state.config) {
+        Ok(dest) => dest,
+        Err(resp) => {
+            state.stats.record(&resp, Duration::ZERO);
+            let _ = reply.send(resp);
+            return None;
+        }
+    };
+
+    let db_path = state.db_path.clone();
+    let db_name = state.db_name.clone();
+    let reads_done = reads_done.clone();
+    tokio::spawn(async move {
+        let start = Instant::now();
+        let resp = run_backup(&db_path, &dest, &db_name).await;
+        let _ = reads_done.send((start.elapsed(), resp.clone()));
+        let _ = reply.send(resp);
+    });
+    None
+}
+
+/// `VACUUM INTO` a temporary file from a fresh read-only connection, then
+/// move it to `dest`. The revision is read from the copy, so it is exactly
+/// the one the snapshot holds.
+async fn run_backup(db_path: &Path, dest: &Path, db_name: &str) -> Response {
+    let tmp = temp_dest(dest);
+    let _ = tokio::fs::remove_file(&tmp).await;
+
+    let start = Instant::now();
+    let copied: Result<i64> = async {
+        let options = SqliteConnectOptions::new().filename(db_path).read_only(true);
+        let mut conn = SqliteConnection::connect_with(&options).await?;
+        sqlx::query("VACUUM INTO ?")
+            .bind(tmp.to_string_lossy().into_owned())
+            .execute(&mut conn)
+            .await?;
+        conn.close().await?;
+
+        let options = SqliteConnectOptions::new().filename(&tmp).read_only(true);
+        let mut copy = SqliteConnection::connect_with(&options).await?;
+        let rev: i64 = sqlx::query_scalar("SELECT rev FROM _daemon_meta").fetch_one(&mut copy).await?;
+        copy.close().await?;
+        tokio::fs::rename(&tmp, dest).await?;
+        Ok(rev)
+    }
+    .await;
+
+    let rev = match copied {
+        Ok(rev) => rev,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            error!(db = %db_name, dest = %dest.display(), error = %e, "Backup failed");
+            let busy = crate::query::extended_code(&*e).is_some_and(|code| matches!(code & 0xff, 5 | 6));
+            let code = if busy { "DB_BUSY" } else { "SQL_ERROR" };
+            return sqlite_error(format!("Backup failed: {}", e), code, &*e);
+        }
+    };
+    let bytes_written = match tokio::fs::metadata(dest).await {
+        Ok(meta) => meta.len(),
+        Err(e) => return Response::error(format!("Failed to stat backup: {}", e)),
+    };
+
+    info!(
+        db = %db_name,
+        dest = %dest.display(),
+        bytes_written,
+        rev,
+        duration_ms = start.elapsed().as_millis() as u64,
+        "Database backed up"
+    );
+    Response::ok_backup(bytes_written, rev)
+}
+
+/// The read pool, opened on first use
+async fn read_pool(state: &mut WorkerState) -> Result<&SqlitePool> {
+    if state.read_pool.is_none() {
+        let pool = open_read_pool(&state.db_path, &state.db_name, &state.config).await?;
+        debug!(db = %state.db_name, connections = state.config.read_connections, "Read pool opened");
+        state.read_pool = Some(pool);
+    }
+    Ok(state.read_pool.as_ref().expect("read pool just opened"))
+}
+
+async fn close_read_pool(state: &mut WorkerState) {
+    if let Some(pool) = state.read_pool.take() {
+        // Waits for queries still running on it
+        pool.close().await;
+    }
+}
+
+/// Read-only connections (`mode=ro`, `query_only`) with the same functions,
+/// hardening and busy policy as the write connection
+async fn open_read_pool(db_path: &Path, db_name: &str, config: &DaemonConfig) -> Result<SqlitePool> {
+    let options = SqliteConnectOptions::new()
+        .filename(db_path)
+        .read_only(true)
+        .busy_timeout(Duration::from_millis(config.busy_timeout_ms))
+        .serialized(config.thread_mode == ThreadMode::Serialized)
+        .pragma("query_only", "ON")
+        .pragma("trusted_schema", if config.hardening { "OFF" } else { "ON" });
+    let hardening = config.hardening;
+    let functions = config.functions.clone();
+    let busy_policy = config.busy_policy_for(db_name);
+    let busy_timeout_ms = config.busy_timeout_ms;
+    let pool = SqlitePoolOptions::new()
+        .max_connections(config.read_connections)
+        .after_connect(move |conn, _meta| {
+            let functions = functions.clone();
+            Box::pin(async move {
+                if hardening {
+                    crate::ffi::set_defensive(conn, true)
+                        .await
+                        .map_err(|e| sqlx::Error::Configuration(e.into()))?;
+                }
+                crate::functions::register(conn, &functions)
+                    .await
+                    .map_err(|e| sqlx::Error::Configuration(e.into()))?;
+                if busy_policy == BusyPolicy::YieldToWriter {
+                    crate::ffi::set_yield_to_writer(conn, busy_timeout_ms)
+                        .await
+                        .map_err(|e| sqlx::Error::Configuration(e.into()))?;
+                }
+                Ok(())
+            })
+        })
+        .connect_with(options)
+        .await
+        .context("Failed to open read pool")?;
+    Ok(pool)
+}
+
+fn in_memory_error(message: &str) -> Response {
+    Response::error_with_code(format!("{} (in-memory database)", message), "IN_MEMORY_DB")
+}
+
+fn initializing_response() -> Response {
+    Response::error_with_code("Database is still opening, retry shortly", "DB_INITIALIZING")
+}
+
+fn snapshot(state: &WorkerState, queued: usize) -> WorkerSnapshot {
+    WorkerSnapshot {
+        state: match state.db_state {
+            DatabaseState::Open(_) => "open",
+            DatabaseState::Preparing => "preparing",
+            DatabaseState::Closed => "closed",
+            DatabaseState::Parked => "parked",
+        },
+        state_age: state.state_since.elapsed(),
+        worker_age: state.started.elapsed(),
+        idle: state.last_activity.elapsed(),
+        idle_timeout_in: match state.db_state {
+            DatabaseState::Open(_) if state.tx.is_none() => state
+                .config
+                .idle_timeout_for(&state.db_name)
+                .map(|timeout| timeout.saturating_sub(state.last_activity.elapsed())),
+            DatabaseState::Parked => {
+                let grace = Duration::from_secs(state.config.idle_grace_secs);
+                Some(grace.saturating_sub(state.state_since.elapsed()))
+            }
+            _ => None,
+        },
+        queued,
+        requests: state.stats.requests,
+        errors: state.stats.errors,
+        latency: state.stats.latency.quantiles(),
+        circuit: state.breaker.state_name(),
+        recent_errors: state.stats.recent_errors.iter().rev().cloned().collect(),
+    }
+}
+
+/// Checkpoint and release the connection, keeping the worker alive
+async fn park(state: &mut WorkerState) {
+    close_read_pool(state).await;
+    if let DatabaseState::Open(pool) = std::mem::replace(&mut state.db_state, DatabaseState::Parked) {
+        state.state_since = Instant::now();
+        if let Err(e) = checkpoint_wal(&pool, &mut state.stats).await {
+            warn!(db = %state.db_name, error = %e, "Failed checkpoint before parking");
+        }
+        pool.close().await;
+    }
+    info!(
+        db = %state.db_name,
+        grace_secs = state.config.idle_grace_secs,
+        "Idle timeout reached, worker parked"
+    );
+}
+
+/// Reopen a parked database for an incoming request
+async fn unpark(state: &mut WorkerState) -> Result<(), Response> {
+    match init_database(&state.db_path, &state.db_name, &state.config).await {
+        Ok((pool, schema_frozen)) => {
+            state.db_state = DatabaseState::Open(pool);
+            state.state_since = Instant::now();
+            state.schema_frozen = schema_frozen;
+            debug!(db = %state.db_name, "Parked worker resumed");
+            Ok(())
+        }
+        Err(e) => {
+            error!(db = %state.db_name, error = %e, "Failed to reopen parked database");
+            Err(open_error_response(&e))
+        }
+    }
+}
+
+/// Open the pool and run migrations; also returns the stored frozen-schema flag.
+/// [`MEMORY_DB`] opens a fresh in-memory database each time.
+async fn init_database(db_path: &Path, db_name: &str, config: &DaemonConfig) -> Result<(SqlitePool, bool)> {
+    let in_memory = db_path.as_os_str() == MEMORY_DB;
+    let db_url = if in_memory {
+        "sqlite::memory:".to_string()
+    } else {
+        format!("sqlite:{}", db_path.display())
+    };
+    let mut options = SqliteConnectOptions::from_str(&db_url)?
+        .create_if_missing(config.auto_create)
+        .foreign_keys(config.foreign_keys)
+        .pragma("synchronous", config.synchronous.as_pragma())
+        .busy_timeout(Duration::from_millis(config.busy_timeout_ms))
+        .serialized(config.thread_mode == ThreadMode::Serialized)
+        // Schema-embedded SQL (triggers, views, defaults) may not call
+        // functions with side effects; see also defensive mode below
+        .pragma("trusted_schema", if config.hardening { "OFF" } else { "ON" });
+    if !in_memory {
+        options = options.journal_mode(sqlx::sqlite::SqliteJournalMode::Wal);
+    }
+    let hardening = config.hardening;
+    let functions = config.functions.clone();
+    let busy_policy = config.busy_policy_for(db_name);
+    let busy_timeout_ms = config.busy_timeout_ms;
+    // One connection: the worker already serializes every request, and
+    // connection state (locking mode, attachments) must apply to all of them
+    let mut pool_options = SqlitePoolOptions::new().max_connections(1);
+    if in_memory {
+        // The database is gone once its last connection closes
+        pool_options = pool_options.min_connections(1).idle_timeout(None).max_lifetime(None);
+    }
+    let pool = pool_options
+        .after_connect(move |conn, _meta| {
+            let functions = functions.clone();
+            Box::pin(async move {
+                if hardening {
+                    crate::ffi::set_defensive(conn, true)
+                        .await
+                        .map_err(|e| sqlx::Error::Configuration(e.into()))?;
+                }
+                crate::functions::register(conn, &functions)
+                    .await
+                    .map_err(|e| sqlx::Error::Configuration(e.into()))?;
+                if busy_policy == BusyPolicy::YieldToWriter {
+                    crate::ffi::set_yield_to_writer(conn, busy_timeout_ms)
+                        .await
+                        .map_err(|e| sqlx::Error::Configuration(e.into()))?;
+                }
+                Ok(())
+            })
+        })
+        .connect_with(options)
         .await
         .context("Failed to connect to database")?;
-    sqlx::query("PRAGMA wal_autocheckpoint=1000")
-        .execute(&pool)
-        .await?;
+    if !in_memory {
+        sqlx::query(&format!("PRAGMA wal_autocheckpoint={}", config.wal_autocheckpoint))
+            .execute(&pool)
+            .await?;
+
+        // SQLite keeps the old mode instead of failing when WAL is unavailable
+        // (e.g. some network filesystems); everything here assumes WAL
+        let mode = journal_mode(&pool).await?;
+        if mode != "wal" {
+            warn!(
+                db = %db_path.display(),
+                journal_mode = %mode,
+                "WAL mode could not be enabled; readers will block writers and \
+                 maintenance assumptions do not hold (network filesystem?)"
+            );
+        }
+    }
+
+    run_migrations(&pool, config).await?;
+    if let Err(e) = run_on_open(&pool, &config.on_open).await {
+        pool.close().await;
+        return Err(e.into());
+    }
 
-    run_migrations(&pool).await?;
+    let schema_frozen = db_meta_get(&pool, SCHEMA_FROZEN_KEY).await?.as_deref() == Some("1");
 
-    Ok(pool)
+    Ok((pool, schema_frozen))
+}
+
+/// A configured `--on-open` statement failed
+#[derive(Debug, thiserror::Error)]
+#[error("on-open statement {index} failed: {source}")]
+struct OnOpenError {
+    index: usize,
+    source: sqlx::Error,
+}
+
+async fn run_on_open(pool: &SqlitePool, on_open: &[String]) -> Result<(), OnOpenError> {
+    for (index, sql) in on_open.iter().enumerate() {
+        sqlx::query(sql)
+            .execute(pool)
+            .await
+            .map_err(|source| OnOpenError { index, source })?;
+    }
+    Ok(())
+}
+
+/// Response for a failed `init_database`
+fn open_error_response(e: &anyhow::Error) -> Response {
+    match e.downcast_ref::<OnOpenError>() {
+        Some(on_open) => Response::error_with_code(on_open.to_string(), "ON_OPEN_FAILED"),
+        None => Response::error(format!("Failed to open database: {}", e)),
+    }
 }
 
-async fn run_migrations(pool: &SqlitePool) -> Result<()> {
+async fn run_migrations(pool: &SqlitePool, config: &DaemonConfig) -> Result<()> {
+    rename_legacy_tables(pool).await?;
+
     sqlx::query(
         r#"
-        CREATE TABLE IF NOT EXISTS meta (
+        CREATE TABLE IF NOT EXISTS _daemon_meta (
             rev INTEGER NOT NULL PRIMARY KEY,
             ts INTEGER NOT NULL
         )
@@ -113,106 +1124,2060 @@ async fn run_migrations(pool: &SqlitePool) -> Result<()> {
     // Initialize revision to 0 if not exists
     sqlx::query(
         r#"
-        INSERT INTO meta(rev, ts)
+        INSERT INTO _daemon_meta(rev, ts)
         SELECT 0, CAST(strftime('%s','now') AS INTEGER)
-        WHERE NOT EXISTS(SELECT 1 FROM meta)
+        WHERE NOT EXISTS(SELECT 1 FROM _daemon_meta)
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Daemon settings stored with the database (e.g. the frozen-schema flag)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS _daemon_db_meta (
+            key TEXT NOT NULL PRIMARY KEY,
+            value TEXT NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    // Bounded (rev, ts) ring, filled by a trigger so every revision bump is
+    // recorded in the same transaction
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS _daemon_rev_history (
+            rev INTEGER NOT NULL PRIMARY KEY,
+            ts INTEGER NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query(&format!(
+        r#"
+        CREATE TRIGGER IF NOT EXISTS _daemon_rev_history_record AFTER UPDATE OF rev ON _daemon_meta
+        BEGIN
+            INSERT OR REPLACE INTO _daemon_rev_history(rev, ts) VALUES (NEW.rev, NEW.ts);
+            DELETE FROM _daemon_rev_history WHERE rev <= NEW.rev - {};
+        END
+        "#,
+        REV_HISTORY_DEPTH
+    ))
+    .execute(pool)
+    .await?;
+
+    // Named counters for `Increment`
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS _daemon_counters (
+            name TEXT NOT NULL PRIMARY KEY,
+            value INTEGER NOT NULL
+        )
         "#,
     )
     .execute(pool)
     .await?;
 
+    if config.change_feed {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS _daemon_change_log (
+                rev INTEGER NOT NULL PRIMARY KEY,
+                tables TEXT NOT NULL,
+                ts INTEGER NOT NULL
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+    }
+
     Ok(())
 }
 
-async fn handle_request(req: Request, state: &mut WorkerState) -> Response {
-    match req {
-        Request::Ping { db: _ } => handle_ping(state).await,
-        Request::ExecBatch { db: _, stmts, tx } => handle_exec_batch(stmts, tx, state).await,
-        Request::PrepareForMaintenance { db: _ } => handle_prepare_maintenance(state).await,
-        Request::CloseDatabase { db: _ } => handle_close_database(state).await,
-        Request::ReopenDatabase { db: _ } => handle_reopen_database(state).await,
-        Request::Shutdown => {
-            info!("Shutdown requested");
+/// Handle a client's request under its connection's authorizer rules and
+/// answer it
+async fn handle_client(req: Request, rules: ClientRules, reply: Reply, state: &mut WorkerState) {
+    state.client_rules = rules;
+    state.progress = reply.progress.clone();
+    let resp = handle_guarded(req, state).await;
+    state.client_rules = None;
+    state.progress = None;
+    let _ = reply.send(resp);
+}
+
+/// Handle a request, counting it and recording its latency.
+async fn handle_guarded(req: Request, state: &mut WorkerState) -> Response {
+    // Reading stats shouldn't skew them
+    if matches!(req, Request::Stats { .. }) {
+        return handle_request(req, state).await;
+    }
+
+    let start = Instant::now();
+    let resp = handle_breaker(req, state).await;
+    state.stats.record(&resp, start.elapsed());
+    resp
+}
+
+/// Run a request through the circuit breaker. Health checks and the
+/// maintenance cycle always go through, since they are how an operator
+/// inspects and repairs a failing database.
+async fn handle_breaker(req: Request, state: &mut WorkerState) -> Response {
+    let bypass = matches!(
+        req,
+        Request::Ping { .. }
+            | Request::PrepareForMaintenance { .. }
+            | Request::CloseDatabase { .. }
+            | Request::ReopenDatabase { .. }
+            | Request::Rollback { .. }
+    );
+    if bypass {
+        return handle_request(req, state).await;
+    }
+
+    if !state.breaker.allow() {
+        return state.breaker.open_error();
+    }
+    let resp = handle_request(req, state).await;
+    state.breaker.record(&resp);
+    resp
+}
+
+async fn handle_request(req: Request, state: &mut WorkerState) -> Response {
+    match req {
+        Request::Ping { db: _ } => handle_ping(state).await,
+        Request::Begin { db: _ } => handle_begin(state).await,
+        Request::ExecInTx { db: _, tx_token, stmts } => {
+            handle_exec_in_tx(&tx_token, stmts, state).await
+        }
+        Request::Commit { db: _, tx_token } => handle_commit(&tx_token, state).await,
+        Request::Rollback { db: _, tx_token } => handle_rollback(&tx_token, state).await,
+        Request::ExecBatch {
+            db: _,
+            stmts,
+            tx,
+            return_rev,
+            allow_empty,
+            cache_size,
+            max_retries,
+            retry_backoff_ms,
+            total_changes,
+            digest,
+            digest_tables,
+            timeout_ms,
+            count_replaced,
+            changeset,
+        } => {
+            let deadline = timeout_ms.map(|ms| Instant::now() + Duration::from_millis(ms));
+            if stmts.is_empty() && allow_empty {
+                return handle_empty_batch(return_rev, state).await;
+            }
+            if changeset && !cfg!(feature = "session") {
+                return Response::error_with_code(
+                    "Changesets require a daemon built with the `session` feature",
+                    "FEATURE_UNSUPPORTED",
+                );
+            }
+            if changeset && tx != TransactionMode::Atomic {
+                return Response::error_with_code(
+                    "A changeset can only be recorded for an atomic batch",
+                    "CHANGESET_NEEDS_ATOMIC",
+                );
+            }
+            let in_maintenance =
+                matches!(state.db_state, DatabaseState::Preparing | DatabaseState::Closed);
+            if in_maintenance && state.config.journal_closed_writes {
+                return journal_batch(stmts, tx, state);
+            }
+            let before = if total_changes {
+                match read_total_changes(state).await {
+                    Ok(count) => Some(count),
+                    Err(resp) => return resp,
+                }
+            } else {
+                None
+            };
+            let previous = match override_cache_size(cache_size, state).await {
+                Ok(previous) => previous,
+                Err(resp) => return resp,
+            };
+            let retry = BusyRetry {
+                max_retries: max_retries.unwrap_or(state.config.busy_retries),
+                backoff: Duration::from_millis(
+                    retry_backoff_ms.unwrap_or(state.config.busy_retry_backoff_ms),
+                ),
+            };
+            let digest = digest.then_some(digest_tables);
+            let mut resp = handle_exec_batch(
+                stmts,
+                tx,
+                return_rev,
+                retry,
+                digest,
+                deadline,
+                BatchReport { count_replaced, changeset },
+                state,
+            )
+            .await;
+            restore_cache_size(previous, state).await;
+            if let (Some(before), Response::Ok { data: ResponseData::ExecBatch { total_changes, .. } }) =
+                (before, &mut resp)
+            {
+                if let Ok(after) = read_total_changes(state).await {
+                    *total_changes = Some(TotalChanges { before, after });
+                }
+            }
+            resp
+        }
+        Request::ExecMany { db: _, sql, param_sets, tx, return_rev } => {
+            handle_exec_many(sql, param_sets, tx, return_rev, state).await
+        }
+        Request::Query { db: _, sql, params, format, cache_size, nulls, timeout_ms } => {
+            let deadline = timeout_ms.map(|ms| Instant::now() + Duration::from_millis(ms));
+            let previous = match override_cache_size(cache_size, state).await {
+                Ok(previous) => previous,
+                Err(resp) => return resp,
+            };
+            let stmt = Statement { sql, params, named_params: None };
+            let resp = handle_query(stmt, format, nulls, deadline, state).await;
+            restore_cache_size(previous, state).await;
+            resp
+        }
+        Request::MultiQueryScalar { db: _, queries } => {
+            handle_multi_query_scalar(queries, state).await
+        }
+        Request::DescribeStatement { db: _, sql } => handle_describe_statement(&sql, state).await,
+        Request::Explain { db: _, sql, params } => {
+            handle_explain(Statement { sql, params, named_params: None }, state).await
+        }
+        Request::ChangeFeed { db: _, since_rev, limit } => {
+            handle_change_feed(since_rev, limit, state).await
+        }
+        Request::RevHistory { db: _, since_rev, limit } => {
+            handle_rev_history(since_rev, limit, state).await
+        }
+        Request::Increment { db: _, counter, delta } => handle_increment(counter, delta, state).await,
+        Request::ApplyChangeset { db: _, changeset, conflict_policy } => {
+            handle_apply_changeset(changeset, conflict_policy, state).await
+        }
+        Request::Benchmark { db: _, operation, count } => {
+            handle_benchmark(operation, count, state).await
+        }
+        Request::FreezeSchema { db: _ } => handle_set_schema_frozen(true, state).await,
+        Request::ThawSchema { db: _ } => handle_set_schema_frozen(false, state).await,
+        Request::PragmaStats { db: _ } => handle_pragma_stats(state).await,
+        Request::Stats { db: _ } => handle_stats(state).await,
+        Request::CheckpointHistory { db: _ } => {
+            Response::ok_checkpoint_history(state.stats.checkpoints.recent())
+        }
+        Request::ClearStatementCache { db: _ } => handle_clear_statement_cache(state).await,
+        Request::SetSynchronous { db: _, level } => handle_set_synchronous(level, state).await,
+        Request::PrepareForMaintenance { db: _, exclusive } => {
+            handle_prepare_maintenance(exclusive, state).await
+        }
+        Request::CloseDatabase { db: _ } => handle_close_database(state).await,
+        Request::ReopenDatabase { db: _, from_path } => {
+            handle_reopen_database(from_path, state).await
+        }
+        Request::ExportSchema { db: _ } => handle_export_schema(state).await,
+        Request::PruneMeta { db: _, before_ts } => handle_prune_meta(before_ts, state).await,
+        // Started by `spawn_read` whenever the database is open
+        Request::Backup { .. } => match open_pool(state) {
+            Ok(_) => Response::error("Backup runs outside the worker queue"),
+            Err(resp) => resp,
+        },
+        Request::Vacuum { .. } => handle_vacuum(state).await,
+        Request::Analyze { .. } => handle_analyze(state).await,
+        Request::GetSchemaVersion { .. } => handle_get_schema_version(state).await,
+        Request::SetSchemaVersion { db: _, version } => {
+            handle_set_schema_version(version, state).await
+        }
+        Request::Migrate { db: _, migrations, progress } => {
+            let progress = state.progress.clone().filter(|_| progress);
+            handle_migrate(migrations, progress, state).await
+        }
+        Request::VacuumInto { db: _, dest_path, overwrite } => {
+            handle_vacuum_into(&dest_path, overwrite, state).await
+        }
+        Request::UseDatabase { db } => Response::ok_use_database(db),
+        Request::CleanupSidecars { .. }
+        | Request::ExecAll { .. }
+        | Request::ResolvePath { .. }
+        | Request::ListFunctions
+        | Request::DaemonInfo
+        | Request::Diagnostics
+        | Request::Status
+        | Request::ListMaintenance
+        | Request::Compare { .. }
+        | Request::QuerySnapshot { .. }
+        | Request::ValidateSql { .. }
+        | Request::PauseAccept
+        | Request::ResumeAccept
+        | Request::OpenCursor { .. }
+        | Request::FetchCursor { .. }
+        | Request::CloseCursor { .. } => {
+            Response::error("Request is handled by the router")
+        }
+        Request::Shutdown => {
+            info!("Shutdown requested");
             Response::ok_shutdown()
         }
     }
 }
 
+async fn read_total_changes(state: &WorkerState) -> Result<i64, Response> {
+    let pool = open_pool(state)?;
+    let mut conn = pool
+        .acquire()
+        .await
+        .map_err(|e| Response::error(format!("Failed to acquire connection: {}", e)))?;
+    crate::ffi::total_changes(&mut conn)
+        .await
+        .map_err(|e| Response::error(format!("Failed to read total_changes: {}", e)))
+}
+
+/// Apply a per-request `cache_size` (KiB), returning the value to restore
+async fn override_cache_size(
+    kib: Option<u64>,
+    state: &WorkerState,
+) -> Result<Option<i64>, Response> {
+    let Some(kib) = kib else {
+        return Ok(None);
+    };
+    let pool = open_pool(state)?;
+
+    if kib == 0 || kib > state.config.max_cache_size_kib {
+        return Err(Response::error_with_code(
+            format!(
+                "cache_size must be between 1 and {} KiB",
+                state.config.max_cache_size_kib
+            ),
+            "INVALID_CACHE_SIZE",
+        ));
+    }
+
+    let previous: i64 = sqlx::query_scalar("PRAGMA cache_size")
+        .fetch_one(pool)
+        .await
+        .map_err(|e| Response::error(format!("Failed to read cache_size: {}", e)))?;
+    // Negative values are KiB rather than pages
+    sqlx::query(&format!("PRAGMA cache_size = -{}", kib))
+        .execute(pool)
+        .await
+        .map_err(|e| Response::error(format!("Failed to set cache_size: {}", e)))?;
+    Ok(Some(previous))
+}
+
+/// Undo `override_cache_size`, whatever the request's outcome
+async fn restore_cache_size(previous: Option<i64>, state: &WorkerState) {
+    let (Some(previous), Ok(pool)) = (previous, open_pool(state)) else {
+        return;
+    };
+    if let Err(e) = sqlx::query(&format!("PRAGMA cache_size = {}", previous))
+        .execute(pool)
+        .await
+    {
+        warn!(db = %state.db_name, error = %e, "Failed to restore cache_size");
+    }
+}
+
 async fn handle_ping(state: &WorkerState) -> Response {
-    match &state.db_state {
-        DatabaseState::Open(pool) => {
-            match get_current_rev(pool).await {
-                Ok(rev) => Response::ok_ping(
-                    env!("CARGO_PKG_VERSION").to_string(),
-                    state.db_path.display().to_string(),
-                    rev,
-                ),
-                Err(e) => {
-                    error!(error = %e, "Failed to get current revision");
-                    Response::error(format!("Failed to get revision: {}", e))
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+
+    let rev = match get_current_rev(pool).await {
+        Ok(rev) => rev,
+        Err(e) => {
+            error!(error = %e, "Failed to get current revision");
+            return Response::error(format!("Failed to get revision: {}", e));
+        }
+    };
+
+    let defensive = match pool.acquire().await {
+        Ok(mut conn) => crate::ffi::defensive(&mut conn).await.unwrap_or(false),
+        Err(e) => return Response::error(format!("Failed to acquire connection: {}", e)),
+    };
+
+    let journal_mode = match journal_mode(pool).await {
+        Ok(mode) => mode,
+        Err(e) => return Response::error(format!("Failed to read journal mode: {}", e)),
+    };
+
+    Response::ok_ping(
+        env!("CARGO_PKG_VERSION").to_string(),
+        state.db_path.display().to_string(),
+        rev,
+        defensive,
+        state.breaker.state_name().to_string(),
+        state.started.elapsed().as_secs(),
+        state.schema_frozen,
+        journal_mode,
+    )
+}
+
+/// Current journal mode, lowercased (e.g. "wal", "delete")
+async fn journal_mode(pool: &SqlitePool) -> Result<String> {
+    let mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+        .fetch_one(pool)
+        .await?;
+    Ok(mode.to_lowercase())
+}
+
+/// How an atomic batch is retried when the database is busy or locked
+struct BusyRetry {
+    max_retries: u32,
+    /// Delay before the first retry; doubles after each one
+    backoff: Duration,
+}
+
+impl BusyRetry {
+    /// `--busy-retries` and `--busy-retry-backoff-ms`
+    fn from_config(config: &DaemonConfig) -> Self {
+        BusyRetry {
+            max_retries: config.busy_retries,
+            backoff: Duration::from_millis(config.busy_retry_backoff_ms),
+        }
+    }
+
+    /// Delay before retry number `retries` (counting from 0)
+    fn delay(&self, retries: u32) -> Duration {
+        self.backoff.saturating_mul(1 << retries.min(10))
+    }
+}
+
+/// What an `ExecBatch` asked to have reported beyond rows and revision
+#[derive(Clone, Copy, Default)]
+struct BatchReport {
+    /// Split each REPLACE into inserted and replaced rows
+    count_replaced: bool,
+    /// Return the batch's session changeset (atomic batches only)
+    changeset: bool,
+}
+
+/// SQLITE_BUSY / SQLITE_LOCKED; the failed transaction was rolled back
+fn is_busy(resp: &Response) -> bool {
+    matches!(crate::breaker::result_code(resp), Some(5 | 6))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_exec_batch(
+    stmts: Vec<Statement>,
+    tx_mode: TransactionMode,
+    return_rev: bool,
+    retry: BusyRetry,
+    digest: Option<Vec<String>>,
+    deadline: Option<Instant>,
+    report: BatchReport,
+    state: &mut WorkerState,
+) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool.clone(),
+        Err(resp) => return resp,
+    };
+
+    if stmts.is_empty() {
+        return Response::error_with_code("Empty statement batch", "EMPTY_BATCH");
+    }
+
+    // Validate statements
+    for (i, stmt) in stmts.iter().enumerate() {
+        if let Err(e) = validate_statement(stmt, &state.config, state.schema_frozen) {
+            return Response::error_with_code(format!("Statement {}: {}", i, e), e.code());
+        }
+    }
+
+    let changed = state.config.change_feed.then(|| changed_tables(&stmts));
+    let digest = digest.map(|tables| if tables.is_empty() { changed_tables(&stmts) } else { tables });
+
+    let resp = match tx_mode {
+        TransactionMode::Atomic => {
+            let mut retries = 0;
+            loop {
+                let resp = execute_atomic_batch(
+                    &stmts,
+                    changed.as_deref(),
+                    return_rev,
+                    retries,
+                    digest.as_deref(),
+                    deadline,
+                    report,
+                    &authorizer_rules(state),
+                    &pool,
+                )
+                .await;
+                if !is_busy(&resp) || retries >= retry.max_retries {
+                    break resp;
                 }
+                let delay = retry.delay(retries);
+                if deadline.is_some_and(|deadline| Instant::now() + delay >= deadline) {
+                    break resp;
+                }
+                debug!(
+                    db = %state.db_name,
+                    retries,
+                    delay_ms = delay.as_millis() as u64,
+                    "Batch hit a busy database, retrying"
+                );
+                tokio::time::sleep(delay).await;
+                retries += 1;
             }
         }
-        DatabaseState::Preparing => {
-            Response::error_with_code(
-                "Database is preparing for maintenance",
-                "DATABASE_PREPARING"
+        TransactionMode::None => {
+            execute_separate_batch(
+                stmts,
+                changed,
+                return_rev,
+                digest,
+                deadline,
+                report,
+                &authorizer_rules(state),
+                &pool,
             )
+            .await
         }
-        DatabaseState::Closed => {
-            Response::error_with_code(
-                "Database is closed for maintenance",
-                "DATABASE_CLOSED"
-            )
+    };
+
+    if !matches!(resp, Response::Error { .. }) {
+        checkpoint_after_write(&pool, state).await;
+    }
+    resp
+}
+
+async fn handle_exec_many(
+    sql: String,
+    mut param_sets: Vec<Vec<serde_json::Value>>,
+    tx_mode: TransactionMode,
+    return_rev: bool,
+    state: &mut WorkerState,
+) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool.clone(),
+        Err(resp) => return resp,
+    };
+    if param_sets.is_empty() {
+        return Response::error_with_code("No parameter sets", "EMPTY_BATCH");
+    }
+
+    // Validate each set as a statement of its own, lending it the params
+    let mut stmt = Statement { sql, params: Vec::new(), named_params: None };
+    for (i, params) in param_sets.iter_mut().enumerate() {
+        stmt.params = std::mem::take(params);
+        let checked = validate_statement(&stmt, &state.config, state.schema_frozen);
+        *params = std::mem::take(&mut stmt.params);
+        if let Err(e) = checked {
+            return Response::error_with_code(format!("Parameter set {}: {}", i, e), e.code());
+        }
+    }
+    let sql = stmt.sql;
+
+    let changed = state
+        .config
+        .change_feed
+        .then(|| crate::sql::written_table(&sql).into_iter().collect::<Vec<_>>());
+    let read_rev = return_rev || changed.is_some();
+    let start = Instant::now();
+    let mut conn = match pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => return Response::error(format!("Failed to acquire connection: {}", e)),
+    };
+    let atomic = tx_mode == TransactionMode::Atomic;
+    if atomic {
+        if let Err(e) = sqlx::query("BEGIN").execute(&mut *conn).await {
+            return sqlite_error(e.to_string(), "TX_BEGIN_FAILED", &e);
+        }
+    }
+    let rules = &authorizer_rules(state);
+    let executed: Result<(u64, Option<i64>)> = async {
+        if let Err(Response::Error { message, .. }) = arm_authorizer(&mut conn, rules).await {
+            anyhow::bail!(message);
+        }
+        let rows = crate::query::execute_many(&mut conn, &sql, &param_sets).await;
+        disarm_authorizer(&mut conn, rules).await;
+        let rows = match rows {
+            Ok(rows) => rows,
+            // Without a transaction the sets before `i` committed on their
+            // own, and are a change like any other
+            Err((i, e)) if !atomic && i > 0 => {
+                let rev = bump_revision_in_tx(&mut conn, read_rev).await?;
+                if let (Some(tables), Some(rev)) = (&changed, rev) {
+                    record_change(&mut conn, rev, tables).await?;
+                }
+                let committed = format!("Parameter set {} (sets 0-{} committed)", i, i - 1);
+                return Err(anyhow::Error::from(e).context(committed));
+            }
+            Err((i, e)) => return Err(anyhow::Error::from(e).context(format!("Parameter set {}", i))),
+        };
+        let rev = bump_revision_in_tx(&mut conn, read_rev).await?;
+        if let (Some(tables), Some(rev)) = (&changed, rev) {
+            record_change(&mut conn, rev, tables).await?;
+        }
+        if atomic {
+            sqlx::query("COMMIT").execute(&mut *conn).await?;
+        }
+        Ok((rows, rev))
+    }
+    .await;
+    if executed.is_err() && atomic {
+        if let Err(e) = sqlx::query("ROLLBACK").execute(&mut *conn).await {
+            warn!(db = %state.db_name, error = %e, "Failed to roll back ExecMany");
+        }
+    }
+    drop(conn);
+
+    match executed {
+        Ok((rows, rev)) => {
+            debug!(
+                db = %state.db_name,
+                sets = param_sets.len(),
+                rows_affected = rows,
+                duration_ms = start.elapsed().as_millis() as u64,
+                "Executed statement over parameter sets"
+            );
+            checkpoint_after_write(&pool, state).await;
+            Response::ok_exec(rev.filter(|_| return_rev), rows, Vec::new(), 0, None)
+        }
+        Err(e) => {
+            let message = format!("{:#}", e);
+            error!(db = %state.db_name, error = %message, "ExecMany failed");
+            sqlite_error(message, "SQL_ERROR", &*e)
+        }
+    }
+}
+
+/// Hold a batch for replay after the reopen. It is validated now so the
+/// client hears about bad statements immediately.
+fn journal_batch(stmts: Vec<Statement>, tx: TransactionMode, state: &mut WorkerState) -> Response {
+    if stmts.is_empty() {
+        return Response::error_with_code("Empty statement batch", "EMPTY_BATCH");
+    }
+    for (i, stmt) in stmts.iter().enumerate() {
+        if let Err(e) = validate_statement(stmt, &state.config, state.schema_frozen) {
+            return Response::error_with_code(format!("Statement {}: {}", i, e), e.code());
+        }
+    }
+    if state.journal.len() >= state.config.max_journaled_batches {
+        return Response::error_with_code(
+            format!(
+                "Write journal is full ({} batches) until the database is reopened",
+                state.config.max_journaled_batches
+            ),
+            "JOURNAL_FULL",
+        );
+    }
+
+    state.journal.push((stmts, tx, state.client_rules.clone()));
+    debug!(
+        db = %state.db_name,
+        journaled = state.journal.len(),
+        "Batch journaled during maintenance"
+    );
+    Response::ok_journaled(state.journal.len())
+}
+
+/// Apply journaled batches in arrival order; returns (applied, failed)
+async fn replay_journal(state: &mut WorkerState) -> (u32, u32) {
+    let journal = std::mem::take(&mut state.journal);
+    let Ok(pool) = open_pool(state).cloned() else {
+        return (0, journal.len() as u32);
+    };
+
+    let (mut applied, mut failed) = (0, 0);
+    for (i, (stmts, tx, client_rules)) in journal.into_iter().enumerate() {
+        let changed = state.config.change_feed.then(|| changed_tables(&stmts));
+        let rules = &AuthorizerRules::combined(&state.config.authorizer, client_rules.as_deref());
+        let resp = match tx {
+            TransactionMode::Atomic => {
+                let changed = changed.as_deref();
+                let report = BatchReport::default();
+                execute_atomic_batch(&stmts, changed, false, 0, None, None, report, rules, &pool).await
+            }
+            TransactionMode::None => {
+                let report = BatchReport::default();
+                execute_separate_batch(stmts, changed, false, None, None, report, rules, &pool).await
+            }
+        };
+        match resp {
+            Response::Error { message, .. } => {
+                error!(
+                    db = %state.db_name,
+                    batch = i,
+                    error = %message,
+                    "Journaled batch failed on replay"
+                );
+                failed += 1;
+            }
+            _ => applied += 1,
+        }
+    }
+    if applied > 0 {
+        checkpoint_after_write(&pool, state).await;
+    }
+    info!(db = %state.db_name, applied, failed, "Replayed journaled batches");
+    (applied, failed)
+}
+
+/// No-op batch: report the current revision without bumping it
+async fn handle_empty_batch(return_rev: bool, state: &WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+
+    if !return_rev {
+        return Response::ok_exec(None, 0, Vec::new(), 0, None);
+    }
+    match get_current_rev(pool).await {
+        Ok(rev) => Response::ok_exec(Some(rev), 0, Vec::new(), 0, None),
+        Err(e) => {
+            error!(error = %e, "Failed to get current revision");
+            Response::error(format!("Failed to get revision: {}", e))
+        }
+    }
+}
+
+/// `error_with_code` carrying SQLite's result codes when `e` came from SQLite
+fn sqlite_error(
+    message: impl Into<String>,
+    code: &str,
+    e: &(dyn std::error::Error + 'static),
+) -> Response {
+    let code = if crate::ffi::denied(e) { "AUTHORIZER_DENIED" } else { code };
+    Response::error_with_code(message, code).with_sqlite_code(crate::query::extended_code(e))
+}
+
+/// Requests that run inside an open transaction instead of waiting for it
+fn is_tx_request(req: &Request) -> bool {
+    matches!(req, Request::ExecInTx { .. } | Request::Commit { .. } | Request::Rollback { .. })
+}
+
+/// Handle a request while the worker stops: nobody will end an open
+/// transaction any more, so it is rolled back before anything that needs
+/// the connection
+async fn handle_stopping(req: Request, state: &mut WorkerState) -> Response {
+    if !is_tx_request(&req) {
+        abandon_tx(state, "worker stopping").await;
+    }
+    handle_guarded(req, state).await
+}
+
+fn tx_not_found() -> Response {
+    Response::error_with_code(
+        "Transaction not found (committed, rolled back or expired)",
+        "TX_NOT_FOUND",
+    )
+}
+
+async fn handle_begin(state: &mut WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+    // A second Begin waits in the queue until the first transaction ends
+    if state.tx.is_some() {
+        return Response::error("A transaction is already open");
+    }
+    let mut conn = match pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => return sqlite_error(e.to_string(), "TX_BEGIN_FAILED", &e),
+    };
+    let changes_at_begin = match crate::ffi::total_changes(&mut conn).await {
+        Ok(changes) => changes,
+        Err(e) => return sqlite_error(e.to_string(), "TX_BEGIN_FAILED", &*e),
+    };
+    // IMMEDIATE takes the write lock now, so a read-then-write transaction
+    // can't fail with SQLITE_BUSY at its first write
+    if let Err(e) = sqlx::query("BEGIN IMMEDIATE").execute(&mut *conn).await {
+        return sqlite_error(e.to_string(), "TX_BEGIN_FAILED", &e);
+    }
+
+    let token = uuid::Uuid::new_v4().to_string();
+    debug!(db = %state.db_name, tx = %token, "Transaction opened");
+    state.tx = Some(OpenTx {
+        token: token.clone(),
+        conn,
+        last_used: Instant::now(),
+        changes_at_begin,
+        wrote: false,
+        changed: Vec::new(),
+    });
+    Response::ok_tx_begun(token)
+}
+
+async fn handle_exec_in_tx(tx_token: &str, stmts: Vec<Statement>, state: &mut WorkerState) -> Response {
+    if stmts.is_empty() {
+        return Response::error_with_code("Empty statement batch", "EMPTY_BATCH");
+    }
+    for (i, stmt) in stmts.iter().enumerate() {
+        if let Err(e) = validate_statement(stmt, &state.config, state.schema_frozen) {
+            return Response::error_with_code(format!("Statement {}: {}", i, e), e.code());
+        }
+    }
+
+    let config = Arc::clone(&state.config);
+    let rules = authorizer_rules(state);
+    let tx = match &mut state.tx {
+        Some(tx) if tx.token == tx_token => tx,
+        _ => return tx_not_found(),
+    };
+    tx.last_used = Instant::now();
+    // Each request runs under its own savepoint, so one that fails leaves
+    // the transaction as it was before the request
+    if let Err(e) = sqlx::query("SAVEPOINT exec_in_tx").execute(&mut *tx.conn).await {
+        return sqlite_error(format!("Failed to open savepoint: {}", e), "SQL_ERROR", &e);
+    }
+    if let Err(resp) = arm_authorizer(&mut tx.conn, &rules).await {
+        let _ = sqlx::query("RELEASE exec_in_tx").execute(&mut *tx.conn).await;
+        return resp;
+    }
+
+    let mut results = Vec::with_capacity(stmts.len());
+    let mut failed = None;
+    for (i, stmt) in stmts.iter().enumerate() {
+        match execute_in_tx(stmt, &mut tx.conn, config.max_result_bytes).await {
+            Ok(result) => results.push(result),
+            Err(e) => {
+                failed = Some((i, e));
+                break;
+            }
+        }
+    }
+    disarm_authorizer(&mut tx.conn, &rules).await;
+
+    let Some((i, e)) = failed else {
+        if let Err(e) = sqlx::query("RELEASE exec_in_tx").execute(&mut *tx.conn).await {
+            let resp = sqlite_error(format!("Failed to release savepoint: {}", e), "TX_ABORTED", &e);
+            abandon_tx(state, "savepoint release failed").await;
+            return resp;
+        }
+        tx.wrote |= results.iter().any(|result| result.columns.is_empty());
+        for table in changed_tables(&stmts) {
+            if !tx.changed.contains(&table) {
+                tx.changed.push(table);
+            }
+        }
+        return Response::ok_tx_executed(results);
+    };
+
+    let message = format!("Statement {}: {}", i, e);
+    // SQLite may already have rolled the whole transaction back
+    let undone = match crate::ffi::in_transaction(&mut tx.conn).await {
+        Ok(true) => sqlx::query("ROLLBACK TO exec_in_tx; RELEASE exec_in_tx")
+            .execute(&mut *tx.conn)
+            .await
+            .is_ok(),
+        Ok(false) => {
+            warn!(db = %state.db_name, tx = %tx.token, "Transaction rolled back by SQLite");
+            // Nothing left to roll back; the connection goes back as is
+            state.tx = None;
+            false
+        }
+        Err(_) => false,
+    };
+    if !undone {
+        abandon_tx(state, "savepoint rollback failed").await;
+        let message = format!("{}; the transaction was rolled back", message);
+        return sqlite_error(message, "TX_ABORTED", &*e);
+    }
+    let code = if e.is::<crate::query::ResultTooLarge>() { "RESULT_TOO_LARGE" } else { "SQL_ERROR" };
+    sqlite_error(message, code, &*e)
+}
+
+/// Run one statement of an `ExecInTx`: the rows of a statement that
+/// returns them, otherwise the rows it changed
+async fn execute_in_tx(
+    stmt: &Statement,
+    conn: &mut SqliteConnection,
+    max_bytes: usize,
+) -> Result<TxStatementResult> {
+    let numbered = stmt
+        .named_params
+        .as_ref()
+        .map(|named| crate::sql::number_named_params(&stmt.sql, named));
+    let (sql, params) = match &numbered {
+        Some((sql, params)) => (sql, params),
+        None => (&stmt.sql, &stmt.params),
+    };
+
+    let prepared = (&mut *conn).prepare(sql.as_str()).await?;
+    let columns: Vec<String> = sqlx::Statement::columns(&prepared)
+        .iter()
+        .map(|c| c.name().to_string())
+        .collect();
+    let mut query = sqlx::Statement::query(&prepared);
+    for param in params {
+        query = bind_param(query, param);
+    }
+
+    if columns.is_empty() {
+        let rows_affected = query.execute(&mut *conn).await?.rows_affected();
+        return Ok(TxStatementResult { rows_affected, columns, rows: Vec::new() });
+    }
+    let rows = crate::query::fetch_rows(query, &mut *conn, max_bytes, NullStyle::Null).await?;
+    Ok(TxStatementResult { rows_affected: 0, columns, rows })
+}
+
+/// Take the open transaction if `tx_token` names it
+fn take_tx(tx_token: &str, state: &mut WorkerState) -> Option<OpenTx> {
+    match state.tx.take() {
+        Some(tx) if tx.token == tx_token => Some(tx),
+        other => {
+            state.tx = other;
+            None
+        }
+    }
+}
+
+async fn handle_commit(tx_token: &str, state: &mut WorkerState) -> Response {
+    let Some(mut tx) = take_tx(tx_token, state) else {
+        return tx_not_found();
+    };
+    let change_feed = state.config.change_feed;
+    match commit_tx(&mut tx, change_feed).await {
+        Ok((rev, wrote)) => {
+            debug!(db = %state.db_name, tx = %tx.token, rev, wrote, "Transaction committed");
+            drop(tx);
+            if let (true, Ok(pool)) = (wrote, open_pool(state).cloned()) {
+                checkpoint_after_write(&pool, state).await;
+            }
+            Response::ok_tx_ended(true, Some(rev))
+        }
+        Err(e) => {
+            error!(db = %state.db_name, error = %e, "Failed to commit transaction");
+            if let Err(e) = rollback_tx(tx).await {
+                warn!(db = %state.db_name, error = %e, "Rollback after failed commit failed");
+            }
+            sqlite_error(e.to_string(), "TX_COMMIT_FAILED", &*e)
+        }
+    }
+}
+
+/// Bump the revision (if anything was written) and commit; returns the
+/// revision and whether the transaction wrote
+async fn commit_tx(tx: &mut OpenTx, change_feed: bool) -> Result<(i64, bool)> {
+    // Bumping the revision outside a transaction would persist it alone
+    if !crate::ffi::in_transaction(&mut tx.conn).await? {
+        anyhow::bail!("Transaction was already rolled back");
+    }
+    let changes = crate::ffi::total_changes(&mut tx.conn).await?;
+    let wrote = tx.wrote || changes != tx.changes_at_begin;
+    let rev = if wrote {
+        let rev = bump_revision_in_tx(&mut tx.conn, true).await?.unwrap_or_default();
+        if change_feed {
+            record_change(&mut tx.conn, rev, &tx.changed).await?;
+        }
+        rev
+    } else {
+        sqlx::query_scalar("SELECT rev FROM _daemon_meta").fetch_one(&mut *tx.conn).await?
+    };
+    sqlx::query("COMMIT").execute(&mut *tx.conn).await?;
+    Ok((rev, wrote))
+}
+
+async fn handle_rollback(tx_token: &str, state: &mut WorkerState) -> Response {
+    let Some(tx) = take_tx(tx_token, state) else {
+        return tx_not_found();
+    };
+    debug!(db = %state.db_name, tx = %tx.token, "Transaction rolled back");
+    match rollback_tx(tx).await {
+        Ok(()) => Response::ok_tx_ended(false, None),
+        Err(e) => Response::error(format!("Rollback failed: {}", e)),
+    }
+}
+
+/// Roll back the open transaction, if any, without its client asking
+async fn abandon_tx(state: &mut WorkerState, reason: &str) {
+    let Some(tx) = state.tx.take() else {
+        return;
+    };
+    warn!(db = %state.db_name, tx = %tx.token, reason, "Rolling back abandoned transaction");
+    if let Err(e) = rollback_tx(tx).await {
+        warn!(db = %state.db_name, error = %e, "Rollback failed");
+    }
+}
+
+/// Roll back and return the connection to the pool. A connection that
+/// fails to roll back is closed instead, so it never goes back to the pool
+/// mid-transaction.
+async fn rollback_tx(mut tx: OpenTx) -> Result<()> {
+    if let Err(e) = sqlx::query("ROLLBACK").execute(&mut *tx.conn).await {
+        drop(tx.conn.detach());
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+async fn handle_query(
+    stmt: Statement,
+    format: QueryFormat,
+    nulls: NullStyle,
+    deadline: Option<Instant>,
+    state: &WorkerState,
+) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+    let (config, rules) = (&state.config, &authorizer_rules(state));
+    run_query(&stmt, format, nulls, deadline, pool, config, rules, state.schema_frozen, &state.db_name)
+        .await
+}
+
+/// Run a `Query` on `pool`, the write pool or the read pool
+#[allow(clippy::too_many_arguments)]
+async fn run_query(
+    stmt: &Statement,
+    format: QueryFormat,
+    nulls: NullStyle,
+    deadline: Option<Instant>,
+    pool: &SqlitePool,
+    config: &DaemonConfig,
+    rules: &[AuthorizerRules],
+    schema_frozen: bool,
+    db_name: &str,
+) -> Response {
+    if let Err(e) = validate_statement(stmt, config, schema_frozen) {
+        return Response::error_with_code(e.to_string(), e.code());
+    }
+    let mut conn = match pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => return sqlite_error(e.to_string(), "SQL_ERROR", &e),
+    };
+    if let Err(resp) = arm_authorizer(&mut conn, rules).await {
+        return resp;
+    }
+
+    // Prepare first so column names are known even for empty results
+    let prepared = match (&mut *conn).prepare(stmt.sql.as_str()).await {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            disarm_authorizer(&mut conn, rules).await;
+            return sqlite_error(e.to_string(), "SQL_ERROR", &e);
+        }
+    };
+    // Writes belong in ExecBatch, which bumps the revision and records them
+    let refused = match crate::ffi::statements_readonly(&mut conn, &stmt.sql).await {
+        Ok(true) => None,
+        Ok(false) => Some(Response::error_with_code(
+            "Query only runs read-only statements; send writes with ExecBatch",
+            "QUERY_NOT_READ_ONLY",
+        )),
+        Err(e) => Some(Response::error(format!("Failed to check statement: {}", e))),
+    };
+    if let Some(resp) = refused {
+        disarm_authorizer(&mut conn, rules).await;
+        return resp;
+    }
+    let columns: Vec<String> = sqlx::Statement::columns(&prepared)
+        .iter()
+        .map(|c| c.name().to_string())
+        .collect();
+    // sqlx derives the type from the column's declared type ("NULL" if none)
+    let decl_types: Vec<Option<String>> = sqlx::Statement::columns(&prepared)
+        .iter()
+        .map(|c| Some(c.type_info().name().to_string()).filter(|t| t != "NULL"))
+        .collect();
+
+    let mut query = sqlx::Statement::query(&prepared);
+    for param in &stmt.params {
+        query = bind_param(query, param);
+    }
+
+    // Only JSON rows carry the sentinel; CSV and Arrow encode NULL themselves
+    let nulls = if format == QueryFormat::Json { nulls } else { NullStyle::Null };
+    let max_bytes = config.max_result_bytes;
+    if let Err(resp) = arm_deadline(&mut conn, deadline).await {
+        disarm_authorizer(&mut conn, rules).await;
+        return resp;
+    }
+    let fetched = crate::query::fetch_rows(query, &mut *conn, max_bytes, nulls).await;
+    disarm_deadline(&mut conn, deadline).await;
+    disarm_authorizer(&mut conn, rules).await;
+    let rows = match fetched {
+        Ok(rows) => rows,
+        Err(e) if e.is::<crate::query::ResultTooLarge>() => {
+            warn!(db = %db_name, sql = %stmt.sql, "Query result too large");
+            return Response::error_with_code(e.to_string(), "RESULT_TOO_LARGE");
+        }
+        Err(e) if timed_out(&*e, deadline) => {
+            warn!(db = %db_name, sql = %stmt.sql, "Query timed out");
+            return sqlite_error("Query exceeded its timeout_ms", "TIMEOUT", &*e);
+        }
+        Err(e) => {
+            debug!(error = %e, sql = %stmt.sql, "Query failed");
+            return sqlite_error(e.to_string(), "SQL_ERROR", &*e);
+        }
+    };
+
+    crate::query::format_results(columns, &decl_types, rows, format)
+}
+
+async fn handle_multi_query_scalar(queries: Vec<ScalarQuery>, state: &WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+    let rules = &authorizer_rules(state);
+    run_multi_query_scalar(&queries, pool, &state.config, rules, state.schema_frozen).await
+}
+
+/// Run a `MultiQueryScalar` on `pool`, the write pool or the read pool
+async fn run_multi_query_scalar(
+    queries: &[ScalarQuery],
+    pool: &SqlitePool,
+    config: &DaemonConfig,
+    rules: &[AuthorizerRules],
+    schema_frozen: bool,
+) -> Response {
+    let stmts: Vec<Statement> = queries
+        .iter()
+        .map(|q| Statement { sql: q.sql.clone(), params: q.params.clone(), named_params: None })
+        .collect();
+    for (q, stmt) in queries.iter().zip(&stmts) {
+        if let Err(e) = validate_statement(stmt, config, schema_frozen) {
+            return Response::error_with_code(format!("Query {}: {}", q.key, e), e.code());
+        }
+    }
+
+    // One deferred transaction gives every query the same snapshot; it is
+    // always rolled back, so nothing a query does can persist
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return sqlite_error(e.to_string(), "TX_BEGIN_FAILED", &e),
+    };
+    if let Err(resp) = arm_authorizer(&mut tx, rules).await {
+        return resp;
+    }
+
+    let mut values = BTreeMap::new();
+    let mut failed = None;
+    for (q, stmt) in queries.iter().zip(&stmts) {
+        // Writes belong in ExecBatch; a statement that doesn't compile
+        // fails below with its own error
+        if let Ok(false) = crate::ffi::statements_readonly(&mut tx, &stmt.sql).await {
+            failed = Some(Response::error_with_code(
+                format!("Query {}: only read-only statements run; send writes with ExecBatch", q.key),
+                "QUERY_NOT_READ_ONLY",
+            ));
+            break;
+        }
+        let mut query = sqlx::query(&stmt.sql);
+        for param in &stmt.params {
+            query = bind_param(query, param);
+        }
+        match query.fetch_optional(&mut *tx).await {
+            Ok(row) => {
+                let value = row
+                    .and_then(|row| crate::query::row_values(&row).into_iter().next())
+                    .unwrap_or(serde_json::Value::Null);
+                values.insert(q.key.clone(), value);
+            }
+            Err(e) => {
+                debug!(error = %e, key = %q.key, "Scalar query failed");
+                failed = Some(sqlite_error(format!("Query {}: {}", q.key, e), "SQL_ERROR", &e));
+                break;
+            }
+        }
+    }
+    disarm_authorizer(&mut tx, rules).await;
+
+    if let Err(e) = tx.rollback().await {
+        warn!(error = %e, "Failed to end read transaction");
+    }
+
+    failed.unwrap_or_else(|| Response::ok_multi_query_scalar(values))
+}
+
+async fn handle_describe_statement(sql: &str, state: &WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+
+    let described = match pool.acquire().await {
+        Ok(mut conn) => crate::ffi::try_prepare(&mut conn, sql).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match described {
+        Ok(check) => match check.error {
+            Some((message, offset)) => {
+                let message = match offset {
+                    Some(offset) => format!("{} (at byte {})", message, offset),
+                    None => message,
+                };
+                Response::error_with_code(message, "SQL_ERROR")
+            }
+            None => {
+                let columns = check
+                    .columns
+                    .into_iter()
+                    .map(|(name, decl_type)| ColumnInfo { name, decl_type })
+                    .collect();
+                Response::ok_describe_statement(check.params, columns)
+            }
+        },
+        Err(e) => Response::error(format!("Failed to describe statement: {}", e)),
+    }
+}
+
+async fn handle_explain(stmt: Statement, state: &WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+
+    if let Err(e) = validate_statement(&stmt, &state.config, state.schema_frozen) {
+        return Response::error_with_code(e.to_string(), e.code());
+    }
+    if crate::sql::leading_keyword(&stmt.sql).as_deref() == Some("EXPLAIN") {
+        return Response::error_with_code("Pass the statement without EXPLAIN", "INVALID_STATEMENT");
+    }
+
+    // EXPLAIN compiles the statement and lists its program; nothing runs
+    let sql = format!("EXPLAIN {}", stmt.sql);
+    let mut query = sqlx::query(&sql);
+    for param in &stmt.params {
+        query = bind_param(query, param);
+    }
+
+    let rows = match query.fetch_all(pool).await {
+        Ok(rows) => rows,
+        Err(e) => return sqlite_error(e.to_string(), "SQL_ERROR", &e),
+    };
+
+    let opcodes = rows
+        .iter()
+        .map(|row| {
+            // Columns: addr, opcode, p1, p2, p3, p4, p5, comment
+            let values = crate::query::row_values(row);
+            let int = |i: usize| values.get(i).and_then(|v| v.as_i64()).unwrap_or(0);
+            let text = |i: usize| values.get(i).and_then(|v| v.as_str()).map(str::to_string);
+            ExplainOp {
+                addr: int(0),
+                opcode: text(1).unwrap_or_default(),
+                p1: int(2),
+                p2: int(3),
+                p3: int(4),
+                p4: values.get(5).cloned().unwrap_or_default(),
+                p5: int(6),
+                comment: text(7),
+            }
+        })
+        .collect();
+
+    Response::ok_explain(opcodes)
+}
+
+async fn handle_change_feed(since_rev: i64, limit: u32, state: &WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+
+    if !state.config.change_feed {
+        return Response::error_with_code(
+            "Change feed is disabled (start the daemon with --change-feed)",
+            "CHANGE_FEED_DISABLED",
+        );
+    }
+
+    let rows: Vec<(i64, String, i64)> = match sqlx::query_as(
+        "SELECT rev, tables, ts FROM _daemon_change_log WHERE rev > ? ORDER BY rev LIMIT ?",
+    )
+    .bind(since_rev)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!(db = %state.db_name, error = %e, "Failed to read change log");
+            return Response::error(format!("Failed to read change log: {}", e));
+        }
+    };
+
+    let events = rows
+        .into_iter()
+        .map(|(rev, tables, ts)| ChangeEvent {
+            rev,
+            tables: serde_json::from_str(&tables).unwrap_or_default(),
+            ts,
+        })
+        .collect();
+
+    Response::ok_change_feed(events)
+}
+
+async fn handle_rev_history(since_rev: i64, limit: u32, state: &WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+
+    let rows: Vec<(i64, i64)> = match sqlx::query_as(
+        "SELECT rev, ts FROM _daemon_rev_history WHERE rev > ? ORDER BY rev LIMIT ?",
+    )
+    .bind(since_rev)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!(db = %state.db_name, error = %e, "Failed to read revision history");
+            return Response::error(format!("Failed to read revision history: {}", e));
+        }
+    };
+
+    let revisions = rows
+        .into_iter()
+        .map(|(rev, ts)| RevisionEntry { rev, ts })
+        .collect();
+
+    Response::ok_rev_history(revisions)
+}
+
+async fn handle_increment(counter: String, delta: i64, state: &mut WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool.clone(),
+        Err(resp) => return resp,
+    };
+
+    let change_feed = state.config.change_feed;
+    let result: Result<Option<i64>> = async {
+        let mut tx = pool.begin().await?;
+        // The update is skipped (no row returned) if the sum would leave
+        // the integer range, where SQLite would silently switch to REAL
+        let value: Option<i64> = sqlx::query_scalar(
+            r#"
+            INSERT INTO _daemon_counters(name, value) VALUES (?, ?)
+            ON CONFLICT(name) DO UPDATE SET value = value + excluded.value
+            WHERE typeof(value + excluded.value) = 'integer'
+            RETURNING value
+            "#,
+        )
+        .bind(&counter)
+        .bind(delta)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if value.is_none() {
+            return Ok(None);
+        }
+        let rev = bump_revision_in_tx(&mut tx, change_feed).await?;
+        if let Some(rev) = rev {
+            record_change(&mut tx, rev, &["_daemon_counters".to_string()]).await?;
+        }
+        tx.commit().await?;
+        Ok(value)
+    }
+    .await;
+
+    match result {
+        Ok(Some(value)) => {
+            checkpoint_after_write(&pool, state).await;
+            Response::ok_counter(counter, value)
+        }
+        Ok(None) => Response::error_with_code(
+            format!("Counter {} would overflow", counter),
+            "COUNTER_OVERFLOW",
+        ),
+        Err(e) => {
+            error!(db = %state.db_name, error = %e, "Failed to increment counter");
+            Response::error(format!("Failed to increment counter: {}", e))
+        }
+    }
+}
+
+/// Apply a session-extension changeset in one transaction, bumping the
+/// revision like a write batch
+#[cfg(feature = "session")]
+async fn handle_apply_changeset(
+    changeset: String,
+    policy: ChangesetConflictPolicy,
+    state: &mut WorkerState,
+) -> Response {
+    use base64::Engine;
+    let changeset = match base64::engine::general_purpose::STANDARD.decode(changeset.trim()) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return Response::error_with_code(
+                format!("Changeset is not valid base64: {}", e),
+                "INVALID_CHANGESET",
+            )
+        }
+    };
+    let pool = match open_pool(state) {
+        Ok(pool) => pool.clone(),
+        Err(resp) => return resp,
+    };
+
+    let config = Arc::clone(&state.config);
+    let rules = authorizer_rules(state);
+    let result: Result<(Option<i64>, crate::ffi::ChangesetOutcome)> = async {
+        let mut tx = pool.begin().await?;
+        let outcome = crate::ffi::apply_changeset(&mut *tx, &changeset, policy, &rules).await?;
+        if outcome.aborted || !outcome.denied.is_empty() {
+            // Dropping the transaction rolls back whatever was applied
+            return Ok((None, outcome));
+        }
+        let rev = bump_revision_in_tx(&mut tx, true).await?;
+        if let (true, Some(rev)) = (config.change_feed, rev) {
+            record_change(&mut *tx, rev, &outcome.tables).await?;
+        }
+        tx.commit().await?;
+        Ok((rev, outcome))
+    }
+    .await;
+
+    match result {
+        Ok((Some(rev), outcome)) => {
+            checkpoint_after_write(&pool, state).await;
+            Response::ok_changeset_applied(rev, outcome.conflicts)
+        }
+        Ok((None, outcome)) if !outcome.denied.is_empty() => Response::error_with_code(
+            format!("Changeset writes tables not allowed: {}", outcome.denied.join(", ")),
+            "AUTHORIZER_DENIED",
+        ),
+        Ok((None, outcome)) => {
+            let conflict = outcome.conflicts.last().map(|c| match &c.table {
+                Some(table) => format!("{} conflict in {}", c.kind, table),
+                None => format!("{} conflict", c.kind),
+            });
+            Response::error_with_code(
+                format!("Changeset aborted on {}", conflict.unwrap_or_default()),
+                "CHANGESET_CONFLICT",
+            )
+        }
+        Err(e) if e.is::<crate::ffi::ChangesetRejected>() => {
+            Response::error_with_code(format!("Changeset rejected: {}", e), "INVALID_CHANGESET")
+        }
+        Err(e) => {
+            error!(db = %state.db_name, error = %e, "Failed to apply changeset");
+            Response::error(format!("Failed to apply changeset: {}", e))
+        }
+    }
+}
+
+#[cfg(not(feature = "session"))]
+async fn handle_apply_changeset(
+    _changeset: String,
+    _policy: ChangesetConflictPolicy,
+    _state: &mut WorkerState,
+) -> Response {
+    Response::error_with_code(
+        "Changesets require a daemon built with the `session` feature",
+        "FEATURE_UNSUPPORTED",
+    )
+}
+
+/// Scratch table written by `Benchmark`; dropped when the run ends
+const BENCHMARK_TABLE: &str = "_daemon_benchmark";
+
+/// Most operations one `Benchmark` request may run
+const MAX_BENCHMARK_OPS: u32 = 1_000_000;
+
+async fn handle_benchmark(operation: BenchmarkOp, count: u32, state: &mut WorkerState) -> Response {
+    if !state.config.enable_benchmark {
+        return Response::error_with_code(
+            "Benchmark is disabled; start the daemon with --enable-benchmark",
+            "BENCHMARK_DISABLED",
+        );
+    }
+    if count == 0 || count > MAX_BENCHMARK_OPS {
+        return Response::error_with_code(
+            format!("Benchmark count must be between 1 and {}", MAX_BENCHMARK_OPS),
+            "INVALID_BENCHMARK",
+        );
+    }
+    if state.schema_frozen {
+        return Response::error_with_code(
+            "Benchmark creates a scratch table, which a frozen schema forbids",
+            "SCHEMA_FROZEN",
+        );
+    }
+    let pool = match open_pool(state) {
+        Ok(pool) => pool.clone(),
+        Err(resp) => return resp,
+    };
+
+    let create = format!(
+        "CREATE TABLE IF NOT EXISTS {} (id INTEGER PRIMARY KEY, payload BLOB NOT NULL)",
+        BENCHMARK_TABLE
+    );
+    if let Err(e) = sqlx::query(&create).execute(&pool).await {
+        return Response::error(format!("Failed to create benchmark table: {}", e));
+    }
+
+    let insert = format!("INSERT INTO {} (payload) VALUES (randomblob(64))", BENCHMARK_TABLE);
+    let mut samples = Vec::with_capacity(count as usize);
+    let mut errors = 0;
+    let start = Instant::now();
+    let run: Result<()> = async {
+        match operation {
+            BenchmarkOp::Insert => {
+                for _ in 0..count {
+                    let op = Instant::now();
+                    if sqlx::query(&insert).execute(&pool).await.is_err() {
+                        errors += 1;
+                    }
+                    samples.push(crate::stats::micros(op.elapsed()));
+                }
+            }
+            BenchmarkOp::InsertTx => {
+                let mut tx = pool.begin().await?;
+                for _ in 0..count {
+                    let op = Instant::now();
+                    if sqlx::query(&insert).execute(&mut *tx).await.is_err() {
+                        errors += 1;
+                    }
+                    samples.push(crate::stats::micros(op.elapsed()));
+                }
+                tx.commit().await?;
+            }
+        }
+        Ok(())
+    }
+    .await;
+    let duration = start.elapsed();
+
+    if let Err(e) = sqlx::query(&format!("DROP TABLE IF EXISTS {}", BENCHMARK_TABLE))
+        .execute(&pool)
+        .await
+    {
+        warn!(db = %state.db_name, error = %e, "Failed to drop benchmark table");
+    }
+    if let Err(e) = run {
+        return Response::error(format!("Benchmark failed: {}", e));
+    }
+    checkpoint_after_write(&pool, state).await;
+
+    info!(
+        db = %state.db_name,
+        ?operation,
+        count,
+        errors,
+        duration_ms = duration.as_millis() as u64,
+        "Benchmark finished"
+    );
+    Response::ok_benchmark(operation, count, errors, duration, crate::stats::quantiles(samples))
+}
+
+async fn handle_stats(state: &WorkerState) -> Response {
+    let cached_statements = match open_pool(state) {
+        Ok(pool) => pool.acquire().await.ok().map(|conn| conn.cached_statements_size()),
+        Err(_) => None,
+    };
+    Response::ok_stats(
+        state.stats.requests,
+        state.stats.errors,
+        state.stats.latency.quantiles(),
+        state.breaker.state_name().to_string(),
+        state.started.elapsed().as_secs(),
+        cached_statements,
+    )
+}
+
+async fn handle_set_synchronous(level: SyncLevel, state: &WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+
+    let previous: i64 = match sqlx::query_scalar("PRAGMA synchronous").fetch_one(pool).await {
+        Ok(previous) => previous,
+        Err(e) => return Response::error(format!("Failed to read synchronous: {}", e)),
+    };
+    let Some(previous) = SyncLevel::from_pragma(previous) else {
+        return Response::error(format!("Unexpected synchronous value: {}", previous));
+    };
+    if let Err(e) = sqlx::query(&format!("PRAGMA synchronous = {}", level.as_pragma()))
+        .execute(pool)
+        .await
+    {
+        return Response::error(format!("Failed to set synchronous: {}", e));
+    }
+
+    if level == SyncLevel::Off {
+        warn!(db = %state.db_name, "synchronous=OFF: database may corrupt on power loss");
+    } else {
+        info!(db = %state.db_name, ?level, "synchronous changed");
+    }
+    Response::ok_synchronous(level, previous)
+}
+
+async fn handle_clear_statement_cache(state: &WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+
+    // The pool holds a single connection, so this is the whole cache
+    let mut conn = match pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => return Response::error(format!("Failed to acquire connection: {}", e)),
+    };
+    let cleared = conn.cached_statements_size();
+    if let Err(e) = conn.clear_cached_statements().await {
+        return Response::error(format!("Failed to clear statement cache: {}", e));
+    }
+
+    info!(db = %state.db_name, cleared, "Statement cache cleared");
+    Response::ok_statement_cache_cleared(cleared)
+}
+
+/// Pragmas reported by `PragmaStats`; all are read-only queries
+const STAT_PRAGMAS: &[&str] = &[
+    "page_count",
+    "freelist_count",
+    "page_size",
+    "cache_size",
+    "wal_autocheckpoint",
+    "journal_mode",
+    "synchronous",
+    "foreign_keys",
+    "data_version",
+];
+
+async fn handle_pragma_stats(state: &WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+
+    let mut pragmas = BTreeMap::new();
+    for &pragma in STAT_PRAGMAS {
+        let row = match sqlx::query(&format!("PRAGMA {}", pragma)).fetch_optional(pool).await {
+            Ok(row) => row,
+            Err(e) => return Response::error(format!("Failed to read PRAGMA {}: {}", pragma, e)),
+        };
+        let value = row
+            .and_then(|row| crate::query::row_values(&row).into_iter().next())
+            .unwrap_or(serde_json::Value::Null);
+        pragmas.insert(pragma.to_string(), value);
+    }
+
+    Response::ok_pragma_stats(pragmas)
+}
+
+async fn handle_set_schema_frozen(frozen: bool, state: &mut WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+
+    let value = if frozen { "1" } else { "0" };
+    if let Err(e) = db_meta_set(pool, SCHEMA_FROZEN_KEY, value).await {
+        error!(db = %state.db_name, error = %e, "Failed to store schema flag");
+        return Response::error(format!("Failed to store schema flag: {}", e));
+    }
+
+    state.schema_frozen = frozen;
+    info!(db = %state.db_name, frozen, "Schema freeze flag changed");
+    Response::ok_schema_frozen(frozen)
+}
+
+async fn handle_get_schema_version(state: &WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+    match user_version(pool).await {
+        Ok(version) => Response::ok_schema_version(version),
+        Err(e) => sqlite_error(format!("Failed to read user_version: {}", e), "SQL_ERROR", &e),
+    }
+}
+
+async fn handle_set_schema_version(version: i32, state: &mut WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool.clone(),
+        Err(resp) => return resp,
+    };
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return sqlite_error(e.to_string(), "TX_BEGIN_FAILED", &e),
+    };
+    if let Err(e) = set_user_version(&mut tx, version).await {
+        return sqlite_error(format!("Failed to set user_version: {}", e), "SQL_ERROR", &e);
+    }
+    if let Err(e) = bump_revision_in_tx(&mut tx, false).await {
+        error!(db = %state.db_name, error = %e, "Failed to update revision");
+        return Response::error("Failed to update revision");
+    }
+    if let Err(e) = tx.commit().await {
+        return sqlite_error(e.to_string(), "TX_COMMIT_FAILED", &e);
+    }
+    checkpoint_after_write(&pool, state).await;
+
+    info!(db = %state.db_name, version, "Schema version set");
+    Response::ok_schema_version(version)
+}
+
+async fn handle_migrate(
+    mut migrations: Vec<Migration>,
+    progress: Option<ProgressSender>,
+    state: &mut WorkerState,
+) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool.clone(),
+        Err(resp) => return resp,
+    };
+
+    migrations.sort_by_key(|m| m.version);
+    if let Some(pair) = migrations.windows(2).find(|pair| pair[0].version == pair[1].version) {
+        return Response::error_with_code(
+            format!("Migration version {} appears more than once", pair[0].version),
+            "INVALID_MIGRATIONS",
+        );
+    }
+    // Checked up front, so a rejected migration doesn't leave the run half done
+    for m in &migrations {
+        let stmt = Statement { sql: m.sql.clone(), params: Vec::new(), named_params: None };
+        if let Err(e) = validate_statement(&stmt, &state.config, state.schema_frozen) {
+            return Response::error_with_code(format!("Migration {}: {}", m.version, e), e.code());
+        }
+    }
+
+    let current = match user_version(&pool).await {
+        Ok(version) => version,
+        Err(e) => return sqlite_error(format!("Failed to read user_version: {}", e), "SQL_ERROR", &e),
+    };
+    let pending: Vec<&Migration> = migrations.iter().filter(|m| m.version > current).collect();
+    let mut report = MigrateReport { progress, started: Instant::now(), migrations: pending.len(), migration: 0 };
+    let mut applied = 0;
+    let mut last_rev = None;
+    for m in pending {
+        report.migration += 1;
+        match apply_migration(m, &report, &authorizer_rules(state), &pool).await {
+            Ok(rev) => {
+                info!(db = %state.db_name, version = m.version, rev, "Migration applied");
+                applied += 1;
+                last_rev = Some((m.version, rev));
+            }
+            Err(e) => {
+                error!(db = %state.db_name, version = m.version, error = %e, "Migration failed");
+                if applied > 0 {
+                    checkpoint_after_write(&pool, state).await;
+                }
+                let message = format!(
+                    "Migration {} failed, rolled back ({} applied before it): {:#}",
+                    m.version, applied, e
+                );
+                return sqlite_error(message, "MIGRATION_FAILED", &*e);
+            }
+        }
+    }
+
+    let (version, rev) = match last_rev {
+        Some(last) => {
+            checkpoint_after_write(&pool, state).await;
+            last
+        }
+        None => match get_current_rev(&pool).await {
+            Ok(rev) => (current, rev),
+            Err(e) => return Response::error(format!("Failed to read revision: {}", e)),
+        },
+    };
+    Response::ok_migrate(applied, version, rev)
+}
+
+/// Where a `Migrate` run stands, for its progress frames
+struct MigrateReport {
+    progress: Option<ProgressSender>,
+    started: Instant,
+    migrations: usize,
+    /// Position of the migration being applied, from 1
+    migration: usize,
+}
+
+impl MigrateReport {
+    fn send(&self, migration: &Migration, statement: usize, statements: usize, phase: MigratePhase) {
+        let Some(progress) = &self.progress else { return };
+        let _ = progress.send(MigrateProgress {
+            version: migration.version,
+            migration: self.migration,
+            migrations: self.migrations,
+            statement,
+            statements,
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+            phase,
+        });
+    }
+}
+
+/// Run one migration, set `user_version` to its version and bump the
+/// revision in a single transaction; returns the new revision. Statements
+/// run one at a time, so progress and a failure can name the statement.
+async fn apply_migration(
+    migration: &Migration,
+    report: &MigrateReport,
+    rules: &[AuthorizerRules],
+    pool: &SqlitePool,
+) -> Result<i64> {
+    let statements = crate::sql::split_statements(&migration.sql);
+    let mut done = 0;
+    let applied = async {
+        let mut tx = pool.begin().await?;
+        if let Err(Response::Error { message, .. }) = arm_authorizer(&mut tx, rules).await {
+            anyhow::bail!(message);
+        }
+        for sql in &statements {
+            // Not `raw_sql`: its future is not `Send` for every borrow of
+            // the connection, which keeps the worker task from spawning
+            if let Err(e) = (&mut *tx).execute(*sql).await {
+                disarm_authorizer(&mut tx, rules).await;
+                return Err(anyhow::Error::new(e)
+                    .context(format!("statement {} of {}", done + 1, statements.len())));
+            }
+            done += 1;
+            report.send(migration, done, statements.len(), MigratePhase::Running);
+        }
+        disarm_authorizer(&mut tx, rules).await;
+        set_user_version(&mut tx, migration.version).await?;
+        let rev = bump_revision_in_tx(&mut tx, true).await?.context("Revision not read back")?;
+        tx.commit().await?;
+        Ok(rev)
+    }
+    .await;
+    let phase = if applied.is_ok() { MigratePhase::Committed } else { MigratePhase::RolledBack };
+    report.send(migration, done, statements.len(), phase);
+    applied
+}
+
+async fn user_version<'c, E>(executor: E) -> sqlx::Result<i32>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    sqlx::query_scalar("PRAGMA user_version").fetch_one(executor).await
+}
+
+async fn set_user_version(conn: &mut SqliteConnection, version: i32) -> sqlx::Result<()> {
+    // PRAGMA values can't be bound; an i32 formats safely
+    sqlx::query(&format!("PRAGMA user_version = {}", version)).execute(conn).await?;
+    Ok(())
+}
+
+async fn handle_vacuum(state: &mut WorkerState) -> Response {
+    // Readers would keep the checkpoints from emptying the WAL
+    close_read_pool(state).await;
+    let pool = match open_pool(state) {
+        Ok(pool) => pool.clone(),
+        Err(resp) => return resp,
+    };
+    let in_memory = state.in_memory;
+    let file_size = |path: &Path| {
+        if in_memory { 0 } else { std::fs::metadata(path).map(|meta| meta.len()).unwrap_or(0) }
+    };
+
+    let start = Instant::now();
+    if let Err(e) = checkpoint_wal(&pool, &mut state.stats).await {
+        warn!(db = %state.db_name, error = %e, "Checkpoint before VACUUM failed");
+    }
+    let size_before = file_size(&state.db_path);
+    if let Err(e) = sqlx::query("VACUUM").execute(&pool).await {
+        error!(db = %state.db_name, error = %e, "VACUUM failed");
+        let busy = crate::query::extended_code(&e).is_some_and(|code| matches!(code & 0xff, 5 | 6));
+        let code = if busy { "DB_BUSY" } else { "SQL_ERROR" };
+        return sqlite_error(format!("VACUUM failed: {}", e), code, &e);
+    }
+    // In WAL mode the rebuilt pages land in the WAL first
+    if let Err(e) = checkpoint_wal(&pool, &mut state.stats).await {
+        warn!(db = %state.db_name, error = %e, "Checkpoint after VACUUM failed");
+    }
+    let size_after = file_size(&state.db_path);
+    let duration_ms = start.elapsed().as_millis() as u64;
+
+    let rev = match get_current_rev(&pool).await {
+        Ok(rev) => rev,
+        Err(e) => return Response::error(format!("Failed to read revision: {}", e)),
+    };
+    info!(db = %state.db_name, size_before, size_after, duration_ms, "Database vacuumed");
+    Response::ok_vacuum(size_before, size_after, duration_ms, rev)
+}
+
+async fn handle_analyze(state: &WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+
+    let start = Instant::now();
+    if let Err(e) = sqlx::query("ANALYZE").execute(pool).await {
+        error!(db = %state.db_name, error = %e, "ANALYZE failed");
+        return sqlite_error(format!("ANALYZE failed: {}", e), "SQL_ERROR", &e);
+    }
+    let duration_ms = start.elapsed().as_millis() as u64;
+
+    // ANALYZE creates sqlite_stat1, so it exists from here on
+    let analyzed_tables: Vec<String> =
+        match sqlx::query_scalar("SELECT DISTINCT tbl FROM sqlite_stat1 ORDER BY tbl")
+            .fetch_all(pool)
+            .await
+        {
+            Ok(tables) => tables,
+            Err(e) => return Response::error(format!("Failed to read statistics: {}", e)),
+        };
+    let rev = match get_current_rev(pool).await {
+        Ok(rev) => rev,
+        Err(e) => return Response::error(format!("Failed to read revision: {}", e)),
+    };
+    info!(db = %state.db_name, tables = analyzed_tables.len(), duration_ms, "Database analyzed");
+    Response::ok_analyze(analyzed_tables, duration_ms, rev)
+}
+
+async fn handle_vacuum_into(dest_path: &str, overwrite: bool, state: &WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+
+    let dest = match backup_dest(dest_path, overwrite, &state.config) {
+        Ok(dest) => dest,
+        Err(resp) => return resp,
+    };
+
+    // Vacuum into a temporary name and rename, so a failure never leaves a
+    // partial file at `dest` (and VACUUM INTO refuses existing files)
+    let tmp = temp_dest(&dest);
+    let _ = tokio::fs::remove_file(&tmp).await;
+
+    let start = Instant::now();
+    if let Err(e) = sqlx::query("VACUUM INTO ?")
+        .bind(tmp.to_string_lossy().into_owned())
+        .execute(pool)
+        .await
+    {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        error!(db = %state.db_name, dest = %dest.display(), error = %e, "VACUUM INTO failed");
+        // SQLITE_BUSY/LOCKED: another process holds a lock or open transaction
+        let busy = crate::query::extended_code(&e).is_some_and(|code| matches!(code & 0xff, 5 | 6));
+        let code = if busy { "DB_BUSY" } else { "SQL_ERROR" };
+        return sqlite_error(format!("VACUUM INTO failed: {}", e), code, &e);
+    }
+    if let Err(e) = tokio::fs::rename(&tmp, &dest).await {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Response::error(format!("Failed to move backup into place: {}", e));
+    }
+    let duration_ms = start.elapsed().as_millis() as u64;
+
+    let size_bytes = match tokio::fs::metadata(&dest).await {
+        Ok(meta) => meta.len(),
+        Err(e) => return Response::error(format!("Failed to stat backup: {}", e)),
+    };
+
+    info!(
+        db = %state.db_name,
+        dest = %dest.display(),
+        size_bytes,
+        duration_ms,
+        "Database vacuumed into backup"
+    );
+    Response::ok_vacuum_into(dest.display().to_string(), size_bytes, duration_ms)
+}
+
+/// Where `VacuumInto`/`Backup` write `dest_path`: a plain relative path
+/// inside `--backup-dir`, which must be set
+#[allow(clippy::result_large_err)]
+fn backup_dest(dest_path: &str, overwrite: bool, config: &DaemonConfig) -> Result<PathBuf, Response> {
+    let Some(backup_dir) = &config.backup_dir else {
+        return Err(Response::error_with_code(
+            "Backups are disabled (start the daemon with --backup-dir)",
+            "BACKUP_DIR_NOT_SET",
+        ));
+    };
+    // Same sandbox rule as database names
+    let name = Path::new(dest_path);
+    if dest_path.is_empty() || !name.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(Response::error_with_code(
+            format!("Invalid destination: {}", dest_path),
+            "INVALID_DEST_PATH",
+        ));
+    }
+    let dest = backup_dir.join(name);
+    if dest.exists() && !overwrite {
+        return Err(Response::error_with_code(
+            format!("Destination already exists: {}", dest.display()),
+            "DEST_EXISTS",
+        ));
+    }
+    Ok(dest)
+}
+
+/// `dest` with `.tmp` appended, written first and renamed into place
+fn temp_dest(dest: &Path) -> PathBuf {
+    let mut tmp = dest.as_os_str().to_owned();
+    tmp.push(".tmp");
+    PathBuf::from(tmp)
+}
+
+async fn handle_export_schema(state: &WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
+
+    match crate::schema::export_schema(pool).await {
+        Ok(ddl) => Response::ok_export_schema(ddl),
+        Err(e) => {
+            error!(db = %state.db_name, error = %e, "Failed to export schema");
+            Response::error(format!("Failed to export schema: {}", e))
         }
     }
 }
 
-async fn handle_exec_batch(
-    stmts: Vec<Statement>,
-    tx_mode: TransactionMode,
-    state: &WorkerState,
-) -> Response {
-    match &state.db_state {
-        DatabaseState::Open(pool) => {
-            if stmts.is_empty() {
-                return Response::error("Empty statement batch");
-            }
-
-            // Validate statements
-            for (i, stmt) in stmts.iter().enumerate() {
-                if let Err(e) = validate_statement(stmt) {
-                    return Response::error(format!("Statement {}: {}", i, e));
-                }
-            }
+async fn handle_prune_meta(before_ts: i64, state: &WorkerState) -> Response {
+    let pool = match open_pool(state) {
+        Ok(pool) => pool,
+        Err(resp) => return resp,
+    };
 
-            match tx_mode {
-                TransactionMode::Atomic => execute_atomic_batch(stmts, pool).await,
-                TransactionMode::None => execute_separate_batch(stmts, pool).await,
-            }
+    match crate::schema::prune_daemon_tables(pool, before_ts).await {
+        Ok(pruned) => {
+            info!(db = %state.db_name, before_ts, ?pruned, "Pruned daemon tables");
+            Response::ok_prune_meta(pruned)
         }
-        DatabaseState::Preparing => {
-            Response::error_with_code(
-                "Database is preparing for maintenance",
-                "DATABASE_PREPARING"
-            )
-        }
-        DatabaseState::Closed => {
-            Response::error_with_code(
-                "Database is closed for maintenance",
-                "DATABASE_CLOSED"
-            )
+        Err(e) => {
+            error!(db = %state.db_name, error = %e, "Failed to prune daemon tables");
+            Response::error(format!("Failed to prune daemon tables: {}", e))
         }
     }
 }
 
-async fn handle_prepare_maintenance(state: &mut WorkerState) -> Response {
+async fn handle_prepare_maintenance(exclusive: bool, state: &mut WorkerState) -> Response {
+    if state.in_memory {
+        return in_memory_error("No file to maintain");
+    }
+    // Readers would keep the checkpoint from emptying the WAL
+    close_read_pool(state).await;
     match &state.db_state {
         DatabaseState::Open(pool) => {
-            info!(db = %state.db_name, "Preparing database for maintenance");
+            info!(db = %state.db_name, exclusive, "Preparing database for maintenance");
             
+            if exclusive {
+                if let Err(e) = acquire_exclusive_lock(pool).await {
+                    error!(db = %state.db_name, error = %e, "Failed to acquire exclusive lock");
+                    return Response::error_with_code(
+                        format!("Failed to acquire exclusive lock: {}", e),
+                        "LOCK_FAILED",
+                    );
+                }
+            }
+
             // Checkpoint WAL to flush all data to main DB file
-            if let Err(e) = checkpoint_wal(pool).await {
+            let checkpointed = checkpoint_wal(pool, &mut state.stats).await;
+
+            if exclusive {
+                if let Err(e) = release_exclusive_lock(pool).await {
+                    warn!(db = %state.db_name, error = %e, "Failed to return to normal locking mode");
+                }
+            }
+
+            if let Err(e) = checkpointed {
                 error!(db = %state.db_name, error = %e, "Failed to checkpoint WAL");
                 return Response::error(format!("Failed to checkpoint WAL: {}", e));
             }
@@ -224,6 +3189,7 @@ async fn handle_prepare_maintenance(state: &mut WorkerState) -> Response {
                 DatabaseState::Open(p) => p,
                 _ => unreachable!(),
             };
+            state.state_since = Instant::now();
             pool.close().await;
             
             info!(db = %state.db_name, "Database in preparing state, read locks released");
@@ -231,47 +3197,96 @@ async fn handle_prepare_maintenance(state: &mut WorkerState) -> Response {
         }
         DatabaseState::Preparing => Response::error("Database is already preparing"),
         DatabaseState::Closed => Response::error("Database is already closed"),
+        DatabaseState::Parked => Response::error("Database is parked"),
     }
 }
 
+/// Switch the worker connection to `locking_mode=EXCLUSIVE` and take the
+/// lock. In WAL mode an exclusive connection keeps its locks after each
+/// transaction ends, so no other process can read or write the file until
+/// `release_exclusive_lock`. Relies on the pool having a single connection.
+async fn acquire_exclusive_lock(pool: &SqlitePool) -> Result<()> {
+    let mut conn = pool.acquire().await?;
+    sqlx::query("PRAGMA locking_mode=EXCLUSIVE").execute(&mut *conn).await?;
+    // The lock is taken by the next write transaction and then kept
+    sqlx::query("BEGIN EXCLUSIVE").execute(&mut *conn).await?;
+    sqlx::query("COMMIT").execute(&mut *conn).await?;
+    Ok(())
+}
+
+/// Return to `locking_mode=NORMAL`; the lock is dropped on the next access
+async fn release_exclusive_lock(pool: &SqlitePool) -> Result<()> {
+    let mut conn = pool.acquire().await?;
+    sqlx::query("PRAGMA locking_mode=NORMAL").execute(&mut *conn).await?;
+    sqlx::query("SELECT 1 FROM sqlite_master LIMIT 1").execute(&mut *conn).await?;
+    Ok(())
+}
+
 async fn handle_close_database(state: &mut WorkerState) -> Response {
+    close_read_pool(state).await;
     match &state.db_state {
         DatabaseState::Open(pool) => {
             info!(db = %state.db_name, "Closing database");
             
             // Final checkpoint before closing
-            if let Err(e) = checkpoint_wal(pool).await {
+            if let Err(e) = checkpoint_wal(pool, &mut state.stats).await {
                 warn!(db = %state.db_name, error = %e, "Failed final checkpoint before close");
             }
             
             pool.close().await;
             state.db_state = DatabaseState::Closed;
+            state.state_since = Instant::now();
             
             info!(db = %state.db_name, "Database closed, file locks released");
             Response::ok_close_database()
         }
-        DatabaseState::Preparing => {
-            // Allow closing from Preparing state (pool already closed)
+        DatabaseState::Preparing | DatabaseState::Parked => {
+            // Allow closing from Preparing/Parked state (pool already closed)
             info!(db = %state.db_name, "Closing database from preparing state");
             state.db_state = DatabaseState::Closed;
+            state.state_since = Instant::now();
             Response::ok_close_database()
         }
         DatabaseState::Closed => Response::error("Database is already closed"),
     }
 }
 
-async fn handle_reopen_database(state: &mut WorkerState) -> Response {
+async fn handle_reopen_database(from_path: Option<String>, state: &mut WorkerState) -> Response {
     if matches!(state.db_state, DatabaseState::Open(_)) {
         return Response::error("Database is already open");
     }
+
+    if let Some(from_path) = from_path {
+        if state.in_memory {
+            return in_memory_error("Cannot reopen from a file");
+        }
+        let from = match import_source(&from_path, &state.config) {
+            Ok(from) => from,
+            Err(resp) => return resp,
+        };
+        if let Err(e) = replace_database_file(&from, state).await {
+            error!(db = %state.db_name, from = %from_path, error = %e, "Failed to replace database file");
+            return Response::error(format!("Failed to replace database file: {}", e));
+        }
+    }
     
+    if !state.in_memory && !state.config.auto_create && !state.db_path.exists() {
+        return Response::error_with_code(
+            format!("Database not found: {}", state.db_path.display()),
+            "DB_NOT_FOUND",
+        );
+    }
+
     info!(db = %state.db_name, "Reopening database");
     
-    let pool = match init_database(&state.db_path).await {
-        Ok(pool) => pool,
+    let pool = match init_database(&state.db_path, &state.db_name, &state.config).await {
+        Ok((pool, schema_frozen)) => {
+            state.schema_frozen = schema_frozen;
+            pool
+        }
         Err(e) => {
             error!(db = %state.db_name, error = %e, "Failed to reopen database");
-            return Response::error(format!("Failed to open database: {}", e));
+            return open_error_response(&e);
         }
     };
     
@@ -280,16 +3295,120 @@ async fn handle_reopen_database(state: &mut WorkerState) -> Response {
         Err(e) => {
             error!(db = %state.db_name, error = %e, "Failed to get revision after reopen");
             state.db_state = DatabaseState::Open(pool);
+            state.state_since = Instant::now();
             return Response::error(format!("Database opened but failed to get revision: {}", e));
         }
     };
     
     state.db_state = DatabaseState::Open(pool);
+    state.state_since = Instant::now();
+    // A fresh file gets a fresh breaker
+    state.breaker.reset();
     info!(db = %state.db_name, rev = rev, "Database reopened successfully");
-    Response::ok_reopen_database(rev)
+
+    if state.journal.is_empty() {
+        return Response::ok_reopen_database(rev, 0, 0);
+    }
+    let (replayed, replay_failed) = replay_journal(state).await;
+    let rev = match open_pool(state) {
+        Ok(pool) => get_current_rev(pool).await.unwrap_or(rev),
+        Err(_) => rev,
+    };
+    Response::ok_reopen_database(rev, replayed, replay_failed)
+}
+
+/// The file a `ReopenDatabase` `from_path` names, which must resolve
+/// (symlinks and `..` included) to a file inside `--import-dir`. A relative
+/// path is taken from that directory.
+#[allow(clippy::result_large_err)]
+fn import_source(from_path: &str, config: &DaemonConfig) -> Result<PathBuf, Response> {
+    let Some(import_dir) = &config.import_dir else {
+        return Err(Response::error_with_code(
+            "Reopening from a file is disabled (start the daemon with --import-dir)",
+            "IMPORT_DIR_NOT_SET",
+        ));
+    };
+    let invalid = |reason: String| {
+        Response::error_with_code(format!("Invalid from_path {}: {}", from_path, reason), "INVALID_FROM_PATH")
+    };
+    let import_dir = import_dir
+        .canonicalize()
+        .map_err(|e| invalid(format!("import dir {}: {}", import_dir.display(), e)))?;
+    let from = import_dir
+        .join(from_path)
+        .canonicalize()
+        .map_err(|e| invalid(e.to_string()))?;
+    if !from.starts_with(&import_dir) || !from.is_file() {
+        return Err(invalid(format!("not a file inside {}", import_dir.display())));
+    }
+    Ok(from)
+}
+
+/// Rename `from_path` over the database file (pool must be closed). With
+/// `fsync_dir`, the parent directory is fsynced afterwards: on Unix a rename
+/// is only durable once its directory entry is flushed. Windows has no
+/// directory fsync; NTFS journals the rename metadata, so it is skipped.
+async fn replace_database_file(from_path: &Path, state: &WorkerState) -> Result<()> {
+    let from = from_path.to_path_buf();
+    let to = state.db_path.clone();
+    let fsync_dir = state.config.fsync_dir;
+
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        std::fs::rename(&from, &to)
+            .with_context(|| format!("rename {} -> {}", from.display(), to.display()))?;
+
+        #[cfg(unix)]
+        if fsync_dir {
+            if let Some(dir) = to.parent() {
+                std::fs::File::open(dir)
+                    .and_then(|d| d.sync_all())
+                    .with_context(|| format!("fsync directory {}", dir.display()))?;
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = fsync_dir;
+
+        Ok(())
+    })
+    .await
+    .context("File replacement task failed")??;
+
+    info!(db = %state.db_name, from = %from_path.display(), "Database file replaced");
+    Ok(())
+}
+
+/// Get the open pool, or the coded error for a database in maintenance
+#[allow(clippy::result_large_err)]
+fn open_pool(state: &WorkerState) -> Result<&SqlitePool, Response> {
+    match &state.db_state {
+        DatabaseState::Open(pool) => Ok(pool),
+        DatabaseState::Preparing => Err(Response::error_with_code(
+            "Database is preparing for maintenance",
+            "DATABASE_PREPARING",
+        )),
+        DatabaseState::Closed => Err(Response::error_with_code(
+            "Database is closed for maintenance",
+            "DATABASE_CLOSED",
+        )),
+        DatabaseState::Parked => Err(Response::error_with_code(
+            "Database is parked after idling",
+            "DATABASE_PARKED",
+        )),
+    }
 }
 
-async fn execute_atomic_batch(stmts: Vec<Statement>, pool: &SqlitePool) -> Response {
+#[allow(clippy::too_many_arguments)]
+async fn execute_atomic_batch(
+    stmts: &[Statement],
+    changed: Option<&[String]>,
+    return_rev: bool,
+    retries: u32,
+    digest_tables: Option<&[String]>,
+    deadline: Option<Instant>,
+    report: BatchReport,
+    rules: &[AuthorizerRules],
+    pool: &SqlitePool,
+) -> Response {
     let start = Instant::now();
 
     // Begin transaction
@@ -297,21 +3416,56 @@ async fn execute_atomic_batch(stmts: Vec<Statement>, pool: &SqlitePool) -> Respo
         Ok(tx) => tx,
         Err(e) => {
             error!(error = %e, "Failed to begin transaction");
-            return Response::error_with_code(e.to_string(), "TX_BEGIN_FAILED");
+            return sqlite_error(e.to_string(), "TX_BEGIN_FAILED", &e);
         }
     };
 
-    // Execute all statements
-    let total_rows = match execute_statements_in_tx(&stmts, &mut tx).await {
-        Ok(rows) => rows,
+    let session = match report.changeset {
+        true => match crate::ffi::start_session(&mut tx).await {
+            Ok(session) => Some(session),
+            Err(e) => return Response::error(format!("Failed to start session: {}", e)),
+        },
+        false => None,
+    };
+
+    // Execute all statements; dropping `tx` on failure rolls them back
+    if let Err(resp) = arm_authorizer(&mut tx, rules).await {
+        return resp;
+    }
+    if let Err(resp) = arm_deadline(&mut tx, deadline).await {
+        disarm_authorizer(&mut tx, rules).await;
+        return resp;
+    }
+    let executed = execute_statements(stmts, report.count_replaced, &mut tx).await;
+    disarm_deadline(&mut tx, deadline).await;
+    disarm_authorizer(&mut tx, rules).await;
+    // Ended before the revision bump, so daemon bookkeeping stays out of it
+    let changeset = match session {
+        Some(session) => match session.changeset(&mut tx).await {
+            Ok(bytes) => {
+                use base64::Engine;
+                Some(base64::engine::general_purpose::STANDARD.encode(bytes))
+            }
+            Err(e) => return Response::error(format!("Failed to read changeset: {}", e)),
+        },
+        None => None,
+    };
+    let (total_rows, conflicts) = match executed {
+        Ok(result) => result,
+        Err((i, e)) if timed_out(&e, deadline) => {
+            warn!(statement_index = i, sql = %stmts[i].sql, "Batch timed out, rolling back");
+            let message = format!("Statement {}: batch exceeded its timeout_ms, rolled back", i);
+            return sqlite_error(message, "TIMEOUT", &e);
+        }
         Err((i, e)) => {
             error!(error = %e, statement_index = i, sql = %stmts[i].sql, "Statement execution failed");
-            return Response::error_with_code(format!("Statement {}: {}", i, e), "SQL_ERROR");
+            return sqlite_error(format!("Statement {}: {}", i, e), "SQL_ERROR", &e);
         }
     };
 
-    // Bump revision
-    let rev = match bump_revision_in_tx(&mut tx).await {
+    // Bump revision (the change feed needs the new rev even if the client doesn't)
+    let read_rev = return_rev || changed.is_some();
+    let rev = match bump_revision_in_tx(&mut tx, read_rev).await {
         Ok(rev) => rev,
         Err(e) => {
             error!(error = %e, "Failed to update revision");
@@ -319,42 +3473,89 @@ async fn execute_atomic_batch(stmts: Vec<Statement>, pool: &SqlitePool) -> Respo
         }
     };
 
+    if let (Some(tables), Some(rev)) = (changed, rev) {
+        if let Err(e) = record_change(&mut tx, rev, tables).await {
+            error!(error = %e, "Failed to record change feed entry");
+            return Response::error("Failed to record change feed entry");
+        }
+    }
+
+    // Digest inside the transaction, so it reflects exactly this commit
+    let digest = match digest_tables {
+        Some(tables) => match table_digests(&mut tx, tables).await {
+            Ok(digest) => Some(digest),
+            Err(e) => return Response::error(format!("Failed to compute digest: {}", e)),
+        },
+        None => None,
+    };
+
     // Commit transaction
     if let Err(e) = tx.commit().await {
         error!(error = %e, "Failed to commit transaction");
-        return Response::error_with_code(e.to_string(), "TX_COMMIT_FAILED");
+        return sqlite_error(e.to_string(), "TX_COMMIT_FAILED", &e);
     }
 
     debug!(
         batch_size = stmts.len(),
         rows_affected = total_rows,
         duration_ms = start.elapsed().as_millis(),
-        rev = rev,
+        rev = ?rev,
         "Executed atomic batch"
     );
 
-    // Passive checkpoint after write (non-blocking, won't fail the write)
-    if let Err(e) = checkpoint_wal_passive(pool).await {
-        debug!(error = %e, "Passive WAL checkpoint failed (non-critical)");
+    let mut resp = Response::ok_exec(rev.filter(|_| return_rev), total_rows, conflicts, retries, digest);
+    if let Response::Ok { data: ResponseData::ExecBatch { changeset: recorded, .. } } = &mut resp {
+        *recorded = changeset;
     }
-
-    Response::ok_exec(rev, total_rows)
+    resp
 }
 
-async fn execute_separate_batch(stmts: Vec<Statement>, pool: &SqlitePool) -> Response {
+#[allow(clippy::too_many_arguments)]
+async fn execute_separate_batch(
+    stmts: Vec<Statement>,
+    changed: Option<Vec<String>>,
+    return_rev: bool,
+    digest_tables: Option<Vec<String>>,
+    deadline: Option<Instant>,
+    report: BatchReport,
+    rules: &[AuthorizerRules],
+    pool: &SqlitePool,
+) -> Response {
     warn!("Executing batch in separate transactions (dangerous!)");
 
     // Execute all statements
-    let total_rows = match execute_statements_in_pool(&stmts, pool).await {
-        Ok(rows) => rows,
+    let mut conn = match pool.acquire().await {
+        Ok(conn) => conn,
+        Err(e) => return sqlite_error(format!("Statement 0: {}", e), "SQL_ERROR", &e),
+    };
+    if let Err(resp) = arm_authorizer(&mut conn, rules).await {
+        return resp;
+    }
+    if let Err(resp) = arm_deadline(&mut conn, deadline).await {
+        disarm_authorizer(&mut conn, rules).await;
+        return resp;
+    }
+    let executed = execute_statements(&stmts, report.count_replaced, &mut conn).await;
+    disarm_deadline(&mut conn, deadline).await;
+    disarm_authorizer(&mut conn, rules).await;
+    drop(conn);
+    let (total_rows, conflicts) = match executed {
+        Ok(result) => result,
+        Err((i, e)) if timed_out(&e, deadline) => {
+            // Statements before `i` committed on their own
+            warn!(statement_index = i, sql = %stmts[i].sql, "Batch timed out");
+            let message = format!("Statement {}: batch exceeded its timeout_ms", i);
+            return sqlite_error(message, "TIMEOUT", &e);
+        }
         Err((i, e)) => {
             error!(error = %e, statement_index = i, sql = %stmts[i].sql, "Statement execution failed");
-            return Response::error_with_code(format!("Statement {}: {}", i, e), "SQL_ERROR");
+            return sqlite_error(format!("Statement {}: {}", i, e), "SQL_ERROR", &e);
         }
     };
 
-    // Bump revision
-    let rev = match bump_revision(pool).await {
+    // Bump revision (the change feed needs the new rev even if the client doesn't)
+    let read_rev = return_rev || changed.is_some();
+    let rev = match bump_revision(pool, read_rev).await {
         Ok(rev) => rev,
         Err(e) => {
             error!(error = %e, "Failed to read revision");
@@ -362,138 +3563,776 @@ async fn execute_separate_batch(stmts: Vec<Statement>, pool: &SqlitePool) -> Res
         }
     };
 
-    // Passive checkpoint after write (non-blocking, won't fail the write)
-    if let Err(e) = checkpoint_wal_passive(pool).await {
-        debug!(error = %e, "Passive WAL checkpoint failed (non-critical)");
+    if let (Some(tables), Some(rev)) = (&changed, rev) {
+        let recorded = match pool.acquire().await {
+            Ok(mut conn) => record_change(&mut conn, rev, tables).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = recorded {
+            warn!(error = %e, "Failed to record change feed entry");
+        }
     }
 
-    Response::ok_exec(rev, total_rows)
+    let digest = match digest_tables {
+        Some(tables) => {
+            let digest = match pool.acquire().await {
+                Ok(mut conn) => table_digests(&mut conn, &tables).await,
+                Err(e) => Err(e.into()),
+            };
+            match digest {
+                Ok(digest) => Some(digest),
+                Err(e) => return Response::error(format!("Failed to compute digest: {}", e)),
+            }
+        }
+        None => None,
+    };
+
+    Response::ok_exec(rev.filter(|_| return_rev), total_rows, conflicts, 0, digest)
 }
 
-fn bind_param<'q>(
-    query: sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>>,
-    value: &'q serde_json::Value,
-) -> sqlx::query::Query<'q, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'q>> {
-    use serde_json::Value;
+/// Arm a request's `timeout_ms` deadline, if it has one, on the connection
+/// about to run its statements
+async fn arm_deadline(conn: &mut SqliteConnection, deadline: Option<Instant>) -> Result<(), Response> {
+    let Some(deadline) = deadline else {
+        return Ok(());
+    };
+    crate::ffi::set_deadline(conn, Some(deadline))
+        .await
+        .map_err(|e| Response::error(format!("Failed to set statement deadline: {}", e)))
+}
 
-    match value {
-        Value::Null => query.bind(None::<String>),
-        Value::Bool(b) => query.bind(*b as i64),
-        Value::Number(n) => {
-            if let Some(i) = n.as_i64() {
-                query.bind(i)
-            } else if let Some(u) = n.as_u64() {
-                query.bind(u as i64)
-            } else if let Some(f) = n.as_f64() {
-                query.bind(f)
-            } else {
-                query.bind(None::<i64>)
-            }
-        }
-        Value::String(s) => query.bind(s.as_str()),
-        Value::Array(_) | Value::Object(_) => {
-            query.bind(value.to_string())
-        }
+/// Remove an armed deadline, so it can't interrupt whatever runs next on
+/// the connection
+async fn disarm_deadline(conn: &mut SqliteConnection, deadline: Option<Instant>) {
+    if deadline.is_none() {
+        return;
+    }
+    if let Err(e) = crate::ffi::set_deadline(conn, None).await {
+        warn!(error = %e, "Failed to remove statement deadline");
     }
 }
 
-fn validate_statement(stmt: &Statement) -> Result<()> {
-    if stmt.sql.len() > 100_000 {
-        bail!("SQL statement too long (max 100KB)");
+/// Arm the authorizer rules, if any, on the connection about to run a
+/// request's client statements. Daemon bookkeeping (revision bumps,
+/// savepoints, `user_version`) runs after they are removed again.
+async fn arm_authorizer(conn: &mut SqliteConnection, rules: &[AuthorizerRules]) -> Result<(), Response> {
+    if rules.is_empty() {
+        return Ok(());
     }
+    crate::ffi::set_authorizer(conn, rules.to_vec())
+        .await
+        .map_err(|e| Response::error(format!("Failed to set authorizer: {}", e)))
+}
 
-    if stmt.params.len() > 999 {
-        bail!("Too many parameters (SQLite limit is 999)");
+/// Remove armed authorizer rules, so they can't deny daemon bookkeeping
+async fn disarm_authorizer(conn: &mut SqliteConnection, rules: &[AuthorizerRules]) {
+    if rules.is_empty() {
+        return;
     }
-
-    let sql_upper = stmt.sql.trim().to_uppercase();
-    if sql_upper.contains("PRAGMA WRITABLE_SCHEMA") {
-        bail!("Dangerous pragma rejected");
+    if let Err(e) = crate::ffi::set_authorizer(conn, Vec::new()).await {
+        warn!(error = %e, "Failed to remove authorizer");
     }
+}
 
-    Ok(())
+/// The daemon's rules plus those of the client whose request is running
+fn authorizer_rules(state: &WorkerState) -> Vec<AuthorizerRules> {
+    AuthorizerRules::combined(&state.config.authorizer, state.client_rules.as_deref())
+}
+
+/// A statement failed because its request's deadline interrupted it
+/// (`SQLITE_INTERRUPT`)
+fn timed_out(e: &(dyn std::error::Error + 'static), deadline: Option<Instant>) -> bool {
+    deadline.is_some() && crate::query::extended_code(e).is_some_and(|code| code & 0xff == 9)
+}
+
+/// Digest of each table in `tables` that exists (a batch may have dropped one)
+async fn table_digests(
+    conn: &mut sqlx::SqliteConnection,
+    tables: &[String],
+) -> Result<BTreeMap<String, TableDigest>> {
+    let mut digests = BTreeMap::new();
+    for table in tables {
+        let exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ? COLLATE NOCASE)",
+        )
+        .bind(table)
+        .fetch_one(&mut *conn)
+        .await?;
+        if !exists {
+            continue;
+        }
+        let summary = crate::compare::summarize_table(conn, table).await?;
+        digests.insert(
+            table.clone(),
+            TableDigest { rows: summary.rows, checksum: format!("{:016x}", summary.checksum) },
+        );
+    }
+    Ok(digests)
 }
 
 async fn get_current_rev(pool: &SqlitePool) -> Result<i64> {
-    let rev: i64 = sqlx::query_scalar("SELECT rev FROM meta")
+    let rev: i64 = sqlx::query_scalar("SELECT rev FROM _daemon_meta")
         .fetch_one(pool)
         .await?;
     Ok(rev)
 }
 
-async fn bump_revision(pool: &SqlitePool) -> Result<i64> {
+/// `_daemon_meta.ts` never goes backwards: if the wall clock steps back (NTP
+/// correction, VM resume) the previous timestamp is kept, so `ts` is
+/// monotonic non-decreasing alongside the strictly increasing `rev`
+const BUMP_REVISION_SQL: &str = "UPDATE _daemon_meta SET rev = rev + 1, ts = MAX(ts, ?)";
+
+/// Bump the revision; the new value is read back only if `read` is set
+async fn bump_revision(pool: &SqlitePool, read: bool) -> Result<Option<i64>> {
     let ts = time::OffsetDateTime::now_utc().unix_timestamp();
-    sqlx::query("UPDATE meta SET rev = rev + 1, ts = ?")
+    sqlx::query(BUMP_REVISION_SQL)
         .bind(ts)
         .execute(pool)
         .await?;
-    get_current_rev(pool).await
+    if !read {
+        return Ok(None);
+    }
+    get_current_rev(pool).await.map(Some)
 }
 
-async fn bump_revision_in_tx(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>) -> Result<i64> {
+/// Bump the revision on a connection inside a transaction
+async fn bump_revision_in_tx(conn: &mut SqliteConnection, read: bool) -> Result<Option<i64>> {
     let ts = time::OffsetDateTime::now_utc().unix_timestamp();
-    sqlx::query("UPDATE meta SET rev = rev + 1, ts = ?")
+    sqlx::query(BUMP_REVISION_SQL)
         .bind(ts)
-        .execute(&mut **tx)
+        .execute(&mut *conn)
         .await?;
-    let rev: i64 = sqlx::query_scalar("SELECT rev FROM meta")
-        .fetch_one(&mut **tx)
+    if !read {
+        return Ok(None);
+    }
+    let rev: i64 = sqlx::query_scalar("SELECT rev FROM _daemon_meta")
+        .fetch_one(&mut *conn)
         .await?;
-    Ok(rev)
+    Ok(Some(rev))
 }
 
-async fn checkpoint_wal(pool: &SqlitePool) -> Result<()> {
-    sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
-        .execute(pool)
-        .await?;
-    Ok(())
+/// Distinct tables written by a batch, in first-seen order
+fn changed_tables(stmts: &[Statement]) -> Vec<String> {
+    let mut tables: Vec<String> = Vec::new();
+    for table in stmts.iter().filter_map(|s| crate::sql::written_table(&s.sql)) {
+        if !tables.contains(&table) {
+            tables.push(table);
+        }
+    }
+    tables
 }
 
-async fn checkpoint_wal_passive(pool: &SqlitePool) -> Result<()> {
-    sqlx::query("PRAGMA wal_checkpoint(PASSIVE)")
-        .execute(pool)
+async fn record_change(
+    conn: &mut sqlx::SqliteConnection,
+    rev: i64,
+    tables: &[String],
+) -> Result<()> {
+    // Same (clamped) timestamp the revision bump stored in _daemon_meta
+    sqlx::query("INSERT INTO _daemon_change_log(rev, tables, ts) VALUES (?, ?, (SELECT ts FROM _daemon_meta))")
+        .bind(rev)
+        .bind(serde_json::to_string(tables)?)
+        .execute(conn)
         .await?;
     Ok(())
 }
 
-async fn execute_statements_in_tx(
-    stmts: &[Statement],
-    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
-) -> Result<u64, (usize, sqlx::Error)> {
-    let mut total_rows = 0u64;
-    
-    for (i, stmt) in stmts.iter().enumerate() {
-        let mut query = sqlx::query(&stmt.sql);
-        for param in &stmt.params {
-            query = bind_param(query, param);
+async fn checkpoint_wal(pool: &SqlitePool, stats: &mut WorkerStats) -> Result<()> {
+    run_checkpoint(pool, "TRUNCATE", stats).await.map(|_| ())
+}
+
+/// Passive checkpoint after a write, escalated to TRUNCATE once the WAL
+/// reaches `--checkpoint-truncate-frames`. Never fails the write.
+async fn checkpoint_after_write(pool: &SqlitePool, state: &mut WorkerState) {
+    if state.in_memory {
+        return;
+    }
+    let event = match run_checkpoint(pool, "PASSIVE", &mut state.stats).await {
+        Ok(event) => event,
+        Err(e) => {
+            debug!(error = %e, "Passive WAL checkpoint failed (non-critical)");
+            return;
         }
-        
-        match query.execute(&mut **tx).await {
-            Ok(result) => total_rows += result.rows_affected(),
-            Err(e) => return Err((i, e)),
+    };
+    let threshold = state.config.checkpoint_truncate_frames;
+    if threshold > 0 && event.log_frames >= threshold {
+        if let Err(e) = checkpoint_wal(pool, &mut state.stats).await {
+            debug!(db = %state.db_name, error = %e, "WAL truncate after write failed (non-critical)");
         }
     }
-    
-    Ok(total_rows)
 }
 
-async fn execute_statements_in_pool(
-    stmts: &[Statement],
+/// Run `PRAGMA wal_checkpoint(<mode>)` and add it to the worker's history
+async fn run_checkpoint(
     pool: &SqlitePool,
-) -> Result<u64, (usize, sqlx::Error)> {
+    mode: &'static str,
+    stats: &mut WorkerStats,
+) -> Result<CheckpointEvent> {
+    let start = Instant::now();
+    let (busy, log_frames, checkpointed_frames): (i64, i64, i64) =
+        sqlx::query_as(&format!("PRAGMA wal_checkpoint({})", mode))
+            .fetch_one(pool)
+            .await?;
+    let event = CheckpointEvent {
+        mode: mode.to_string(),
+        busy: busy != 0,
+        log_frames,
+        checkpointed_frames,
+        duration_us: start.elapsed().as_micros() as u64,
+        ts: time::OffsetDateTime::now_utc().unix_timestamp(),
+    };
+    stats.checkpoints.record(event.clone());
+    Ok(event)
+}
+
+/// Execute statements in order on one connection, returning the total
+/// rows affected plus the outcome of every OR-conflict insert
+async fn execute_statements(
+    stmts: &[Statement],
+    count_replaced: bool,
+    conn: &mut sqlx::SqliteConnection,
+) -> Result<(u64, Vec<ConflictOutcome>), (usize, sqlx::Error)> {
     let mut total_rows = 0u64;
-    
+    let mut conflicts = Vec::new();
+
     for (i, stmt) in stmts.iter().enumerate() {
-        let mut query = sqlx::query(&stmt.sql);
-        for param in &stmt.params {
+        // REPLACE deletes are not counted in rows_affected (nor any change
+        // counter), so with `count_replaced` inserted vs replaced is derived
+        // from the table's row count before and after
+        let resolution = crate::sql::insert_conflict_resolution(&stmt.sql);
+        let table = resolution.as_ref().and_then(|_| crate::sql::written_table(&stmt.sql));
+        let rows_before = match (&resolution, &table) {
+            (Some(r), Some(table)) if r == "REPLACE" && count_replaced => {
+                Some(count_rows(conn, table).await.map_err(|e| (i, e))?)
+            }
+            _ => None,
+        };
+
+        let numbered = stmt
+            .named_params
+            .as_ref()
+            .map(|named| crate::sql::number_named_params(&stmt.sql, named));
+        let (sql, params) = match &numbered {
+            Some((sql, params)) => (sql, params),
+            None => (&stmt.sql, &stmt.params),
+        };
+        let mut query = sqlx::query(sql);
+        for param in params {
             query = bind_param(query, param);
         }
-        
-        match query.execute(pool).await {
-            Ok(result) => total_rows += result.rows_affected(),
+
+        let rows = match query.execute(&mut *conn).await {
+            Ok(result) => result.rows_affected(),
             Err(e) => return Err((i, e)),
+        };
+        total_rows += rows;
+
+        if let Some(resolution) = resolution {
+            let inserted = match (rows_before, &table) {
+                (Some(before), Some(table)) => {
+                    let after = count_rows(conn, table).await.map_err(|e| (i, e))?;
+                    (after - before).max(0) as u64
+                }
+                _ => rows,
+            };
+            let counted = rows_before.is_some() || resolution != "REPLACE";
+            conflicts.push(ConflictOutcome {
+                index: i,
+                resolution,
+                inserted,
+                replaced: counted.then(|| rows.saturating_sub(inserted)),
+            });
         }
     }
-    
-    Ok(total_rows)
+
+    Ok((total_rows, conflicts))
+}
+
+async fn count_rows(conn: &mut sqlx::SqliteConnection, table: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(&format!("SELECT COUNT(*) FROM \"{}\"", table.replace('"', "\"\"")))
+        .fetch_one(conn)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// A worker serving `test.db` in a temporary directory of its own
+    struct TestWorker {
+        dir: PathBuf,
+        commands: mpsc::Sender<WorkerCommand>,
+        task: tokio::task::JoinHandle<()>,
+    }
+
+    const DB: &str = "test.db";
+
+    impl TestWorker {
+        fn start(name: &str, config: DaemonConfig) -> Self {
+            let dir = std::env::temp_dir().join(format!("skylinedb-worker-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            let (commands, rx) = mpsc::channel(16);
+            let evictable = Arc::new(AtomicBool::new(false));
+            let task = tokio::spawn(worker_loop(rx, dir.join(DB), DB.to_string(), Arc::new(config), evictable));
+            TestWorker { dir, commands, task }
+        }
+
+        async fn request(&self, req: Request) -> Response {
+            send(&self.commands, req).await
+        }
+
+        async fn exec(&self, sqls: &[&str], tx: TransactionMode) -> Response {
+            self.request(batch(sqls, tx)).await
+        }
+
+        async fn rows(&self, sql: &str) -> Vec<Vec<serde_json::Value>> {
+            let req = serde_json::from_value(json!({ "type": "Query", "db": DB, "sql": sql })).unwrap();
+            match self.request(req).await {
+                Response::Ok { data: ResponseData::Query { rows, .. } } => rows,
+                other => panic!("{:?}", other),
+            }
+        }
+
+        async fn stop(self) {
+            self.commands.send(WorkerCommand::Drain).await.unwrap();
+            self.task.await.unwrap();
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    async fn send(commands: &mpsc::Sender<WorkerCommand>, req: Request) -> Response {
+        let (reply, response) = oneshot::channel();
+        let cmd = WorkerCommand::Request { req, rules: None, reply: Reply::new(reply, None) };
+        commands.send(cmd).await.unwrap();
+        response.await.unwrap()
+    }
+
+    fn batch(sqls: &[&str], tx: TransactionMode) -> Request {
+        let stmts = sqls.iter().map(|sql| Statement::new(*sql, Vec::new())).collect();
+        Request::exec_batch(DB, stmts, tx)
+    }
+
+    fn assert_ok(resp: &Response) {
+        assert!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+    }
+
+    fn assert_code(resp: &Response, expected: &str) {
+        assert!(matches!(resp, Response::Error { code: Some(code), .. } if code == expected), "{:?}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_journal_replays_in_order_past_a_failed_batch() {
+        let config = DaemonConfig { journal_closed_writes: true, ..DaemonConfig::default() };
+        let worker = TestWorker::start("journal", config);
+        assert_ok(&worker.exec(&["CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT)"], TransactionMode::Atomic).await);
+        let db = DB.to_string();
+        assert_ok(&worker.request(Request::PrepareForMaintenance { db: db.clone(), exclusive: false }).await);
+        assert_ok(&worker.request(Request::CloseDatabase { db: db.clone() }).await);
+
+        // The second batch fails halfway on replay; the third depends on the
+        // first having run before it
+        let batches = [
+            batch(&["INSERT INTO t VALUES (1, 'a')"], TransactionMode::Atomic),
+            batch(&["INSERT INTO t VALUES (2, 'b')", "INSERT INTO t VALUES (1, 'dup')"], TransactionMode::Atomic),
+            batch(&["UPDATE t SET v = v || '!' WHERE id = 1", "INSERT INTO t VALUES (3, 'c')"], TransactionMode::None),
+        ];
+        for (i, req) in batches.into_iter().enumerate() {
+            let resp = worker.request(req).await;
+            let Response::Ok { data: ResponseData::Journaled { journaled } } = resp else { panic!("{:?}", resp) };
+            assert_eq!(journaled, i + 1);
+        }
+
+        let resp = worker.request(Request::ReopenDatabase { db: db.clone(), from_path: None }).await;
+        let Response::Ok { data: ResponseData::ReopenDatabase { replayed, replay_failed, .. } } = resp else {
+            panic!("{:?}", resp);
+        };
+        assert_eq!((replayed, replay_failed), (2, 1));
+        // The failed batch was rolled back whole, the others applied in order
+        assert_eq!(worker.rows("SELECT id, v FROM t ORDER BY id").await, [[json!(1), json!("a!")], [json!(3), json!("c")]]);
+
+        // Replayed once only: the journal is empty after a reopen
+        assert_ok(&worker.request(Request::PrepareForMaintenance { db: db.clone(), exclusive: false }).await);
+        assert_ok(&worker.request(Request::CloseDatabase { db: db.clone() }).await);
+        let resp = worker.request(Request::ReopenDatabase { db, from_path: None }).await;
+        let Response::Ok { data: ResponseData::ReopenDatabase { replayed: 0, replay_failed: 0, .. } } = resp else {
+            panic!("{:?}", resp);
+        };
+        assert_eq!(worker.rows("SELECT count(*) FROM t").await, [[json!(2)]]);
+        worker.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_increment_refuses_to_overflow() {
+        let worker = TestWorker::start("increment", DaemonConfig::default());
+        let increment = |counter: &str, delta| Request::Increment { db: DB.to_string(), counter: counter.to_string(), delta };
+        let value = |resp: Response| match resp {
+            Response::Ok { data: ResponseData::Counter { value, .. } } => value,
+            other => panic!("{:?}", other),
+        };
+
+        assert_eq!(value(worker.request(increment("up", i64::MAX - 1)).await), i64::MAX - 1);
+        assert_eq!(value(worker.request(increment("up", 1)).await), i64::MAX);
+        assert_code(&worker.request(increment("up", 1)).await, "COUNTER_OVERFLOW");
+        assert_eq!(value(worker.request(increment("up", 0)).await), i64::MAX);
+
+        assert_eq!(value(worker.request(increment("down", i64::MIN)).await), i64::MIN);
+        assert_code(&worker.request(increment("down", -1)).await, "COUNTER_OVERFLOW");
+        assert_eq!(value(worker.request(increment("down", 1)).await), i64::MIN + 1);
+        worker.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_query_maps_each_storage_class_to_json() {
+        let worker = TestWorker::start("query", DaemonConfig::default());
+        let rev = |resp: Response| match resp {
+            Response::Ok { data: ResponseData::ExecBatch { rev: Some(rev), .. } } => rev,
+            other => panic!("{:?}", other),
+        };
+        let before = rev(worker.exec(&["CREATE TABLE t (x INTEGER)"], TransactionMode::Atomic).await);
+
+        let row = [json!(null), json!(42), json!(-1.5), json!("text"), json!({ "$blob": "AQI=" })];
+        assert_eq!(worker.rows("SELECT NULL, 42, -1.5, 'text', x'0102'").await, [row]);
+
+        // Columns come from the statement, so an empty result still names them
+        let req = serde_json::from_value(json!({ "type": "Query", "db": DB, "sql": "SELECT x AS n FROM t" })).unwrap();
+        let resp = worker.request(req).await;
+        let Response::Ok { data: ResponseData::Query { columns, rows, .. } } = resp else { panic!("{:?}", resp) };
+        assert_eq!((columns, rows.len()), (vec!["n".to_string()], 0));
+
+        // Reads leave the revision alone
+        let after = rev(worker.exec(&["INSERT INTO t VALUES (1)"], TransactionMode::Atomic).await);
+        assert_eq!(after, before + 1);
+        worker.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_busy_batch_is_retried_until_the_lock_is_released() {
+        let config = DaemonConfig { busy_timeout_ms: 0, ..DaemonConfig::default() };
+        let worker = TestWorker::start("busy", config);
+        assert_ok(&worker.exec(&["CREATE TABLE t (x INTEGER)"], TransactionMode::Atomic).await);
+
+        // Another process holds the write lock
+        let options = SqliteConnectOptions::new().filename(worker.dir.join(DB));
+        let mut other = SqliteConnection::connect_with(&options).await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut other).await.unwrap();
+
+        let retried = |max_retries| {
+            let mut req = batch(&["INSERT INTO t VALUES (1)"], TransactionMode::Atomic);
+            if let Request::ExecBatch { max_retries: retries, retry_backoff_ms, .. } = &mut req {
+                *retries = Some(max_retries);
+                *retry_backoff_ms = Some(10);
+            }
+            req
+        };
+        let resp = worker.request(retried(0)).await;
+        assert!(is_busy(&resp), "{:?}", resp);
+
+        let commands = worker.commands.clone();
+        let pending = tokio::spawn(async move { send(&commands, retried(100)).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        sqlx::query("COMMIT").execute(&mut other).await.unwrap();
+        let resp = pending.await.unwrap();
+        let Response::Ok { data: ResponseData::ExecBatch { retries, .. } } = resp else { panic!("{:?}", resp) };
+        assert!(retries > 0);
+        assert_eq!(worker.rows("SELECT count(*) FROM t").await, [[json!(1)]]);
+        other.close().await.unwrap();
+        worker.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_exec_many_without_tx_bumps_rev_for_committed_sets() {
+        let worker = TestWorker::start("many-partial", DaemonConfig::default());
+        let rev = |resp: Response| match resp {
+            Response::Ok { data: ResponseData::ExecBatch { rev: Some(rev), .. } } => rev,
+            other => panic!("{:?}", other),
+        };
+        let create = worker.exec(&["CREATE TABLE t (x INTEGER NOT NULL)"], TransactionMode::Atomic).await;
+        let before = rev(create);
+        let many = |tx| Request::ExecMany {
+            db: DB.to_string(),
+            sql: "INSERT INTO t (x) VALUES (?)".to_string(),
+            param_sets: vec![vec![json!(1)], vec![json!(2)], vec![serde_json::Value::Null]],
+            tx,
+            return_rev: true,
+        };
+
+        // Atomic: nothing is kept, so the revision stays
+        let resp = worker.request(many(TransactionMode::Atomic)).await;
+        assert_code(&resp, "SQL_ERROR");
+        assert_eq!(worker.rows("SELECT count(*) FROM t").await, [[json!(0)]]);
+
+        // Separate: the first two sets stay, and count as a change
+        let resp = worker.request(many(TransactionMode::None)).await;
+        assert_code(&resp, "SQL_ERROR");
+        let Response::Error { message, .. } = &resp else { unreachable!() };
+        assert!(message.starts_with("Parameter set 2 (sets 0-1 committed)"), "{}", message);
+        assert_eq!(worker.rows("SELECT count(*) FROM t").await, [[json!(2)]]);
+        let after = rev(worker.exec(&["INSERT INTO t (x) VALUES (3)"], TransactionMode::Atomic).await);
+        assert_eq!(after, before + 2);
+        worker.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_coalesced_batches_report_busy_retries() {
+        let config = DaemonConfig {
+            busy_timeout_ms: 0,
+            busy_retries: 100,
+            busy_retry_backoff_ms: 10,
+            coalesce_window_ms: 50,
+            ..DaemonConfig::default()
+        };
+        let worker = TestWorker::start("coalesce-busy", config);
+        assert_ok(&worker.exec(&["CREATE TABLE t (x INTEGER)"], TransactionMode::Atomic).await);
+
+        let options = SqliteConnectOptions::new().filename(worker.dir.join(DB));
+        let mut other = SqliteConnection::connect_with(&options).await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut other).await.unwrap();
+
+        // Both land in one coalescing window and are retried as a group
+        let pending: Vec<_> = (0..2)
+            .map(|_| {
+                let commands = worker.commands.clone();
+                let req = batch(&["INSERT INTO t VALUES (1)"], TransactionMode::Atomic);
+                tokio::spawn(async move { send(&commands, req).await })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        sqlx::query("COMMIT").execute(&mut other).await.unwrap();
+        for pending in pending {
+            let resp = pending.await.unwrap();
+            let Response::Ok { data: ResponseData::ExecBatch { retries, .. } } = resp else { panic!("{:?}", resp) };
+            assert!(retries > 0);
+        }
+        assert_eq!(worker.rows("SELECT count(*) FROM t").await, [[json!(2)]]);
+        other.close().await.unwrap();
+        worker.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_yield_to_writer_gives_up_after_busy_timeout() {
+        let config = DaemonConfig {
+            busy_policy: BusyPolicy::YieldToWriter,
+            busy_timeout_ms: 200,
+            ..DaemonConfig::default()
+        };
+        let worker = TestWorker::start("yield", config);
+        assert_ok(&worker.exec(&["CREATE TABLE t (x INTEGER)"], TransactionMode::Atomic).await);
+
+        let options = SqliteConnectOptions::new().filename(worker.dir.join(DB));
+        let mut other = SqliteConnection::connect_with(&options).await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut other).await.unwrap();
+
+        // The handler waits out the configured timeout, not a fixed 5 s
+        let started = Instant::now();
+        let resp = worker.exec(&["INSERT INTO t VALUES (1)"], TransactionMode::Atomic).await;
+        assert!(is_busy(&resp), "{:?}", resp);
+        let waited = started.elapsed();
+        assert!(waited >= Duration::from_millis(200) && waited < Duration::from_secs(2), "{:?}", waited);
+        other.close().await.unwrap();
+        worker.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_transaction_lifecycle() {
+        let worker = TestWorker::start("tx", DaemonConfig::default());
+        assert_ok(&worker.exec(&["CREATE TABLE t (x INTEGER)"], TransactionMode::Atomic).await);
+        let db = DB.to_string();
+        let begin = || Request::Begin { db: db.clone() };
+        let exec_in_tx = |tx_token: &str, sql: &str| Request::ExecInTx {
+            db: db.clone(),
+            tx_token: tx_token.to_string(),
+            stmts: vec![Statement::new(sql, Vec::new())],
+        };
+        let token = |resp: Response| match resp {
+            Response::Ok { data: ResponseData::TxBegun { tx_token } } => tx_token,
+            other => panic!("{:?}", other),
+        };
+
+        let tx_token = token(worker.request(begin()).await);
+        assert_ok(&worker.request(exec_in_tx(&tx_token, "INSERT INTO t VALUES (1)")).await);
+        let resp = worker.request(exec_in_tx(&tx_token, "SELECT count(*) FROM t")).await;
+        let Response::Ok { data: ResponseData::TxExecuted { tx_results } } = resp else { panic!("{:?}", resp) };
+        assert_eq!(tx_results[0].rows, [[json!(1)]]);
+        assert_code(&worker.request(exec_in_tx("other", "INSERT INTO t VALUES (9)")).await, "TX_NOT_FOUND");
+
+        // A batch from another client waits for the transaction to end
+        let commands = worker.commands.clone();
+        let waiting = tokio::spawn(async move {
+            send(&commands, batch(&["INSERT INTO t VALUES (2)"], TransactionMode::Atomic)).await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        let resp = worker.request(Request::Commit { db: db.clone(), tx_token: tx_token.clone() }).await;
+        let Response::Ok { data: ResponseData::TxEnded { tx_committed: true, rev: Some(_) } } = resp else {
+            panic!("{:?}", resp);
+        };
+        assert_ok(&waiting.await.unwrap());
+        assert_code(&worker.request(Request::Commit { db: db.clone(), tx_token }).await, "TX_NOT_FOUND");
+
+        // Rolled back: nothing it wrote stays
+        let tx_token = token(worker.request(begin()).await);
+        assert_ok(&worker.request(exec_in_tx(&tx_token, "INSERT INTO t VALUES (3)")).await);
+        let resp = worker.request(Request::Rollback { db: db.clone(), tx_token }).await;
+        let Response::Ok { data: ResponseData::TxEnded { tx_committed: false, .. } } = resp else {
+            panic!("{:?}", resp);
+        };
+        assert_eq!(worker.rows("SELECT x FROM t ORDER BY x").await, [[json!(1)], [json!(2)]]);
+        worker.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_failed_exec_in_tx_is_undone_whole() {
+        let config = DaemonConfig { change_feed: true, ..DaemonConfig::default() };
+        let worker = TestWorker::start("tx-savepoint", config);
+        let schema = ["CREATE TABLE t (x INTEGER UNIQUE)", "CREATE TABLE u (y INTEGER)"];
+        assert_ok(&worker.exec(&schema, TransactionMode::Atomic).await);
+        let db = DB.to_string();
+        let exec_in_tx = |tx_token: &str, sqls: &[&str]| Request::ExecInTx {
+            db: db.clone(),
+            tx_token: tx_token.to_string(),
+            stmts: sqls.iter().map(|sql| Statement::new(*sql, Vec::new())).collect(),
+        };
+        let resp = worker.request(Request::Begin { db: db.clone() }).await;
+        let Response::Ok { data: ResponseData::TxBegun { tx_token } } = resp else { panic!("{:?}", resp) };
+
+        assert_ok(&worker.request(exec_in_tx(&tx_token, &["INSERT INTO t VALUES (1)"])).await);
+        // The duplicate fails, taking the insert into u before it along
+        let failing = exec_in_tx(&tx_token, &["INSERT INTO u VALUES (1)", "INSERT INTO t VALUES (1)"]);
+        assert_code(&worker.request(failing).await, "SQL_ERROR");
+        assert_ok(&worker.request(exec_in_tx(&tx_token, &["INSERT INTO t VALUES (2)"])).await);
+
+        let resp = worker.request(Request::Commit { db: db.clone(), tx_token }).await;
+        let Response::Ok { data: ResponseData::TxEnded { tx_committed: true, rev: Some(rev) } } = resp else {
+            panic!("{:?}", resp);
+        };
+        assert_eq!(worker.rows("SELECT x FROM t ORDER BY x").await, [[json!(1)], [json!(2)]]);
+        assert!(worker.rows("SELECT y FROM u").await.is_empty());
+
+        let resp = worker.request(Request::ChangeFeed { db: db.clone(), since_rev: rev - 1, limit: 10 }).await;
+        let Response::Ok { data: ResponseData::ChangeFeed { events } } = resp else { panic!("{:?}", resp) };
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].rev, events[0].tables.as_slice()), (rev, ["t".to_string()].as_slice()));
+        worker.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_exec_in_tx_rolled_back_by_sqlite_ends_the_transaction() {
+        let worker = TestWorker::start("tx-aborted", DaemonConfig::default());
+        assert_ok(&worker.exec(&["CREATE TABLE t (x INTEGER UNIQUE)"], TransactionMode::Atomic).await);
+        let rev_before = worker.rows("SELECT rev FROM _daemon_meta").await;
+        let db = DB.to_string();
+        let exec_in_tx = |tx_token: &str, sql: &str| Request::ExecInTx {
+            db: db.clone(),
+            tx_token: tx_token.to_string(),
+            stmts: vec![Statement::new(sql, Vec::new())],
+        };
+        let resp = worker.request(Request::Begin { db: db.clone() }).await;
+        let Response::Ok { data: ResponseData::TxBegun { tx_token } } = resp else { panic!("{:?}", resp) };
+
+        assert_ok(&worker.request(exec_in_tx(&tx_token, "INSERT INTO t VALUES (1)")).await);
+        // OR ROLLBACK makes SQLite roll back the whole transaction itself
+        let resp = worker.request(exec_in_tx(&tx_token, "INSERT OR ROLLBACK INTO t VALUES (1)")).await;
+        assert_code(&resp, "TX_ABORTED");
+        assert_code(&worker.request(exec_in_tx(&tx_token, "INSERT INTO t VALUES (2)")).await, "TX_NOT_FOUND");
+        assert_code(&worker.request(Request::Commit { db: db.clone(), tx_token }).await, "TX_NOT_FOUND");
+
+        assert!(worker.rows("SELECT x FROM t").await.is_empty());
+        assert_eq!(worker.rows("SELECT rev FROM _daemon_meta").await, rev_before);
+        worker.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_multi_query_scalar_is_read_only() {
+        for read_connections in [0, 2] {
+            let config = DaemonConfig { read_connections, ..DaemonConfig::default() };
+            let worker = TestWorker::start(&format!("scalar-{}", read_connections), config);
+            assert_ok(&worker.exec(&["CREATE TABLE t (x INTEGER)", "INSERT INTO t VALUES (1)"], TransactionMode::Atomic).await);
+            let scalar = |queries: &[(&str, &str)]| Request::MultiQueryScalar {
+                db: DB.to_string(),
+                queries: queries
+                    .iter()
+                    .map(|(key, sql)| ScalarQuery { key: key.to_string(), sql: sql.to_string(), params: Vec::new() })
+                    .collect(),
+            };
+
+            let resp = worker.request(scalar(&[("n", "SELECT count(*) FROM t"), ("w", "INSERT INTO t VALUES (2) RETURNING x")])).await;
+            assert_code(&resp, "QUERY_NOT_READ_ONLY");
+            assert_eq!(worker.rows("SELECT x FROM t").await, [[json!(1)]]);
+            worker.stop().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multi_query_scalar_runs_on_the_read_pool() {
+        let worker = TestWorker::start("scalar-read-pool", DaemonConfig::default());
+        assert_ok(&worker.exec(&["CREATE TABLE t (x INTEGER)", "INSERT INTO t VALUES (1)"], TransactionMode::Atomic).await);
+        let resp = worker.request(Request::Begin { db: DB.to_string() }).await;
+        let Response::Ok { data: ResponseData::TxBegun { tx_token } } = resp else { panic!("{:?}", resp) };
+
+        // Answered while the transaction holds the write connection
+        let req = Request::MultiQueryScalar {
+            db: DB.to_string(),
+            queries: vec![ScalarQuery { key: "n".to_string(), sql: "SELECT count(*) FROM t".to_string(), params: Vec::new() }],
+        };
+        let resp = tokio::time::timeout(Duration::from_secs(2), worker.request(req)).await.unwrap();
+        let Response::Ok { data: ResponseData::MultiQueryScalar { values } } = resp else { panic!("{:?}", resp) };
+        assert_eq!(values["n"], json!(1));
+
+        assert_ok(&worker.request(Request::Rollback { db: DB.to_string(), tx_token }).await);
+        worker.stop().await;
+    }
+
+    #[cfg(not(feature = "session"))]
+    #[tokio::test]
+    async fn test_apply_changeset_needs_the_session_feature() {
+        let worker = TestWorker::start("changeset", DaemonConfig::default());
+        let req = Request::ApplyChangeset {
+            db: DB.to_string(),
+            changeset: String::new(),
+            conflict_policy: ChangesetConflictPolicy::Abort,
+        };
+        assert_code(&worker.request(req).await, "FEATURE_UNSUPPORTED");
+        worker.stop().await;
+    }
+
+    #[cfg(feature = "session")]
+    #[tokio::test]
+    async fn test_apply_changeset_from_another_database() {
+        let source = TestWorker::start("changeset-source", DaemonConfig::default());
+        let target = TestWorker::start("changeset-target", DaemonConfig::default());
+        let schema = "CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT)";
+        assert_ok(&source.exec(&[schema, "INSERT INTO t VALUES (1, 'old')"], TransactionMode::Atomic).await);
+        assert_ok(&target.exec(&[schema, "INSERT INTO t VALUES (1, 'old')"], TransactionMode::Atomic).await);
+
+        let mut req = batch(&["UPDATE t SET v = 'new' WHERE id = 1", "INSERT INTO t VALUES (2, 'b')"], TransactionMode::Atomic);
+        if let Request::ExecBatch { changeset, .. } = &mut req {
+            *changeset = true;
+        }
+        let resp = source.request(req).await;
+        let Response::Ok { data: ResponseData::ExecBatch { changeset: Some(changeset), .. } } = resp else {
+            panic!("{:?}", resp);
+        };
+
+        let apply = |changeset: String, conflict_policy| Request::ApplyChangeset { db: DB.to_string(), changeset, conflict_policy };
+        let resp = target.request(apply(changeset.clone(), ChangesetConflictPolicy::Abort)).await;
+        let Response::Ok { data: ResponseData::ChangesetApplied { changeset_conflicts, .. } } = resp else {
+            panic!("{:?}", resp);
+        };
+        assert!(changeset_conflicts.is_empty());
+        assert_eq!(target.rows("SELECT id, v FROM t ORDER BY id").await, source.rows("SELECT id, v FROM t ORDER BY id").await);
+
+        // Applied again, every change conflicts: abort keeps the database as
+        // it was, skip reports the conflicts
+        assert!(matches!(target.request(apply(changeset.clone(), ChangesetConflictPolicy::Abort)).await, Response::Error { .. }));
+        let resp = target.request(apply(changeset, ChangesetConflictPolicy::Skip)).await;
+        let Response::Ok { data: ResponseData::ChangesetApplied { changeset_conflicts, .. } } = resp else {
+            panic!("{:?}", resp);
+        };
+        assert_eq!(changeset_conflicts.len(), 2);
+        assert_code(&target.request(apply("not base64!".into(), ChangesetConflictPolicy::Abort)).await, "INVALID_CHANGESET");
+        source.stop().await;
+        target.stop().await;
+    }
 }
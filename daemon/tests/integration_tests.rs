@@ -2,18 +2,14 @@
 //!
 //! ## Running Tests
 //!
-//! These tests require the daemon to be running before execution:
+//! Each test starts its own daemon in-process, serving a fresh temporary
+//! directory on a pipe (Windows) or socket (Unix) of its own, and stops it at
+//! the end. No daemon needs to be running and tests may run in parallel:
 //!
 //! ```powershell
-//! # Terminal 1: Start the daemon
-//! .\target\release\skylinedb-daemon.exe
-//!
-//! # Terminal 2: Run tests
-//! cargo test --manifest-path daemon/Cargo.toml --test integration_tests -- --test-threads=1
+//! cargo test --manifest-path daemon/Cargo.toml --test integration_tests
 //! ```
 //!
-//! Tests are run with `--test-threads=1` to avoid conflicts between concurrent tests.
-//!
 //! ## Test Coverage
 //!
 //! - Multi-database operations
@@ -22,103 +18,122 @@
 //! - File replacement simulation
 //! - Concurrent operations across multiple databases
 //! - Error handling and recovery
-
-use anyhow::Result;
-use serde::{Deserialize, Serialize};
+//! - Protocol version handshake
+//! - Migration progress frames
+//! - TCP listening limited to loopback without `--listen-public`
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use skylinedb_daemon::{server, DaemonConfig, Router};
+use skylinedb_protocol::{
+    Hello, MigratePhase, Request, Response, ResponseData, Statement, TransactionMode, PROTOCOL_VERSION,
+};
 use std::fs;
 use std::path::PathBuf;
-use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::windows::named_pipe::ClientOptions;
-
-const PIPE_NAME: &str = r"\\.\pipe\SkylineDBd-v1";
-const TEST_DB_DIR: &str = "test_dbs";
-
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(tag = "type")]
-enum Request {
-    Ping {
-        db: String,
-    },
-    ExecBatch {
-        db: String,
-        stmts: Vec<Statement>,
-        #[serde(default = "default_tx_mode")]
-        tx: String,
-    },
-    PrepareForMaintenance {
-        db: String,
-    },
-    CloseDatabase {
-        db: String,
-    },
-    ReopenDatabase {
-        db: String,
-    },
-}
+use tokio::task::JoinHandle;
 
-fn default_tx_mode() -> String {
-    "atomic".to_string()
-}
+#[cfg(windows)]
+use tokio::net::windows::named_pipe::{ClientOptions, NamedPipeClient as Stream};
+#[cfg(unix)]
+use tokio::net::UnixStream as Stream;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Statement {
-    sql: String,
-    #[serde(default)]
-    params: Vec<serde_json::Value>,
-}
+/// How long a client keeps retrying while the daemon starts up (or, on
+/// Windows, while every pipe instance is busy)
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 
-#[derive(Debug, Deserialize)]
-#[serde(tag = "status")]
-enum Response {
-    #[serde(rename = "ok")]
-    Ok {
-        #[serde(flatten)]
-        data: ResponseData,
-    },
-    #[serde(rename = "error")]
-    Error { message: String },
+static NEXT_DAEMON: AtomicUsize = AtomicUsize::new(0);
+
+/// A daemon served in-process for one test, removed again by `stop`
+struct TestDaemon {
+    dir: PathBuf,
+    client: TestClient,
+    router: Arc<Router>,
+    server: JoinHandle<Result<()>>,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum ResponseData {
-    #[allow(dead_code)]
-    Ping {
-        version: String,
-        db_path: String,
-        rev: i64,
-    },
-    #[allow(dead_code)]
-    ExecBatch {
-        rev: i64,
-        rows_affected: u64,
-    },
-    PrepareForMaintenance {
-        checkpointed: bool,
-    },
-    CloseDatabase {
-        closed: bool,
-    },
-    ReopenDatabase {
-        reopened: bool,
-        rev: i64,
-    },
+impl TestDaemon {
+    async fn start() -> Result<Self> {
+        let id = format!("{}-{}", std::process::id(), NEXT_DAEMON.fetch_add(1, Ordering::Relaxed));
+        let dir = std::env::temp_dir().join(format!("skylinedb-it-{}", id));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+
+        #[cfg(windows)]
+        let pipe_name = format!(r"\\.\pipe\SkylineDBd-test-{}", id);
+        #[cfg(unix)]
+        let pipe_name = dir.join("daemon.sock").display().to_string();
+
+        let router = Arc::new(Router::builder().base_path(&dir).transport(&pipe_name).build());
+        let server = tokio::spawn({
+            let router = Arc::clone(&router);
+            let pipe_name = pipe_name.clone();
+            async move { server::run_server(&pipe_name, router).await }
+        });
+
+        let client = TestClient { pipe_name };
+        // Ready once the pipe/socket accepts a connection
+        client.connect().await?;
+        Ok(TestDaemon { dir, client, router, server })
+    }
+
+    /// Shut the daemon down as `Shutdown` would and delete its directory
+    async fn stop(self) -> Result<()> {
+        self.router.route_request(Request::Shutdown).await;
+        self.server.await??;
+        let _ = fs::remove_dir_all(&self.dir);
+        Ok(())
+    }
 }
 
-struct TestClient;
+/// Connects to one `TestDaemon`; cheap to clone into spawned tasks
+#[derive(Clone)]
+struct TestClient {
+    pipe_name: String,
+}
 
 impl TestClient {
-    async fn send_request(request: Request) -> Result<Response> {
-        let mut stream = ClientOptions::new().open(PIPE_NAME)?;
+    async fn connect(&self) -> Result<Stream> {
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        loop {
+            #[cfg(windows)]
+            let attempt = ClientOptions::new().open(&self.pipe_name);
+            #[cfg(unix)]
+            let attempt = Stream::connect(&self.pipe_name).await;
+            match attempt {
+                Ok(stream) => return Ok(stream),
+                Err(e) if Instant::now() >= deadline => {
+                    return Err(e).with_context(|| format!("Failed to connect to {}", self.pipe_name));
+                }
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+    }
+
+    async fn send_request(&self, request: Request) -> Result<Response> {
+        let mut stream = self.connect().await?;
 
-        let json = serde_json::to_vec(&request)?;
+        let hello = Self::round_trip(&mut stream, &Hello::new(PROTOCOL_VERSION)).await?;
+        if let Response::Error { message, .. } = hello {
+            anyhow::bail!("Handshake refused: {}", message);
+        }
+        Self::round_trip(&mut stream, &request).await
+    }
+
+    async fn round_trip<T: Serialize>(stream: &mut Stream, message: &T) -> Result<Response> {
+        let json = serde_json::to_vec(message)?;
         let length = json.len() as u32;
 
         stream.write_all(&length.to_le_bytes()).await?;
         stream.write_all(&json).await?;
         stream.flush().await?;
+        Self::read_response(stream).await
+    }
 
+    async fn read_response(stream: &mut Stream) -> Result<Response> {
         let mut len_buf = [0u8; 4];
         stream.read_exact(&mut len_buf).await?;
         let response_len = u32::from_le_bytes(len_buf) as usize;
@@ -130,102 +145,84 @@ impl TestClient {
         Ok(response)
     }
 
-    async fn exec(db: &str, sql: &str) -> Result<Response> {
-        Self::send_request(Request::ExecBatch {
-            db: db.to_string(),
-            stmts: vec![Statement {
-                sql: sql.to_string(),
-                params: vec![],
-            }],
-            tx: "atomic".to_string(),
-        })
+    async fn exec(&self, db: &str, sql: &str) -> Result<Response> {
+        self.send_request(Request::exec_batch(
+            db,
+            vec![Statement::new(sql, vec![])],
+            TransactionMode::Atomic,
+        ))
         .await
     }
 
-    async fn ping(db: &str) -> Result<Response> {
-        Self::send_request(Request::Ping {
+    async fn ping(&self, db: &str) -> Result<Response> {
+        self.send_request(Request::Ping {
             db: db.to_string(),
         })
         .await
     }
 
-    async fn prepare_maintenance(db: &str) -> Result<Response> {
-        Self::send_request(Request::PrepareForMaintenance {
+    async fn prepare_maintenance(&self, db: &str) -> Result<Response> {
+        self.send_request(Request::PrepareForMaintenance {
             db: db.to_string(),
+            exclusive: false,
         })
         .await
     }
 
-    async fn close_database(db: &str) -> Result<Response> {
-        Self::send_request(Request::CloseDatabase {
+    async fn close_database(&self, db: &str) -> Result<Response> {
+        self.send_request(Request::CloseDatabase {
             db: db.to_string(),
         })
         .await
     }
 
-    async fn reopen_database(db: &str) -> Result<Response> {
-        Self::send_request(Request::ReopenDatabase {
+    async fn reopen_database(&self, db: &str) -> Result<Response> {
+        self.send_request(Request::ReopenDatabase {
             db: db.to_string(),
+            from_path: None,
         })
         .await
     }
 }
 
-fn setup_test_env() {
-    let _ = fs::create_dir(TEST_DB_DIR);
-}
-
-fn cleanup_test_db(db_name: &str) {
-    let path = PathBuf::from(TEST_DB_DIR).join(db_name);
-    let _ = fs::remove_file(&path);
-    let _ = fs::remove_file(format!("{}-wal", path.display()));
-    let _ = fs::remove_file(format!("{}-shm", path.display()));
-}
-
 #[tokio::test]
 async fn test_multi_database_operations() -> Result<()> {
-    setup_test_env();
-    
-    let db1 = format!("{}/test_multi1.db", TEST_DB_DIR);
-    let db2 = format!("{}/test_multi2.db", TEST_DB_DIR);
-    
-    cleanup_test_db(&db1);
-    cleanup_test_db(&db2);
+    let daemon = TestDaemon::start().await?;
+    let client = &daemon.client;
 
     // Create tables in both databases
-    TestClient::exec(&db1, "CREATE TABLE test (id INTEGER, name TEXT)").await?;
-    TestClient::exec(&db2, "CREATE TABLE users (id INTEGER, email TEXT)").await?;
+    client.exec("multi1.db", "CREATE TABLE test (id INTEGER, name TEXT)").await?;
+    client.exec("multi2.db", "CREATE TABLE users (id INTEGER, email TEXT)").await?;
 
     // Insert data into both
-    TestClient::exec(&db1, "INSERT INTO test VALUES (1, 'Alice')").await?;
-    TestClient::exec(&db2, "INSERT INTO users VALUES (1, 'alice@example.com')").await?;
+    client.exec("multi1.db", "INSERT INTO test VALUES (1, 'Alice')").await?;
+    client.exec("multi2.db", "INSERT INTO users VALUES (1, 'alice@example.com')").await?;
 
     // Verify both databases work
-    let resp1 = TestClient::ping(&db1).await?;
-    let resp2 = TestClient::ping(&db2).await?;
+    let resp1 = client.ping("multi1.db").await?;
+    let resp2 = client.ping("multi2.db").await?;
 
     match (resp1, resp2) {
         (Response::Ok { .. }, Response::Ok { .. }) => {
             println!("✓ Multi-database operations work");
-            Ok(())
         }
         _ => panic!("Expected OK responses"),
     }
+    daemon.stop().await
 }
 
 #[tokio::test]
 async fn test_maintenance_cycle() -> Result<()> {
-    setup_test_env();
-    
-    let db = format!("{}/test_maintenance.db", TEST_DB_DIR);
-    cleanup_test_db(&db);
+    let daemon = TestDaemon::start().await?;
+    let client = &daemon.client;
+    let db = "maintenance.db";
 
     // Create test data
-    TestClient::exec(&db, "CREATE TABLE test (id INTEGER)").await?;
-    TestClient::exec(&db, "INSERT INTO test VALUES (1)").await?;
+    client.exec(db, "CREATE TABLE test (id INTEGER)").await?;
+    client.exec(db, "INSERT INTO test VALUES (1)").await?;
 
     // Step 1: Prepare for maintenance
-    let prep_resp = TestClient::prepare_maintenance(&db).await?;
+    let prep_resp = client.prepare_maintenance(db).await?;
     match prep_resp {
         Response::Ok {
             data: ResponseData::PrepareForMaintenance { checkpointed },
@@ -237,7 +234,7 @@ async fn test_maintenance_cycle() -> Result<()> {
     }
 
     // Step 2: Close database
-    let close_resp = TestClient::close_database(&db).await?;
+    let close_resp = client.close_database(db).await?;
     match close_resp {
         Response::Ok {
             data: ResponseData::CloseDatabase { closed },
@@ -249,9 +246,9 @@ async fn test_maintenance_cycle() -> Result<()> {
     }
 
     // Step 3: Verify operations are blocked
-    let blocked_resp = TestClient::exec(&db, "INSERT INTO test VALUES (2)").await?;
+    let blocked_resp = client.exec(db, "INSERT INTO test VALUES (2)").await?;
     match blocked_resp {
-        Response::Error { message } => {
+        Response::Error { message, .. } => {
             assert!(message.contains("closed"));
             println!("✓ Operations correctly blocked while closed");
         }
@@ -259,10 +256,10 @@ async fn test_maintenance_cycle() -> Result<()> {
     }
 
     // Step 4: Reopen database
-    let reopen_resp = TestClient::reopen_database(&db).await?;
+    let reopen_resp = client.reopen_database(db).await?;
     match reopen_resp {
         Response::Ok {
-            data: ResponseData::ReopenDatabase { reopened, rev },
+            data: ResponseData::ReopenDatabase { reopened, rev, .. },
         } => {
             assert!(reopened);
             assert!(rev >= 0);
@@ -272,38 +269,33 @@ async fn test_maintenance_cycle() -> Result<()> {
     }
 
     // Step 5: Verify operations work again
-    let final_resp = TestClient::exec(&db, "INSERT INTO test VALUES (2)").await?;
+    let final_resp = client.exec(db, "INSERT INTO test VALUES (2)").await?;
     match final_resp {
         Response::Ok {
             data: ResponseData::ExecBatch { .. },
         } => {
             println!("✓ Operations work after reopen");
-            Ok(())
         }
         _ => panic!("Expected ExecBatch OK response"),
     }
+    daemon.stop().await
 }
 
 #[tokio::test]
 async fn test_multi_db_isolation_during_maintenance() -> Result<()> {
-    setup_test_env();
-    
-    let db1 = format!("{}/test_isolation1.db", TEST_DB_DIR);
-    let db2 = format!("{}/test_isolation2.db", TEST_DB_DIR);
-    
-    cleanup_test_db(&db1);
-    cleanup_test_db(&db2);
+    let daemon = TestDaemon::start().await?;
+    let client = &daemon.client;
 
     // Setup both databases
-    TestClient::exec(&db1, "CREATE TABLE test (id INTEGER)").await?;
-    TestClient::exec(&db2, "CREATE TABLE test (id INTEGER)").await?;
+    client.exec("isolation1.db", "CREATE TABLE test (id INTEGER)").await?;
+    client.exec("isolation2.db", "CREATE TABLE test (id INTEGER)").await?;
 
-    // Close db1
-    TestClient::prepare_maintenance(&db1).await?;
-    TestClient::close_database(&db1).await?;
+    // Close isolation1.db
+    client.prepare_maintenance("isolation1.db").await?;
+    client.close_database("isolation1.db").await?;
 
-    // Verify db1 is blocked
-    let blocked_resp = TestClient::exec(&db1, "INSERT INTO test VALUES (1)").await?;
+    // Verify isolation1.db is blocked
+    let blocked_resp = client.exec("isolation1.db", "INSERT INTO test VALUES (1)").await?;
     match blocked_resp {
         Response::Error { .. } => {
             println!("✓ db1 correctly blocked");
@@ -311,8 +303,8 @@ async fn test_multi_db_isolation_during_maintenance() -> Result<()> {
         _ => panic!("Expected db1 to be blocked"),
     }
 
-    // Verify db2 still works
-    let working_resp = TestClient::exec(&db2, "INSERT INTO test VALUES (1)").await?;
+    // Verify isolation2.db still works
+    let working_resp = client.exec("isolation2.db", "INSERT INTO test VALUES (1)").await?;
     match working_resp {
         Response::Ok { .. } => {
             println!("✓ db2 continues working while db1 is closed");
@@ -320,45 +312,42 @@ async fn test_multi_db_isolation_during_maintenance() -> Result<()> {
         _ => panic!("Expected db2 to work"),
     }
 
-    // Reopen db1
-    TestClient::reopen_database(&db1).await?;
+    // Reopen isolation1.db
+    client.reopen_database("isolation1.db").await?;
 
     // Verify both databases work now
-    let resp1 = TestClient::exec(&db1, "INSERT INTO test VALUES (2)").await?;
-    let resp2 = TestClient::exec(&db2, "INSERT INTO test VALUES (2)").await?;
+    let resp1 = client.exec("isolation1.db", "INSERT INTO test VALUES (2)").await?;
+    let resp2 = client.exec("isolation2.db", "INSERT INTO test VALUES (2)").await?;
 
     match (resp1, resp2) {
         (Response::Ok { .. }, Response::Ok { .. }) => {
             println!("✓ Both databases working after maintenance");
-            Ok(())
         }
         _ => panic!("Expected both databases to work"),
     }
+    daemon.stop().await
 }
 
 #[tokio::test]
 async fn test_file_replacement_simulation() -> Result<()> {
-    setup_test_env();
-    
-    let db_path = PathBuf::from(TEST_DB_DIR).join("test_replacement.db");
-    let db = db_path.to_str().unwrap();
-    let backup_path = PathBuf::from(TEST_DB_DIR).join("test_replacement_backup.db");
-    
-    cleanup_test_db(db);
-    cleanup_test_db(backup_path.to_str().unwrap());
+    let daemon = TestDaemon::start().await?;
+    let client = &daemon.client;
+    let db = "replacement.db";
+    let db_path = daemon.dir.join(db);
+    let backup_path = daemon.dir.join("replacement_backup.db");
 
     // Create original database
-    TestClient::exec(db, "CREATE TABLE test (id INTEGER)").await?;
-    TestClient::exec(db, "INSERT INTO test VALUES (1)").await?;
+    client.exec(db, "CREATE TABLE test (id INTEGER)").await?;
+    client.exec(db, "INSERT INTO test VALUES (1)").await?;
 
     // Prepare for maintenance
-    TestClient::prepare_maintenance(db).await?;
-    
+    client.prepare_maintenance(db).await?;
+
     // Backup the current file
     fs::copy(&db_path, &backup_path)?;
 
     // Close database
-    TestClient::close_database(db).await?;
+    client.close_database(db).await?;
 
     // Wait a bit to ensure file locks are released
     tokio::time::sleep(Duration::from_millis(100)).await;
@@ -370,7 +359,7 @@ async fn test_file_replacement_simulation() -> Result<()> {
     println!("✓ File replacement simulated");
 
     // Reopen database
-    let reopen_resp = TestClient::reopen_database(db).await?;
+    let reopen_resp = client.reopen_database(db).await?;
     match reopen_resp {
         Response::Ok {
             data: ResponseData::ReopenDatabase { reopened, .. },
@@ -382,68 +371,57 @@ async fn test_file_replacement_simulation() -> Result<()> {
     }
 
     // Verify database is accessible
-    let verify_resp = TestClient::exec(db, "SELECT * FROM test").await?;
+    let verify_resp = client.exec(db, "SELECT * FROM test").await?;
     match verify_resp {
         Response::Ok { .. } => {
             println!("✓ Database accessible after replacement");
-            Ok(())
         }
         _ => panic!("Expected OK response after replacement"),
     }
+    daemon.stop().await
 }
 
 #[tokio::test]
 async fn test_concurrent_operations() -> Result<()> {
-    setup_test_env();
-    
-    let db1 = format!("{}/test_concurrent1.db", TEST_DB_DIR);
-    let db2 = format!("{}/test_concurrent2.db", TEST_DB_DIR);
-    let db3 = format!("{}/test_concurrent3.db", TEST_DB_DIR);
-    
-    cleanup_test_db(&db1);
-    cleanup_test_db(&db2);
-    cleanup_test_db(&db3);
+    let daemon = TestDaemon::start().await?;
 
     // Setup databases
-    TestClient::exec(&db1, "CREATE TABLE test (id INTEGER)").await?;
-    TestClient::exec(&db2, "CREATE TABLE test (id INTEGER)").await?;
-    TestClient::exec(&db3, "CREATE TABLE test (id INTEGER)").await?;
+    for db in ["concurrent1.db", "concurrent2.db", "concurrent3.db"] {
+        daemon.client.exec(db, "CREATE TABLE test (id INTEGER)").await?;
+    }
 
     // Run concurrent operations
-    let handle1 = tokio::spawn(async move {
-        for i in 0..10 {
-            let _ = TestClient::exec(&db1, &format!("INSERT INTO test VALUES ({})", i)).await;
-        }
-    });
-
-    let handle2 = tokio::spawn(async move {
-        for i in 0..10 {
-            let _ = TestClient::exec(&db2, &format!("INSERT INTO test VALUES ({})", i)).await;
-        }
-    });
-
-    let handle3 = tokio::spawn(async move {
-        for i in 0..10 {
-            let _ = TestClient::exec(&db3, &format!("INSERT INTO test VALUES ({})", i)).await;
-        }
-    });
+    let handles: Vec<_> = ["concurrent1.db", "concurrent2.db", "concurrent3.db"]
+        .into_iter()
+        .map(|db| {
+            let client = daemon.client.clone();
+            tokio::spawn(async move {
+                for i in 0..10 {
+                    let resp = client.exec(db, &format!("INSERT INTO test VALUES ({})", i)).await?;
+                    anyhow::ensure!(matches!(resp, Response::Ok { .. }), "{:?}", resp);
+                }
+                Ok(())
+            })
+        })
+        .collect();
 
     // Wait for all to complete
-    let _ = tokio::try_join!(handle1, handle2, handle3);
+    for handle in handles {
+        handle.await??;
+    }
 
     println!("✓ Concurrent operations on multiple databases completed");
-    Ok(())
+    daemon.stop().await
 }
 
 #[tokio::test]
 async fn test_error_handling() -> Result<()> {
-    setup_test_env();
-    
-    let db = format!("{}/test_errors.db", TEST_DB_DIR);
-    cleanup_test_db(&db);
+    let daemon = TestDaemon::start().await?;
+    let client = &daemon.client;
+    let db = "errors.db";
 
     // Test 1: Close database that doesn't exist yet
-    let close_resp = TestClient::close_database(&db).await?;
+    let close_resp = client.close_database(db).await?;
     match close_resp {
         Response::Error { .. } => {
             println!("✓ Closing non-existent database returns error");
@@ -452,15 +430,15 @@ async fn test_error_handling() -> Result<()> {
     }
 
     // Test 2: Create database
-    TestClient::exec(&db, "CREATE TABLE test (id INTEGER)").await?;
+    client.exec(db, "CREATE TABLE test (id INTEGER)").await?;
 
     // Test 3: Double close
-    TestClient::prepare_maintenance(&db).await?;
-    TestClient::close_database(&db).await?;
-    
-    let double_close = TestClient::close_database(&db).await?;
+    client.prepare_maintenance(db).await?;
+    client.close_database(db).await?;
+
+    let double_close = client.close_database(db).await?;
     match double_close {
-        Response::Error { message } => {
+        Response::Error { message, .. } => {
             assert!(message.contains("already closed"));
             println!("✓ Double close returns appropriate error");
         }
@@ -468,13 +446,117 @@ async fn test_error_handling() -> Result<()> {
     }
 
     // Test 4: Reopen and verify it works
-    TestClient::reopen_database(&db).await?;
-    let verify = TestClient::exec(&db, "INSERT INTO test VALUES (1)").await?;
+    client.reopen_database(db).await?;
+    let verify = client.exec(db, "INSERT INTO test VALUES (1)").await?;
     match verify {
         Response::Ok { .. } => {
             println!("✓ Database works after error recovery");
-            Ok(())
         }
         _ => panic!("Expected OK after recovery"),
     }
+    daemon.stop().await
+}
+
+#[tokio::test]
+async fn test_protocol_handshake() -> Result<()> {
+    let daemon = TestDaemon::start().await?;
+
+    // Current version: accepted, daemon reports what it speaks
+    let mut stream = daemon.client.connect().await?;
+    let hello = Hello::new(PROTOCOL_VERSION);
+    match TestClient::round_trip(&mut stream, &hello).await? {
+        Response::Ok {
+            data: ResponseData::Hello { protocol, min_protocol, .. },
+        } => {
+            assert!((min_protocol..=protocol).contains(&PROTOCOL_VERSION));
+            println!("✓ Handshake accepted");
+        }
+        other => panic!("Expected Hello response, got {:?}", other),
+    }
+
+    // A version from the future: refused with a clear error, then closed
+    let mut stream = daemon.client.connect().await?;
+    let hello = Hello::new(999);
+    match TestClient::round_trip(&mut stream, &hello).await? {
+        Response::Error { message, .. } => {
+            assert!(message.contains("Unsupported protocol version 999"));
+            println!("✓ Unsupported protocol version refused");
+        }
+        other => panic!("Expected error for unsupported version, got {:?}", other),
+    }
+    let mut buf = [0u8; 1];
+    // The pipe may report the close as EOF or as a broken pipe
+    assert!(
+        matches!(stream.read(&mut buf).await, Ok(0) | Err(_)),
+        "connection should be closed"
+    );
+    drop(stream);
+    daemon.stop().await
+}
+
+#[tokio::test]
+async fn test_migrate_progress_frames() -> Result<()> {
+    let daemon = TestDaemon::start().await?;
+    let mut stream = daemon.client.connect().await?;
+    TestClient::round_trip(&mut stream, &Hello::new(PROTOCOL_VERSION)).await?;
+
+    let migrate = serde_json::json!({
+        "type": "Migrate",
+        "db": "progress.db",
+        "progress": true,
+        "migrations": [
+            { "version": 1, "sql": "CREATE TABLE a (x INTEGER); INSERT INTO a VALUES (1);" },
+            { "version": 2, "sql": "INSERT INTO a VALUES (2); INSERT INTO missing VALUES (1)" },
+        ],
+    });
+    let mut frames = Vec::new();
+    let mut response = TestClient::round_trip(&mut stream, &migrate).await?;
+    while let Response::Ok { data: ResponseData::MigrateProgress { progress } } = response {
+        frames.push((progress.migration, progress.statement, progress.statements, progress.phase));
+        response = TestClient::read_response(&mut stream).await?;
+    }
+    assert_eq!(
+        frames,
+        [
+            (1, 1, 2, MigratePhase::Running),
+            (1, 2, 2, MigratePhase::Running),
+            (1, 2, 2, MigratePhase::Committed),
+            (2, 1, 2, MigratePhase::Running),
+            (2, 1, 2, MigratePhase::RolledBack),
+        ]
+    );
+    match response {
+        Response::Error { message, code, .. } => {
+            assert_eq!(code.as_deref(), Some("MIGRATION_FAILED"));
+            assert!(message.contains("statement 2 of 2"), "{}", message);
+        }
+        other => panic!("Expected the failed migration's error, got {:?}", other),
+    }
+
+    // Without `progress` only the response comes back
+    let migrate = serde_json::json!({ "type": "Migrate", "db": "progress.db", "migrations": [] });
+    let response = TestClient::round_trip(&mut stream, &migrate).await?;
+    assert!(matches!(response, Response::Ok { data: ResponseData::Migrate { user_version: 1, .. } }), "{:?}", response);
+    drop(stream);
+    daemon.stop().await
+}
+
+#[tokio::test]
+async fn test_listen_refuses_public_address_without_opt_in() -> Result<()> {
+    let id = format!("{}-{}", std::process::id(), NEXT_DAEMON.fetch_add(1, Ordering::Relaxed));
+    let dir = std::env::temp_dir().join(format!("skylinedb-it-{}", id));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir)?;
+    #[cfg(windows)]
+    let pipe_name = format!(r"\\.\pipe\SkylineDBd-test-{}", id);
+    #[cfg(unix)]
+    let pipe_name = dir.join("daemon.sock").display().to_string();
+
+    let config = DaemonConfig { listen: Some("0.0.0.0:0".parse()?), ..DaemonConfig::default() };
+    let router = Arc::new(Router::builder().base_path(&dir).config(config).transport(&pipe_name).build());
+    let result = tokio::time::timeout(CONNECT_TIMEOUT, server::run_server(&pipe_name, router)).await?;
+    let error = result.expect_err("a public --listen address needs --listen-public");
+    assert!(error.to_string().contains("--listen-public"), "{}", error);
+    let _ = fs::remove_dir_all(&dir);
+    Ok(())
 }
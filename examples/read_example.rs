@@ -1,5 +1,5 @@
 use anyhow::Result;
-use sqlx::{SqlitePool, Row};
+use sqlx::Row;
 use std::path::Path;
 
 #[tokio::main]
@@ -0,0 +1,1797 @@
+//! Messages exchanged between SkyLineDB clients and the daemon.
+//!
+//! Each message is a JSON object framed by a 4-byte little-endian length.
+//! Clients send a [`Request`] (optionally preceded by a [`Hello`]) and read
+//! back a [`Response`].
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Default IPC endpoint of the standalone daemon
+#[cfg(windows)]
+pub const PIPE_NAME: &str = r"\\.\pipe\SkylineDBd-v1";
+
+/// Default IPC endpoint of the standalone daemon
+#[cfg(unix)]
+pub const PIPE_NAME: &str = "/tmp/skylinedb-v1.sock";
+
+/// Wire protocol version, exchanged by the optional `Hello` handshake. Bump
+/// it for changes an older peer would misread (a field renamed, retyped or
+/// given a new meaning, a default changed); new request types and new
+/// optional fields don't need a bump.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest client protocol version the daemon still accepts. Raise it when
+/// support for an older version is dropped.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Database name that opens a private in-memory database instead of a file.
+/// It lives as long as its worker: idle timeout, `CloseDatabase` or a
+/// daemon restart discard it.
+pub const MEMORY_DB: &str = ":memory:";
+
+/// First message a client may send on a new connection, before any request:
+/// `{"protocol": 1}`. The daemon answers with `ResponseData::Hello`, or with
+/// `UNSUPPORTED_PROTOCOL` and closes the connection. Clients that skip it
+/// are served as before.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hello {
+    pub protocol: u32,
+    /// Authorizer rules for every request on this connection, enforced on
+    /// top of the daemon's own `--allow-table` / `--deny-action`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authorizer: Option<ClientAuthorizer>,
+}
+
+/// Rules SQLite's authorizer enforces on one connection's statements; a
+/// statement they deny fails with `AUTHORIZER_DENIED`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ClientAuthorizer {
+    /// Tables statements may read, write or alter; empty allows all
+    #[serde(default)]
+    pub allowed_tables: Vec<String>,
+    /// Authorizer actions always denied, by name (`DELETE`, `ATTACH`,
+    /// `PRAGMA` ...)
+    #[serde(default)]
+    pub denied_actions: Vec<String>,
+}
+
+impl Hello {
+    pub fn new(protocol: u32) -> Self {
+        Hello { protocol, authorizer: None }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&self.protocol)
+    }
+}
+
+/// Request from client to daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Request {
+    /// Health check
+    Ping {
+        /// Database identifier (file name)
+        db: String,
+    },
+    
+    /// Execute a batch of write statements
+    ExecBatch {
+        /// Database identifier (file name)
+        db: String,
+        /// SQL statements with parameters
+        stmts: Vec<Statement>,
+        /// Transaction mode: "atomic" or "none"
+        #[serde(default = "default_tx_mode")]
+        tx: TransactionMode,
+        /// Read back the new revision (false skips the read; `rev` is null)
+        #[serde(default = "default_true")]
+        return_rev: bool,
+        /// Treat an empty `stmts` as a no-op returning the current revision
+        /// (without bumping it) instead of an `EMPTY_BATCH` error
+        #[serde(default)]
+        allow_empty: bool,
+        /// Page cache size in KiB for this request only; the previous
+        /// `cache_size` is restored afterwards (max `--max-cache-size-kib`)
+        #[serde(default)]
+        cache_size: Option<u64>,
+        /// Retries of an atomic batch that fails with SQLITE_BUSY/LOCKED
+        /// (default `--busy-retries`)
+        #[serde(default)]
+        max_retries: Option<u32>,
+        /// Delay before the first retry in ms, doubling each time
+        /// (default `--busy-retry-backoff-ms`)
+        #[serde(default)]
+        retry_backoff_ms: Option<u64>,
+        /// Report the connection's `total_changes()` before and after the
+        /// batch, which also counts rows changed by triggers
+        #[serde(default)]
+        total_changes: bool,
+        /// Checksum the written tables before commit and return the digest
+        #[serde(default)]
+        digest: bool,
+        /// Tables to checksum with `digest` (default: tables the statements
+        /// write to); tables that don't exist afterwards are left out
+        #[serde(default)]
+        digest_tables: Vec<String>,
+        /// Interrupt the batch once it has run this long, busy retries
+        /// included, and roll it back (`TIMEOUT`)
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+        /// Split each `INSERT OR REPLACE` into inserted and replaced rows,
+        /// counting the table's rows before and after it (a full count
+        /// each time)
+        #[serde(default)]
+        count_replaced: bool,
+        /// Record the batch's row changes with SQLite's session extension
+        /// and return them as a base64 changeset, ready for
+        /// `ApplyChangeset` on another copy (atomic batches only; needs a
+        /// daemon built with the `session` feature). Tables without a
+        /// `PRIMARY KEY` are not tracked and leave no trace in it.
+        #[serde(default)]
+        changeset: bool,
+    },
+    
+    /// Execute one statement once per parameter set, preparing it only
+    /// once; for bulk loads. Answers like `ExecBatch`, with
+    /// `rows_affected` summed over all sets.
+    ExecMany {
+        /// Database identifier (file name)
+        db: String,
+        sql: String,
+        param_sets: Vec<Vec<serde_json::Value>>,
+        /// Transaction mode: "atomic" or "none"
+        #[serde(default = "default_tx_mode")]
+        tx: TransactionMode,
+        /// Read back the new revision (false skips the read; `rev` is null)
+        #[serde(default = "default_true")]
+        return_rev: bool,
+    },
+    
+    /// Set the default database for the rest of this connection;
+    /// later requests may omit `db` (an explicit `db` still wins)
+    UseDatabase {
+        /// Database identifier (file name)
+        db: String,
+    },
+    
+    /// Run a read query and return its rows (does not bump the revision);
+    /// a statement that would write fails with `QUERY_NOT_READ_ONLY`
+    Query {
+        /// Database identifier (file name)
+        db: String,
+        sql: String,
+        #[serde(default)]
+        params: Vec<serde_json::Value>,
+        /// Result encoding: "json" (default), "csv" or "arrow"
+        #[serde(default)]
+        format: QueryFormat,
+        /// Page cache size in KiB for this request only; the previous
+        /// `cache_size` is restored afterwards (max `--max-cache-size-kib`)
+        #[serde(default)]
+        cache_size: Option<u64>,
+        /// SQL NULL in JSON rows: "null" (default) or "sentinel"
+        #[serde(default)]
+        nulls: NullStyle,
+        /// Interrupt the query once it has run this long (`TIMEOUT`)
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+    },
+    
+    /// Run several single-value queries in one read transaction and return
+    /// the first column of each query's first row, keyed by `key`. Like
+    /// `Query`, a statement that would write fails with `QUERY_NOT_READ_ONLY`.
+    MultiQueryScalar {
+        /// Database identifier (file name)
+        db: String,
+        queries: Vec<ScalarQuery>,
+    },
+    
+    /// Run a read query against a point-in-time copy of the database
+    /// (taken at the moment of the call) instead of the live file, so long
+    /// reads never hold up writes or WAL checkpoints
+    /// (refused with `DATABASE_PREPARING` or `DATABASE_CLOSED` during
+    /// maintenance, like `Compare`)
+    QuerySnapshot {
+        /// Database identifier (file name)
+        db: String,
+        sql: String,
+        #[serde(default)]
+        params: Vec<serde_json::Value>,
+        /// Result encoding: "json" (default), "csv" or "arrow"
+        #[serde(default)]
+        format: QueryFormat,
+    },
+    
+    /// Start a server-held cursor over a read query, for paging through
+    /// a large result with `FetchCursor`. The cursor reads one consistent
+    /// snapshot on its own connection; it is released when exhausted, by
+    /// `CloseCursor`, after `--cursor-idle-secs` without a fetch, or by
+    /// `PrepareForMaintenance` / `CloseDatabase` on its database. Refused
+    /// with `DATABASE_PREPARING` or `DATABASE_CLOSED` while those hold it.
+    OpenCursor {
+        /// Database identifier (file name)
+        db: String,
+        sql: String,
+        #[serde(default)]
+        params: Vec<serde_json::Value>,
+    },
+    
+    /// Next `n` rows (at most 10000) of an open cursor
+    FetchCursor {
+        cursor_id: String,
+        n: u32,
+    },
+    
+    /// Release a cursor before it is exhausted
+    CloseCursor {
+        cursor_id: String,
+    },
+    
+    /// Open a transaction that stays open across requests (`BEGIN
+    /// IMMEDIATE`, so it holds the write lock from the start). Returns a
+    /// `tx_token` for `ExecInTx`, `Commit` and `Rollback`; other writes to
+    /// the database wait until it ends. Rolled back after
+    /// `--tx-idle-secs` without a request.
+    Begin {
+        /// Database identifier (file name)
+        db: String,
+    },
+    
+    /// Run statements inside an open transaction, returning the rows of
+    /// each one that produces rows. A failing request is undone whole
+    /// (statements before the failing one included) and the transaction
+    /// stays open, unless SQLite rolled it back: then it fails with
+    /// `TX_ABORTED` and the token is gone.
+    ExecInTx {
+        /// Database identifier (file name)
+        db: String,
+        tx_token: String,
+        stmts: Vec<Statement>,
+    },
+    
+    /// Commit an open transaction, bumping the revision if it wrote
+    Commit {
+        /// Database identifier (file name)
+        db: String,
+        tx_token: String,
+    },
+    
+    /// Roll back an open transaction
+    Rollback {
+        /// Database identifier (file name)
+        db: String,
+        tx_token: String,
+    },
+    
+    /// Prepare a statement without executing it and report the bind
+    /// parameters it expects and the columns it returns
+    DescribeStatement {
+        /// Database identifier (file name)
+        db: String,
+        sql: String,
+    },
+    
+    /// Return the VDBE bytecode listing (`EXPLAIN`) of a statement
+    /// without executing it
+    Explain {
+        /// Database identifier (file name)
+        db: String,
+        sql: String,
+        #[serde(default)]
+        params: Vec<serde_json::Value>,
+    },
+    
+    /// List table-level changes committed after `since_rev`
+    /// (requires the daemon to run with `--change-feed`)
+    ChangeFeed {
+        /// Database identifier (file name)
+        db: String,
+        since_rev: i64,
+        #[serde(default = "default_change_feed_limit")]
+        limit: u32,
+    },
+    
+    /// `(rev, ts)` pairs of recent revisions, oldest first. Only the last
+    /// 1000 revisions are retained; history starts when the database is
+    /// first opened by a daemon that records it.
+    RevHistory {
+        /// Database identifier (file name)
+        db: String,
+        /// Return revisions strictly greater than this
+        #[serde(default)]
+        since_rev: i64,
+        #[serde(default = "default_change_feed_limit")]
+        limit: u32,
+    },
+    
+    /// Add `delta` to a named counter (created at 0) and return the new
+    /// value, atomically. Counters live in the daemon's `_daemon_counters` table.
+    Increment {
+        /// Database identifier (file name)
+        db: String,
+        counter: String,
+        #[serde(default = "default_delta")]
+        delta: i64,
+    },
+    
+    /// Apply a changeset produced by SQLite's session extension in one
+    /// transaction (needs the `session` build feature)
+    ApplyChangeset {
+        /// Database identifier (file name)
+        db: String,
+        /// Base64-encoded changeset
+        changeset: String,
+        /// What to do when a change conflicts with the database
+        #[serde(default)]
+        conflict_policy: ChangesetConflictPolicy,
+    },
+    
+    /// Time `count` canned writes against a scratch table that is dropped
+    /// afterwards (needs `--enable-benchmark`). The writes run one after
+    /// another on the worker's write connection, like every other write.
+    Benchmark {
+        /// Database identifier (file name)
+        db: String,
+        #[serde(default)]
+        operation: BenchmarkOp,
+        count: u32,
+    },
+    
+    /// Reject CREATE/ALTER/DROP on this database (code `SCHEMA_FROZEN`)
+    /// until thawed; the flag is stored in the database file
+    FreezeSchema {
+        /// Database identifier (file name)
+        db: String,
+    },
+    
+    /// Allow schema changes again after `FreezeSchema`
+    ThawSchema {
+        /// Database identifier (file name)
+        db: String,
+    },
+    
+    /// Read-only pragma values describing the file and connection settings
+    /// (page_count, freelist_count, journal_mode, ...) in one call
+    PragmaStats {
+        /// Database identifier (file name)
+        db: String,
+    },
+    
+    /// Request counters and recent latency quantiles of a database's worker
+    Stats {
+        /// Database identifier (file name)
+        db: String,
+    },
+    
+    /// Recent WAL checkpoints run by a database's worker, newest first
+    CheckpointHistory {
+        /// Database identifier (file name)
+        db: String,
+    },
+    
+    /// Change `PRAGMA synchronous` on the worker's connection until it is
+    /// next reopened (parking, maintenance), which restores `--synchronous`
+    SetSynchronous {
+        /// Database identifier (file name)
+        db: String,
+        level: SyncLevel,
+    },
+    
+    /// Drop the worker connection's prepared-statement cache so later
+    /// statements are compiled against the current schema
+    ClearStatementCache {
+        /// Database identifier (file name)
+        db: String,
+    },
+    
+    /// Prepare database for maintenance (checkpoint WAL)
+    PrepareForMaintenance {
+        /// Database identifier (file name)
+        db: String,
+        /// Checkpoint under `locking_mode=EXCLUSIVE`, locking out every
+        /// other process (including external readers) until it completes
+        #[serde(default)]
+        exclusive: bool,
+    },
+    
+    /// Close database connection (for file replacement)
+    CloseDatabase {
+        /// Database identifier (file name)
+        db: String,
+    },
+    
+    /// Reopen database connection (after file replacement)
+    ReopenDatabase {
+        /// Database identifier (file name)
+        db: String,
+        /// Replacement file to rename over the database before reopening
+        /// (must be inside the daemon's `--import-dir`, on the same
+        /// filesystem)
+        #[serde(default)]
+        from_path: Option<String>,
+    },
+    
+    /// Export the schema as ordered DDL (no data)
+    ExportSchema {
+        /// Database identifier (file name)
+        db: String,
+    },
+    
+    /// Trim the daemon's own bookkeeping tables (never user tables)
+    PruneMeta {
+        /// Database identifier (file name)
+        db: String,
+        /// Remove entries recorded before this time (unix seconds)
+        before_ts: i64,
+    },
+    
+    /// Rebuild the database file with `VACUUM` to reclaim free pages. The
+    /// WAL is checkpointed before and after, so the file shrinks right away.
+    /// The database is unavailable to other requests while it runs.
+    Vacuum {
+        /// Database identifier (file name)
+        db: String,
+    },
+    
+    /// Refresh the query planner's statistics with `ANALYZE`
+    Analyze {
+        /// Database identifier (file name)
+        db: String,
+    },
+    
+    /// Read the application's schema version (`PRAGMA user_version`)
+    GetSchemaVersion {
+        /// Database identifier (file name)
+        db: String,
+    },
+    
+    /// Set `PRAGMA user_version`; bumps the revision
+    SetSchemaVersion {
+        /// Database identifier (file name)
+        db: String,
+        version: i32,
+    },
+    
+    /// Apply, in version order, the migrations newer than `user_version`.
+    /// Each runs in its own transaction together with setting
+    /// `user_version` to its version; the first failure stops the run
+    /// with earlier migrations kept and the failing one rolled back.
+    Migrate {
+        /// Database identifier (file name)
+        db: String,
+        migrations: Vec<Migration>,
+        /// Send a `progress` frame after each statement and when each
+        /// migration commits or rolls back, ahead of the response
+        #[serde(default)]
+        progress: bool,
+    },
+    
+    /// Write a compacted copy of the database with `VACUUM INTO`; the live
+    /// database is not modified
+    VacuumInto {
+        /// Database identifier (file name)
+        db: String,
+        /// File name inside the daemon's `--backup-dir`
+        dest_path: String,
+        /// Replace an existing file instead of failing with `DEST_EXISTS`
+        #[serde(default)]
+        overwrite: bool,
+    },
+    
+    /// Copy the live database to a file in `--backup-dir` without taking
+    /// it out of service: the copy is a consistent snapshot written from
+    /// its own read-only connection while the worker keeps serving
+    Backup {
+        /// Database identifier (file name)
+        db: String,
+        /// File name inside the daemon's `--backup-dir`
+        dest_path: String,
+        /// Replace an existing file instead of failing with `DEST_EXISTS`
+        #[serde(default)]
+        overwrite: bool,
+    },
+    
+    /// Compare row counts and content checksums of the tables in two
+    /// databases (both read-only, and neither held for maintenance)
+    Compare {
+        db_a: String,
+        db_b: String,
+    },
+    
+    /// Find `-wal`/`-shm` files whose main database file is gone
+    CleanupSidecars {
+        /// Actually delete the orphans (otherwise only list them)
+        #[serde(default)]
+        remove: bool,
+    },
+    
+    /// Apply a batch to every database whose name matches a glob
+    /// (`*` and `?`), each in its own transaction. Failures are reported
+    /// per database and do not roll back databases that succeeded.
+    ExecAll {
+        /// Glob matched against database names on disk and live workers
+        pattern: String,
+        stmts: Vec<Statement>,
+        #[serde(default = "default_tx_mode")]
+        tx: TransactionMode,
+    },
+    
+    /// Check SQL syntax against an empty in-memory database (no db file is
+    /// touched) and list the bind parameters it expects
+    ValidateSql {
+        sql: String,
+    },
+    
+    /// Report the absolute path the daemon uses for a database
+    /// (validated like any other request, but the db is not opened)
+    ResolvePath {
+        /// Database identifier (file name)
+        db: String,
+    },
+    
+    /// List the built-in SQL functions and whether each is registered
+    ListFunctions,
+    
+    /// List databases that are preparing for maintenance or closed
+    ListMaintenance,
+    
+    /// Describe the daemon process itself (pid, version, uptime, workers)
+    DaemonInfo,
+    
+    /// Dump everything useful for a bug report in one response: process
+    /// info, the config in effect, and each live worker's state, queue
+    /// depth, stats and recent errors
+    Diagnostics,
+    
+    /// List live workers: state, uptime, time since their last request,
+    /// requests served and when they will idle out
+    Status,
+    
+    /// Stop accepting new client connections; existing connections and
+    /// workers keep running (for handing the socket to a standby)
+    PauseAccept,
+    
+    /// Start accepting client connections again after `PauseAccept`
+    ResumeAccept,
+    
+    /// Graceful shutdown (for testing)
+    Shutdown,
+}
+
+impl Request {
+    /// `ExecBatch` with every optional field at its default
+    pub fn exec_batch(db: impl Into<String>, stmts: Vec<Statement>, tx: TransactionMode) -> Self {
+        Request::ExecBatch {
+            db: db.into(),
+            stmts,
+            tx,
+            return_rev: true,
+            allow_empty: false,
+            cache_size: None,
+            max_retries: None,
+            retry_backoff_ms: None,
+            total_changes: false,
+            digest: false,
+            digest_tables: Vec::new(),
+            timeout_ms: None,
+            count_replaced: false,
+            changeset: false,
+        }
+    }
+
+    /// `Query` with every optional field at its default
+    pub fn query(
+        db: impl Into<String>,
+        sql: impl Into<String>,
+        params: Vec<serde_json::Value>,
+        format: QueryFormat,
+    ) -> Self {
+        Request::Query {
+            db: db.into(),
+            sql: sql.into(),
+            params,
+            format,
+            cache_size: None,
+            nulls: NullStyle::default(),
+            timeout_ms: None,
+        }
+    }
+}
+
+fn default_tx_mode() -> TransactionMode {
+    TransactionMode::Atomic
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_delta() -> i64 {
+    1
+}
+
+fn default_change_feed_limit() -> u32 {
+    1000
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// A single SQL statement with parameters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Statement {
+    pub sql: String,
+    #[serde(default)]
+    pub params: Vec<serde_json::Value>,
+    /// Values for named placeholders (`:name`, `@name`, `$name`), keyed
+    /// with or without the prefix; can't be combined with `params`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub named_params: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+impl Statement {
+    pub fn new(sql: impl Into<String>, params: Vec<serde_json::Value>) -> Self {
+        Statement { sql: sql.into(), params, named_params: None }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionMode {
+    /// All statements in one transaction (recommended)
+    Atomic,
+    /// Each statement separate (dangerous!)
+    None,
+}
+
+/// Encoding of query results
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryFormat {
+    /// `columns` plus `rows` as JSON arrays
+    #[default]
+    Json,
+    /// A single RFC 4180 CSV string (header row, CRLF line endings)
+    Csv,
+    /// Base64 Arrow IPC stream with one record batch, typed from the
+    /// declared column types (needs the `arrow` build feature)
+    Arrow,
+}
+
+/// `PRAGMA synchronous` level
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncLevel {
+    /// No fsync: fastest, but power loss can corrupt the database
+    Off,
+    /// The daemon default: WAL is synced at checkpoints, so power loss can
+    /// roll back the latest commits but never corrupts
+    Normal,
+    Full,
+    Extra,
+}
+
+impl SyncLevel {
+    pub fn from_pragma(value: i64) -> Option<Self> {
+        match value {
+            0 => Some(SyncLevel::Off),
+            1 => Some(SyncLevel::Normal),
+            2 => Some(SyncLevel::Full),
+            3 => Some(SyncLevel::Extra),
+            _ => None,
+        }
+    }
+
+    pub fn as_pragma(self) -> &'static str {
+        match self {
+            SyncLevel::Off => "OFF",
+            SyncLevel::Normal => "NORMAL",
+            SyncLevel::Full => "FULL",
+            SyncLevel::Extra => "EXTRA",
+        }
+    }
+}
+
+impl std::str::FromStr for SyncLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "off" => Ok(SyncLevel::Off),
+            "normal" => Ok(SyncLevel::Normal),
+            "full" => Ok(SyncLevel::Full),
+            "extra" => Ok(SyncLevel::Extra),
+            other => anyhow::bail!("expected off, normal, full or extra, got {}", other),
+        }
+    }
+}
+
+/// Canned write timed by `Benchmark`
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BenchmarkOp {
+    /// One 64-byte row per transaction (measures commit cost)
+    #[default]
+    Insert,
+    /// All rows in a single transaction (measures statement cost)
+    InsertTx,
+}
+
+/// Conflict handling for `ApplyChangeset`. A changeset that would leave
+/// foreign key violations aborts under every policy.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangesetConflictPolicy {
+    /// Roll back the whole changeset at the first conflict
+    #[default]
+    Abort,
+    /// Overwrite the conflicting row with the changeset's version; changes
+    /// that can't be replaced (missing row, constraint) are skipped
+    Replace,
+    /// Keep the database's row and skip the conflicting change
+    Skip,
+}
+
+/// A conflict met while applying a changeset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangesetConflict {
+    /// "data", "not_found", "conflict", "constraint" or "foreign_key"
+    pub kind: String,
+    /// Table of the conflicting change (null for "foreign_key", which is
+    /// only detected for the changeset as a whole)
+    pub table: Option<String>,
+    /// "insert", "update" or "delete" (null for "foreign_key")
+    pub op: Option<String>,
+}
+
+/// How SQL NULL is written in JSON query rows (CSV and Arrow have their
+/// own NULL encodings and ignore this)
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NullStyle {
+    /// JSON `null`; non-finite REAL values are also written as `null`
+    #[default]
+    Null,
+    /// `{"$null": true}`, so only an actual SQL NULL produces it
+    Sentinel,
+}
+
+/// A keyed query of `MultiQueryScalar`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScalarQuery {
+    pub key: String,
+    pub sql: String,
+    #[serde(default)]
+    pub params: Vec<serde_json::Value>,
+}
+
+/// A step of `Migrate`: `sql` may hold several statements, but no
+/// transaction control (each migration already runs in one)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Migration {
+    pub version: i32,
+    pub sql: String,
+}
+
+/// A frame sent while `Migrate` runs with `progress` set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrateProgress {
+    /// Version of the migration the frame is about
+    pub version: i32,
+    /// Its position among the migrations being applied (from 1), and their count
+    pub migration: usize,
+    pub migrations: usize,
+    /// Statements of the migration run so far, and its statement count
+    pub statement: usize,
+    pub statements: usize,
+    /// Time since the run started
+    pub elapsed_ms: u64,
+    pub phase: MigratePhase,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MigratePhase {
+    /// A statement finished; the migration's transaction is still open
+    Running,
+    /// The migration's transaction committed
+    Committed,
+    /// The migration failed after `statement` statements and was rolled back
+    RolledBack,
+}
+
+/// One committed batch in the change feed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    pub rev: i64,
+    /// Tables written by the batch
+    pub tables: Vec<String>,
+    /// Commit time (unix seconds); never decreases with `rev`, even if the
+    /// system clock steps backwards
+    pub ts: i64,
+}
+
+/// A result column of a described statement
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColumnInfo {
+    pub name: String,
+    /// Declared type of the source table column (null for expressions)
+    pub decl_type: Option<String>,
+}
+
+/// `total_changes()` of the connection around a batch. The difference
+/// includes the daemon's own bookkeeping rows (revision, history, change
+/// log) as well as anything user triggers changed.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TotalChanges {
+    pub before: i64,
+    pub after: i64,
+}
+
+/// A database held out of service by the maintenance cycle
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceEntry {
+    pub db: String,
+    /// "preparing" or "closed"
+    pub state: String,
+    /// Seconds since the database entered `state`
+    pub since_secs: u64,
+    /// Longer than `--maintenance-warn-secs`; likely a forgotten reopen
+    pub overdue: bool,
+}
+
+/// A live worker, as listed by `Status`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkerStatus {
+    pub db: String,
+    /// "initializing", "open", "preparing", "closed" or "parked"
+    pub state: String,
+    /// Seconds since the worker started
+    pub uptime_secs: u64,
+    /// Seconds since the worker last received a request
+    pub idle_secs: u64,
+    /// Seconds until the worker parks or stops if nothing arrives (null
+    /// while initializing, in a transaction, held for maintenance or with
+    /// an idle timeout of 0)
+    pub idle_timeout_secs: Option<u64>,
+    /// Requests served since the worker started
+    pub requests: u64,
+}
+
+/// Row count and content checksum of a table, as seen by the batch's own
+/// transaction; equal digests mean equal contents
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableDigest {
+    pub rows: u64,
+    /// 64-bit FNV-1a over the rows in primary key order, as 16 hex digits
+    pub checksum: String,
+}
+
+/// A recorded revision and its commit time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevisionEntry {
+    pub rev: i64,
+    /// Commit time (unix seconds), non-decreasing with `rev`
+    pub ts: i64,
+}
+
+/// One `PRAGMA wal_checkpoint` run by a worker
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointEvent {
+    /// PASSIVE, TRUNCATE, ...
+    pub mode: String,
+    /// A reader or writer kept the checkpoint from completing
+    pub busy: bool,
+    /// Frames in the WAL when the checkpoint ran
+    pub log_frames: i64,
+    /// Frames copied back into the database file
+    pub checkpointed_frames: i64,
+    pub duration_us: u64,
+    /// Unix seconds
+    pub ts: i64,
+}
+
+/// A failed request, as kept for `Diagnostics`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorEvent {
+    /// Unix seconds
+    pub ts: i64,
+    pub code: Option<String>,
+    pub message: String,
+}
+
+/// The daemon process, as reported by `Diagnostics`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessDiagnostics {
+    pub pid: u32,
+    pub version: String,
+    /// Process start (unix seconds)
+    pub started_at: i64,
+    pub uptime_secs: u64,
+    pub base_dir: String,
+    pub transport: String,
+    pub accept_paused: bool,
+    pub open_cursors: usize,
+}
+
+/// One live worker, as reported by `Diagnostics`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkerDiagnostics {
+    pub db: String,
+    /// "initializing", "open", "preparing", "closed" or "parked"
+    pub state: String,
+    pub state_age_secs: u64,
+    pub worker_age_secs: u64,
+    /// Commands waiting in the worker's queue
+    pub queued: usize,
+    pub requests: u64,
+    pub errors: u64,
+    pub latency: LatencyQuantiles,
+    /// Circuit breaker: "closed", "open" or "half_open"
+    pub circuit: String,
+    /// Most recent failed requests, newest first
+    pub recent_errors: Vec<ErrorEvent>,
+}
+
+/// Latency distribution over a worker's most recent requests
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyQuantiles {
+    /// Operations measured (for worker stats, the last 1024 requests)
+    pub samples: usize,
+    pub p50_us: u32,
+    pub p95_us: u32,
+    pub p99_us: u32,
+    pub max_us: u32,
+}
+
+/// One VDBE instruction from an `EXPLAIN` listing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExplainOp {
+    pub addr: i64,
+    pub opcode: String,
+    pub p1: i64,
+    pub p2: i64,
+    pub p3: i64,
+    /// Operand of varying type (text, integer, null, ...)
+    pub p4: serde_json::Value,
+    pub p5: i64,
+    pub comment: Option<String>,
+}
+
+/// A built-in SQL function the daemon can register
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionInfo {
+    pub name: String,
+    /// Argument count (-1 for variadic)
+    pub n_args: i32,
+    pub deterministic: bool,
+    /// Registered on connections (selected with `--functions`)
+    pub enabled: bool,
+    pub description: String,
+}
+
+/// What an `INSERT OR REPLACE` / `INSERT OR IGNORE` statement actually did
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConflictOutcome {
+    /// Statement index in the batch
+    pub index: usize,
+    /// Conflict resolution clause ("REPLACE", "IGNORE", ...)
+    pub resolution: String,
+    /// Rows that did not exist before; for REPLACE without
+    /// `count_replaced`, every row written
+    pub inserted: u64,
+    /// Existing rows deleted and rewritten by REPLACE; unknown (`None`) for
+    /// REPLACE unless the batch set `count_replaced`
+    pub replaced: Option<u64>,
+}
+
+/// Result of one statement of an `ExecInTx`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxStatementResult {
+    /// Rows changed; 0 for statements that return rows (including
+    /// `RETURNING`, whose rows say what changed)
+    pub rows_affected: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub columns: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rows: Vec<Vec<serde_json::Value>>,
+}
+
+/// Per-table result of `Compare`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableComparison {
+    pub name: String,
+    /// Row count in `db_a` (null if the table only exists in `db_b`)
+    pub rows_a: Option<u64>,
+    /// Row count in `db_b` (null if the table only exists in `db_a`)
+    pub rows_b: Option<u64>,
+    /// Present in both with the same rows in primary-key order
+    pub identical: bool,
+}
+
+/// Response from daemon to client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status")]
+pub enum Response {
+    /// Success response
+    #[serde(rename = "ok")]
+    Ok {
+        #[serde(flatten)]
+        data: ResponseData,
+    },
+    
+    /// Error response
+    #[serde(rename = "error")]
+    Error {
+        message: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        code: Option<String>,
+        /// SQLite primary result code when SQLite reported the failure
+        /// (e.g. 5 `SQLITE_BUSY`, 19 `SQLITE_CONSTRAINT`)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sqlite_code: Option<i32>,
+        /// SQLite extended result code (e.g. 2067 `SQLITE_CONSTRAINT_UNIQUE`)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sqlite_extended_code: Option<i32>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ResponseData {
+    /// Reply to a `Hello`
+    Hello {
+        /// Protocol version the daemon speaks
+        protocol: u32,
+        /// Oldest client protocol version it accepts
+        min_protocol: u32,
+        /// Daemon release (`CARGO_PKG_VERSION`)
+        version: String,
+    },
+    Ping {
+        version: String,
+        db_path: String,
+        rev: i64,
+        /// SQLite defensive mode is active on the connection
+        defensive: bool,
+        /// Worker circuit breaker: "closed", "open" or "half_open"
+        circuit: String,
+        /// Seconds since this database's worker started
+        worker_age_secs: u64,
+        /// DDL is rejected (see `FreezeSchema`)
+        schema_frozen: bool,
+        /// Current `PRAGMA journal_mode`; anything but "wal" means WAL could
+        /// not be enabled or was switched off
+        journal_mode: String,
+    },
+    ExecBatch {
+        /// New revision (null when the request set `return_rev: false`)
+        rev: Option<i64>,
+        rows_affected: u64,
+        /// One entry per OR-conflict insert in the batch
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        conflicts: Vec<ConflictOutcome>,
+        /// Busy/locked retries before the batch went through
+        #[serde(default, skip_serializing_if = "is_zero")]
+        retries: u32,
+        /// Set when the request asked for `total_changes`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        total_changes: Option<TotalChanges>,
+        /// Per-table digest after the batch, when the request set `digest`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        digest: Option<BTreeMap<String, TableDigest>>,
+        /// Base64 changeset of the batch's row changes, when the request
+        /// set `changeset`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        changeset: Option<String>,
+    },
+    Query {
+        columns: Vec<String>,
+        /// Per column: the declared type (`INTEGER`, `REAL`, `TEXT`, `BLOB`,
+        /// `NUMERIC`, ...) or, for an expression without one, the storage
+        /// class of its first non-NULL value (`NULL` if there is none)
+        #[serde(default)]
+        column_types: Vec<String>,
+        rows: Vec<Vec<serde_json::Value>>,
+    },
+    QueryCsv {
+        csv: String,
+    },
+    QueryArrow {
+        /// Base64-encoded Arrow IPC stream
+        arrow: String,
+    },
+    MultiQueryScalar {
+        /// Null for a query that returned no rows
+        values: BTreeMap<String, serde_json::Value>,
+    },
+    ChangeFeed {
+        events: Vec<ChangeEvent>,
+    },
+    RevHistory {
+        revisions: Vec<RevisionEntry>,
+    },
+    Counter {
+        counter: String,
+        value: i64,
+    },
+    Benchmark {
+        operation: BenchmarkOp,
+        count: u32,
+        /// Operations that failed (counted in `latency` but not throughput)
+        errors: u32,
+        duration_ms: u64,
+        ops_per_sec: f64,
+        latency: LatencyQuantiles,
+    },
+    Explain {
+        opcodes: Vec<ExplainOp>,
+    },
+    DescribeStatement {
+        param_count: usize,
+        /// Bind parameter names in index order (`?N` for anonymous ones)
+        params: Vec<String>,
+        columns: Vec<ColumnInfo>,
+    },
+    Stats {
+        requests: u64,
+        errors: u64,
+        latency: LatencyQuantiles,
+        /// Worker circuit breaker: "closed", "open" or "half_open"
+        circuit: String,
+        worker_age_secs: u64,
+        /// Prepared statements cached on the connection (null when the
+        /// database is not open)
+        cached_statements: Option<usize>,
+    },
+    CursorOpened {
+        cursor_id: String,
+        columns: Vec<String>,
+    },
+    CursorRows {
+        rows: Vec<Vec<serde_json::Value>>,
+        /// The result is exhausted and the cursor released
+        done: bool,
+    },
+    CursorClosed {
+        /// False if the cursor had already finished or expired
+        cursor_closed: bool,
+    },
+    TxBegun {
+        tx_token: String,
+    },
+    TxExecuted {
+        /// One entry per statement, in order
+        tx_results: Vec<TxStatementResult>,
+    },
+    TxEnded {
+        /// False for a rollback
+        tx_committed: bool,
+        /// Revision after the commit (bumped only if the transaction wrote)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rev: Option<i64>,
+    },
+    Synchronous {
+        synchronous: SyncLevel,
+        previous: SyncLevel,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        warning: Option<String>,
+    },
+    ChangesetApplied {
+        rev: i64,
+        /// Conflicts that were skipped or replaced under the policy
+        changeset_conflicts: Vec<ChangesetConflict>,
+    },
+    StatementCacheCleared {
+        /// Statements that were dropped from the cache
+        cleared: usize,
+    },
+    SchemaFrozen {
+        schema_frozen: bool,
+    },
+    PragmaStats {
+        pragmas: BTreeMap<String, serde_json::Value>,
+    },
+    CheckpointHistory {
+        checkpoints: Vec<CheckpointEvent>,
+    },
+    PrepareForMaintenance {
+        checkpointed: bool,
+    },
+    CloseDatabase {
+        closed: bool,
+    },
+    ReopenDatabase {
+        reopened: bool,
+        /// Revision after any journaled batches were applied
+        rev: i64,
+        /// Batches journaled while closed and applied on reopen
+        #[serde(default, skip_serializing_if = "is_zero")]
+        replayed: u32,
+        /// Journaled batches that failed when applied (see the daemon log)
+        #[serde(default, skip_serializing_if = "is_zero")]
+        replay_failed: u32,
+    },
+    Journaled {
+        /// Batches now waiting for the reopen, this one included
+        journaled: usize,
+    },
+    ExportSchema {
+        ddl: String,
+    },
+    UseDatabase {
+        default_db: String,
+    },
+    PruneMeta {
+        pruned: BTreeMap<String, u64>,
+    },
+    Vacuum {
+        /// Database file size before and after, in bytes
+        size_before: u64,
+        size_after: u64,
+        duration_ms: u64,
+        /// Unchanged: vacuuming keeps the data as it is
+        rev: i64,
+    },
+    Analyze {
+        /// Tables that now have planner statistics
+        analyzed_tables: Vec<String>,
+        duration_ms: u64,
+        rev: i64,
+    },
+    Migrate {
+        /// Migrations newer than the starting `user_version`, all applied
+        migrations_applied: u32,
+        /// `user_version` afterwards
+        user_version: i32,
+        rev: i64,
+    },
+    /// Progress of a running `Migrate`; its response follows the last one
+    MigrateProgress {
+        progress: MigrateProgress,
+    },
+    SchemaVersion {
+        user_version: i32,
+    },
+    VacuumInto {
+        path: String,
+        size_bytes: u64,
+        duration_ms: u64,
+    },
+    Backup {
+        bytes_written: u64,
+        /// Revision the copy holds
+        rev: i64,
+    },
+    ExecAll {
+        results: BTreeMap<String, Response>,
+    },
+    CleanupSidecars {
+        orphans: Vec<String>,
+        removed: Vec<String>,
+    },
+    ResolvePath {
+        path: String,
+        exists: bool,
+    },
+    ListFunctions {
+        functions: Vec<FunctionInfo>,
+    },
+    ListMaintenance {
+        databases: Vec<MaintenanceEntry>,
+    },
+    DaemonInfo {
+        pid: u32,
+        version: String,
+        /// Process start (unix seconds)
+        started_at: i64,
+        uptime_secs: u64,
+        base_dir: String,
+        /// Pipe or socket the daemon listens on ("embedded" in-process)
+        transport: String,
+        workers: usize,
+        /// New connections are refused until `ResumeAccept`
+        accept_paused: bool,
+    },
+    AcceptPaused {
+        accept_paused: bool,
+    },
+    Diagnostics {
+        process: ProcessDiagnostics,
+        /// `DaemonConfig` as parsed from the command line
+        config: serde_json::Value,
+        /// Sorted by database name
+        workers: Vec<WorkerDiagnostics>,
+    },
+    /// After `Diagnostics`, which also carries `workers`
+    Status {
+        /// Sorted by database name
+        workers: Vec<WorkerStatus>,
+    },
+    ValidateSql {
+        valid: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        /// Byte offset of the error in `sql`, when SQLite can point at it
+        #[serde(skip_serializing_if = "Option::is_none")]
+        offset: Option<usize>,
+        /// Bind parameter names in index order (`?N` for anonymous ones)
+        params: Vec<String>,
+    },
+    Compare {
+        identical: bool,
+        tables: Vec<TableComparison>,
+    },
+    Shutdown,
+}
+
+impl Response {
+    pub fn ok_hello(version: String) -> Self {
+        Response::Ok {
+            data: ResponseData::Hello {
+                protocol: PROTOCOL_VERSION,
+                min_protocol: MIN_PROTOCOL_VERSION,
+                version,
+            },
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn ok_ping(
+        version: String,
+        db_path: String,
+        rev: i64,
+        defensive: bool,
+        circuit: String,
+        worker_age_secs: u64,
+        schema_frozen: bool,
+        journal_mode: String,
+    ) -> Self {
+        Response::Ok {
+            data: ResponseData::Ping {
+                version,
+                db_path,
+                rev,
+                defensive,
+                circuit,
+                worker_age_secs,
+                schema_frozen,
+                journal_mode,
+            },
+        }
+    }
+
+    pub fn ok_exec(
+        rev: Option<i64>,
+        rows_affected: u64,
+        conflicts: Vec<ConflictOutcome>,
+        retries: u32,
+        digest: Option<BTreeMap<String, TableDigest>>,
+    ) -> Self {
+        Response::Ok {
+            data: ResponseData::ExecBatch {
+                rev,
+                rows_affected,
+                conflicts,
+                retries,
+                total_changes: None,
+                digest,
+                changeset: None,
+            },
+        }
+    }
+
+    pub fn ok_query(
+        columns: Vec<String>,
+        column_types: Vec<String>,
+        rows: Vec<Vec<serde_json::Value>>,
+    ) -> Self {
+        Response::Ok {
+            data: ResponseData::Query { columns, column_types, rows },
+        }
+    }
+
+    pub fn ok_query_arrow(arrow: String) -> Self {
+        Response::Ok {
+            data: ResponseData::QueryArrow { arrow },
+        }
+    }
+
+    pub fn ok_query_csv(csv: String) -> Self {
+        Response::Ok {
+            data: ResponseData::QueryCsv { csv },
+        }
+    }
+
+    pub fn ok_multi_query_scalar(values: BTreeMap<String, serde_json::Value>) -> Self {
+        Response::Ok {
+            data: ResponseData::MultiQueryScalar { values },
+        }
+    }
+
+    pub fn ok_change_feed(events: Vec<ChangeEvent>) -> Self {
+        Response::Ok {
+            data: ResponseData::ChangeFeed { events },
+        }
+    }
+
+    pub fn ok_rev_history(revisions: Vec<RevisionEntry>) -> Self {
+        Response::Ok {
+            data: ResponseData::RevHistory { revisions },
+        }
+    }
+
+    pub fn ok_benchmark(
+        operation: BenchmarkOp,
+        count: u32,
+        errors: u32,
+        duration: Duration,
+        latency: LatencyQuantiles,
+    ) -> Self {
+        let ops_per_sec = (count - errors) as f64 / duration.as_secs_f64().max(1e-6);
+        Response::Ok {
+            data: ResponseData::Benchmark {
+                operation,
+                count,
+                errors,
+                duration_ms: duration.as_millis() as u64,
+                ops_per_sec,
+                latency,
+            },
+        }
+    }
+
+    pub fn ok_counter(counter: String, value: i64) -> Self {
+        Response::Ok {
+            data: ResponseData::Counter { counter, value },
+        }
+    }
+
+    pub fn ok_explain(opcodes: Vec<ExplainOp>) -> Self {
+        Response::Ok {
+            data: ResponseData::Explain { opcodes },
+        }
+    }
+
+    pub fn ok_describe_statement(params: Vec<String>, columns: Vec<ColumnInfo>) -> Self {
+        Response::Ok {
+            data: ResponseData::DescribeStatement {
+                param_count: params.len(),
+                params,
+                columns,
+            },
+        }
+    }
+
+    pub fn ok_stats(
+        requests: u64,
+        errors: u64,
+        latency: LatencyQuantiles,
+        circuit: String,
+        worker_age_secs: u64,
+        cached_statements: Option<usize>,
+    ) -> Self {
+        Response::Ok {
+            data: ResponseData::Stats {
+                requests,
+                errors,
+                latency,
+                circuit,
+                worker_age_secs,
+                cached_statements,
+            },
+        }
+    }
+
+    pub fn ok_cursor_opened(cursor_id: String, columns: Vec<String>) -> Self {
+        Response::Ok {
+            data: ResponseData::CursorOpened { cursor_id, columns },
+        }
+    }
+
+    pub fn ok_cursor_rows(rows: Vec<Vec<serde_json::Value>>, done: bool) -> Self {
+        Response::Ok {
+            data: ResponseData::CursorRows { rows, done },
+        }
+    }
+
+    pub fn ok_cursor_closed(cursor_closed: bool) -> Self {
+        Response::Ok {
+            data: ResponseData::CursorClosed { cursor_closed },
+        }
+    }
+
+    pub fn ok_tx_begun(tx_token: String) -> Self {
+        Response::Ok {
+            data: ResponseData::TxBegun { tx_token },
+        }
+    }
+
+    pub fn ok_tx_executed(tx_results: Vec<TxStatementResult>) -> Self {
+        Response::Ok {
+            data: ResponseData::TxExecuted { tx_results },
+        }
+    }
+
+    pub fn ok_tx_ended(tx_committed: bool, rev: Option<i64>) -> Self {
+        Response::Ok {
+            data: ResponseData::TxEnded { tx_committed, rev },
+        }
+    }
+
+    pub fn ok_synchronous(synchronous: SyncLevel, previous: SyncLevel) -> Self {
+        let warning = (synchronous == SyncLevel::Off).then(|| {
+            "synchronous=OFF skips fsync: a power loss or OS crash can corrupt the database"
+                .to_string()
+        });
+        Response::Ok {
+            data: ResponseData::Synchronous { synchronous, previous, warning },
+        }
+    }
+
+    pub fn ok_changeset_applied(rev: i64, changeset_conflicts: Vec<ChangesetConflict>) -> Self {
+        Response::Ok {
+            data: ResponseData::ChangesetApplied { rev, changeset_conflicts },
+        }
+    }
+
+    pub fn ok_statement_cache_cleared(cleared: usize) -> Self {
+        Response::Ok {
+            data: ResponseData::StatementCacheCleared { cleared },
+        }
+    }
+
+    pub fn ok_pragma_stats(pragmas: BTreeMap<String, serde_json::Value>) -> Self {
+        Response::Ok {
+            data: ResponseData::PragmaStats { pragmas },
+        }
+    }
+
+    pub fn ok_checkpoint_history(checkpoints: Vec<CheckpointEvent>) -> Self {
+        Response::Ok {
+            data: ResponseData::CheckpointHistory { checkpoints },
+        }
+    }
+
+    pub fn ok_schema_frozen(schema_frozen: bool) -> Self {
+        Response::Ok {
+            data: ResponseData::SchemaFrozen { schema_frozen },
+        }
+    }
+
+    pub fn ok_shutdown() -> Self {
+        Response::Ok {
+            data: ResponseData::Shutdown,
+        }
+    }
+
+    pub fn ok_prepare_maintenance() -> Self {
+        Response::Ok {
+            data: ResponseData::PrepareForMaintenance {
+                checkpointed: true,
+            },
+        }
+    }
+
+    pub fn ok_close_database() -> Self {
+        Response::Ok {
+            data: ResponseData::CloseDatabase {
+                closed: true,
+            },
+        }
+    }
+
+    pub fn ok_reopen_database(rev: i64, replayed: u32, replay_failed: u32) -> Self {
+        Response::Ok {
+            data: ResponseData::ReopenDatabase {
+                reopened: true,
+                rev,
+                replayed,
+                replay_failed,
+            },
+        }
+    }
+
+    pub fn ok_journaled(journaled: usize) -> Self {
+        Response::Ok {
+            data: ResponseData::Journaled { journaled },
+        }
+    }
+
+    pub fn ok_export_schema(ddl: String) -> Self {
+        Response::Ok {
+            data: ResponseData::ExportSchema { ddl },
+        }
+    }
+
+    pub fn ok_prune_meta(pruned: BTreeMap<String, u64>) -> Self {
+        Response::Ok {
+            data: ResponseData::PruneMeta { pruned },
+        }
+    }
+
+    pub fn ok_backup(bytes_written: u64, rev: i64) -> Self {
+        Response::Ok {
+            data: ResponseData::Backup { bytes_written, rev },
+        }
+    }
+
+    pub fn ok_migrate(migrations_applied: u32, user_version: i32, rev: i64) -> Self {
+        Response::Ok {
+            data: ResponseData::Migrate { migrations_applied, user_version, rev },
+        }
+    }
+
+    pub fn ok_migrate_progress(progress: MigrateProgress) -> Self {
+        Response::Ok {
+            data: ResponseData::MigrateProgress { progress },
+        }
+    }
+
+    pub fn ok_schema_version(user_version: i32) -> Self {
+        Response::Ok {
+            data: ResponseData::SchemaVersion { user_version },
+        }
+    }
+
+    pub fn ok_vacuum(size_before: u64, size_after: u64, duration_ms: u64, rev: i64) -> Self {
+        Response::Ok {
+            data: ResponseData::Vacuum { size_before, size_after, duration_ms, rev },
+        }
+    }
+
+    pub fn ok_analyze(analyzed_tables: Vec<String>, duration_ms: u64, rev: i64) -> Self {
+        Response::Ok {
+            data: ResponseData::Analyze { analyzed_tables, duration_ms, rev },
+        }
+    }
+
+    pub fn ok_vacuum_into(path: String, size_bytes: u64, duration_ms: u64) -> Self {
+        Response::Ok {
+            data: ResponseData::VacuumInto {
+                path,
+                size_bytes,
+                duration_ms,
+            },
+        }
+    }
+
+    pub fn ok_cleanup_sidecars(orphans: Vec<String>, removed: Vec<String>) -> Self {
+        Response::Ok {
+            data: ResponseData::CleanupSidecars { orphans, removed },
+        }
+    }
+
+    pub fn ok_use_database(default_db: String) -> Self {
+        Response::Ok {
+            data: ResponseData::UseDatabase { default_db },
+        }
+    }
+
+    pub fn ok_exec_all(results: BTreeMap<String, Response>) -> Self {
+        Response::Ok {
+            data: ResponseData::ExecAll { results },
+        }
+    }
+
+    pub fn ok_resolve_path(path: String, exists: bool) -> Self {
+        Response::Ok {
+            data: ResponseData::ResolvePath { path, exists },
+        }
+    }
+
+    pub fn ok_list_maintenance(databases: Vec<MaintenanceEntry>) -> Self {
+        Response::Ok {
+            data: ResponseData::ListMaintenance { databases },
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn ok_daemon_info(
+        pid: u32,
+        version: String,
+        started_at: i64,
+        uptime_secs: u64,
+        base_dir: String,
+        transport: String,
+        workers: usize,
+        accept_paused: bool,
+    ) -> Self {
+        Response::Ok {
+            data: ResponseData::DaemonInfo {
+                pid,
+                version,
+                started_at,
+                uptime_secs,
+                base_dir,
+                transport,
+                workers,
+                accept_paused,
+            },
+        }
+    }
+
+    pub fn ok_diagnostics(
+        process: ProcessDiagnostics,
+        config: serde_json::Value,
+        workers: Vec<WorkerDiagnostics>,
+    ) -> Self {
+        Response::Ok {
+            data: ResponseData::Diagnostics { process, config, workers },
+        }
+    }
+
+    pub fn ok_status(workers: Vec<WorkerStatus>) -> Self {
+        Response::Ok {
+            data: ResponseData::Status { workers },
+        }
+    }
+
+    pub fn ok_accept_paused(accept_paused: bool) -> Self {
+        Response::Ok {
+            data: ResponseData::AcceptPaused { accept_paused },
+        }
+    }
+
+    pub fn ok_list_functions(functions: Vec<FunctionInfo>) -> Self {
+        Response::Ok {
+            data: ResponseData::ListFunctions { functions },
+        }
+    }
+
+    pub fn ok_compare(tables: Vec<TableComparison>) -> Self {
+        Response::Ok {
+            data: ResponseData::Compare {
+                identical: tables.iter().all(|t| t.identical),
+                tables,
+            },
+        }
+    }
+
+    pub fn ok_validate_sql(error: Option<(String, Option<usize>)>, params: Vec<String>) -> Self {
+        let (error, offset) = match error {
+            Some((message, offset)) => (Some(message), offset),
+            None => (None, None),
+        };
+        Response::Ok {
+            data: ResponseData::ValidateSql {
+                valid: error.is_none(),
+                error,
+                offset,
+                params,
+            },
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Response::Error {
+            message: message.into(),
+            code: None,
+            sqlite_code: None,
+            sqlite_extended_code: None,
+        }
+    }
+
+    pub fn error_with_code(message: impl Into<String>, code: impl Into<String>) -> Self {
+        Response::Error {
+            message: message.into(),
+            code: Some(code.into()),
+            sqlite_code: None,
+            sqlite_extended_code: None,
+        }
+    }
+
+    /// Attach SQLite's extended result code (see `query::extended_code`)
+    /// and the primary code it carries in its low byte to an error
+    pub fn with_sqlite_code(mut self, extended: Option<i32>) -> Self {
+        if let Response::Error { sqlite_code, sqlite_extended_code, .. } = &mut self {
+            *sqlite_code = extended.map(|code| code & 0xff);
+            *sqlite_extended_code = extended;
+        }
+        self
+    }
+}